@@ -0,0 +1,125 @@
+//! Benchmarks for the per-request x402 code paths.
+//!
+//! Run with `cargo bench --features integration-test` (the test stubs stand in
+//! for the nginx symbols). Only built on x86_64 Linux so results are comparable
+//! across CI runs.
+//!
+//! `x402_handler_impl` itself is not covered: it needs a live
+//! `ngx_http_request_t` (header lists, pool, connection) that only nginx can
+//! provide. Its no-payment fast path is `parse` + `create_requirements` +
+//! response rendering, which are benchmarked individually below.
+//!
+//! Baselines: record with `cargo bench --features integration-test -- --save-baseline main`
+//! on the CI runner and compare branches with `-- --baseline main`.
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod benches {
+    use criterion::{black_box, criterion_group, Criterion};
+    use ngx::ffi::ngx_str_t;
+    use ngx_x402::ngx_module::requirements::{amount_to_smallest_unit, create_requirements};
+    use ngx_x402::ngx_module::response::generate_paywall_html;
+    use ngx_x402::validation::validate_ethereum_address;
+    use ngx_x402::{ParsedX402Config, X402Config};
+    use rust_decimal::Decimal;
+
+    const PAY_TO: &str = "0x1234567890abcdef1234567890abcdef12345678";
+
+    fn ngx_str(s: &'static str) -> ngx_str_t {
+        ngx_str_t {
+            len: s.len(),
+            data: s.as_ptr() as *mut u8,
+        }
+    }
+
+    fn full_config() -> ParsedX402Config {
+        let mut conf = X402Config::default();
+        conf.enabled = 1;
+        conf.amount_str = ngx_str("0.001");
+        conf.pay_to_str = ngx_str(PAY_TO);
+        conf.facilitator_url_str = ngx_str("https://x402.org/facilitator");
+        conf.description_str = ngx_str("Weather API");
+        conf.network_str = ngx_str("base-sepolia");
+        conf.resource_str = ngx_str("https://api.example.com/weather");
+        conf.asset_str = ngx_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e");
+        conf.asset_decimals_str = ngx_str("6");
+        conf.verify_timeout_str = ngx_str("10");
+        conf.facilitator_fallback_str = ngx_str("error");
+        conf.ttl_str = ngx_str("60");
+        conf.replay_ttl_str = ngx_str("86400");
+        conf.parse().expect("benchmark config must parse")
+    }
+
+    fn bench_create_requirements(c: &mut Criterion) {
+        let config = full_config();
+        c.bench_function("create_requirements", |b| {
+            b.iter(|| create_requirements(black_box(&config), black_box("/api/weather")))
+        });
+    }
+
+    fn bench_parse_config(c: &mut Criterion) {
+        c.bench_function("config_parse_and_requirements", |b| {
+            b.iter(|| {
+                let config = full_config();
+                create_requirements(black_box(&config), black_box("/api/weather"))
+            })
+        });
+    }
+
+    fn bench_generate_paywall_html(c: &mut Criterion) {
+        let mut config = full_config();
+        let requirement_sets: Vec<_> = (1..=10)
+            .map(|i| {
+                config.amount = Some(Decimal::new(i, 3));
+                vec![create_requirements(&config, "/api/weather").unwrap()]
+            })
+            .collect();
+        c.bench_function("generate_paywall_html_x10", |b| {
+            b.iter(|| {
+                for reqs in &requirement_sets {
+                    black_box(generate_paywall_html(
+                        black_box("Payment required"),
+                        reqs,
+                        "https://example.com/api/weather",
+                        true,
+                    ));
+                }
+            })
+        });
+    }
+
+    fn bench_validate_ethereum_address(c: &mut Criterion) {
+        c.bench_function("validate_ethereum_address", |b| {
+            b.iter(|| {
+                for _ in 0..1000 {
+                    let _ = black_box(validate_ethereum_address(black_box(PAY_TO)));
+                }
+            })
+        });
+    }
+
+    fn bench_amount_to_smallest_unit(c: &mut Criterion) {
+        let amount = Decimal::new(12345, 6);
+        let mut group = c.benchmark_group("amount_to_smallest_unit");
+        for decimals in [0u8, 6, 9, 18] {
+            group.bench_function(format!("decimals_{decimals}"), |b| {
+                b.iter(|| amount_to_smallest_unit(black_box(amount), black_box(decimals)))
+            });
+        }
+        group.finish();
+    }
+
+    criterion_group!(
+        handler_benches,
+        bench_create_requirements,
+        bench_parse_config,
+        bench_generate_paywall_html,
+        bench_validate_ethereum_address,
+        bench_amount_to_smallest_unit
+    );
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+criterion::criterion_main!(benches::handler_benches);
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+fn main() {}
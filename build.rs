@@ -9,7 +9,25 @@ fn main() {
     if let Ok(output) = Command::new(&nginx_bin).arg("-v").output() {
         let version_str = String::from_utf8_lossy(&output.stderr);
         if let Some(version) = version_str.strip_prefix("nginx version: nginx/") {
-            let _ = version.trim();
+            // Suffix of the facilitator User-Agent (`runtime::default_user_agent`).
+            println!("cargo:rustc-env=X402_NGINX_VERSION={}", version.trim());
+        }
+    }
+
+    // Label of the `x402_build_info` metric.
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Ok(output) = Command::new(&rustc).arg("--version").output() {
+        let version_str = String::from_utf8_lossy(&output.stdout);
+        if let Some(version) = version_str.split_whitespace().nth(1) {
+            println!("cargo:rustc-env=X402_RUSTC_VERSION={version}");
+        }
+    }
+
+    // Client and (for the tests' mock) server of the gRPC facilitator API.
+    #[cfg(feature = "grpc")]
+    {
+        if let Err(e) = tonic_build::compile_protos("proto/x402_facilitator.proto") {
+            panic!("Failed to compile proto/x402_facilitator.proto: {e}");
         }
     }
 
@@ -0,0 +1,42 @@
+//! `verify_payment` must never panic on an arbitrary `Payment-Signature` value;
+//! malformed input has to surface as `Err(X402Error)`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ngx_x402::ngx_module::runtime::{
+    get_runtime, verify_payment, DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Nothing listens here, so inputs that survive decoding fail fast on connect.
+const MOCK_FACILITATOR_URL: &str = "http://127.0.0.1:9";
+
+fn requirements() -> &'static serde_json::Value {
+    static REQUIREMENTS: OnceLock<serde_json::Value> = OnceLock::new();
+    REQUIREMENTS.get_or_init(|| {
+        serde_json::json!({
+            "scheme": "exact",
+            "network": "eip155:84532",
+            "amount": "1000",
+            "payTo": "0x1234567890abcdef1234567890abcdef12345678",
+            "maxTimeoutSeconds": 60,
+            "asset": "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+            "extra": { "name": "USDC", "version": "2" }
+        })
+    })
+}
+
+fuzz_target!(|payment_b64: &str| {
+    let runtime = get_runtime().expect("tokio runtime");
+    let _ = runtime.block_on(verify_payment(
+        payment_b64,
+        requirements(),
+        MOCK_FACILITATOR_URL,
+        Some(Duration::from_millis(50)),
+        &[],
+        Duration::ZERO,
+        DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+    ));
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ngx_x402::validation::validate_ethereum_address;
+
+fuzz_target!(|address: &str| {
+    if validate_ethereum_address(address).is_ok() {
+        assert_eq!(address.trim().len(), 42);
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ngx_x402::validation::{
+    validate_resource_path, DEFAULT_RESOURCE_ALLOWED_SCHEMES, DEFAULT_RESOURCE_MAX_LENGTH,
+};
+
+fuzz_target!(|path: &str| {
+    if let Ok(validated) = validate_resource_path(
+        path,
+        DEFAULT_RESOURCE_ALLOWED_SCHEMES,
+        DEFAULT_RESOURCE_MAX_LENGTH,
+    ) {
+        assert!(!validated.is_empty());
+        assert!(validated.len() <= DEFAULT_RESOURCE_MAX_LENGTH);
+        assert!(!validated.split('?').next().unwrap_or("").contains(".."));
+    }
+});
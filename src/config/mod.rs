@@ -1,3 +1,7 @@
 pub mod validation;
 
+pub use crate::ngx_module::config::{
+    DEFAULT_ASSET_DECIMALS, DEFAULT_FACILITATOR_TIMEOUT_SECS, DEFAULT_NONCE_TTL_MS,
+    DEFAULT_REPLAY_TTL_SECONDS, DEFAULT_TIMEOUT_SECONDS, DEFAULT_TTL_SECONDS,
+};
 pub use validation::{validate_amount, validate_ethereum_address, validate_network, validate_url};
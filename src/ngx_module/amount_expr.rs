@@ -0,0 +1,346 @@
+//! `x402_amount_expr`: a price computed per request from a small arithmetic
+//! formula, e.g. `req_size_kb * 0.001`.
+//!
+//! Grammar (usual precedence, left associative):
+//!
+//! ```text
+//! expr   := term (('+' | '-') term)*
+//! term   := factor (('*' | '/') factor)*
+//! factor := number | variable | '(' expr ')' | '-' factor
+//! ```
+//!
+//! Variables are `req_size_kb` (request `Content-Length` / 1024) and
+//! `req_path_depth` (number of non-empty `/` segments in the path).
+
+use rust_decimal::Decimal;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ngx_module::error::{Result, X402Error};
+
+/// Per-request inputs of an amount expression.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EvalContext {
+    pub req_size_kb: f64,
+    pub req_path_depth: u32,
+}
+
+impl EvalContext {
+    /// Context for a request with the given `Content-Length` and path.
+    pub fn new(content_length: Option<u64>, path: &str) -> Self {
+        Self {
+            req_size_kb: content_length.unwrap_or(0) as f64 / 1024.0,
+            req_path_depth: path.split('/').filter(|s| !s.is_empty()).count() as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Var {
+    ReqSizeKb,
+    ReqPathDepth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Parsed `x402_amount_expr`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(Decimal),
+    Var(Var),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(s: &str) -> Result<Self> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.expr()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(X402Error::Config(format!(
+                "x402_amount_expr: unexpected '{token}'"
+            ))),
+        }
+    }
+
+    /// Evaluate to a price. Negative results and arithmetic failures (division
+    /// by zero, overflow, a non-finite `req_size_kb`) are errors.
+    pub fn amount(&self, ctx: &EvalContext) -> Result<Decimal> {
+        let value = self.eval(ctx)?;
+        if value < Decimal::ZERO {
+            return Err(X402Error::Config(format!(
+                "x402_amount_expr evaluated to a negative amount ({value})"
+            )));
+        }
+        Ok(value.normalize())
+    }
+
+    fn eval(&self, ctx: &EvalContext) -> Result<Decimal> {
+        match self {
+            Expr::Const(value) => Ok(*value),
+            Expr::Var(Var::ReqSizeKb) => Decimal::try_from(ctx.req_size_kb).map_err(|_| {
+                X402Error::Config(format!(
+                    "x402_amount_expr: req_size_kb is not a number ({})",
+                    ctx.req_size_kb
+                ))
+            }),
+            Expr::Var(Var::ReqPathDepth) => Ok(Decimal::from(ctx.req_path_depth)),
+            Expr::Neg(inner) => Ok(-inner.eval(ctx)?),
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(ctx)?, rhs.eval(ctx)?);
+                let result = match op {
+                    Op::Add => lhs.checked_add(rhs),
+                    Op::Sub => lhs.checked_sub(rhs),
+                    Op::Mul => lhs.checked_mul(rhs),
+                    Op::Div => lhs.checked_div(rhs),
+                };
+                result.ok_or_else(|| {
+                    X402Error::Config(format!(
+                        "x402_amount_expr: {lhs} {op} {rhs} is not a number"
+                    ))
+                })
+            }
+        }
+    }
+}
+
+/// Parse and evaluate `expr` in one go. The handler keeps the parsed form from
+/// the config instead.
+pub fn eval_expr(expr: &str, ctx: &EvalContext) -> Result<Decimal> {
+    Expr::parse(expr)?.amount(ctx)
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+        };
+        f.write_str(symbol)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Decimal),
+    Ident(String),
+    Op(Op),
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{value}"),
+            Token::Ident(name) => f.write_str(name),
+            Token::Op(op) => write!(f, "{op}"),
+            Token::LParen => f.write_str("("),
+            Token::RParen => f.write_str(")"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            c if c.is_ascii_digit() || c == '.' => {
+                let end = take_while(&mut chars, |c| c.is_ascii_digit() || c == '.');
+                let text = &s[start..end];
+                let value = Decimal::from_str(text).map_err(|_| {
+                    X402Error::Config(format!("x402_amount_expr: invalid number '{text}'"))
+                })?;
+                tokens.push(Token::Number(value));
+                continue;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let end = take_while(&mut chars, |c| c.is_ascii_alphanumeric() || c == '_');
+                tokens.push(Token::Ident(s[start..end].to_string()));
+                continue;
+            }
+            other => {
+                return Err(X402Error::Config(format!(
+                    "x402_amount_expr: unexpected character '{other}'"
+                )))
+            }
+        };
+        chars.next();
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Consume characters matching `pred`; returns the byte offset after them.
+fn take_while(
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+    pred: impl Fn(char) -> bool,
+) -> usize {
+    let mut end = 0;
+    while let Some(&(i, c)) = chars.peek() {
+        if !pred(c) {
+            return i;
+        }
+        end = i + c.len_utf8();
+        chars.next();
+    }
+    end
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        while let Some(&Token::Op(op @ (Op::Add | Op::Sub))) = self.peek() {
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.factor()?;
+        while let Some(&Token::Op(op @ (Op::Mul | Op::Div))) = self.peek() {
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Expr> {
+        match self.advance() {
+            Some(Token::Number(value)) => Ok(Expr::Const(value)),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "req_size_kb" => Ok(Expr::Var(Var::ReqSizeKb)),
+                "req_path_depth" => Ok(Expr::Var(Var::ReqPathDepth)),
+                _ => Err(X402Error::Config(format!(
+                    "x402_amount_expr: unknown variable '{name}' (expected req_size_kb or req_path_depth)"
+                ))),
+            },
+            Some(Token::Op(Op::Sub)) => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Some(Token::LParen) => {
+                let inner = self.expr()?;
+                match self.advance() {
+                    Some(Token::RParen) => Ok(inner),
+                    _ => Err(X402Error::Config(
+                        "x402_amount_expr: missing ')'".into(),
+                    )),
+                }
+            }
+            Some(token) => Err(X402Error::Config(format!(
+                "x402_amount_expr: unexpected '{token}'"
+            ))),
+            None => Err(X402Error::Config(
+                "x402_amount_expr: unexpected end of expression".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(req_size_kb: f64, req_path_depth: u32) -> EvalContext {
+        EvalContext {
+            req_size_kb,
+            req_path_depth,
+        }
+    }
+
+    fn eval(expr: &str) -> Decimal {
+        eval_expr(expr, &ctx(2.5, 3)).unwrap()
+    }
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_constants_and_operators() {
+        assert_eq!(eval("0.001"), dec("0.001"));
+        assert_eq!(eval("0.001 + 0.002"), dec("0.003"));
+        assert_eq!(eval("0.01 - 0.002"), dec("0.008"));
+        assert_eq!(eval("3 * 0.001"), dec("0.003"));
+        assert_eq!(eval("1 / 4"), dec("0.25"));
+        assert_eq!(eval("1 - 0.5 - 0.25"), dec("0.25"), "left associative");
+        assert_eq!(eval("8 / 4 / 2"), dec("1"));
+        assert_eq!(eval("1 + 2 * 3"), dec("7"), "* binds tighter than +");
+        assert_eq!(eval("(1 + 2) * 3"), dec("9"));
+        assert_eq!(eval("-1 + 2"), dec("1"));
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(eval("req_size_kb"), dec("2.5"));
+        assert_eq!(eval("req_path_depth"), dec("3"));
+        assert_eq!(eval("req_size_kb * 0.001"), dec("0.0025"));
+        assert_eq!(eval("0.001 + req_path_depth * 0.0005"), dec("0.0025"));
+    }
+
+    #[test]
+    fn test_eval_context_new() {
+        assert_eq!(EvalContext::new(Some(2048), "/api/v1/items"), ctx(2.0, 3));
+        assert_eq!(EvalContext::new(None, "/"), ctx(0.0, 0));
+        assert_eq!(EvalContext::new(Some(512), "//a/"), ctx(0.5, 1));
+    }
+
+    #[test]
+    fn test_rejects_negative_and_non_numbers() {
+        let c = ctx(1.0, 1);
+        assert!(eval_expr("0.001 - 0.002", &c).is_err());
+        assert!(eval_expr("-req_size_kb", &c).is_err());
+        assert!(eval_expr("1 / 0", &c).is_err());
+        assert!(eval_expr("1 / (req_path_depth - 1)", &c).is_err());
+        assert!(eval_expr("req_size_kb", &ctx(f64::NAN, 0)).is_err());
+        assert!(eval_expr("req_size_kb", &ctx(f64::INFINITY, 0)).is_err());
+        assert!(matches!(eval_expr("0 - 1", &c), Err(X402Error::Config(_))));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for invalid in [
+            "", "req_size", "1 +", "(1 + 2", "1 2", "1.2.3", "1 % 2", "* 2", "0.001)",
+        ] {
+            assert!(
+                Expr::parse(invalid).is_err(),
+                "{invalid:?} should not parse"
+            );
+        }
+    }
+}
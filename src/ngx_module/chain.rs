@@ -0,0 +1,195 @@
+use crate::ngx_module::config::DEFAULT_ASSET_DECIMALS;
+use crate::ngx_module::error::{Result, X402Error};
+use crate::ngx_module::logging::{log_info, log_warn};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+/// Selector of ERC-20 `decimals()`.
+const DECIMALS_SELECTOR: &str = "313ce567";
+
+/// Decimals used for each `(rpc_url, asset)`, fallback included.
+static DECIMALS_CACHE: OnceLock<Mutex<HashMap<(String, String), u8>>> = OnceLock::new();
+
+/// Perform a JSON-RPC `eth_call` against the latest block and return the raw result.
+pub(crate) async fn eth_call(
+    client: &reqwest::Client,
+    rpc_url: &str,
+    to: &str,
+    data: String,
+) -> std::result::Result<String, String> {
+    let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_call",
+        "params": [{ "to": to, "data": data }, "latest"],
+    });
+    let resp = client
+        .post(rpc_url)
+        .json(&body)
+        .timeout(RPC_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| format!("RPC request failed: {e}"))?;
+    let json: serde_json::Value = resp
+        .json()
+        .await
+        .map_err(|e| format!("Invalid RPC response: {e}"))?;
+    if let Some(err) = json.get("error") {
+        return Err(format!("RPC error: {err}"));
+    }
+    json.get("result")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+        .ok_or_else(|| "RPC response has no result".to_string())
+}
+
+/// Decode an ABI-encoded `uint8` return value.
+fn decode_uint8(word: &str) -> std::result::Result<u8, String> {
+    let hex = word.strip_prefix("0x").unwrap_or(word);
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Unexpected decimals() result: {word}"));
+    }
+    if hex[..62].chars().any(|c| c != '0') {
+        return Err(format!("decimals() result out of range: {word}"));
+    }
+    u8::from_str_radix(&hex[62..], 16).map_err(|e| e.to_string())
+}
+
+/// Decimals of the ERC-20 contract at `asset` for `x402_asset_decimals_auto`,
+/// falling back to [`DEFAULT_ASSET_DECIMALS`] when `decimals()` fails or is
+/// out of range.
+///
+/// Called while merging configs, before nginx forks, so workers inherit the
+/// result; it is cached either way and later calls never reach the RPC
+/// endpoint. [`clear_decimals_cache`] starts over on reload.
+pub fn asset_decimals_blocking(asset: &str, rpc_url: &str) -> u8 {
+    let asset = asset.trim().to_lowercase();
+    let key = (rpc_url.to_string(), asset.clone());
+    let cache = DECIMALS_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(decimals) = cache.lock().ok().and_then(|c| c.get(&key).copied()) {
+        return decimals;
+    }
+
+    let decimals = match fetch_decimals_blocking(&asset, rpc_url) {
+        Ok(d) if d <= 28 => d,
+        Ok(d) => {
+            log_warn(
+                None,
+                &format!(
+                    "Asset {asset} reports {d} decimals; falling back to {DEFAULT_ASSET_DECIMALS}"
+                ),
+            );
+            DEFAULT_ASSET_DECIMALS
+        }
+        Err(e) => {
+            log_warn(
+                None,
+                &format!(
+                    "Failed to read decimals() of {asset}: {e}; falling back to {DEFAULT_ASSET_DECIMALS}"
+                ),
+            );
+            DEFAULT_ASSET_DECIMALS
+        }
+    };
+    if let Ok(mut cache) = cache.lock() {
+        cache.insert(key, decimals);
+    }
+    decimals
+}
+
+/// Forget every asset's decimals, so the next configuration load queries again.
+pub fn clear_decimals_cache() {
+    if let Some(Ok(mut cache)) = DECIMALS_CACHE.get().map(Mutex::lock) {
+        cache.clear();
+    }
+}
+
+/// Read `decimals()` from the ERC-20 contract at `asset`. Uncached; see
+/// [`asset_decimals_blocking`].
+pub fn fetch_decimals_blocking(asset: &str, rpc_url: &str) -> Result<u8> {
+    // Config is read before the shared runtime exists (and before nginx
+    // forks), so the call uses a throwaway runtime.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| X402Error::Runtime(format!("Failed to create tokio runtime: {e}")))?;
+    let result = runtime
+        .block_on(async {
+            let client = reqwest::Client::new();
+            eth_call(&client, rpc_url, asset, format!("0x{DECIMALS_SELECTOR}")).await
+        })
+        .map_err(X402Error::Runtime)?;
+    let decimals = decode_uint8(&result).map_err(X402Error::Runtime)?;
+    log_info(None, &format!("Asset {asset} reports {decimals} decimals"));
+    Ok(decimals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngx_module::test_support::MockServer;
+
+    fn rpc_result(result: String) -> (u16, String) {
+        (
+            200,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string(),
+        )
+    }
+
+    #[test]
+    fn test_decode_uint8() {
+        assert_eq!(decode_uint8(&format!("0x{:0>64}", "12")).unwrap(), 18);
+        assert_eq!(decode_uint8(&format!("0x{:0>64}", "6")).unwrap(), 6);
+        assert!(decode_uint8(&format!("0x{:0>64}", "100")).is_err());
+        assert!(decode_uint8("0x").is_err());
+    }
+
+    #[test]
+    fn test_fetch_decimals_blocking() {
+        let server = MockServer::start(|_| rpc_result(format!("0x{:0>64}", "12")));
+        let asset = "0x2222222222222222222222222222222222222222";
+        assert_eq!(fetch_decimals_blocking(asset, &server.url).unwrap(), 18);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("\"0x313ce567\""));
+    }
+
+    #[test]
+    fn test_fetch_decimals_rpc_error() {
+        let server = MockServer::start(|_| {
+            (
+                200,
+                r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"execution reverted"}}"#
+                    .to_string(),
+            )
+        });
+        assert!(
+            fetch_decimals_blocking("0x3333333333333333333333333333333333333333", &server.url)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_asset_decimals_blocking_caches_result_and_fallback() {
+        let server = MockServer::start(|_| rpc_result(format!("0x{:0>64}", "12")));
+        let asset = "0x5555555555555555555555555555555555555555";
+        assert_eq!(asset_decimals_blocking(asset, &server.url), 18);
+        asset_decimals_blocking(&asset.to_uppercase().replace("0X", "0x"), &server.url);
+        assert_eq!(server.requests().len(), 1);
+
+        // Out of range falls back to the default, and the fallback is cached.
+        let server = MockServer::start(|_| rpc_result(format!("0x{:0>64}", "ff")));
+        let asset = "0x6666666666666666666666666666666666666666";
+        assert_eq!(
+            asset_decimals_blocking(asset, &server.url),
+            DEFAULT_ASSET_DECIMALS
+        );
+        assert_eq!(
+            asset_decimals_blocking(asset, &server.url),
+            DEFAULT_ASSET_DECIMALS
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -1,11 +1,13 @@
 use ngx::ffi::{
-    ngx_command_t, ngx_conf_t, ngx_str_t, ngx_uint_t, NGX_CONF_TAKE1, NGX_HTTP_LOC_CONF,
-    NGX_HTTP_LOC_CONF_OFFSET,
+    ngx_array_t, ngx_command_t, ngx_conf_t, ngx_pnalloc, ngx_str_t, ngx_uint_t, NGX_CONF_1MORE,
+    NGX_CONF_TAKE1, NGX_CONF_TAKE2, NGX_HTTP_LOC_CONF, NGX_HTTP_LOC_CONF_OFFSET,
+    NGX_HTTP_MAIN_CONF, NGX_HTTP_SRV_CONF, NGX_LOG_WARN,
 };
 use ngx::ngx_string;
 use std::os::raw::c_void;
 
-use crate::ngx_module::config::X402Config;
+use crate::ngx_module::config::{X402Config, INHERIT};
+use crate::ngx_module::logging::log_warn;
 
 macro_rules! ngx_conf_set_str_slot {
     ($handler:ident, $field:ident) => {
@@ -16,10 +18,54 @@ macro_rules! ngx_conf_set_str_slot {
         ) -> *mut u8 {
             let conf = &mut *(conf as *mut X402Config);
             let args = (*(*cf).args).elts as *mut ngx_str_t;
+            if conf.$field.len > 0 {
+                if let Some(err) = duplicate_directive(cf, conf) {
+                    return err;
+                }
+            }
             conf.$field = *args.add(1);
             std::ptr::null_mut()
         }
     };
+    // Also accepts `inherit`, stored as [`INHERIT`] until merge.
+    ($handler:ident, $field:ident, inherit) => {
+        unsafe extern "C" fn $handler(
+            cf: *mut ngx_conf_t,
+            _cmd: *mut ngx_command_t,
+            conf: *mut c_void,
+        ) -> *mut u8 {
+            let conf = &mut *(conf as *mut X402Config);
+            let args = (*(*cf).args).elts as *mut ngx_str_t;
+            if conf.$field.len > 0 {
+                if let Some(err) = duplicate_directive(cf, conf) {
+                    return err;
+                }
+            }
+            conf.$field = match ngx_str_slice(&*args.add(1)) {
+                b"inherit" => INHERIT,
+                _ => *args.add(1),
+            };
+            std::ptr::null_mut()
+        }
+    };
+}
+
+/// A single-value directive given twice in one block. An error, unless
+/// `x402_allow_duplicate_directives on` came earlier in the block; then a
+/// warning, and the last value wins as with nginx's own slots.
+unsafe fn duplicate_directive(cf: *mut ngx_conf_t, conf: &X402Config) -> Option<*mut u8> {
+    if conf.allow_duplicate_directives == 0 {
+        // nginx prefixes the directive name and appends the file and line.
+        return Some(c"is duplicate".as_ptr() as *mut u8);
+    }
+    let args = unsafe { (*(*cf).args).elts as *mut ngx_str_t };
+    let name = String::from_utf8_lossy(unsafe { ngx_str_slice(&*args) });
+    ngx::ngx_conf_log_error!(
+        NGX_LOG_WARN,
+        cf,
+        "\"{name}\" directive is duplicate, using the last value"
+    );
+    None
 }
 
 unsafe extern "C" fn ngx_http_x402_set(
@@ -37,25 +83,555 @@ unsafe extern "C" fn ngx_http_x402_set(
     std::ptr::null_mut()
 }
 
-ngx_conf_set_str_slot!(ngx_http_x402_amount_set, amount_str);
-ngx_conf_set_str_slot!(ngx_http_x402_pay_to_set, pay_to_str);
+/// `x402_merge on|off`, stored like `x402` in an integer field.
+unsafe extern "C" fn ngx_http_x402_merge_set(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut c_void,
+) -> *mut u8 {
+    let conf = unsafe { &mut *(conf as *mut X402Config) };
+    let args = unsafe { (*(*cf).args).elts as *mut ngx_str_t };
+    match unsafe { ngx_str_slice(&*args.add(1)) } {
+        b"on" => conf.merge_enabled = 1,
+        b"off" => conf.merge_enabled = 0,
+        _ => return c"must be \"on\" or \"off\"".as_ptr() as *mut u8,
+    }
+    std::ptr::null_mut()
+}
+
+/// `x402_allow_duplicate_directives on|off`, read by the other directives of
+/// the same block as they are parsed.
+unsafe extern "C" fn ngx_http_x402_allow_duplicate_directives_set(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut c_void,
+) -> *mut u8 {
+    let conf = unsafe { &mut *(conf as *mut X402Config) };
+    let args = unsafe { (*(*cf).args).elts as *mut ngx_str_t };
+    match unsafe { ngx_str_slice(&*args.add(1)) } {
+        b"on" => conf.allow_duplicate_directives = 1,
+        b"off" => conf.allow_duplicate_directives = 0,
+        _ => return c"must be \"on\" or \"off\"".as_ptr() as *mut u8,
+    }
+    std::ptr::null_mut()
+}
+
+/// Copy `parts` back to back into a single pool-allocated string.
+unsafe fn conf_str_concat(cf: *mut ngx_conf_t, parts: &[&[u8]]) -> Option<ngx_str_t> {
+    let len = parts.iter().map(|p| p.len()).sum();
+    let data = unsafe { ngx_pnalloc((*cf).pool, len) as *mut u8 };
+    if data.is_null() {
+        return None;
+    }
+    let buf = unsafe { std::slice::from_raw_parts_mut(data, len) };
+    let mut pos = 0;
+    for part in parts {
+        buf[pos..pos + part.len()].copy_from_slice(part);
+        pos += part.len();
+    }
+    Some(ngx_str_t { len, data })
+}
+
+unsafe fn ngx_str_slice(s: &ngx_str_t) -> &[u8] {
+    if s.len == 0 {
+        return &[];
+    }
+    unsafe { std::slice::from_raw_parts(s.data, s.len) }
+}
+
+/// Like `ngx_conf_set_str_slot!` for `NGX_CONF_1MORE` directives: all
+/// arguments are stored space-separated in one string.
+macro_rules! ngx_conf_set_list_slot {
+    ($handler:ident, $field:ident) => {
+        unsafe extern "C" fn $handler(
+            cf: *mut ngx_conf_t,
+            _cmd: *mut ngx_command_t,
+            conf: *mut c_void,
+        ) -> *mut u8 {
+            let conf = &mut *(conf as *mut X402Config);
+            let args = (*(*cf).args).elts as *mut ngx_str_t;
+            let nelts = (*(*cf).args).nelts;
+            let mut parts: Vec<&[u8]> = Vec::with_capacity(2 * nelts);
+            for i in 1..nelts {
+                if i > 1 {
+                    parts.push(b" ");
+                }
+                parts.push(ngx_str_slice(&*args.add(i)));
+            }
+            match conf_str_concat(cf, &parts) {
+                Some(joined) => {
+                    conf.$field = joined;
+                    std::ptr::null_mut()
+                }
+                None => ngx::core::NGX_CONF_ERROR as *mut u8,
+            }
+        }
+    };
+}
+
+/// `x402_facilitator_header <name> <value>` may repeat, so each occurrence is
+/// appended to `facilitator_headers_str` as a `name: value` line.
+unsafe extern "C" fn ngx_http_x402_facilitator_header_set(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut c_void,
+) -> *mut u8 {
+    let conf = unsafe { &mut *(conf as *mut X402Config) };
+    let args = unsafe { (*(*cf).args).elts as *mut ngx_str_t };
+    let name = unsafe { ngx_str_slice(&*args.add(1)) };
+    let value = unsafe { ngx_str_slice(&*args.add(2)) };
+    if value.iter().any(|&b| b == b'\r' || b == b'\n') {
+        return c"header value must not contain line breaks".as_ptr() as *mut u8;
+    }
+
+    let prev = unsafe { ngx_str_slice(&conf.facilitator_headers_str) };
+    match unsafe { conf_str_concat(cf, &[prev, name, b": ", value, b"\n"]) } {
+        Some(headers) => {
+            conf.facilitator_headers_str = headers;
+            std::ptr::null_mut()
+        }
+        None => ngx::core::NGX_CONF_ERROR,
+    }
+}
+
+/// One directive of an `x402_include` file: its line number and words.
+type IncludedDirective = (usize, Vec<String>);
+
+/// Split an `x402_include` file into directives, one per line in nginx
+/// syntax: `x402_amount 0.01;`. Words may be quoted; `#` starts a comment.
+/// Only `x402` directives are accepted, and includes do not nest.
+fn parse_include_file(text: &str) -> Result<Vec<IncludedDirective>, String> {
+    let mut directives = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let mut words = Vec::new();
+        let mut word: Option<String> = None;
+        let mut quote = None;
+        let mut terminated = false;
+        for c in line.chars() {
+            if terminated {
+                if c == '#' {
+                    break;
+                }
+                if !c.is_whitespace() {
+                    return Err(format!("line {line_no}: one directive per line"));
+                }
+                continue;
+            }
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+                (None, '"' | '\'') => {
+                    quote = Some(c);
+                    word.get_or_insert_with(String::new);
+                }
+                (None, '#') => break,
+                (None, ';') => {
+                    words.extend(word.take());
+                    terminated = true;
+                }
+                (None, c) if c.is_whitespace() => words.extend(word.take()),
+                (None, c) => word.get_or_insert_with(String::new).push(c),
+            }
+        }
+        if quote.is_some() {
+            return Err(format!("line {line_no}: unterminated quote"));
+        }
+        words.extend(word.take());
+        if words.is_empty() {
+            if terminated {
+                return Err(format!("line {line_no}: unexpected \";\""));
+            }
+            continue;
+        }
+        if !terminated {
+            return Err(format!("line {line_no}: missing \";\""));
+        }
+        let name = words[0].as_str();
+        if name != "x402" && !name.starts_with("x402_") {
+            return Err(format!(
+                "line {line_no}: \"{name}\" is not an x402 directive"
+            ));
+        }
+        if name == "x402_include" {
+            return Err(format!("line {line_no}: x402_include cannot be nested"));
+        }
+        directives.push((line_no, words));
+    }
+    Ok(directives)
+}
+
+/// Whether `args` arguments fit a command's `NGX_CONF_*` argument flags.
+fn include_arg_count_ok(type_: ngx_uint_t, args: usize) -> bool {
+    let type_ = type_ as u32;
+    if type_ & NGX_CONF_1MORE != 0 {
+        args >= 1
+    } else if type_ & NGX_CONF_TAKE2 != 0 {
+        args == 2
+    } else {
+        args == 1
+    }
+}
+
+/// A pool-allocated, NUL-terminated message for nginx to report.
+unsafe fn conf_error(cf: *mut ngx_conf_t, msg: &str) -> *mut u8 {
+    match unsafe { conf_str_concat(cf, &[msg.as_bytes(), b"\0"]) } {
+        Some(s) => s.data,
+        None => ngx::core::NGX_CONF_ERROR,
+    }
+}
+
+/// `x402_include /path/to/file`: apply the x402 directives in the file to
+/// this block, as if written in place. The file must be a regular file that
+/// is not world-writable, since it may set `x402_pay_to`.
+unsafe extern "C" fn ngx_http_x402_include_set(
+    cf: *mut ngx_conf_t,
+    _cmd: *mut ngx_command_t,
+    conf: *mut c_void,
+) -> *mut u8 {
+    use std::os::unix::fs::PermissionsExt;
+
+    let args = unsafe { (*(*cf).args).elts as *mut ngx_str_t };
+    let path = String::from_utf8_lossy(unsafe { ngx_str_slice(&*args.add(1)) }).into_owned();
+    let meta = match std::fs::metadata(&path) {
+        Ok(meta) => meta,
+        Err(e) => return unsafe { conf_error(cf, &format!("cannot read {path}: {e}")) },
+    };
+    if !meta.is_file() {
+        return unsafe { conf_error(cf, &format!("{path} is not a regular file")) };
+    }
+    if meta.permissions().mode() & 0o002 != 0 {
+        return unsafe { conf_error(cf, &format!("{path} must not be world-writable")) };
+    }
+    let directives = match std::fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read {path}: {e}"))
+        .and_then(|text| parse_include_file(&text).map_err(|e| format!("{path}: {e}")))
+    {
+        Ok(directives) => directives,
+        Err(e) => return unsafe { conf_error(cf, &e) },
+    };
+
+    let saved_args = unsafe { (*cf).args };
+    for (line_no, words) in &directives {
+        let rv = unsafe { apply_included_directive(cf, conf, words) };
+        unsafe { (*cf).args = saved_args };
+        if let Err(e) = rv {
+            return unsafe { conf_error(cf, &format!("{path}:{line_no}: \"{}\" {e}", words[0])) };
+        }
+    }
+    std::ptr::null_mut()
+}
+
+/// Run the setter of the command named by `words[0]` with the remaining
+/// words as its arguments. Leaves `cf.args` pointing at a temporary array;
+/// the caller restores it.
+unsafe fn apply_included_directive(
+    cf: *mut ngx_conf_t,
+    conf: *mut c_void,
+    words: &[String],
+) -> Result<(), String> {
+    let commands = unsafe { &mut *std::ptr::addr_of_mut!(NGX_HTTP_X402_COMMANDS) };
+    let cmd = commands
+        .iter_mut()
+        .take_while(|cmd| cmd.name.len > 0)
+        .find(|cmd| unsafe { ngx_str_slice(&cmd.name) } == words[0].as_bytes())
+        .ok_or("is an unknown directive")?;
+    if cmd.type_ & unsafe { (*cf).cmd_type } == 0 {
+        return Err("is not allowed here".into());
+    }
+    if !include_arg_count_ok(cmd.type_, words.len() - 1) {
+        return Err("has an invalid number of arguments".into());
+    }
+    // Setters keep pointers to their arguments, so copy them into the pool.
+    let mut elts = Vec::with_capacity(words.len());
+    for word in words {
+        elts.push(
+            unsafe { conf_str_concat(cf, &[word.as_bytes()]) }.ok_or("could not be allocated")?,
+        );
+    }
+    let mut args: ngx_array_t = unsafe { std::mem::zeroed() };
+    args.elts = elts.as_mut_ptr() as *mut c_void;
+    args.nelts = elts.len() as _;
+    args.size = std::mem::size_of::<ngx_str_t>() as _;
+    args.nalloc = elts.len() as _;
+    unsafe { (*cf).args = &mut args };
+
+    let set = cmd.set.ok_or("has no handler")?;
+    let rv = unsafe { set(cf, cmd, conf) };
+    if rv.is_null() {
+        Ok(())
+    } else if std::ptr::eq(rv, ngx::core::NGX_CONF_ERROR) {
+        Err("is invalid".into())
+    } else {
+        Err(
+            unsafe { std::ffi::CStr::from_ptr(rv as *const std::ffi::c_char) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+ngx_conf_set_str_slot!(ngx_http_x402_amount_set, amount_str, inherit);
+ngx_conf_set_str_slot!(ngx_http_x402_pay_to_set, pay_to_str, inherit);
 ngx_conf_set_str_slot!(ngx_http_x402_facilitator_url_set, facilitator_url_str);
 ngx_conf_set_str_slot!(ngx_http_x402_description_set, description_str);
-ngx_conf_set_str_slot!(ngx_http_x402_network_set, network_str);
+ngx_conf_set_str_slot!(ngx_http_x402_network_set, network_str, inherit);
 ngx_conf_set_str_slot!(ngx_http_x402_network_id_set, network_id_str);
 ngx_conf_set_str_slot!(ngx_http_x402_resource_set, resource_str);
 ngx_conf_set_str_slot!(ngx_http_x402_asset_set, asset_str);
 ngx_conf_set_str_slot!(ngx_http_x402_asset_decimals_set, asset_decimals_str);
-ngx_conf_set_str_slot!(ngx_http_x402_timeout_set, timeout_str);
+ngx_conf_set_str_slot!(ngx_http_x402_verify_timeout_set, verify_timeout_str);
+ngx_conf_set_str_slot!(ngx_http_x402_settle_timeout_set, settle_timeout_str);
+
+/// Deprecated alias of `x402_verify_timeout_secs`.
+unsafe extern "C" fn ngx_http_x402_timeout_set(
+    cf: *mut ngx_conf_t,
+    cmd: *mut ngx_command_t,
+    conf: *mut c_void,
+) -> *mut u8 {
+    log_warn(
+        None,
+        "x402_timeout is deprecated; use x402_verify_timeout_secs and x402_settle_timeout_secs",
+    );
+    unsafe { ngx_http_x402_verify_timeout_set(cf, cmd, conf) }
+}
 ngx_conf_set_str_slot!(ngx_http_x402_fallback_set, facilitator_fallback_str);
 ngx_conf_set_str_slot!(ngx_http_x402_ttl_set, ttl_str);
 ngx_conf_set_str_slot!(ngx_http_x402_redis_url_set, redis_url_str);
+ngx_conf_set_str_slot!(ngx_http_x402_redis_url_env_set, redis_url_env_str);
 ngx_conf_set_str_slot!(ngx_http_x402_replay_ttl_set, replay_ttl_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_allow_location_override_set,
+    allow_location_override_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_max_payment_size_set, max_payment_size_kb_str);
+ngx_conf_set_str_slot!(ngx_http_x402_ens_rpc_url_set, ens_rpc_url_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_replay_ttl_min_multiplier_set,
+    replay_ttl_min_multiplier_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_inherit_set, inherit_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_header_file_set,
+    facilitator_header_file_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_asset_symbol_set, asset_symbol_str);
+ngx_conf_set_str_slot!(ngx_http_x402_asset_symbol_auto_set, asset_symbol_auto_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_payment_window_start_set,
+    payment_window_start_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_payment_window_end_set, payment_window_end_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_outside_window_status_set,
+    outside_window_status_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_free_quota_set, free_quota_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_free_quota_window_secs_set,
+    free_quota_window_secs_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_trust_forwarded_headers_set,
+    trust_forwarded_headers_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_description_auto_set, description_auto_str);
+ngx_conf_set_str_slot!(ngx_http_x402_scheme_set, scheme_str);
+ngx_conf_set_str_slot!(ngx_http_x402_amount_max_set, amount_max_str);
+ngx_conf_set_str_slot!(ngx_http_x402_amount_expr_set, amount_expr_str);
+ngx_conf_set_str_slot!(ngx_http_x402_amount_bps_set, amount_bps_str);
+ngx_conf_set_str_slot!(ngx_http_x402_amount_bps_source_set, amount_bps_source_str);
+ngx_conf_set_str_slot!(ngx_http_x402_redis_init_timeout_set, redis_init_timeout_str);
+ngx_conf_set_str_slot!(ngx_http_x402_replay_ttl_auto_set, replay_ttl_auto_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_resource_max_length_set,
+    resource_max_length_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_skip_head_set, skip_head_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_protocol_set,
+    facilitator_protocol_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_method_override_header_set,
+    method_override_header_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_local_cache_max_entries_set,
+    local_cache_max_entries_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_resource_prefix_set, resource_prefix_str);
+ngx_conf_set_str_slot!(ngx_http_x402_resource_suffix_set, resource_suffix_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_user_agent_set,
+    facilitator_user_agent_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_response_max_size_set,
+    facilitator_response_max_size_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_upstream_payment_pass_set,
+    upstream_payment_pass_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_upstream_payment_hmac_secret_set,
+    upstream_payment_hmac_secret_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_fallback_reject_message_set,
+    fallback_reject_message_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_fallback_reject_retry_after_set,
+    fallback_reject_retry_after_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_subscription_period_secs_set,
+    subscription_period_secs_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_dynamic_price_prefix_match_set,
+    dynamic_price_prefix_match_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_resource_include_query_set,
+    resource_include_query_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_metrics_format_set, metrics_format_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_asset_decimals_auto_set,
+    asset_decimals_auto_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_rpc_url_set, rpc_url_str);
+ngx_conf_set_list_slot!(ngx_http_x402_pay_to_any_set, pay_to_any_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_pay_to_any_requires_all_set,
+    pay_to_any_requires_all_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_scheme_version_set, scheme_version_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_paywall_include_meta_set,
+    paywall_include_meta_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_preflight_set,
+    facilitator_preflight_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_preflight_path_set,
+    facilitator_preflight_path_str
+);
+ngx_conf_set_list_slot!(
+    ngx_http_x402_metrics_amount_buckets_set,
+    metrics_amount_buckets_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_expose_payer_header_set,
+    expose_payer_header_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_dry_run_set, dry_run_str);
+ngx_conf_set_str_slot!(ngx_http_x402_log_request_id_set, log_request_id_str);
+ngx_conf_set_str_slot!(ngx_http_x402_env_prefix_set, env_prefix_str);
+ngx_conf_set_str_slot!(ngx_http_x402_response_format_set, response_format_str);
+ngx_conf_set_str_slot!(ngx_http_x402_metrics_set, metrics_str);
+ngx_conf_set_str_slot!(ngx_http_x402_metrics_auth_token_set, metrics_auth_token_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_https_required_set,
+    facilitator_https_required_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_allow_loopback_http_set,
+    facilitator_allow_loopback_http_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_redis_storage_mode_set, redis_storage_mode_str);
+ngx_conf_set_str_slot!(ngx_http_x402_forward_payer_set, forward_payer_str);
+ngx_conf_set_str_slot!(ngx_http_x402_total_deadline_set, total_deadline_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_file_delay_set,
+    facilitator_file_delay_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_browser_detect_set, browser_detect_str);
+ngx_conf_set_str_slot!(ngx_http_x402_nonce_set, nonce_str);
+ngx_conf_set_str_slot!(ngx_http_x402_nonce_ttl_set, nonce_ttl_str);
+ngx_conf_set_list_slot!(ngx_http_x402_allowed_networks_set, allowed_networks_str);
+ngx_conf_set_list_slot!(ngx_http_x402_allowed_assets_set, allowed_assets_str);
+ngx_conf_set_list_slot!(ngx_http_x402_denied_assets_set, denied_assets_str);
+ngx_conf_set_list_slot!(
+    ngx_http_x402_metrics_allowed_ips_set,
+    metrics_allowed_ips_str
+);
+ngx_conf_set_list_slot!(ngx_http_x402_whitelist_ip_set, whitelist_ip_str);
+ngx_conf_set_list_slot!(ngx_http_x402_skip_user_agents_set, skip_user_agents_str);
+ngx_conf_set_list_slot!(
+    ngx_http_x402_resource_allowed_schemes_set,
+    resource_allowed_schemes_str
+);
+ngx_conf_set_list_slot!(ngx_http_x402_merge_except_set, merge_except_str);
+ngx_conf_set_list_slot!(
+    ngx_http_x402_pay_to_blocked_addresses_set,
+    pay_to_blocked_addresses_str
+);
+ngx_conf_set_list_slot!(
+    ngx_http_x402_method_override_methods_set,
+    method_override_methods_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_response_buffer_size_set,
+    response_buffer_size_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_service_scheme_set,
+    facilitator_service_scheme_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_cors_origin_set, cors_origin_str);
+ngx_conf_set_str_slot!(ngx_http_x402_cors_max_age_set, cors_max_age_str);
+ngx_conf_set_str_slot!(ngx_http_x402_allow_testnet_set, allow_testnet_str);
+ngx_conf_set_str_slot!(ngx_http_x402_replay_hmac_secret_set, replay_hmac_secret_str);
+ngx_conf_set_str_slot!(ngx_http_x402_payment_info_set, payment_info_str);
+ngx_conf_set_str_slot!(ngx_http_x402_payment_info_path_set, payment_info_path_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_description_max_length_set,
+    description_max_length_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_description_sanitize_set,
+    description_sanitize_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_keepalive_requests_set,
+    facilitator_keepalive_requests_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_facilitator_keepalive_timeout_set,
+    facilitator_keepalive_timeout_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_access_log_set, access_log_str);
+ngx_conf_set_str_slot!(ngx_http_x402_redis_error_policy_set, redis_error_policy_str);
+ngx_conf_set_str_slot!(ngx_http_x402_vault_addr_set, vault_addr_str);
+ngx_conf_set_str_slot!(ngx_http_x402_vault_token_file_set, vault_token_file_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_vault_secret_ttl_secs_set,
+    vault_secret_ttl_secs_str
+);
+ngx_conf_set_str_slot!(ngx_http_x402_paywall_script_url_set, paywall_script_url_str);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_paywall_script_integrity_set,
+    paywall_script_integrity_str
+);
+ngx_conf_set_str_slot!(
+    ngx_http_x402_paywall_auto_connect_set,
+    paywall_auto_connect_str
+);
+ngx_conf_set_list_slot!(
+    ngx_http_x402_passthrough_on_methods_set,
+    passthrough_methods_str
+);
 
-pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
+pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 119] = [
     ngx_command_t {
         name: ngx_string!("x402"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -63,7 +639,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_amount"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_amount_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -71,7 +647,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_pay_to"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_pay_to_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -79,7 +655,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_facilitator_url"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_facilitator_url_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -87,7 +663,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_description"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_description_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -95,7 +671,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_network"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_network_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -103,7 +679,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_network_id"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_network_id_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -111,7 +687,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_resource"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_resource_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -119,7 +695,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_asset"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_asset_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -127,7 +703,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_asset_decimals"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_asset_decimals_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -135,7 +711,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_timeout"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_timeout_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -143,7 +719,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_facilitator_fallback"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_fallback_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -151,7 +727,7 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_ttl"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_ttl_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
@@ -159,19 +735,1058 @@ pub static mut NGX_HTTP_X402_COMMANDS: [ngx_command_t; 16] = [
     },
     ngx_command_t {
         name: ngx_string!("x402_redis_url"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_redis_url_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
         post: std::ptr::null_mut(),
     },
+    ngx_command_t {
+        name: ngx_string!("x402_redis_url_env"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_redis_url_env_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
     ngx_command_t {
         name: ngx_string!("x402_replay_ttl"),
-        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
         set: Some(ngx_http_x402_replay_ttl_set),
         conf: NGX_HTTP_LOC_CONF_OFFSET,
         offset: 0,
         post: std::ptr::null_mut(),
     },
-    ngx_command_t::empty(),
-];
+    ngx_command_t {
+        name: ngx_string!("x402_allow_location_override"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_allow_location_override_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_max_payment_size_kb"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_max_payment_size_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_ens_rpc_url"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_ens_rpc_url_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_replay_ttl_min_multiplier"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_replay_ttl_min_multiplier_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_inherit"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_inherit_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_header"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE2) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_header_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_header_file"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_header_file_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_allowed_networks"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_allowed_networks_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_allowed_assets"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_allowed_assets_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_denied_assets"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_denied_assets_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_asset_symbol"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_asset_symbol_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_asset_symbol_auto"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_asset_symbol_auto_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_payment_window_start"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_payment_window_start_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_payment_window_end"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_payment_window_end_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_outside_window_status_code"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_outside_window_status_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_free_quota"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_free_quota_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_free_quota_window_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_free_quota_window_secs_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_trust_forwarded_headers"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_trust_forwarded_headers_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_description_auto"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_description_auto_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_scheme"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_scheme_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_amount_max"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_amount_max_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_subscription_period_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_subscription_period_secs_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_dynamic_price_prefix_match"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_dynamic_price_prefix_match_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_verify_timeout_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_verify_timeout_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_settle_timeout_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_settle_timeout_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_resource_include_query"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_resource_include_query_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_metrics_format"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_metrics_format_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_asset_decimals_auto"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_asset_decimals_auto_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_rpc_url"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_rpc_url_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_pay_to_any"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_pay_to_any_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_pay_to_any_requires_all"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_pay_to_any_requires_all_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_scheme_version"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_scheme_version_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_paywall_include_meta"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_paywall_include_meta_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_preflight"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_preflight_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_preflight_path"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_preflight_path_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_metrics_amount_buckets"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_metrics_amount_buckets_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_expose_payer_header"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_expose_payer_header_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_dry_run"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_dry_run_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_log_request_id"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_log_request_id_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_env_prefix"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_env_prefix_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_response_format"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_response_format_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_metrics"),
+        type_: (NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_metrics_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_metrics_auth_token"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_metrics_auth_token_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_metrics_allowed_ips"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_metrics_allowed_ips_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_https_required"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_https_required_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_allow_loopback_http"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_allow_loopback_http_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_redis_storage_mode"),
+        type_: (NGX_HTTP_MAIN_CONF | NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1)
+            as ngx_uint_t,
+        set: Some(ngx_http_x402_redis_storage_mode_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_forward_payer"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_forward_payer_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_total_deadline_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_total_deadline_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_file_response_delay_ms"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_file_delay_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_browser_detect"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_browser_detect_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_nonce"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_nonce_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_nonce_ttl_ms"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_nonce_ttl_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_whitelist_ip"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_whitelist_ip_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    // Alias of `x402_trust_forwarded_headers`.
+    ngx_command_t {
+        name: ngx_string!("x402_trust_xforwarded_for"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_trust_forwarded_headers_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_amount_expr"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_amount_expr_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_resource_prefix"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_resource_prefix_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_resource_suffix"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_resource_suffix_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_user_agent"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_user_agent_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_response_max_size_kb"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_response_max_size_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_upstream_payment_pass"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_upstream_payment_pass_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_upstream_payment_hmac_secret"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_upstream_payment_hmac_secret_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_fallback_reject_message"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_fallback_reject_message_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_fallback_reject_retry_after_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_fallback_reject_retry_after_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_amount_bps"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_amount_bps_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_amount_bps_source"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_amount_bps_source_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_redis_init_timeout_ms"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_redis_init_timeout_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_local_cache_max_entries"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_local_cache_max_entries_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_skip_user_agents"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_skip_user_agents_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_replay_ttl_auto"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_replay_ttl_auto_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_resource_allowed_schemes"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_resource_allowed_schemes_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_resource_max_length"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_resource_max_length_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_merge"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_merge_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_merge_except"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_merge_except_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_skip_head"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_skip_head_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_pay_to_blocked_addresses"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_pay_to_blocked_addresses_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_protocol"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_protocol_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_method_override_header"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_method_override_header_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_method_override_methods"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_method_override_methods_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_include"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_include_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_allow_duplicate_directives"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_allow_duplicate_directives_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_response_buffer_size_kb"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_response_buffer_size_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_service_scheme"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_service_scheme_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_cors_origin"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_cors_origin_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_cors_max_age_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_cors_max_age_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_allow_testnet"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_allow_testnet_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_replay_hmac_secret"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_replay_hmac_secret_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_payment_info"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_payment_info_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_payment_info_path"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_payment_info_path_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_description_max_length"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_description_max_length_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_description_sanitize"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_description_sanitize_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_keepalive_requests"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_keepalive_requests_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_facilitator_keepalive_timeout_ms"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_facilitator_keepalive_timeout_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_access_log"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_access_log_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_redis_error_policy"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_redis_error_policy_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_vault_addr"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_vault_addr_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_vault_token_file"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_vault_token_file_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_vault_secret_ttl_secs"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_vault_secret_ttl_secs_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_paywall_script_url"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_paywall_script_url_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_paywall_script_integrity"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_paywall_script_integrity_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_paywall_auto_connect"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_TAKE1) as ngx_uint_t,
+        set: Some(ngx_http_x402_paywall_auto_connect_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t {
+        name: ngx_string!("x402_passthrough_on_methods"),
+        type_: (NGX_HTTP_SRV_CONF | NGX_HTTP_LOC_CONF | NGX_CONF_1MORE) as ngx_uint_t,
+        set: Some(ngx_http_x402_passthrough_on_methods_set),
+        conf: NGX_HTTP_LOC_CONF_OFFSET,
+        offset: 0,
+        post: std::ptr::null_mut(),
+    },
+    ngx_command_t::empty(),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ngx::ffi::ngx_log_t;
+
+    fn ngx_str(s: &'static str) -> ngx_str_t {
+        ngx_str_t {
+            len: s.len(),
+            data: s.as_ptr() as *mut u8,
+        }
+    }
+
+    /// Run the `x402_amount` handler on `conf` with `value`; returns the
+    /// handler's error message, if any.
+    fn set_amount(conf: &mut X402Config, value: &'static str) -> Option<String> {
+        let mut elts = [ngx_str("x402_amount"), ngx_str(value)];
+        let mut args: ngx_array_t = unsafe { std::mem::zeroed() };
+        args.elts = elts.as_mut_ptr() as *mut c_void;
+        args.nelts = elts.len() as _;
+        // Level 0 keeps the warning from reaching the (absent) error log.
+        let mut log: ngx_log_t = unsafe { std::mem::zeroed() };
+        let mut cf: ngx_conf_t = unsafe { std::mem::zeroed() };
+        cf.args = &mut args;
+        cf.log = &mut log;
+        let rv = unsafe {
+            ngx_http_x402_amount_set(
+                &mut cf,
+                std::ptr::null_mut(),
+                conf as *mut X402Config as *mut c_void,
+            )
+        };
+        (!rv.is_null()).then(|| {
+            unsafe { std::ffi::CStr::from_ptr(rv as *const std::ffi::c_char) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    fn amount(conf: &X402Config) -> &[u8] {
+        unsafe { ngx_str_slice(&conf.amount_str) }
+    }
+
+    #[test]
+    fn test_duplicate_directive_is_an_error_by_default() {
+        let mut conf = X402Config::default();
+        assert_eq!(set_amount(&mut conf, "0.01"), None);
+        assert_eq!(
+            set_amount(&mut conf, "0.02").as_deref(),
+            Some("is duplicate")
+        );
+        assert_eq!(amount(&conf), b"0.01", "the first value is kept");
+    }
+
+    #[test]
+    fn test_duplicate_directive_warns_when_allowed() {
+        let mut conf = X402Config {
+            allow_duplicate_directives: 1,
+            ..Default::default()
+        };
+        assert_eq!(set_amount(&mut conf, "0.01"), None);
+        assert_eq!(set_amount(&mut conf, "0.02"), None);
+        assert_eq!(amount(&conf), b"0.02", "the last value wins");
+    }
+
+    #[test]
+    fn test_inherit_keyword() {
+        let mut conf = X402Config::default();
+        assert_eq!(set_amount(&mut conf, "inherit"), None);
+        assert!(crate::ngx_module::config::is_inherit(&conf.amount_str));
+        assert_eq!(
+            set_amount(&mut conf, "0.02").as_deref(),
+            Some("is duplicate")
+        );
+    }
+
+    #[test]
+    fn test_parse_include_file() {
+        let text = "# payment terms\n\
+                    x402_amount 0.01;\n\
+                    \n\
+                    x402_description \"Weather API; daily\";  # trailing comment\n\
+                    x402_allowed_networks base 'base-sepolia';\n";
+        let directives = parse_include_file(text).unwrap();
+        let words: Vec<(usize, Vec<&str>)> = directives
+            .iter()
+            .map(|(line, w)| (*line, w.iter().map(String::as_str).collect()))
+            .collect();
+        assert_eq!(
+            words,
+            vec![
+                (2, vec!["x402_amount", "0.01"]),
+                (4, vec!["x402_description", "Weather API; daily"]),
+                (5, vec!["x402_allowed_networks", "base", "base-sepolia"]),
+            ]
+        );
+
+        for bad in [
+            "x402_amount 0.01",
+            "x402_amount \"0.01;",
+            "x402_amount 0.01; x402_asset usdc;",
+            "proxy_pass http://backend;",
+            "x402_include other.conf;",
+            ";",
+        ] {
+            assert!(parse_include_file(bad).is_err(), "{bad}");
+        }
+    }
+
+    /// Write `contents` to a fresh temp file with `mode`; returns its path.
+    fn include_file(name: &str, contents: &str, mode: u32) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("x402-include-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    /// Run `x402_include path` in a location block; returns the error, if any.
+    fn include(conf: &mut X402Config, path: &std::path::Path) -> Option<String> {
+        let path = path.to_str().unwrap();
+        let mut elts = [
+            ngx_str("x402_include"),
+            ngx_str_t {
+                len: path.len(),
+                data: path.as_ptr() as *mut u8,
+            },
+        ];
+        let mut args: ngx_array_t = unsafe { std::mem::zeroed() };
+        args.elts = elts.as_mut_ptr() as *mut c_void;
+        args.nelts = elts.len() as _;
+        let mut log: ngx_log_t = unsafe { std::mem::zeroed() };
+        let mut cf: ngx_conf_t = unsafe { std::mem::zeroed() };
+        cf.args = &mut args;
+        cf.log = &mut log;
+        cf.cmd_type = NGX_HTTP_LOC_CONF as _;
+        let rv = unsafe {
+            ngx_http_x402_include_set(
+                &mut cf,
+                std::ptr::null_mut(),
+                conf as *mut X402Config as *mut c_void,
+            )
+        };
+        assert_eq!(cf.args, &mut args as *mut _, "cf.args is restored");
+        (!rv.is_null()).then(|| {
+            unsafe { std::ffi::CStr::from_ptr(rv as *const std::ffi::c_char) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    #[test]
+    fn test_include_applies_directives() {
+        let path = include_file(
+            "ok.conf",
+            "x402_amount 0.01;\n\
+             x402_description \"Weather API\";\n\
+             x402_network base-sepolia;\n",
+            0o644,
+        );
+        let mut conf = X402Config::default();
+        assert_eq!(include(&mut conf, &path), None);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(amount(&conf), b"0.01");
+        assert_eq!(
+            unsafe { ngx_str_slice(&conf.description_str) },
+            b"Weather API"
+        );
+        assert_eq!(unsafe { ngx_str_slice(&conf.network_str) }, b"base-sepolia");
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let mut conf = X402Config::default();
+        let missing = std::env::temp_dir().join("x402-include-does-not-exist.conf");
+        assert!(include(&mut conf, &missing)
+            .unwrap()
+            .starts_with("cannot read"));
+
+        let path = include_file("writable.conf", "x402_amount 0.01;\n", 0o666);
+        let err = include(&mut conf, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.ends_with("must not be world-writable"), "{err}");
+
+        let path = include_file("unknown.conf", "x402_amount 0.01;\nx402_bogus on;\n", 0o644);
+        let err = include(&mut conf, &path).unwrap();
+        assert!(
+            err.ends_with(":2: \"x402_bogus\" is an unknown directive"),
+            "{err}"
+        );
+
+        // Setter errors carry the file and line; the first value is kept.
+        std::fs::write(&path, "x402_amount 0.02;\n").unwrap();
+        let err = include(&mut conf, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.ends_with(":1: \"x402_amount\" is duplicate"), "{err}");
+        assert_eq!(amount(&conf), b"0.01");
+
+        let path = include_file("args.conf", "x402_amount 0.01 0.02;\n", 0o644);
+        let err = include(&mut conf, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.ends_with("has an invalid number of arguments"), "{err}");
+    }
+}
@@ -1,9 +1,52 @@
+//! nginx directive storage ([`X402Config`]) and its validated form
+//! ([`ParsedX402Config`]).
+//!
+//! Migration note: `pay_to`, `pay_to_any` and `asset` are stored lowercase, so
+//! `0xABC...` and `0xabc...` configure the same thing. Code that compared them
+//! against a checksummed address should compare case-insensitively or
+//! lowercase the other side.
+
+use ipnet::IpNet;
 use ngx::core::NgxStr;
 use ngx::ffi::ngx_str_t;
+use ngx::http::{Merge, MergeConfigError};
 use rust_decimal::Decimal;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use x402_types::chain::ChainId;
+
+use crate::ngx_module::amount_expr::Expr;
+use crate::ngx_module::error::{Result, X402Error};
+use crate::ngx_module::logging::{log_debug, log_error, log_warn};
+use crate::ngx_module::metrics::MetricsFormat;
+use crate::ngx_module::redis::{RedisErrorPolicy, ReplayStorageMode};
+use crate::ngx_module::requirements::X402Version;
+use crate::ngx_module::response::{
+    PaywallScript, DEFAULT_CORS_MAX_AGE, DEFAULT_RESPONSE_BUFFER_SIZE,
+};
+use crate::ngx_module::runtime::{
+    FacilitatorKeepalive, DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE, MAX_PAYMENT_HEADER_SIZE,
+};
+use crate::ngx_module::secrets::{
+    parse_vault_ref, register_vault_refresh, resolve_vault_secret_with, VaultSettings, VAULT_PREFIX,
+};
 
-use crate::ngx_module::error::{ConfigError, Result};
+/// `maxTimeoutSeconds` advertised in requirements when `x402_ttl` is unset.
+pub const DEFAULT_TTL_SECONDS: u32 = 60;
+/// Verify timeout when neither `x402_verify_timeout_secs` nor the deprecated
+/// `x402_timeout` is set.
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = DEFAULT_FACILITATOR_TIMEOUT_SECS;
+/// Decimals assumed for the asset when `x402_asset_decimals` is unset (USDC).
+pub const DEFAULT_ASSET_DECIMALS: u8 = 6;
+/// Timeout of a facilitator call made without an explicit one.
+pub const DEFAULT_FACILITATOR_TIMEOUT_SECS: u64 = 10;
+/// How long a used payment is remembered in Redis when `x402_replay_ttl` is unset.
+pub const DEFAULT_REPLAY_TTL_SECONDS: u64 = 86400;
+/// How long each `x402_nonce` stays current when `x402_nonce_ttl_ms` is unset.
+pub const DEFAULT_NONCE_TTL_MS: u64 = 60_000;
 
 /// Raw configuration from nginx directives.
 ///
@@ -13,6 +56,10 @@ use crate::ngx_module::error::{ConfigError, Result};
 #[derive(Clone)]
 pub struct X402Config {
     pub enabled: i64,
+    /// `x402_merge`: 0 when this block inherits nothing from enclosing blocks.
+    pub merge_enabled: i64,
+    /// `x402_allow_duplicate_directives`; only checked while parsing the block.
+    pub allow_duplicate_directives: i64,
     pub amount_str: ngx_str_t,
     pub pay_to_str: ngx_str_t,
     pub facilitator_url_str: ngx_str_t,
@@ -22,17 +69,119 @@ pub struct X402Config {
     pub resource_str: ngx_str_t,
     pub asset_str: ngx_str_t,
     pub asset_decimals_str: ngx_str_t,
-    pub timeout_str: ngx_str_t,
+    /// Set by `x402_verify_timeout_secs` or the deprecated `x402_timeout`.
+    pub verify_timeout_str: ngx_str_t,
+    pub settle_timeout_str: ngx_str_t,
     pub facilitator_fallback_str: ngx_str_t,
     pub ttl_str: ngx_str_t,
     pub redis_url_str: ngx_str_t,
+    pub redis_url_env_str: ngx_str_t,
     pub replay_ttl_str: ngx_str_t,
+    pub allow_location_override_str: ngx_str_t,
+    pub max_payment_size_kb_str: ngx_str_t,
+    pub ens_rpc_url_str: ngx_str_t,
+    pub replay_ttl_min_multiplier_str: ngx_str_t,
+    pub inherit_str: ngx_str_t,
+    /// `Name: Value` lines accumulated from repeated `x402_facilitator_header`.
+    pub facilitator_headers_str: ngx_str_t,
+    pub facilitator_header_file_str: ngx_str_t,
+    pub allowed_networks_str: ngx_str_t,
+    pub allowed_assets_str: ngx_str_t,
+    pub denied_assets_str: ngx_str_t,
+    pub asset_symbol_str: ngx_str_t,
+    pub asset_symbol_auto_str: ngx_str_t,
+    pub payment_window_start_str: ngx_str_t,
+    pub payment_window_end_str: ngx_str_t,
+    pub outside_window_status_str: ngx_str_t,
+    pub free_quota_str: ngx_str_t,
+    pub free_quota_window_secs_str: ngx_str_t,
+    pub trust_forwarded_headers_str: ngx_str_t,
+    pub description_auto_str: ngx_str_t,
+    pub scheme_str: ngx_str_t,
+    pub amount_max_str: ngx_str_t,
+    pub subscription_period_secs_str: ngx_str_t,
+    pub dynamic_price_prefix_match_str: ngx_str_t,
+    pub resource_include_query_str: ngx_str_t,
+    pub metrics_format_str: ngx_str_t,
+    pub asset_decimals_auto_str: ngx_str_t,
+    pub rpc_url_str: ngx_str_t,
+    pub pay_to_any_str: ngx_str_t,
+    pub pay_to_any_requires_all_str: ngx_str_t,
+    pub scheme_version_str: ngx_str_t,
+    pub paywall_include_meta_str: ngx_str_t,
+    pub facilitator_preflight_str: ngx_str_t,
+    pub facilitator_preflight_path_str: ngx_str_t,
+    pub metrics_amount_buckets_str: ngx_str_t,
+    pub expose_payer_header_str: ngx_str_t,
+    pub dry_run_str: ngx_str_t,
+    pub log_request_id_str: ngx_str_t,
+    pub env_prefix_str: ngx_str_t,
+    pub response_format_str: ngx_str_t,
+    pub metrics_str: ngx_str_t,
+    pub metrics_auth_token_str: ngx_str_t,
+    pub metrics_allowed_ips_str: ngx_str_t,
+    pub facilitator_https_required_str: ngx_str_t,
+    pub facilitator_allow_loopback_http_str: ngx_str_t,
+    pub redis_storage_mode_str: ngx_str_t,
+    pub forward_payer_str: ngx_str_t,
+    pub total_deadline_str: ngx_str_t,
+    pub facilitator_file_delay_str: ngx_str_t,
+    pub browser_detect_str: ngx_str_t,
+    pub nonce_str: ngx_str_t,
+    pub nonce_ttl_str: ngx_str_t,
+    pub whitelist_ip_str: ngx_str_t,
+    pub amount_expr_str: ngx_str_t,
+    pub resource_prefix_str: ngx_str_t,
+    pub resource_suffix_str: ngx_str_t,
+    pub facilitator_user_agent_str: ngx_str_t,
+    pub facilitator_response_max_size_str: ngx_str_t,
+    pub upstream_payment_pass_str: ngx_str_t,
+    pub upstream_payment_hmac_secret_str: ngx_str_t,
+    pub fallback_reject_message_str: ngx_str_t,
+    pub fallback_reject_retry_after_str: ngx_str_t,
+    pub amount_bps_str: ngx_str_t,
+    pub amount_bps_source_str: ngx_str_t,
+    pub redis_init_timeout_str: ngx_str_t,
+    pub local_cache_max_entries_str: ngx_str_t,
+    pub skip_user_agents_str: ngx_str_t,
+    pub replay_ttl_auto_str: ngx_str_t,
+    pub resource_allowed_schemes_str: ngx_str_t,
+    pub resource_max_length_str: ngx_str_t,
+    pub merge_except_str: ngx_str_t,
+    pub skip_head_str: ngx_str_t,
+    pub pay_to_blocked_addresses_str: ngx_str_t,
+    pub facilitator_protocol_str: ngx_str_t,
+    pub method_override_header_str: ngx_str_t,
+    pub method_override_methods_str: ngx_str_t,
+    pub response_buffer_size_str: ngx_str_t,
+    pub facilitator_service_scheme_str: ngx_str_t,
+    pub cors_origin_str: ngx_str_t,
+    pub cors_max_age_str: ngx_str_t,
+    pub allow_testnet_str: ngx_str_t,
+    pub replay_hmac_secret_str: ngx_str_t,
+    pub payment_info_str: ngx_str_t,
+    pub payment_info_path_str: ngx_str_t,
+    pub description_max_length_str: ngx_str_t,
+    pub description_sanitize_str: ngx_str_t,
+    pub facilitator_keepalive_requests_str: ngx_str_t,
+    pub facilitator_keepalive_timeout_str: ngx_str_t,
+    pub access_log_str: ngx_str_t,
+    pub redis_error_policy_str: ngx_str_t,
+    pub vault_addr_str: ngx_str_t,
+    pub vault_token_file_str: ngx_str_t,
+    pub vault_secret_ttl_secs_str: ngx_str_t,
+    pub paywall_script_url_str: ngx_str_t,
+    pub paywall_script_integrity_str: ngx_str_t,
+    pub paywall_auto_connect_str: ngx_str_t,
+    pub passthrough_methods_str: ngx_str_t,
 }
 
 impl Default for X402Config {
     fn default() -> Self {
         Self {
             enabled: 0,
+            merge_enabled: 1,
+            allow_duplicate_directives: 0,
             amount_str: ngx_str_t::default(),
             pay_to_str: ngx_str_t::default(),
             facilitator_url_str: ngx_str_t::default(),
@@ -42,11 +191,109 @@ impl Default for X402Config {
             resource_str: ngx_str_t::default(),
             asset_str: ngx_str_t::default(),
             asset_decimals_str: ngx_str_t::default(),
-            timeout_str: ngx_str_t::default(),
+            verify_timeout_str: ngx_str_t::default(),
+            settle_timeout_str: ngx_str_t::default(),
             facilitator_fallback_str: ngx_str_t::default(),
             ttl_str: ngx_str_t::default(),
             redis_url_str: ngx_str_t::default(),
+            redis_url_env_str: ngx_str_t::default(),
             replay_ttl_str: ngx_str_t::default(),
+            allow_location_override_str: ngx_str_t::default(),
+            max_payment_size_kb_str: ngx_str_t::default(),
+            ens_rpc_url_str: ngx_str_t::default(),
+            replay_ttl_min_multiplier_str: ngx_str_t::default(),
+            inherit_str: ngx_str_t::default(),
+            facilitator_headers_str: ngx_str_t::default(),
+            facilitator_header_file_str: ngx_str_t::default(),
+            allowed_networks_str: ngx_str_t::default(),
+            allowed_assets_str: ngx_str_t::default(),
+            denied_assets_str: ngx_str_t::default(),
+            asset_symbol_str: ngx_str_t::default(),
+            asset_symbol_auto_str: ngx_str_t::default(),
+            payment_window_start_str: ngx_str_t::default(),
+            payment_window_end_str: ngx_str_t::default(),
+            outside_window_status_str: ngx_str_t::default(),
+            free_quota_str: ngx_str_t::default(),
+            free_quota_window_secs_str: ngx_str_t::default(),
+            trust_forwarded_headers_str: ngx_str_t::default(),
+            description_auto_str: ngx_str_t::default(),
+            scheme_str: ngx_str_t::default(),
+            amount_max_str: ngx_str_t::default(),
+            subscription_period_secs_str: ngx_str_t::default(),
+            dynamic_price_prefix_match_str: ngx_str_t::default(),
+            resource_include_query_str: ngx_str_t::default(),
+            metrics_format_str: ngx_str_t::default(),
+            asset_decimals_auto_str: ngx_str_t::default(),
+            rpc_url_str: ngx_str_t::default(),
+            pay_to_any_str: ngx_str_t::default(),
+            pay_to_any_requires_all_str: ngx_str_t::default(),
+            scheme_version_str: ngx_str_t::default(),
+            paywall_include_meta_str: ngx_str_t::default(),
+            facilitator_preflight_str: ngx_str_t::default(),
+            facilitator_preflight_path_str: ngx_str_t::default(),
+            metrics_amount_buckets_str: ngx_str_t::default(),
+            expose_payer_header_str: ngx_str_t::default(),
+            dry_run_str: ngx_str_t::default(),
+            log_request_id_str: ngx_str_t::default(),
+            env_prefix_str: ngx_str_t::default(),
+            response_format_str: ngx_str_t::default(),
+            metrics_str: ngx_str_t::default(),
+            metrics_auth_token_str: ngx_str_t::default(),
+            metrics_allowed_ips_str: ngx_str_t::default(),
+            facilitator_https_required_str: ngx_str_t::default(),
+            facilitator_allow_loopback_http_str: ngx_str_t::default(),
+            redis_storage_mode_str: ngx_str_t::default(),
+            forward_payer_str: ngx_str_t::default(),
+            total_deadline_str: ngx_str_t::default(),
+            facilitator_file_delay_str: ngx_str_t::default(),
+            browser_detect_str: ngx_str_t::default(),
+            nonce_str: ngx_str_t::default(),
+            nonce_ttl_str: ngx_str_t::default(),
+            whitelist_ip_str: ngx_str_t::default(),
+            amount_expr_str: ngx_str_t::default(),
+            resource_prefix_str: ngx_str_t::default(),
+            resource_suffix_str: ngx_str_t::default(),
+            facilitator_user_agent_str: ngx_str_t::default(),
+            facilitator_response_max_size_str: ngx_str_t::default(),
+            upstream_payment_pass_str: ngx_str_t::default(),
+            upstream_payment_hmac_secret_str: ngx_str_t::default(),
+            fallback_reject_message_str: ngx_str_t::default(),
+            fallback_reject_retry_after_str: ngx_str_t::default(),
+            amount_bps_str: ngx_str_t::default(),
+            amount_bps_source_str: ngx_str_t::default(),
+            redis_init_timeout_str: ngx_str_t::default(),
+            local_cache_max_entries_str: ngx_str_t::default(),
+            skip_user_agents_str: ngx_str_t::default(),
+            replay_ttl_auto_str: ngx_str_t::default(),
+            resource_allowed_schemes_str: ngx_str_t::default(),
+            resource_max_length_str: ngx_str_t::default(),
+            merge_except_str: ngx_str_t::default(),
+            skip_head_str: ngx_str_t::default(),
+            pay_to_blocked_addresses_str: ngx_str_t::default(),
+            facilitator_protocol_str: ngx_str_t::default(),
+            method_override_header_str: ngx_str_t::default(),
+            method_override_methods_str: ngx_str_t::default(),
+            response_buffer_size_str: ngx_str_t::default(),
+            facilitator_service_scheme_str: ngx_str_t::default(),
+            cors_origin_str: ngx_str_t::default(),
+            cors_max_age_str: ngx_str_t::default(),
+            allow_testnet_str: ngx_str_t::default(),
+            replay_hmac_secret_str: ngx_str_t::default(),
+            payment_info_str: ngx_str_t::default(),
+            payment_info_path_str: ngx_str_t::default(),
+            description_max_length_str: ngx_str_t::default(),
+            description_sanitize_str: ngx_str_t::default(),
+            facilitator_keepalive_requests_str: ngx_str_t::default(),
+            facilitator_keepalive_timeout_str: ngx_str_t::default(),
+            access_log_str: ngx_str_t::default(),
+            redis_error_policy_str: ngx_str_t::default(),
+            vault_addr_str: ngx_str_t::default(),
+            vault_token_file_str: ngx_str_t::default(),
+            vault_secret_ttl_secs_str: ngx_str_t::default(),
+            paywall_script_url_str: ngx_str_t::default(),
+            paywall_script_integrity_str: ngx_str_t::default(),
+            paywall_auto_connect_str: ngx_str_t::default(),
+            passthrough_methods_str: ngx_str_t::default(),
         }
     }
 }
@@ -55,8 +302,66 @@ impl Default for X402Config {
 pub enum FacilitatorFallback {
     Error,
     Pass,
+    /// Answer with a 402 so clients do not retry as they would on a 500.
+    Reject,
+    /// Accept payments this worker verified recently, else a 500.
+    LocalCache,
+}
+
+/// Wire protocol of the facilitator, set by `x402_facilitator_protocol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FacilitatorProtocol {
+    /// JSON over HTTP: `POST /verify` and `POST /settle`.
+    Rest,
+    /// The `x402.facilitator.Facilitator` gRPC service (`grpc` feature).
+    Grpc,
+}
+
+/// Body of 402 responses, set by `x402_response_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// HTML paywall for browsers, JSON otherwise.
+    Auto,
+    Json,
+    Html,
+}
+
+/// How browsers are told apart from API clients, set by `x402_browser_detect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrowserDetect {
+    /// `Sec-Fetch-*` when the client sends them, else the legacy heuristics.
+    Auto,
+    /// Only `Sec-Fetch-*`; clients without them are treated as API clients.
+    SecFetch,
+    /// `Content-Type`, `Accept` and `User-Agent` heuristics only.
+    Legacy,
+}
+
+/// Payment scheme advertised in requirements, with its scheme-specific terms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scheme {
+    Exact,
+    /// Any amount from `x402_amount` up to this maximum (`x402_amount_max`).
+    Range(Decimal),
+    /// Recurring access; the payment covers this many seconds.
+    Subscription(u64),
+    /// Zero amount: the facilitator verifies the signature to identify the
+    /// payer but moves no funds (`x402_amount free`).
+    Free,
+}
+
+impl Scheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Exact => "exact",
+            Scheme::Range(_) => "range",
+            Scheme::Subscription(_) => "subscription",
+            Scheme::Free => "free",
+        }
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct ParsedX402Config {
     pub enabled: bool,
     pub amount: Option<Decimal>,
@@ -68,161 +373,4740 @@ pub struct ParsedX402Config {
     pub resource: Option<String>,
     pub asset: Option<String>,
     pub asset_decimals: Option<u8>,
-    pub timeout: Option<Duration>,
+    pub verify_timeout: Option<Duration>,
+    pub settle_timeout: Option<Duration>,
     pub facilitator_fallback: FacilitatorFallback,
     pub ttl: Option<u32>,
     pub redis_url: Option<String>,
+    /// `x402_replay_ttl`, or with `x402_replay_ttl_auto` (the default) derived
+    /// from `x402_ttl` when only that is set.
     pub replay_ttl: Option<u64>,
+    /// Maximum accepted `Payment-Signature` header size in bytes.
+    pub max_payment_size: usize,
+    pub ens_rpc_url: Option<String>,
+    /// JSON-RPC endpoint used for on-chain lookups such as `decimals()`.
+    pub rpc_url: Option<String>,
+    /// Alternative recipients from `x402_pay_to_any`; each gets its own requirement.
+    pub pay_to_any: Vec<String>,
+    /// Require one payment to every `pay_to_any` address instead of any one.
+    pub pay_to_any_requires_all: bool,
+    /// Extra headers sent with every facilitator request.
+    pub facilitator_headers: Vec<(String, String)>,
+    /// CAIP-2 networks permitted by `x402_allowed_networks`; `None` allows all.
+    pub allowed_networks: Option<HashSet<String>>,
+    pub asset_symbol: Option<String>,
+    /// Derive `asset_symbol` from the well-known EIP-712 token name when unset.
+    pub asset_symbol_auto: bool,
+    /// Site-wide period during which payments are accepted (end exclusive).
+    pub payment_window_start: Option<SystemTime>,
+    pub payment_window_end: Option<SystemTime>,
+    pub outside_window_status: u16,
+    /// Free requests per client IP per `free_quota_window` before payment is required.
+    pub free_quota: Option<u64>,
+    pub free_quota_window: Duration,
+    pub trust_forwarded_headers: bool,
+    /// Derive a description from the resource path when `description` is unset.
+    pub description_auto: bool,
+    pub scheme: Scheme,
+    /// Fall back to `x402:price:<prefix>/*` and `x402:price:*` Redis price keys.
+    pub dynamic_price_prefix_match: bool,
+    /// Keep the query string in the default resource URL.
+    pub resource_include_query: bool,
+    pub metrics_format: MetricsFormat,
+    /// Protocol version of 402 bodies; `None` negotiates per request (`auto`).
+    pub scheme_version: Option<X402Version>,
+    /// Embed base64 requirements in a `<meta>` tag on the HTML paywall.
+    pub paywall_include_meta: bool,
+    pub facilitator_preflight: bool,
+    /// Path checked with `GET` by the preflight; `OPTIONS` on the base URL if unset.
+    pub facilitator_preflight_path: Option<String>,
+    /// Add `X-Payment-Payer`/`-Network`/`-Amount` to responses for settled payments.
+    pub expose_payer_header: bool,
+    /// Let any request carrying `Payment-Signature` through without verifying it.
+    pub dry_run: bool,
+    /// Prefix log lines with the request ID and echo it as `X-Request-ID`.
+    pub log_request_id: bool,
+    /// JSON, HTML paywall, or picked per request from `Accept`.
+    pub response_format: ResponseFormat,
+    /// Serve metrics from this location instead of gating it (`x402_metrics on`).
+    pub metrics_endpoint: bool,
+    /// Bearer token required by the metrics endpoint.
+    pub metrics_auth_token: Option<String>,
+    /// Clients allowed to scrape; empty allows any.
+    pub metrics_allowed_ips: Vec<IpAddr>,
+    pub redis_storage_mode: ReplayStorageMode,
+    /// Send the payer upstream as `X-Payment-Payer` once the payment is settled.
+    pub forward_payer: bool,
+    /// Budget for the whole payment flow, Redis lookups included
+    /// (`x402_total_deadline_secs`). Unset means only the per-call timeouts apply.
+    pub total_deadline: Option<Duration>,
+    /// Simulated latency of a `file://` facilitator
+    /// (`x402_facilitator_file_response_delay_ms`).
+    pub facilitator_file_delay: Duration,
+    pub browser_detect: BrowserDetect,
+    /// Put a server nonce in each requirement's `extra` and only accept
+    /// payments echoing a current one (`x402_nonce`).
+    pub nonce: bool,
+    /// Lifetime of one nonce in milliseconds (`x402_nonce_ttl_ms`).
+    pub nonce_ttl_ms: u64,
+    /// Clients let through without payment (`x402_whitelist_ip`).
+    pub whitelist_ips: Vec<IpNet>,
+    /// Per-request price formula (`x402_amount_expr`); replaces `x402_amount`.
+    pub amount_expr: Option<Expr>,
+    /// Stripped from the start of the default resource path (`x402_resource_prefix`).
+    pub resource_prefix: Option<String>,
+    /// Stripped from the end of the default resource path (`x402_resource_suffix`).
+    pub resource_suffix: Option<String>,
+    /// Largest facilitator response body in bytes
+    /// (`x402_facilitator_response_max_size_kb`).
+    pub facilitator_response_max_size: usize,
+    /// Set `X-Payment-*` request headers for the upstream once a payment
+    /// settles (`x402_upstream_payment_pass`).
+    pub upstream_payment_pass: bool,
+    /// Key for the `X-Payment-Hmac` upstream header
+    /// (`x402_upstream_payment_hmac_secret`).
+    pub upstream_payment_hmac_secret: Option<String>,
+    /// 402 error message for `x402_facilitator_fallback reject`
+    /// (`x402_fallback_reject_message`).
+    pub fallback_reject_message: Option<String>,
+    /// `Retry-After` seconds sent with that 402
+    /// (`x402_fallback_reject_retry_after_secs`).
+    pub fallback_reject_retry_after: Option<u64>,
+    /// Price as basis points of the content price in
+    /// `amount_bps_source` (`x402_amount_bps`); exclusive with `x402_amount`.
+    pub amount_bps: Option<u32>,
+    /// Name, without the `$`, of the nginx variable holding the content
+    /// price (`x402_amount_bps_source`).
+    pub amount_bps_source: Option<String>,
+    /// How long Redis may take to answer when the client is first created
+    /// (`x402_redis_init_timeout_ms`). Unset defers connecting to the first lookup.
+    pub redis_init_timeout: Option<Duration>,
+    /// Size of the `local_cache` fallback's verification cache
+    /// (`x402_local_cache_max_entries`).
+    pub local_cache_max_entries: usize,
+    /// `User-Agent` prefixes let through without payment, e.g. health-check
+    /// probes (`x402_skip_user_agents`).
+    pub skip_user_agents: Vec<String>,
+    /// Lowercase URL schemes a resource may use (`x402_resource_allowed_schemes`).
+    pub resource_allowed_schemes: Vec<String>,
+    /// Longest accepted resource in bytes (`x402_resource_max_length`).
+    pub resource_max_length: usize,
+    /// Let `HEAD` requests through unpaid (`x402_skip_head`); by default they
+    /// get the same 402 as `GET`, without a body.
+    pub skip_head: bool,
+    /// Lowercase addresses rejected as `x402_pay_to` on top of
+    /// `KNOWN_INVALID_PAY_TO` (`x402_pay_to_blocked_addresses`).
+    pub pay_to_blocked_addresses: Vec<String>,
+    /// Wire protocol of `x402_facilitator_url` (`x402_facilitator_protocol`).
+    pub facilitator_protocol: FacilitatorProtocol,
+    /// Header naming the method to gate and price by
+    /// (`x402_method_override_header`).
+    pub method_override_header: Option<String>,
+    /// Uppercase methods that header may name (`x402_method_override_methods`).
+    pub method_override_methods: Vec<String>,
+    /// Largest single buffer of a generated response body, in bytes
+    /// (`x402_response_buffer_size_kb`).
+    pub response_buffer_size: usize,
+    /// `Access-Control-Allow-Origin` of 402 responses (`x402_cors_origin`).
+    pub cors_origin: Option<String>,
+    /// `Access-Control-Max-Age` sent with `x402_cors_origin`
+    /// (`x402_cors_max_age_secs`).
+    pub cors_max_age: u32,
+    /// Answer requests for the payment terms with 200 instead of gating them
+    /// (`x402_payment_info`).
+    pub payment_info: bool,
+    /// Path always answered with the payment terms under `x402_payment_info`
+    /// (`x402_payment_info_path`).
+    pub payment_info_path: String,
+    /// Log each request's payment outcome at `notice` (`x402_access_log`).
+    pub access_log: bool,
+    /// What a Redis failure during the replay check does
+    /// (`x402_redis_error_policy`).
+    pub redis_error_policy: RedisErrorPolicy,
+    /// x402.js loaded by the HTML paywall; `None` with
+    /// `x402_paywall_script_url off`.
+    pub paywall_script: Option<PaywallScript>,
+    /// Uppercase methods let through without payment
+    /// (`x402_passthrough_on_methods`).
+    pub passthrough_methods: HashSet<String>,
 }
 
 fn parse_ngx_str(s: ngx_str_t) -> Result<Option<String>> {
-    if s.len == 0 {
+    // A null `data` is an unresolved [`INHERIT`]: nothing to inherit from.
+    if s.len == 0 || s.data.is_null() {
         return Ok(None);
     }
     let ngx_str = unsafe { NgxStr::from_ngx_str(s) };
     let val = ngx_str
         .to_str()
-        .map_err(|_| ConfigError::new("Invalid UTF-8 in config string"))?;
+        .map_err(|_| X402Error::Config("Invalid UTF-8 in config string".into()))?;
     Ok(Some(val.to_string()))
 }
 
+/// `inherit` given for `x402_amount`, `x402_pay_to` or `x402_network`. No
+/// argument has a null pointer, and an unset field has `len == 0`, so this
+/// marks the field until merge replaces it with the enclosing block's value.
+pub const INHERIT: ngx_str_t = ngx_str_t {
+    len: 1,
+    data: std::ptr::null_mut(),
+};
+
+pub fn is_inherit(s: &ngx_str_t) -> bool {
+    s.len == INHERIT.len && s.data.is_null()
+}
+
+fn ngx_str_bytes(s: &ngx_str_t) -> &[u8] {
+    if s.len == 0 || s.data.is_null() {
+        return &[];
+    }
+    unsafe { std::slice::from_raw_parts(s.data, s.len) }
+}
+
+/// Default for `x402_env_prefix`: `x402_pay_to env:PAY_TO` reads `$PAY_TO`.
+const DEFAULT_ENV_PREFIX: &str = "env:";
+
+/// Resolve an ENS `x402_pay_to` through `x402_ens_rpc_url`.
+fn resolve_ens(name: &str, rpc_url: Option<&str>) -> Result<String> {
+    let rpc_url = rpc_url.ok_or_else(|| {
+        X402Error::Config(format!(
+            "x402_ens_rpc_url is required to resolve ENS name {name}"
+        ))
+    })?;
+    crate::ngx_module::ens::resolve_ens_blocking(name, rpc_url)
+        .map(|address| address.to_lowercase())
+        .map_err(X402Error::Config)
+}
+
+/// Replace a `<prefix>VAR_NAME` value with the contents of that environment variable.
+fn resolve_env_ref(value: String, prefix: &str, directive: &str) -> Result<String> {
+    let Some(name) = value.strip_prefix(prefix) else {
+        return Ok(value);
+    };
+    if name.is_empty() {
+        return Err(X402Error::Config(format!(
+            "{directive}: missing environment variable name after '{prefix}'"
+        )));
+    }
+    std::env::var(name).map_err(|_| {
+        X402Error::Config(format!(
+            "{directive}: environment variable {name} is not set"
+        ))
+    })
+}
+
+/// `x402_facilitator_url resolve:FACILITATOR` builds the URL from
+/// `$FACILITATOR_SERVICE_HOST` and `$FACILITATOR_SERVICE_PORT`.
+const SERVICE_RESOLVE_PREFIX: &str = "resolve:";
+
+/// `<scheme>://host:port` from the Kubernetes-style `<SERVICE>_SERVICE_HOST`
+/// and `<SERVICE>_SERVICE_PORT` variables. The service name is upper-cased
+/// and `-` becomes `_`, as Kubernetes does for service names.
+pub fn resolve_service_url(service: &str, scheme: &str) -> Result<String> {
+    if service.is_empty() {
+        return Err(X402Error::Config(format!(
+            "missing service name after '{SERVICE_RESOLVE_PREFIX}'"
+        )));
+    }
+    let service = service.to_ascii_uppercase().replace('-', "_");
+    let var = |suffix: &str| {
+        let name = format!("{service}_SERVICE_{suffix}");
+        match std::env::var(&name) {
+            Ok(value) if !value.trim().is_empty() => Ok(value.trim().to_string()),
+            _ => Err(X402Error::Config(format!(
+                "environment variable {name} is not set"
+            ))),
+        }
+    };
+    let host = var("HOST")?;
+    let port = var("PORT")?;
+    if port.parse::<u16>().map_or(true, |p| p == 0) {
+        return Err(X402Error::Config(format!(
+            "{service}_SERVICE_PORT is not a valid port: '{port}'"
+        )));
+    }
+    // Kubernetes hands out bare IPv6 addresses.
+    let url = if host.contains(':') && !host.starts_with('[') {
+        format!("{scheme}://[{host}]:{port}")
+    } else {
+        format!("{scheme}://{host}:{port}")
+    };
+    crate::config::validation::validate_url(&url).map_err(X402Error::Config)?;
+    Ok(url)
+}
+
+/// Default `x402_payment_info_path`.
+const DEFAULT_PAYMENT_INFO_PATH: &str = "/x402/info";
+
+/// Shortest accepted `x402_replay_hmac_secret`.
+const MIN_REPLAY_HMAC_SECRET_LEN: usize = 16;
+
+/// Environment variable checked after `X402_REDIS_URL` when `x402_redis_url_env`
+/// is unset; most PaaS platforms provision Redis under this name.
+const DEFAULT_REDIS_URL_ENV: &str = "REDIS_URL";
+
+/// Replay TTL derived from `x402_ttl`: long enough that a signature cannot be
+/// reused while any payment made with it could still be accepted.
+fn auto_replay_ttl(ttl: u32, multiplier: u32) -> Result<u64> {
+    u64::from(ttl)
+        .checked_mul(u64::from(multiplier))
+        .ok_or_else(|| X402Error::Config("computed x402_replay_ttl overflows".into()))
+}
+
+/// The `x402_redis_url` value, else the first of `env_vars` that is set and non-empty.
+fn resolve_redis_url(directive: Option<String>, env_vars: &[&str]) -> Option<String> {
+    if directive.is_some() {
+        return directive;
+    }
+    env_vars.iter().find_map(|name| {
+        let url = std::env::var(name).ok().filter(|v| !v.trim().is_empty())?;
+        log_debug(
+            None,
+            &format!(
+                "Using Redis URL from ${name}: {}",
+                crate::config::validation::redact_url_credentials(&url)
+            ),
+        );
+        Some(url)
+    })
+}
+
+/// Parse a facilitator timeout such as `30` or `1m` (see
+/// [`parse_duration_str`](crate::config::validation::parse_duration_str)),
+/// limited to 1..=300 seconds.
+fn parse_timeout_secs(s: ngx_str_t, directive: &str) -> Result<Option<Duration>> {
+    let Some(s) = parse_ngx_str(s)? else {
+        return Ok(None);
+    };
+    let secs = crate::config::validation::parse_duration_str(&s)
+        .map_err(|e| X402Error::Config(format!("Invalid {directive}: {e}")))?;
+    if !(1..=300).contains(&secs) {
+        return Err(X402Error::Config(format!(
+            "{directive} must be between 1 and 300 seconds"
+        )));
+    }
+    Ok(Some(Duration::from_secs(secs)))
+}
+
+/// Parse an `on`/`off` directive value, returning `default` when unset.
+fn parse_flag(s: ngx_str_t, directive: &str, default: bool) -> Result<bool> {
+    match parse_ngx_str(s)?.as_deref() {
+        None => Ok(default),
+        Some(v) if v.eq_ignore_ascii_case("on") => Ok(true),
+        Some(v) if v.eq_ignore_ascii_case("off") => Ok(false),
+        Some(v) => Err(X402Error::Config(format!(
+            "{directive} must be 'on' or 'off', got '{v}'"
+        ))),
+    }
+}
+
+/// Parse `Name: Value` lines, skipping blank lines and `#` comments.
+fn parse_header_lines(text: &str) -> Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| X402Error::Config(format!("Invalid facilitator header line: {line}")))?;
+        let (name, value) = (name.trim(), value.trim());
+        crate::config::validation::validate_header_name(name).map_err(X402Error::Config)?;
+        crate::config::validation::validate_header_value(value).map_err(X402Error::Config)?;
+        headers.push((name.to_string(), value.to_string()));
+    }
+    Ok(headers)
+}
+
+/// Testnets configured in any block, with their `x402_allow_testnet`, for the
+/// check in `postconfiguration`.
+static TESTNETS_CONFIGURED: OnceLock<Mutex<Vec<(String, bool)>>> = OnceLock::new();
+
+fn register_testnet(network: String, allowed: bool) {
+    let testnets = TESTNETS_CONFIGURED.get_or_init(|| Mutex::new(Vec::new()));
+    if let Ok(mut testnets) = testnets.lock() {
+        let entry = (network, allowed);
+        if !testnets.contains(&entry) {
+            testnets.push(entry);
+        }
+    }
+}
+
+/// Warn about every testnet the configuration just read uses; false if one
+/// is refused by `x402_allow_testnet off`. Debug builds accept testnets
+/// silently. Clears the list, like [`take_dry_run_configured`].
+pub fn check_configured_testnets() -> bool {
+    let configured = match TESTNETS_CONFIGURED.get().map(|t| t.lock()) {
+        Some(Ok(mut testnets)) => std::mem::take(&mut *testnets),
+        _ => return true,
+    };
+    testnets_allowed(&configured, cfg!(debug_assertions))
+}
+
+fn testnets_allowed(configured: &[(String, bool)], debug_build: bool) -> bool {
+    if debug_build {
+        return true;
+    }
+    let mut ok = true;
+    for (network, allowed) in configured {
+        log_warn(
+            None,
+            &format!("x402 is configured on testnet {network} — ensure this is not production"),
+        );
+        if !allowed {
+            log_error(
+                None,
+                &format!("x402: testnet {network} is refused; set x402_allow_testnet on to use it"),
+            );
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Set when any block enables `x402_dry_run`, for the startup check in `init_module`.
+static DRY_RUN_CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the configuration just read enables `x402_dry_run` anywhere.
+/// Clears the flag so a reload without dry-run does not report it again.
+pub fn take_dry_run_configured() -> bool {
+    DRY_RUN_CONFIGURED.swap(false, Ordering::Relaxed)
+}
+
+/// Headers of each `x402_facilitator_header_file` path read so far.
+type HeaderFiles = HashMap<String, Vec<(String, String)>>;
+
+static HEADER_FILES: OnceLock<Mutex<HeaderFiles>> = OnceLock::new();
+
+/// Load headers from `x402_facilitator_header_file`.
+///
+/// The file is read once per worker; edits take effect on reload.
+fn load_header_file(path: &str) -> Result<Vec<(String, String)>> {
+    let cache = HEADER_FILES.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(headers) = cache.lock().ok().and_then(|c| c.get(path).cloned()) {
+        return Ok(headers);
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        X402Error::Config(format!(
+            "Failed to read facilitator header file {path}: {e}"
+        ))
+    })?;
+    let headers = parse_header_lines(&text)?;
+    if let Ok(mut c) = cache.lock() {
+        c.insert(path.to_string(), headers.clone());
+    }
+    Ok(headers)
+}
+
+/// `x402_facilitator_preflight_path`: an absolute path without whitespace.
+fn parse_preflight_path(s: ngx_str_t) -> Result<Option<String>> {
+    let Some(path) = parse_ngx_str(s)? else {
+        return Ok(None);
+    };
+    if !path.starts_with('/') || path.contains(char::is_whitespace) {
+        return Err(X402Error::Config(format!(
+            "x402_facilitator_preflight_path must be an absolute path, got '{path}'"
+        )));
+    }
+    Ok(Some(path))
+}
+
+fn parse_method_override_header(s: ngx_str_t) -> Result<Option<String>> {
+    let Some(name) = parse_ngx_str(s)? else {
+        return Ok(None);
+    };
+    crate::config::validation::validate_header_name(&name)
+        .map_err(|e| X402Error::Config(format!("x402_method_override_header: {e}")))?;
+    Ok(Some(name))
+}
+
+fn parse_passthrough_methods(s: ngx_str_t) -> Result<HashSet<String>> {
+    let Some(list) = parse_ngx_str(s)? else {
+        return Ok(HashSet::new());
+    };
+    list.split_whitespace()
+        .map(|method| {
+            let upper = method.to_ascii_uppercase();
+            if crate::config::validation::KNOWN_HTTP_METHODS.contains(&upper.as_str()) {
+                Ok(upper)
+            } else {
+                Err(X402Error::Config(format!(
+                    "x402_passthrough_on_methods: unknown HTTP method '{method}'"
+                )))
+            }
+        })
+        .collect()
+}
+
+fn parse_method_override_methods(s: ngx_str_t) -> Result<Vec<String>> {
+    match parse_ngx_str(s)? {
+        Some(list) => list
+            .split_whitespace()
+            .map(|method| {
+                crate::config::validation::validate_override_method(method)
+                    .map_err(|e| X402Error::Config(format!("x402_method_override_methods: {e}")))
+            })
+            .collect(),
+        None => Ok(crate::config::validation::DEFAULT_METHOD_OVERRIDE_METHODS
+            .iter()
+            .map(|m| m.to_string())
+            .collect()),
+    }
+}
+
+fn parse_facilitator_service_scheme(s: ngx_str_t) -> Result<&'static str> {
+    match parse_ngx_str(s)?.map(|p| p.to_lowercase()).as_deref() {
+        None | Some("http") => Ok("http"),
+        Some("https") => Ok("https"),
+        Some(other) => Err(X402Error::Config(format!(
+            "x402_facilitator_service_scheme must be 'http' or 'https', got '{other}'"
+        ))),
+    }
+}
+
+fn parse_facilitator_protocol(s: ngx_str_t) -> Result<FacilitatorProtocol> {
+    match parse_ngx_str(s)?.map(|p| p.to_lowercase()).as_deref() {
+        None | Some("rest") => Ok(FacilitatorProtocol::Rest),
+        Some("grpc") if cfg!(feature = "grpc") => Ok(FacilitatorProtocol::Grpc),
+        Some("grpc") => Err(X402Error::Config(
+            "x402_facilitator_protocol grpc requires ngx-x402 built with the grpc feature".into(),
+        )),
+        Some(other) => Err(X402Error::Config(format!(
+            "x402_facilitator_protocol must be 'rest' or 'grpc', got '{other}'"
+        ))),
+    }
+}
+
+/// Strictly increasing, positive histogram bucket bounds.
+fn parse_amount_buckets(s: ngx_str_t) -> Result<Option<Vec<f64>>> {
+    let Some(list) = parse_ngx_str(s)? else {
+        return Ok(None);
+    };
+    let mut buckets: Vec<f64> = Vec::new();
+    for value in list.split_whitespace() {
+        let bound = value
+            .parse::<f64>()
+            .ok()
+            .filter(|b| b.is_finite() && *b > 0.0)
+            .ok_or_else(|| {
+                X402Error::Config(format!(
+                    "x402_metrics_amount_buckets: invalid bucket '{value}'"
+                ))
+            })?;
+        if buckets.last().is_some_and(|last| *last >= bound) {
+            return Err(X402Error::Config(
+                "x402_metrics_amount_buckets must be strictly increasing".into(),
+            ));
+        }
+        buckets.push(bound);
+    }
+    Ok(Some(buckets))
+}
+
+/// Parse a whitespace-separated list of token addresses, lowercased.
+fn parse_asset_list(s: ngx_str_t, directive: &str) -> Result<Option<HashSet<String>>> {
+    let Some(list) = parse_ngx_str(s)? else {
+        return Ok(None);
+    };
+    let mut assets = HashSet::new();
+    for addr in list.split_whitespace() {
+        crate::config::validation::validate_ethereum_address(addr)
+            .map_err(|e| X402Error::Config(format!("{directive}: {e}")))?;
+        assets.insert(addr.to_lowercase());
+    }
+    Ok(Some(assets))
+}
+
+impl Merge for X402Config {
+    fn merge(&mut self, prev: &X402Config) -> std::result::Result<(), MergeConfigError> {
+        self.resolve_inherit(prev);
+
+        // With `x402_allow_location_override off` in the enclosing block, a child
+        // block may not point payments, verification or replay state elsewhere.
+        let allow_override = parse_flag(
+            prev.allow_location_override_str,
+            "x402_allow_location_override",
+            true,
+        )
+        .map_err(|e| {
+            log_error(None, &e.to_string());
+            MergeConfigError::NoValue
+        })?;
+        if !allow_override {
+            // Nor may it reopen overrides for its own nested blocks, or turn
+            // off verification with `x402_dry_run` unless the parent has.
+            let flags = [
+                (
+                    "x402_allow_location_override",
+                    prev.allow_location_override_str,
+                    self.allow_location_override_str,
+                ),
+                ("x402_dry_run", prev.dry_run_str, self.dry_run_str),
+            ];
+            for (directive, parent, child) in flags {
+                let turned_on = parse_flag(child, directive, false)
+                    .and_then(|child| Ok(child && !parse_flag(parent, directive, false)?))
+                    .map_err(|e| {
+                        log_error(None, &e.to_string());
+                        MergeConfigError::NoValue
+                    })?;
+                if turned_on {
+                    log_error(
+                        None,
+                        &format!(
+                            "{directive} cannot be turned on here: x402_allow_location_override is off"
+                        ),
+                    );
+                    return Err(MergeConfigError::NoValue);
+                }
+            }
+            // The lock holds below a block with `x402_merge off` too.
+            if self.allow_location_override_str.len == 0 {
+                self.allow_location_override_str = prev.allow_location_override_str;
+            }
+
+            // `x402_pay_to_any` replaces `x402_pay_to` when set, so the two are
+            // one recipient setting: once the parent names a recipient either
+            // way, a child may only repeat the parent's values.
+            let recipient = [
+                ("x402_pay_to", &prev.pay_to_str, &self.pay_to_str),
+                (
+                    "x402_pay_to_any",
+                    &prev.pay_to_any_str,
+                    &self.pay_to_any_str,
+                ),
+            ];
+            if prev.pay_to_str.len > 0 || prev.pay_to_any_str.len > 0 {
+                for (directive, parent, child) in recipient {
+                    if child.len > 0 && ngx_str_bytes(parent) != ngx_str_bytes(child) {
+                        log_error(
+                            None,
+                            &format!(
+                                "{directive} cannot be overridden here: x402_allow_location_override is off"
+                            ),
+                        );
+                        return Err(MergeConfigError::NoValue);
+                    }
+                }
+            }
+            let locked = [
+                (
+                    "x402_facilitator_url",
+                    &prev.facilitator_url_str,
+                    &self.facilitator_url_str,
+                ),
+                ("x402_redis_url", &prev.redis_url_str, &self.redis_url_str),
+                // Another Vault could hand out any of the values above.
+                (
+                    "x402_vault_addr",
+                    &prev.vault_addr_str,
+                    &self.vault_addr_str,
+                ),
+                (
+                    "x402_vault_token_file",
+                    &prev.vault_token_file_str,
+                    &self.vault_token_file_str,
+                ),
+            ];
+            for (directive, parent, child) in locked {
+                if parent.len > 0 && child.len > 0 && ngx_str_bytes(parent) != ngx_str_bytes(child)
+                {
+                    log_error(
+                        None,
+                        &format!(
+                            "{directive} cannot be overridden here: x402_allow_location_override is off"
+                        ),
+                    );
+                    return Err(MergeConfigError::NoValue);
+                }
+            }
+        }
+
+        // A missing socket may just mean the facilitator starts after nginx.
+        if let Some(socket) = ngx_str_bytes(&self.facilitator_url_str).strip_prefix(b"unix:") {
+            let socket = String::from_utf8_lossy(socket);
+            if !std::path::Path::new(socket.as_ref()).exists() {
+                log_warn(
+                    None,
+                    &format!("x402_facilitator_url: unix socket {socket} does not exist yet"),
+                );
+            }
+        }
+
+        // `x402_merge off`: the block is complete on its own, not even `x402`
+        // or the network allowlist is inherited.
+        if self.merge_enabled == 0 {
+            return self.register_globals().map_err(|e| {
+                log_error(None, &e.to_string());
+                MergeConfigError::NoValue
+            });
+        }
+
+        if prev.enabled != 0 && self.enabled == 0 {
+            self.enabled = prev.enabled;
+        }
+
+        // The network allowlist is server policy and ignores `x402_inherit`.
+        if self.allowed_networks_str.len == 0 {
+            self.allowed_networks_str = prev.allowed_networks_str;
+        }
+        self.check_allowed_network().map_err(|e| {
+            log_error(None, &e.to_string());
+            MergeConfigError::NoValue
+        })?;
+
+        // `x402_inherit off` isolates this block: only `x402` itself is inherited.
+        let inherit = parse_flag(self.inherit_str, "x402_inherit", true).map_err(|e| {
+            log_error(None, &e.to_string());
+            MergeConfigError::NoValue
+        })?;
+        if !inherit {
+            return self.register_globals().map_err(|e| {
+                log_error(None, &e.to_string());
+                MergeConfigError::NoValue
+            });
+        }
+
+        // `x402_merge_except` names fields by their `X402Config` name without
+        // `_str`; each name is removed once matched, so leftovers are typos.
+        let mut except: HashSet<String> = parse_ngx_str(self.merge_except_str)
+            .map_err(|e| {
+                log_error(None, &e.to_string());
+                MergeConfigError::NoValue
+            })?
+            .iter()
+            .flat_map(|list| list.split_whitespace())
+            .map(|name| name.strip_prefix("x402_").unwrap_or(name).to_string())
+            .collect();
+
+        macro_rules! merge_str {
+            ($field:ident) => {
+                let name = stringify!($field).trim_end_matches("_str");
+                if !except.remove(name) && self.$field.len == 0 && prev.$field.len > 0 {
+                    self.$field = prev.$field;
+                }
+            };
+        }
+        // `x402_amount` and `x402_amount_bps` are exclusive: a block that sets
+        // either inherits neither.
+        let skip_amount = except.remove("amount") | except.remove("amount_bps");
+        if !skip_amount && self.amount_str.len == 0 && self.amount_bps_str.len == 0 {
+            self.amount_str = prev.amount_str;
+            self.amount_bps_str = prev.amount_bps_str;
+        }
+        merge_str!(pay_to_str);
+        merge_str!(facilitator_url_str);
+        merge_str!(description_str);
+        // `x402_network` and `x402_network_id` are one setting: a block that
+        // sets either inherits neither.
+        let skip_network = except.remove("network") | except.remove("network_id");
+        if !skip_network && self.network_str.len == 0 && self.network_id_str.len == 0 {
+            self.network_str = prev.network_str;
+            self.network_id_str = prev.network_id_str;
+        }
+        merge_str!(resource_str);
+        merge_str!(asset_str);
+        merge_str!(asset_decimals_str);
+        merge_str!(verify_timeout_str);
+        merge_str!(settle_timeout_str);
+        merge_str!(facilitator_fallback_str);
+        merge_str!(ttl_str);
+        merge_str!(redis_url_str);
+        merge_str!(redis_url_env_str);
+        merge_str!(replay_ttl_str);
+        merge_str!(allow_location_override_str);
+        merge_str!(max_payment_size_kb_str);
+        merge_str!(ens_rpc_url_str);
+        merge_str!(replay_ttl_min_multiplier_str);
+        merge_str!(facilitator_headers_str);
+        merge_str!(facilitator_header_file_str);
+        merge_str!(allowed_assets_str);
+        merge_str!(denied_assets_str);
+        merge_str!(asset_symbol_str);
+        merge_str!(asset_symbol_auto_str);
+        merge_str!(payment_window_start_str);
+        merge_str!(payment_window_end_str);
+        merge_str!(outside_window_status_str);
+        merge_str!(free_quota_str);
+        merge_str!(free_quota_window_secs_str);
+        merge_str!(trust_forwarded_headers_str);
+        merge_str!(description_auto_str);
+        merge_str!(scheme_str);
+        merge_str!(amount_max_str);
+        merge_str!(subscription_period_secs_str);
+        merge_str!(dynamic_price_prefix_match_str);
+        merge_str!(resource_include_query_str);
+        merge_str!(metrics_format_str);
+        merge_str!(asset_decimals_auto_str);
+        merge_str!(rpc_url_str);
+        merge_str!(pay_to_any_str);
+        merge_str!(pay_to_any_requires_all_str);
+        merge_str!(scheme_version_str);
+        merge_str!(paywall_include_meta_str);
+        merge_str!(facilitator_preflight_str);
+        merge_str!(facilitator_preflight_path_str);
+        merge_str!(metrics_amount_buckets_str);
+        merge_str!(expose_payer_header_str);
+        merge_str!(dry_run_str);
+        merge_str!(log_request_id_str);
+        merge_str!(env_prefix_str);
+        merge_str!(response_format_str);
+        merge_str!(metrics_str);
+        merge_str!(metrics_auth_token_str);
+        merge_str!(metrics_allowed_ips_str);
+        merge_str!(facilitator_https_required_str);
+        merge_str!(facilitator_allow_loopback_http_str);
+        merge_str!(redis_storage_mode_str);
+        merge_str!(forward_payer_str);
+        merge_str!(total_deadline_str);
+        merge_str!(facilitator_file_delay_str);
+        merge_str!(browser_detect_str);
+        merge_str!(nonce_str);
+        merge_str!(nonce_ttl_str);
+        merge_str!(whitelist_ip_str);
+        merge_str!(amount_expr_str);
+        merge_str!(amount_bps_source_str);
+        merge_str!(resource_prefix_str);
+        merge_str!(resource_suffix_str);
+        merge_str!(facilitator_user_agent_str);
+        merge_str!(facilitator_response_max_size_str);
+        merge_str!(upstream_payment_pass_str);
+        merge_str!(upstream_payment_hmac_secret_str);
+        merge_str!(fallback_reject_message_str);
+        merge_str!(fallback_reject_retry_after_str);
+        merge_str!(redis_init_timeout_str);
+        merge_str!(local_cache_max_entries_str);
+        merge_str!(skip_user_agents_str);
+        merge_str!(replay_ttl_auto_str);
+        merge_str!(resource_allowed_schemes_str);
+        merge_str!(resource_max_length_str);
+        merge_str!(skip_head_str);
+        merge_str!(pay_to_blocked_addresses_str);
+        merge_str!(facilitator_protocol_str);
+        merge_str!(method_override_header_str);
+        merge_str!(method_override_methods_str);
+        merge_str!(response_buffer_size_str);
+        merge_str!(facilitator_service_scheme_str);
+        merge_str!(cors_origin_str);
+        merge_str!(cors_max_age_str);
+        merge_str!(allow_testnet_str);
+        merge_str!(replay_hmac_secret_str);
+        merge_str!(payment_info_str);
+        merge_str!(payment_info_path_str);
+        merge_str!(description_max_length_str);
+        merge_str!(description_sanitize_str);
+        merge_str!(facilitator_keepalive_requests_str);
+        merge_str!(facilitator_keepalive_timeout_str);
+        merge_str!(access_log_str);
+        merge_str!(redis_error_policy_str);
+        merge_str!(vault_addr_str);
+        merge_str!(vault_token_file_str);
+        merge_str!(vault_secret_ttl_secs_str);
+        merge_str!(paywall_script_url_str);
+        merge_str!(paywall_script_integrity_str);
+        merge_str!(paywall_auto_connect_str);
+        merge_str!(passthrough_methods_str);
+        if !except.is_empty() {
+            let mut unknown: Vec<_> = except.into_iter().collect();
+            unknown.sort();
+            log_error(
+                None,
+                &format!("x402_merge_except: unknown field(s) {}", unknown.join(", ")),
+            );
+            return Err(MergeConfigError::NoValue);
+        }
+        self.register_globals().map_err(|e| {
+            log_error(None, &e.to_string());
+            MergeConfigError::NoValue
+        })
+    }
+}
+
 impl X402Config {
-    pub fn parse(&self) -> Result<ParsedX402Config> {
-        let amount = if let Some(s) = parse_ngx_str(self.amount_str)? {
-            let amount = crate::config::validation::parse_amount(&s).map_err(ConfigError::new)?;
-            crate::config::validation::validate_amount(amount).map_err(ConfigError::new)?;
-            Some(amount)
-        } else {
-            None
-        };
+    /// Publish settings that apply to the whole process rather than a single
+    /// location: amount histogram buckets, and this block's facilitator for
+    /// the startup preflight in `postconfiguration`. Also rejects a plain-HTTP
+    /// facilitator, an unreadable `vault:` reference and an unresolvable ENS
+    /// `x402_pay_to` at load time rather than on the first request.
+    fn register_globals(&self) -> Result<()> {
+        if let Some(buckets) = parse_amount_buckets(self.metrics_amount_buckets_str)? {
+            if !crate::ngx_module::metrics::X402Metrics::set_amount_unit_buckets(buckets) {
+                log_warn(
+                    None,
+                    "x402_metrics_amount_buckets: histogram already created with other buckets; restart nginx to apply",
+                );
+            }
+        }
 
-        let pay_to = if let Some(s) = parse_ngx_str(self.pay_to_str)? {
-            crate::config::validation::validate_ethereum_address(&s).map_err(ConfigError::new)?;
-            Some(s)
-        } else {
-            None
-        };
+        if parse_flag(self.dry_run_str, "x402_dry_run", false)? {
+            DRY_RUN_CONFIGURED.store(true, Ordering::Relaxed);
+        }
 
-        let facilitator_url = if let Some(s) = parse_ngx_str(self.facilitator_url_str)? {
-            crate::config::validation::validate_url(&s).map_err(ConfigError::new)?;
-            Some(s)
-        } else {
-            None
-        };
+        if let Some(secret) = self.replay_hmac_secret()? {
+            if !crate::ngx_module::redis::set_replay_hmac_key(secret.as_bytes()) {
+                log_warn(
+                    None,
+                    "x402_replay_hmac_secret: another secret is already in use; restart nginx to apply",
+                );
+            }
+        } else if self.redis_url_str.len > 0 || (self.enabled != 0 && self.redis_url()?.is_some()) {
+            // Every instance sharing Redis must hash payments under the same
+            // key, across restarts. A URL from the environment only counts
+            // where payments are on: `$REDIS_URL` may be there for others.
+            return Err(X402Error::Config(
+                "Redis (x402_redis_url, $X402_REDIS_URL or x402_redis_url_env) requires \
+                 x402_replay_hmac_secret"
+                    .into(),
+            ));
+        }
 
-        let description = parse_ngx_str(self.description_str)?;
+        if let Some(network) = self.explicit_network()? {
+            if crate::config::validation::is_testnet(&network) {
+                let allowed = parse_flag(self.allow_testnet_str, "x402_allow_testnet", false)?;
+                register_testnet(network.to_string(), allowed);
+            }
+        }
 
-        let network_id = if let Some(s) = parse_ngx_str(self.network_id_str)? {
-            let id = s
-                .parse::<u64>()
-                .map_err(|e| ConfigError::new(format!("Invalid network_id: {e}")))?;
-            crate::config::validation::chain_id_to_network(id).map_err(ConfigError::new)?;
-            Some(id)
-        } else {
-            None
-        };
+        if let Some(ttl) = self.vault_secret_ttl()? {
+            register_vault_refresh(ttl);
+        }
 
-        let network = if network_id.is_some() {
-            None
-        } else if let Some(s) = parse_ngx_str(self.network_str)? {
-            crate::config::validation::validate_network(&s).map_err(ConfigError::new)?;
-            Some(s)
-        } else {
-            None
-        };
+        // `vault:` references are read here, before nginx forks, so a secret
+        // that cannot be read fails the load and workers start with it cached.
+        // `x402_replay_hmac_secret` was read above, `x402_facilitator_url` is
+        // read below.
+        for (value, directive) in [
+            (self.redis_url_str, "x402_redis_url"),
+            (self.metrics_auth_token_str, "x402_metrics_auth_token"),
+            (
+                self.upstream_payment_hmac_secret_str,
+                "x402_upstream_payment_hmac_secret",
+            ),
+        ] {
+            self.parse_env_str(value, directive)?;
+        }
 
-        let resource = parse_ngx_str(self.resource_str)?;
+        // Likewise an ENS `x402_pay_to`, so a bad name fails the load and
+        // requests only ever see the cached address.
+        if let Some(pay_to) = self.parse_env_str(self.pay_to_str, "x402_pay_to")? {
+            let ens_rpc_url = parse_ngx_str(self.ens_rpc_url_str)?;
+            if crate::ngx_module::ens::is_ens_name(&pay_to)
+                && (self.enabled != 0 || ens_rpc_url.is_some())
+            {
+                resolve_ens(&pay_to, ens_rpc_url.as_deref())?;
+            }
+        }
 
-        let asset = if let Some(s) = parse_ngx_str(self.asset_str)? {
-            crate::config::validation::validate_ethereum_address(&s).map_err(ConfigError::new)?;
-            Some(s)
-        } else {
-            None
+        // Likewise `decimals()` for `x402_asset_decimals_auto`, which falls
+        // back rather than failing; a ticker symbol comes with its decimals.
+        if self.asset_decimals_str.len == 0
+            && parse_flag(
+                self.asset_decimals_auto_str,
+                "x402_asset_decimals_auto",
+                false,
+            )?
+        {
+            if let Some(asset) = parse_ngx_str(self.asset_str)?
+                .filter(|s| s.starts_with("0x") || s.starts_with("0X"))
+            {
+                let rpc_url = parse_ngx_str(self.rpc_url_str)?.ok_or_else(|| {
+                    X402Error::Config("x402_asset_decimals_auto requires x402_rpc_url".into())
+                })?;
+                crate::ngx_module::chain::asset_decimals_blocking(&asset, &rpc_url);
+            }
+        }
+
+        let keepalive = self.facilitator_keepalive()?;
+        let Some(url) = self.facilitator_url()? else {
+            return Ok(());
         };
+        if self.check_facilitator_https(&url)? {
+            log_warn(
+                None,
+                &format!(
+                    "x402_facilitator_url {url} is plain HTTP, allowed on loopback by x402_facilitator_allow_loopback_http"
+                ),
+            );
+        }
 
-        let asset_decimals = if let Some(s) = parse_ngx_str(self.asset_decimals_str)? {
-            let d = s
-                .parse::<u8>()
-                .map_err(|e| ConfigError::new(format!("Invalid asset_decimals: {e}")))?;
-            if d > 28 {
-                return Err(ConfigError::new("asset_decimals must be at most 28"));
+        let protocol = parse_facilitator_protocol(self.facilitator_protocol_str)?;
+        if !crate::ngx_module::runtime::register_facilitator_protocol(&url, protocol) {
+            return Err(X402Error::Config(format!(
+                "x402_facilitator_url {url} is used with both x402_facilitator_protocol rest and grpc"
+            )));
+        }
+        if !crate::ngx_module::runtime::register_facilitator_keepalive(&url, keepalive) {
+            return Err(X402Error::Config(format!(
+                "x402_facilitator_url {url} is used with different x402_facilitator_keepalive_* settings"
+            )));
+        }
+
+        if !parse_flag(
+            self.facilitator_preflight_str,
+            "x402_facilitator_preflight",
+            false,
+        )? || url.trim().starts_with("file://")
+        {
+            return Ok(());
+        }
+        if protocol == FacilitatorProtocol::Grpc {
+            log_warn(
+                None,
+                &format!("x402_facilitator_preflight: skipped for gRPC facilitator {url}"),
+            );
+            return Ok(());
+        }
+        let path = parse_preflight_path(self.facilitator_preflight_path_str)?;
+        crate::ngx_module::runtime::register_preflight_target(&url, path.as_deref());
+        Ok(())
+    }
+
+    /// Apply `x402_facilitator_https_required` (default on) to a facilitator
+    /// URL. `Ok(true)` means plain HTTP was let through only because it is
+    /// loopback and `x402_facilitator_allow_loopback_http` is on.
+    fn check_facilitator_https(&self, url: &str) -> Result<bool> {
+        if url.trim().starts_with("unix:") || url.trim().starts_with("file://") {
+            return Ok(false);
+        }
+        let require_https = parse_flag(
+            self.facilitator_https_required_str,
+            "x402_facilitator_https_required",
+            true,
+        )?;
+        let allow_loopback = parse_flag(
+            self.facilitator_allow_loopback_http_str,
+            "x402_facilitator_allow_loopback_http",
+            false,
+        )?;
+        match crate::config::validation::validate_https_url(url, require_https) {
+            Ok(()) => Ok(false),
+            Err(_) if allow_loopback && crate::config::validation::is_loopback_http_url(url) => {
+                Ok(true)
             }
-            Some(d)
-        } else {
-            None
+            Err(e) => Err(X402Error::Config(format!(
+                "x402_facilitator_url: {e}; set x402_facilitator_https_required off to allow it"
+            ))),
+        }
+    }
+
+    /// `x402_facilitator_url`, with `env:`, `vault:` and `resolve:` references expanded.
+    fn facilitator_url(&self) -> Result<Option<String>> {
+        let Some(url) = self.parse_env_str(self.facilitator_url_str, "x402_facilitator_url")?
+        else {
+            return Ok(None);
+        };
+        let Some(service) = url.strip_prefix(SERVICE_RESOLVE_PREFIX) else {
+            return Ok(Some(url));
         };
+        let scheme = parse_facilitator_service_scheme(self.facilitator_service_scheme_str)?;
+        resolve_service_url(service, scheme)
+            .map(Some)
+            .map_err(|e| X402Error::Config(format!("x402_facilitator_url: {e}")))
+    }
 
-        let timeout = if let Some(s) = parse_ngx_str(self.timeout_str)? {
-            let secs = s
-                .parse::<u64>()
-                .map_err(|e| ConfigError::new(format!("Invalid timeout: {e}")))?;
-            if !(1..=300).contains(&secs) {
-                return Err(ConfigError::new(
-                    "Timeout must be between 1 and 300 seconds",
+    /// Like [`parse_ngx_str`], expanding `env:VAR_NAME` references (see
+    /// `x402_env_prefix`) and `vault:<path>#<field>` references.
+    fn parse_env_str(&self, s: ngx_str_t, directive: &str) -> Result<Option<String>> {
+        let prefix = parse_ngx_str(self.env_prefix_str)?;
+        let prefix = prefix.as_deref().unwrap_or(DEFAULT_ENV_PREFIX);
+        parse_ngx_str(s)?
+            .map(|value| {
+                let value = resolve_env_ref(value, prefix, directive)?;
+                self.resolve_vault_ref(value, directive)
+            })
+            .transpose()
+    }
+
+    /// Replace a `vault:<path>#<field>` value with that secret field.
+    fn resolve_vault_ref(&self, value: String, directive: &str) -> Result<String> {
+        let Some(reference) = value.strip_prefix(VAULT_PREFIX) else {
+            return Ok(value);
+        };
+        let (path, field) = parse_vault_ref(reference)
+            .map_err(|e| X402Error::Config(format!("{directive}: {e}")))?;
+        let settings = VaultSettings::new(
+            parse_ngx_str(self.vault_addr_str)?,
+            parse_ngx_str(self.vault_token_file_str)?.map(Into::into),
+        )
+        .map_err(|e| X402Error::Config(format!("{directive}: {e}")))?;
+        resolve_vault_secret_with(&settings, path, field)
+            .map_err(|e| X402Error::Config(format!("{directive}: {e}")))
+    }
+
+    /// `x402_paywall_script_url` (`off` to drop the script),
+    /// `x402_paywall_script_integrity` and `x402_paywall_auto_connect`.
+    fn paywall_script(&self) -> Result<Option<PaywallScript>> {
+        let url = parse_ngx_str(self.paywall_script_url_str)?;
+        let integrity = parse_ngx_str(self.paywall_script_integrity_str)?;
+        let auto_connect = parse_flag(
+            self.paywall_auto_connect_str,
+            "x402_paywall_auto_connect",
+            false,
+        )?;
+        if url.as_deref() == Some("off") {
+            if integrity.is_some() || auto_connect {
+                return Err(X402Error::Config(
+                    "x402_paywall_script_integrity and x402_paywall_auto_connect need x402_paywall_script_url".into(),
                 ));
             }
-            Some(Duration::from_secs(secs))
-        } else {
-            None
+            return Ok(None);
+        }
+        let mut script = PaywallScript {
+            integrity,
+            auto_connect,
+            ..PaywallScript::default()
         };
+        if let Some(url) = url {
+            crate::config::validation::validate_url(&url)
+                .map_err(|e| X402Error::Config(format!("x402_paywall_script_url: {e}")))?;
+            script.url = url;
+        }
+        if let Some(hash) = &script.integrity {
+            crate::config::validation::validate_sri_hash(hash)
+                .map_err(|e| X402Error::Config(format!("x402_paywall_script_integrity: {e}")))?;
+        }
+        Ok(Some(script))
+    }
 
-        let facilitator_fallback = if let Some(s) = parse_ngx_str(self.facilitator_fallback_str)? {
-            match s.to_lowercase().as_str() {
-                "error" | "500" => FacilitatorFallback::Error,
-                "pass" | "bypass" | "through" => FacilitatorFallback::Pass,
-                _ => {
-                    return Err(ConfigError::new(
-                        "facilitator_fallback must be 'error' or 'pass'",
-                    ))
-                }
-            }
-        } else {
-            FacilitatorFallback::Error
+    /// `x402_vault_secret_ttl_secs`: how often cached Vault secrets are re-read.
+    fn vault_secret_ttl(&self) -> Result<Option<Duration>> {
+        let Some(s) = parse_ngx_str(self.vault_secret_ttl_secs_str)? else {
+            return Ok(None);
         };
+        let secs = s
+            .parse::<u64>()
+            .map_err(|e| X402Error::Config(format!("Invalid x402_vault_secret_ttl_secs: {e}")))?;
+        if !(1..=86_400).contains(&secs) {
+            return Err(X402Error::Config(
+                "x402_vault_secret_ttl_secs must be between 1 and 86400".into(),
+            ));
+        }
+        Ok(Some(Duration::from_secs(secs)))
+    }
 
-        let ttl = if let Some(s) = parse_ngx_str(self.ttl_str)? {
-            let val = s
-                .parse::<u32>()
-                .map_err(|e| ConfigError::new(format!("Invalid ttl: {e}")))?;
-            if !(1..=3600).contains(&val) {
-                return Err(ConfigError::new("ttl must be between 1 and 3600 seconds"));
+    /// `x402_replay_hmac_secret`, at least [`MIN_REPLAY_HMAC_SECRET_LEN`] bytes.
+    /// The Redis URL in effect: `x402_redis_url`, else `$X402_REDIS_URL`, else
+    /// the variable named by `x402_redis_url_env` (`$REDIS_URL` by default).
+    fn redis_url(&self) -> Result<Option<String>> {
+        let redis_url_env = parse_ngx_str(self.redis_url_env_str)?;
+        let redis_url_env = redis_url_env.as_deref().unwrap_or(DEFAULT_REDIS_URL_ENV);
+        Ok(resolve_redis_url(
+            self.parse_env_str(self.redis_url_str, "x402_redis_url")?,
+            &["X402_REDIS_URL", redis_url_env],
+        ))
+    }
+
+    fn replay_hmac_secret(&self) -> Result<Option<String>> {
+        let secret = self.parse_env_str(self.replay_hmac_secret_str, "x402_replay_hmac_secret")?;
+        if secret
+            .as_ref()
+            .is_some_and(|s| s.len() < MIN_REPLAY_HMAC_SECRET_LEN)
+        {
+            return Err(X402Error::Config(format!(
+                "x402_replay_hmac_secret must be at least {MIN_REPLAY_HMAC_SECRET_LEN} bytes"
+            )));
+        }
+        Ok(secret)
+    }
+
+    /// `x402_facilitator_keepalive_requests` (0 disables keep-alive) and
+    /// `x402_facilitator_keepalive_timeout_ms`.
+    fn facilitator_keepalive(&self) -> Result<FacilitatorKeepalive> {
+        let mut keepalive = FacilitatorKeepalive::default();
+        if let Some(s) = parse_ngx_str(self.facilitator_keepalive_requests_str)? {
+            keepalive.max_idle = s.parse::<usize>().map_err(|e| {
+                X402Error::Config(format!("Invalid x402_facilitator_keepalive_requests: {e}"))
+            })?;
+            if keepalive.max_idle > 10_000 {
+                return Err(X402Error::Config(
+                    "x402_facilitator_keepalive_requests must be between 0 and 10000".into(),
+                ));
             }
-            Some(val)
-        } else {
-            None
-        };
+        }
+        if let Some(s) = parse_ngx_str(self.facilitator_keepalive_timeout_str)? {
+            let ms = s.parse::<u64>().map_err(|e| {
+                X402Error::Config(format!(
+                    "Invalid x402_facilitator_keepalive_timeout_ms: {e}"
+                ))
+            })?;
+            if !(1..=3_600_000).contains(&ms) {
+                return Err(X402Error::Config(
+                    "x402_facilitator_keepalive_timeout_ms must be between 1 and 3600000".into(),
+                ));
+            }
+            keepalive.idle_timeout = Duration::from_millis(ms);
+        }
+        Ok(keepalive)
+    }
 
-        let redis_url = if let Some(s) = parse_ngx_str(self.redis_url_str)? {
-            Some(s)
+    /// The network set by `x402_network_id` or `x402_network`, if any.
+    fn explicit_network(&self) -> Result<Option<ChainId>> {
+        if let Some(id) = parse_ngx_str(self.network_id_str)? {
+            let id = crate::config::validation::parse_chain_id(&id).map_err(X402Error::Config)?;
+            Ok(Some(ChainId::new("eip155", id.to_string())))
+        } else if let Some(net) = parse_ngx_str(self.network_str)? {
+            crate::config::validation::network_to_chain_id(&net)
+                .map(Some)
+                .map_err(X402Error::Config)
         } else {
-            std::env::var("X402_REDIS_URL").ok()
-        };
+            Ok(None)
+        }
+    }
 
-        let replay_ttl = if let Some(s) = parse_ngx_str(self.replay_ttl_str)? {
-            Some(
-                s.parse::<u64>()
-                    .map_err(|e| ConfigError::new(format!("Invalid replay_ttl: {e}")))?,
-            )
-        } else {
-            None
+    /// Config-time check that an explicitly configured network is allowlisted.
+    fn check_allowed_network(&self) -> Result<()> {
+        let Some(list) = parse_ngx_str(self.allowed_networks_str)? else {
+            return Ok(());
         };
+        let allowed =
+            crate::config::validation::parse_allowed_networks(&list).map_err(X402Error::Config)?;
+        let Some(network) = self.explicit_network()?.map(|chain| chain.to_string()) else {
+            return Ok(());
+        };
+        if allowed.is_empty() || allowed.contains(&network) {
+            Ok(())
+        } else {
+            Err(X402Error::Config(format!(
+                "Network {network} is not in x402_allowed_networks"
+            )))
+        }
+    }
 
-        Ok(ParsedX402Config {
-            enabled: self.enabled != 0,
-            amount,
-            pay_to,
-            facilitator_url,
-            description,
-            network,
-            network_id,
+    /// [`X402Config::default`] parsed: every directive unset. Optional fields
+    /// stay `None`; the `DEFAULT_*` constants apply where they are used.
+    pub fn default_parsed() -> ParsedX402Config {
+        Self::default()
+            .parse()
+            .expect("the default configuration always parses")
+    }
+
+    /// Whether this location serves metrics (`x402_metrics on`). Checked
+    /// before `x402 on`, so a metrics location is never payment gated.
+    pub fn metrics_endpoint(&self) -> bool {
+        parse_flag(self.metrics_str, "x402_metrics", false).unwrap_or(false)
+    }
+
+    /// Replace [`INHERIT`] with `prev`'s value, even where `x402_merge off`,
+    /// `x402_inherit off` or `x402_merge_except` would keep this block's own.
+    /// As in the plain merge, `x402_amount_bps` follows `x402_amount` and
+    /// `x402_network_id` follows `x402_network`.
+    fn resolve_inherit(&mut self, prev: &X402Config) {
+        // The outermost block has nothing to inherit; it stays unset.
+        let parent = |s: ngx_str_t| {
+            if is_inherit(&s) {
+                ngx_str_t::default()
+            } else {
+                s
+            }
+        };
+        if is_inherit(&self.amount_str) {
+            self.amount_str = parent(prev.amount_str);
+            self.amount_bps_str = prev.amount_bps_str;
+        }
+        if is_inherit(&self.pay_to_str) {
+            self.pay_to_str = parent(prev.pay_to_str);
+        }
+        if is_inherit(&self.network_str) {
+            self.network_str = parent(prev.network_str);
+            self.network_id_str = prev.network_id_str;
+        }
+    }
+
+    /// `x402_access_log`, read in the log phase without a full parse.
+    pub fn access_log(&self) -> bool {
+        parse_flag(self.access_log_str, "x402_access_log", false).unwrap_or(false)
+    }
+
+    /// `x402_skip_head`, read before the full parse like [`Self::metrics_endpoint`].
+    pub fn skip_head(&self) -> bool {
+        parse_flag(self.skip_head_str, "x402_skip_head", false).unwrap_or(false)
+    }
+
+    /// `x402_forward_payer`, read before the full parse like
+    /// [`Self::upstream_payment_pass`].
+    pub fn forward_payer(&self) -> bool {
+        parse_flag(self.forward_payer_str, "x402_forward_payer", false).unwrap_or(false)
+    }
+
+    /// `x402_upstream_payment_pass`, read before the full parse: the headers
+    /// it sets are stripped from every request, even ones this module declines.
+    pub fn upstream_payment_pass(&self) -> bool {
+        parse_flag(
+            self.upstream_payment_pass_str,
+            "x402_upstream_payment_pass",
+            false,
+        )
+        .unwrap_or(false)
+    }
+
+    /// `x402_method_override_header`, read before the full parse like
+    /// [`Self::skip_head`].
+    pub fn method_override_header(&self) -> Option<String> {
+        parse_method_override_header(self.method_override_header_str)
+            .ok()
+            .flatten()
+    }
+
+    /// `x402_method_override_methods`, read before the full parse. The full
+    /// parse rejects invalid lists, so the fallback is never used in practice.
+    pub fn method_override_methods(&self) -> Vec<String> {
+        parse_method_override_methods(self.method_override_methods_str).unwrap_or_default()
+    }
+
+    pub fn parse(&self) -> Result<ParsedX402Config> {
+        let amount = if let Some(s) = parse_ngx_str(self.amount_str)? {
+            let amount = if crate::config::validation::parse_free_amount(&s) {
+                Decimal::ZERO
+            } else {
+                crate::config::validation::parse_amount(&s).map_err(X402Error::Config)?
+            };
+            crate::config::validation::validate_amount(amount).map_err(X402Error::Config)?;
+            Some(amount)
+        } else {
+            None
+        };
+
+        let amount_bps = match parse_ngx_str(self.amount_bps_str)? {
+            Some(s) => {
+                if amount.is_some() {
+                    return Err(X402Error::Config(
+                        "x402_amount and x402_amount_bps are mutually exclusive; use only one"
+                            .into(),
+                    ));
+                }
+                Some(
+                    crate::config::validation::parse_bps(&s)
+                        .map_err(|e| X402Error::Config(format!("x402_amount_bps: {e}")))?,
+                )
+            }
+            None => None,
+        };
+        // The content price must come from the server (e.g. a `map`), never
+        // from a request header the client could set.
+        let amount_bps_source = match parse_ngx_str(self.amount_bps_source_str)? {
+            Some(s) => Some(
+                crate::config::validation::parse_variable_name(&s)
+                    .map_err(|e| X402Error::Config(format!("x402_amount_bps_source: {e}")))?,
+            ),
+            None => None,
+        };
+        if amount_bps.is_some() && amount_bps_source.is_none() {
+            return Err(X402Error::Config(
+                "x402_amount_bps requires x402_amount_bps_source".into(),
+            ));
+        }
+
+        let amount_expr = parse_ngx_str(self.amount_expr_str)?
+            .map(|s| Expr::parse(&s))
+            .transpose()?;
+
+        let ens_rpc_url = if let Some(s) = parse_ngx_str(self.ens_rpc_url_str)? {
+            crate::config::validation::validate_url(&s).map_err(X402Error::Config)?;
+            Some(s)
+        } else {
+            None
+        };
+
+        let pay_to_blocked_addresses = match parse_ngx_str(self.pay_to_blocked_addresses_str)? {
+            Some(list) => list
+                .split_whitespace()
+                .map(|addr| {
+                    crate::config::validation::validate_ethereum_address(addr)
+                        .map(|()| addr.to_lowercase())
+                        .map_err(|e| {
+                            X402Error::Config(format!("x402_pay_to_blocked_addresses: {e}"))
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let pay_to = if let Some(s) = self.parse_env_str(self.pay_to_str, "x402_pay_to")? {
+            if crate::ngx_module::ens::is_ens_name(&s) {
+                Some(resolve_ens(&s, ens_rpc_url.as_deref())?)
+            } else {
+                crate::config::validation::validate_pay_to_address(
+                    &s,
+                    None,
+                    &pay_to_blocked_addresses,
+                )
+                .map_err(X402Error::Config)?;
+                Some(s.to_lowercase())
+            }
+        } else {
+            None
+        };
+
+        let mut pay_to_any: Vec<String> = Vec::new();
+        if let Some(list) = parse_ngx_str(self.pay_to_any_str)? {
+            for addr in list.split_whitespace() {
+                crate::config::validation::validate_pay_to_address(
+                    addr,
+                    None,
+                    &pay_to_blocked_addresses,
+                )
+                .map_err(|e| X402Error::Config(format!("x402_pay_to_any: {e}")))?;
+                let addr = addr.to_lowercase();
+                if !pay_to_any.contains(&addr) {
+                    pay_to_any.push(addr);
+                }
+            }
+        }
+        let pay_to_any_requires_all = parse_flag(
+            self.pay_to_any_requires_all_str,
+            "x402_pay_to_any_requires_all",
+            false,
+        )?;
+        if pay_to_any_requires_all && pay_to_any.is_empty() {
+            return Err(X402Error::Config(
+                "x402_pay_to_any_requires_all requires x402_pay_to_any".into(),
+            ));
+        }
+
+        let facilitator_response_max_size =
+            match parse_ngx_str(self.facilitator_response_max_size_str)? {
+                Some(s) => {
+                    let kb = s.parse::<usize>().map_err(|e| {
+                        X402Error::Config(format!(
+                            "Invalid x402_facilitator_response_max_size_kb: {e}"
+                        ))
+                    })?;
+                    if !(1..=16384).contains(&kb) {
+                        return Err(X402Error::Config(
+                            "x402_facilitator_response_max_size_kb must be between 1 and 16384"
+                                .into(),
+                        ));
+                    }
+                    kb * 1024
+                }
+                None => DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            };
+
+        let facilitator_file_delay = match parse_ngx_str(self.facilitator_file_delay_str)? {
+            Some(s) => {
+                let ms = s.parse::<u64>().map_err(|e| {
+                    X402Error::Config(format!(
+                        "Invalid x402_facilitator_file_response_delay_ms: {e}"
+                    ))
+                })?;
+                if ms > 60_000 {
+                    return Err(X402Error::Config(
+                        "x402_facilitator_file_response_delay_ms must be at most 60000".into(),
+                    ));
+                }
+                Duration::from_millis(ms)
+            }
+            None => Duration::ZERO,
+        };
+        let local_cache_max_entries = match parse_ngx_str(self.local_cache_max_entries_str)? {
+            Some(s) => {
+                let n = s.parse::<usize>().map_err(|e| {
+                    X402Error::Config(format!("Invalid x402_local_cache_max_entries: {e}"))
+                })?;
+                if !(1..=1_000_000).contains(&n) {
+                    return Err(X402Error::Config(
+                        "x402_local_cache_max_entries must be between 1 and 1000000".into(),
+                    ));
+                }
+                n
+            }
+            None => crate::ngx_module::verify_cache::DEFAULT_LOCAL_CACHE_MAX_ENTRIES,
+        };
+        let redis_init_timeout = match parse_ngx_str(self.redis_init_timeout_str)? {
+            Some(s) => {
+                let ms = s.parse::<u64>().map_err(|e| {
+                    X402Error::Config(format!("Invalid x402_redis_init_timeout_ms: {e}"))
+                })?;
+                if !(1..=60_000).contains(&ms) {
+                    return Err(X402Error::Config(
+                        "x402_redis_init_timeout_ms must be between 1 and 60000".into(),
+                    ));
+                }
+                Some(Duration::from_millis(ms))
+            }
+            None => None,
+        };
+        let dry_run = parse_flag(self.dry_run_str, "x402_dry_run", false)?;
+        let facilitator_url = if let Some(s) = self.facilitator_url()? {
+            crate::config::validation::validate_facilitator_url(&s, dry_run)
+                .map_err(X402Error::Config)?;
+            self.check_facilitator_https(&s)?;
+            Some(s)
+        } else {
+            None
+        };
+        let facilitator_protocol = parse_facilitator_protocol(self.facilitator_protocol_str)?;
+        if facilitator_protocol == FacilitatorProtocol::Grpc {
+            if let Some(url) = facilitator_url.as_deref().map(str::trim) {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(X402Error::Config(format!(
+                        "x402_facilitator_protocol grpc needs an http(s) x402_facilitator_url, got '{url}'"
+                    )));
+                }
+            }
+        }
+
+        let description_max_length = match parse_ngx_str(self.description_max_length_str)? {
+            Some(s) => {
+                let n = s.parse::<usize>().map_err(|e| {
+                    X402Error::Config(format!("Invalid x402_description_max_length: {e}"))
+                })?;
+                if !(1..=4096).contains(&n) {
+                    return Err(X402Error::Config(
+                        "x402_description_max_length must be between 1 and 4096".into(),
+                    ));
+                }
+                n
+            }
+            None => crate::config::validation::DEFAULT_DESCRIPTION_MAX_LENGTH,
+        };
+        let description_sanitize = parse_flag(
+            self.description_sanitize_str,
+            "x402_description_sanitize",
+            false,
+        )?;
+        let description = match parse_ngx_str(self.description_str)? {
+            Some(s) if description_sanitize => {
+                Some(crate::config::validation::validate_description_sanitize(
+                    &s,
+                    description_max_length,
+                ))
+            }
+            Some(s) => {
+                crate::config::validation::validate_description(&s, description_max_length)
+                    .map_err(|e| X402Error::Config(format!("x402_description: {e}")))?;
+                Some(s)
+            }
+            None => None,
+        };
+
+        if self.network_str.len > 0 && self.network_id_str.len > 0 {
+            return Err(X402Error::Config(
+                "x402_network and x402_network_id are mutually exclusive; use only one".into(),
+            ));
+        }
+
+        let network_id = if let Some(s) = parse_ngx_str(self.network_id_str)? {
+            crate::config::validation::chain_id_to_network(&s)
+                .map_err(|e| X402Error::Config(format!("Invalid x402_network_id: {e}")))?;
+            Some(crate::config::validation::parse_chain_id(&s).map_err(X402Error::Config)?)
+        } else {
+            None
+        };
+
+        let network = if let Some(s) = parse_ngx_str(self.network_str)? {
+            crate::config::validation::validate_network(&s)
+                .and_then(|()| crate::config::validation::network_to_chain_id(&s))
+                .map_err(|e| X402Error::Config(format!("Invalid x402_network: {e}")))?;
+            Some(s)
+        } else {
+            None
+        };
+
+        let resource = parse_ngx_str(self.resource_str)?;
+        let resource_prefix = parse_ngx_str(self.resource_prefix_str)?;
+        if let Some(ref prefix) = resource_prefix {
+            crate::config::validation::validate_resource_prefix(prefix)
+                .map_err(|e| X402Error::Config(format!("x402_resource_prefix: {e}")))?;
+        }
+        let resource_suffix = parse_ngx_str(self.resource_suffix_str)?;
+        let resource_allowed_schemes = match parse_ngx_str(self.resource_allowed_schemes_str)? {
+            Some(list) => list
+                .split_whitespace()
+                .map(|scheme| {
+                    if crate::config::validation::is_scheme_name(scheme) {
+                        Ok(scheme.to_ascii_lowercase())
+                    } else {
+                        Err(X402Error::Config(format!(
+                            "x402_resource_allowed_schemes: invalid scheme '{scheme}'"
+                        )))
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => crate::config::validation::DEFAULT_RESOURCE_ALLOWED_SCHEMES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        };
+        let resource_max_length = match parse_ngx_str(self.resource_max_length_str)? {
+            Some(s) => {
+                let n = s.parse::<usize>().map_err(|e| {
+                    X402Error::Config(format!("Invalid x402_resource_max_length: {e}"))
+                })?;
+                if !(1..=65_536).contains(&n) {
+                    return Err(X402Error::Config(
+                        "x402_resource_max_length must be between 1 and 65536".into(),
+                    ));
+                }
+                n
+            }
+            None => crate::config::validation::DEFAULT_RESOURCE_MAX_LENGTH,
+        };
+
+        // Same default network as `create_requirements`.
+        let chain = if let Some(id) = network_id {
+            ChainId::new("eip155", id.to_string())
+        } else if let Some(ref net) = network {
+            crate::config::validation::network_to_chain_id(net).map_err(X402Error::Config)?
+        } else {
+            ChainId::new("eip155", "8453")
+        };
+
+        // Decimals of an asset given as a ticker, used unless set explicitly.
+        let mut known_asset_decimals = None;
+        let asset = if let Some(s) = parse_ngx_str(self.asset_str)? {
+            if s.starts_with("0x") || s.starts_with("0X") {
+                crate::config::validation::validate_ethereum_address(&s)
+                    .map_err(X402Error::Config)?;
+                Some(s.to_lowercase())
+            } else {
+                let (address, decimals) =
+                    crate::config::validation::lookup_known_asset(&s, &chain.to_string())
+                        .map_err(X402Error::Config)?;
+                known_asset_decimals = Some(decimals);
+                Some(address.to_lowercase())
+            }
+        } else {
+            None
+        };
+        // The token actually charged: `x402_asset`, else the network's USDC.
+        let effective_asset = asset.clone().or_else(|| {
+            crate::ngx_module::requirements::default_usdc_address(&chain).map(str::to_lowercase)
+        });
+
+        // The checksum was checked on the configured text above; now that the
+        // asset is known, make sure no recipient is its token contract.
+        for addr in pay_to.iter().chain(&pay_to_any) {
+            crate::config::validation::validate_pay_to_address(
+                addr,
+                effective_asset.as_deref(),
+                &pay_to_blocked_addresses,
+            )
+            .map_err(X402Error::Config)?;
+        }
+        if let Some(asset) = &asset {
+            crate::config::validation::cross_validate_asset_network(asset, &chain)
+                .map_err(X402Error::Config)?;
+        }
+
+        let allowed_assets = parse_asset_list(self.allowed_assets_str, "x402_allowed_assets")?;
+        let denied_assets = parse_asset_list(self.denied_assets_str, "x402_denied_assets")?;
+        if allowed_assets.is_some() || denied_assets.is_some() {
+            if let Some(ref effective_asset) = effective_asset {
+                let normalized = effective_asset.to_lowercase();
+                if denied_assets
+                    .as_ref()
+                    .is_some_and(|denied| denied.contains(&normalized))
+                {
+                    return Err(X402Error::Config(format!(
+                        "Asset {effective_asset} is listed in x402_denied_assets"
+                    )));
+                }
+                if allowed_assets
+                    .as_ref()
+                    .is_some_and(|allowed| !allowed.is_empty() && !allowed.contains(&normalized))
+                {
+                    return Err(X402Error::Config(format!(
+                        "Asset {effective_asset} is not in x402_allowed_assets"
+                    )));
+                }
+            }
+        }
+
+        let asset_symbol = if let Some(s) = parse_ngx_str(self.asset_symbol_str)? {
+            let symbol = s.trim();
+            if symbol.is_empty() || symbol.len() > 32 {
+                return Err(X402Error::Config(
+                    "asset_symbol must be between 1 and 32 characters".into(),
+                ));
+            }
+            Some(symbol.to_string())
+        } else {
+            None
+        };
+        let asset_symbol_auto =
+            parse_flag(self.asset_symbol_auto_str, "x402_asset_symbol_auto", false)?;
+
+        let asset_decimals = if let Some(s) = parse_ngx_str(self.asset_decimals_str)? {
+            let d = s
+                .parse::<u8>()
+                .map_err(|e| X402Error::Config(format!("Invalid asset_decimals: {e}")))?;
+            if d > 28 {
+                return Err(X402Error::Config(
+                    "asset_decimals must be at most 28".into(),
+                ));
+            }
+            Some(d)
+        } else {
+            known_asset_decimals
+        };
+
+        let rpc_url = if let Some(s) = parse_ngx_str(self.rpc_url_str)? {
+            crate::config::validation::validate_url(&s).map_err(X402Error::Config)?;
+            Some(s)
+        } else {
+            None
+        };
+        let asset_decimals_auto = parse_flag(
+            self.asset_decimals_auto_str,
+            "x402_asset_decimals_auto",
+            false,
+        )?;
+        let asset_decimals = match (asset_decimals, asset.as_deref()) {
+            (None, Some(asset)) if asset_decimals_auto => {
+                let rpc_url = rpc_url.as_deref().ok_or_else(|| {
+                    X402Error::Config("x402_asset_decimals_auto requires x402_rpc_url".into())
+                })?;
+                Some(crate::ngx_module::chain::asset_decimals_blocking(
+                    asset, rpc_url,
+                ))
+            }
+            (d, _) => d,
+        };
+        // USDC has 6 decimals, which is also the default when the asset's are unknown.
+        let effective_decimals = asset_decimals.unwrap_or(DEFAULT_ASSET_DECIMALS);
+        if let Some(amount) = amount {
+            crate::config::validation::validate_amount_precision(amount, effective_decimals)
+                .map_err(|e| X402Error::Config(format!("x402_amount: {e}")))?;
+        }
+
+        let verify_timeout = parse_timeout_secs(self.verify_timeout_str, "verify_timeout_secs")?;
+        let settle_timeout = parse_timeout_secs(self.settle_timeout_str, "settle_timeout_secs")?;
+        let total_deadline = parse_timeout_secs(self.total_deadline_str, "total_deadline_secs")?;
+
+        let facilitator_fallback =
+            if let Some(s) = parse_ngx_str(self.facilitator_fallback_str)? {
+                match s.to_lowercase().as_str() {
+                    "error" | "500" => FacilitatorFallback::Error,
+                    "pass" | "bypass" | "through" => FacilitatorFallback::Pass,
+                    "reject" | "402" => FacilitatorFallback::Reject,
+                    "local_cache" | "cache_only" => FacilitatorFallback::LocalCache,
+                    _ => return Err(X402Error::Config(
+                        "facilitator_fallback must be 'error', 'pass', 'reject' or 'local_cache'"
+                            .into(),
+                    )),
+                }
+            } else {
+                FacilitatorFallback::Error
+            };
+        let fallback_reject_message = parse_ngx_str(self.fallback_reject_message_str)?;
+        let fallback_reject_retry_after = match parse_ngx_str(self.fallback_reject_retry_after_str)?
+        {
+            Some(s) => {
+                let secs = s.parse::<u64>().map_err(|e| {
+                    X402Error::Config(format!(
+                        "Invalid x402_fallback_reject_retry_after_secs: {e}"
+                    ))
+                })?;
+                if !(1..=86400).contains(&secs) {
+                    return Err(X402Error::Config(
+                        "x402_fallback_reject_retry_after_secs must be between 1 and 86400".into(),
+                    ));
+                }
+                Some(secs)
+            }
+            None => None,
+        };
+
+        let ttl = if let Some(s) = parse_ngx_str(self.ttl_str)? {
+            let val = crate::config::validation::parse_duration_str(&s)
+                .map_err(|e| X402Error::Config(format!("Invalid ttl: {e}")))?;
+            if !(1..=3600).contains(&val) {
+                return Err(X402Error::Config(
+                    "ttl must be between 1 and 3600 seconds".into(),
+                ));
+            }
+            Some(val as u32)
+        } else {
+            None
+        };
+
+        let redis_url = self.redis_url()?;
+
+        let mut replay_ttl = if let Some(s) = parse_ngx_str(self.replay_ttl_str)? {
+            Some(
+                crate::config::validation::parse_duration_str(&s)
+                    .map_err(|e| X402Error::Config(format!("Invalid replay_ttl: {e}")))?,
+            )
+        } else {
+            None
+        };
+        let replay_ttl_auto = parse_flag(self.replay_ttl_auto_str, "x402_replay_ttl_auto", true)?;
+
+        let replay_ttl_min_multiplier =
+            if let Some(s) = parse_ngx_str(self.replay_ttl_min_multiplier_str)? {
+                let m = s.parse::<u32>().map_err(|e| {
+                    X402Error::Config(format!("Invalid replay_ttl_min_multiplier: {e}"))
+                })?;
+                if !(1..=100).contains(&m) {
+                    return Err(X402Error::Config(
+                        "replay_ttl_min_multiplier must be between 1 and 100".into(),
+                    ));
+                }
+                m
+            } else {
+                2
+            };
+
+        if let (None, Some(ttl), true) = (replay_ttl, ttl, replay_ttl_auto) {
+            let computed = auto_replay_ttl(ttl, replay_ttl_min_multiplier)?;
+            log_debug(
+                None,
+                &format!("x402_replay_ttl not set, using {computed}s ({replay_ttl_min_multiplier} x x402_ttl)"),
+            );
+            replay_ttl = Some(computed);
+        }
+
+        if let Some(replay) = replay_ttl {
+            let effective_ttl = ttl.unwrap_or(DEFAULT_TTL_SECONDS);
+            crate::config::validation::validate_replay_ttl(
+                replay,
+                effective_ttl,
+                replay_ttl_min_multiplier,
+            )
+            .map_err(X402Error::Config)?;
+            if replay == u64::from(effective_ttl) {
+                log_warn(
+                    None,
+                    &format!(
+                        "x402_replay_ttl equals x402_ttl ({replay}s); replays are only barely prevented"
+                    ),
+                );
+            }
+        }
+
+        let max_payment_size = if let Some(s) = parse_ngx_str(self.max_payment_size_kb_str)? {
+            let kb = s
+                .parse::<usize>()
+                .map_err(|e| X402Error::Config(format!("Invalid max_payment_size_kb: {e}")))?;
+            if !(1..=1024).contains(&kb) {
+                return Err(X402Error::Config(
+                    "max_payment_size_kb must be between 1 and 1024".into(),
+                ));
+            }
+            kb * 1024
+        } else {
+            MAX_PAYMENT_HEADER_SIZE
+        };
+
+        let mut facilitator_headers = Vec::new();
+        if let Some(s) = parse_ngx_str(self.facilitator_headers_str)? {
+            facilitator_headers.extend(parse_header_lines(&s)?);
+        }
+        if let Some(path) = parse_ngx_str(self.facilitator_header_file_str)? {
+            facilitator_headers.extend(load_header_file(&path)?);
+        }
+        // Sent as a per-request header, which replaces the client's built-in
+        // `default_user_agent()`.
+        match parse_ngx_str(self.facilitator_user_agent_str)?.as_deref() {
+            None | Some("default") => {}
+            Some(user_agent) => {
+                crate::config::validation::validate_header_value(user_agent)
+                    .map_err(|e| X402Error::Config(format!("x402_facilitator_user_agent: {e}")))?;
+                facilitator_headers.retain(|(name, _)| !name.eq_ignore_ascii_case("user-agent"));
+                facilitator_headers.push(("User-Agent".to_string(), user_agent.to_string()));
+            }
+        }
+
+        let allowed_networks = match parse_ngx_str(self.allowed_networks_str)? {
+            Some(list) => {
+                let allowed = crate::config::validation::parse_allowed_networks(&list)
+                    .map_err(X402Error::Config)?;
+                (!allowed.is_empty()).then_some(allowed)
+            }
+            None => None,
+        };
+
+        let payment_window_start = parse_ngx_str(self.payment_window_start_str)?
+            .map(|s| crate::config::validation::parse_rfc3339(&s))
+            .transpose()
+            .map_err(|e| X402Error::Config(format!("Invalid payment_window_start: {e}")))?;
+        let payment_window_end = parse_ngx_str(self.payment_window_end_str)?
+            .map(|s| crate::config::validation::parse_rfc3339(&s))
+            .transpose()
+            .map_err(|e| X402Error::Config(format!("Invalid payment_window_end: {e}")))?;
+        if let (Some(start), Some(end)) = (payment_window_start, payment_window_end) {
+            if start >= end {
+                return Err(X402Error::Config(
+                    "payment_window_start must be before payment_window_end".into(),
+                ));
+            }
+        }
+
+        let outside_window_status = if let Some(s) = parse_ngx_str(self.outside_window_status_str)?
+        {
+            let code = s.parse::<u16>().map_err(|e| {
+                X402Error::Config(format!("Invalid outside_window_status_code: {e}"))
+            })?;
+            if !(400..=599).contains(&code) {
+                return Err(X402Error::Config(
+                    "outside_window_status_code must be between 400 and 599".into(),
+                ));
+            }
+            code
+        } else {
+            503
+        };
+
+        let free_quota = if let Some(s) = parse_ngx_str(self.free_quota_str)? {
+            let n = s
+                .parse::<u64>()
+                .map_err(|e| X402Error::Config(format!("Invalid free_quota: {e}")))?;
+            if n == 0 {
+                return Err(X402Error::Config("free_quota must be at least 1".into()));
+            }
+            if redis_url.is_none() {
+                return Err(X402Error::Config(
+                    "x402_free_quota requires x402_redis_url".into(),
+                ));
+            }
+            Some(n)
+        } else {
+            None
+        };
+        let free_quota_window = if let Some(s) = parse_ngx_str(self.free_quota_window_secs_str)? {
+            let secs = s
+                .parse::<u64>()
+                .map_err(|e| X402Error::Config(format!("Invalid free_quota_window_secs: {e}")))?;
+            if secs == 0 {
+                return Err(X402Error::Config(
+                    "free_quota_window_secs must be at least 1".into(),
+                ));
+            }
+            Duration::from_secs(secs)
+        } else {
+            Duration::from_secs(86400)
+        };
+        let trust_forwarded_headers = parse_flag(
+            self.trust_forwarded_headers_str,
+            "x402_trust_forwarded_headers",
+            false,
+        )?;
+
+        let description_auto =
+            parse_flag(self.description_auto_str, "x402_description_auto", true)?;
+
+        let scheme_name = match parse_ngx_str(self.scheme_str)? {
+            Some(s) => crate::config::validation::validate_scheme(&s).map_err(X402Error::Config)?,
+            None if amount.is_some_and(|a| a.is_zero()) => "free",
+            None => "exact",
+        };
+        let amount_max = if let Some(s) = parse_ngx_str(self.amount_max_str)? {
+            let max = crate::config::validation::parse_amount(&s).map_err(X402Error::Config)?;
+            crate::config::validation::validate_amount(max).map_err(X402Error::Config)?;
+            crate::config::validation::validate_amount_precision(max, effective_decimals)
+                .map_err(|e| X402Error::Config(format!("x402_amount_max: {e}")))?;
+            Some(max)
+        } else {
+            None
+        };
+        let subscription_period = if let Some(s) = parse_ngx_str(self.subscription_period_secs_str)?
+        {
+            let secs = s
+                .parse::<u64>()
+                .map_err(|e| X402Error::Config(format!("Invalid subscription_period_secs: {e}")))?;
+            if secs == 0 {
+                return Err(X402Error::Config(
+                    "subscription_period_secs must be at least 1".into(),
+                ));
+            }
+            Some(secs)
+        } else {
+            None
+        };
+        let scheme = match scheme_name {
+            "range" => {
+                let max = amount_max.ok_or_else(|| {
+                    X402Error::Config("x402_scheme range requires x402_amount_max".into())
+                })?;
+                if amount.is_some_and(|min| min > max) {
+                    return Err(X402Error::Config(
+                        "x402_amount_max must not be below x402_amount".into(),
+                    ));
+                }
+                Scheme::Range(max)
+            }
+            "subscription" => Scheme::Subscription(subscription_period.ok_or_else(|| {
+                X402Error::Config(
+                    "x402_scheme subscription requires x402_subscription_period_secs".into(),
+                )
+            })?),
+            "free" => {
+                if !amount.is_some_and(|a| a.is_zero()) {
+                    return Err(X402Error::Config(
+                        "x402_scheme free requires x402_amount free or 0".into(),
+                    ));
+                }
+                Scheme::Free
+            }
+            _ => Scheme::Exact,
+        };
+
+        let dynamic_price_prefix_match = parse_flag(
+            self.dynamic_price_prefix_match_str,
+            "x402_dynamic_price_prefix_match",
+            false,
+        )?;
+
+        let resource_include_query = parse_flag(
+            self.resource_include_query_str,
+            "x402_resource_include_query",
+            false,
+        )?;
+
+        let metrics_format = match parse_ngx_str(self.metrics_format_str)?.as_deref() {
+            None => MetricsFormat::Prometheus,
+            Some(v) if v.eq_ignore_ascii_case("prometheus") => MetricsFormat::Prometheus,
+            Some(v) if v.eq_ignore_ascii_case("openmetrics") => MetricsFormat::OpenMetrics,
+            Some(v) => {
+                return Err(X402Error::Config(format!(
+                    "x402_metrics_format must be 'prometheus' or 'openmetrics', got '{v}'"
+                )))
+            }
+        };
+
+        let redis_storage_mode = match parse_ngx_str(self.redis_storage_mode_str)?.as_deref() {
+            None => ReplayStorageMode::Keyspace,
+            Some(v) if v.eq_ignore_ascii_case("keyspace") => ReplayStorageMode::Keyspace,
+            Some(v) if v.eq_ignore_ascii_case("hashbucket") => ReplayStorageMode::HashBucket,
+            Some(v) => {
+                return Err(X402Error::Config(format!(
+                    "x402_redis_storage_mode must be 'keyspace' or 'hashbucket', got '{v}'"
+                )))
+            }
+        };
+
+        let redis_error_policy = match parse_ngx_str(self.redis_error_policy_str)?.as_deref() {
+            None | Some("allow") => RedisErrorPolicy::Allow,
+            Some("deny") => RedisErrorPolicy::Deny,
+            Some("error") => RedisErrorPolicy::Error,
+            Some(v) => {
+                return Err(X402Error::Config(format!(
+                    "x402_redis_error_policy must be 'allow', 'deny' or 'error', got '{v}'"
+                )))
+            }
+        };
+
+        let response_format = match parse_ngx_str(self.response_format_str)?.as_deref() {
+            None => ResponseFormat::Auto,
+            Some(v) if v.eq_ignore_ascii_case("auto") => ResponseFormat::Auto,
+            Some(v) if v.eq_ignore_ascii_case("json") => ResponseFormat::Json,
+            Some(v) if v.eq_ignore_ascii_case("html") => ResponseFormat::Html,
+            Some(v) => {
+                return Err(X402Error::Config(format!(
+                    "x402_response_format must be 'json', 'html' or 'auto', got '{v}'"
+                )))
+            }
+        };
+
+        let browser_detect = match parse_ngx_str(self.browser_detect_str)?.as_deref() {
+            None => BrowserDetect::Auto,
+            Some(v) if v.eq_ignore_ascii_case("auto") => BrowserDetect::Auto,
+            Some(v) if v.eq_ignore_ascii_case("sec-fetch") => BrowserDetect::SecFetch,
+            Some(v) if v.eq_ignore_ascii_case("legacy") => BrowserDetect::Legacy,
+            Some(v) => {
+                return Err(X402Error::Config(format!(
+                    "x402_browser_detect must be 'legacy', 'sec-fetch' or 'auto', got '{v}'"
+                )))
+            }
+        };
+
+        let nonce = parse_flag(self.nonce_str, "x402_nonce", false)?;
+        let nonce_ttl_ms = match parse_ngx_str(self.nonce_ttl_str)? {
+            Some(s) => {
+                let ms = s
+                    .parse::<u64>()
+                    .map_err(|e| X402Error::Config(format!("Invalid x402_nonce_ttl_ms: {e}")))?;
+                if !(1000..=3_600_000).contains(&ms) {
+                    return Err(X402Error::Config(
+                        "x402_nonce_ttl_ms must be between 1000 and 3600000".into(),
+                    ));
+                }
+                ms
+            }
+            None => DEFAULT_NONCE_TTL_MS,
+        };
+
+        let metrics_endpoint = parse_flag(self.metrics_str, "x402_metrics", false)?;
+        let skip_head = parse_flag(self.skip_head_str, "x402_skip_head", false)?;
+        let metrics_auth_token =
+            self.parse_env_str(self.metrics_auth_token_str, "x402_metrics_auth_token")?;
+        let metrics_allowed_ips = match parse_ngx_str(self.metrics_allowed_ips_str)? {
+            Some(list) => list
+                .split_whitespace()
+                .map(|ip| {
+                    ip.parse::<IpAddr>().map_err(|_| {
+                        X402Error::Config(format!(
+                            "x402_metrics_allowed_ips: invalid IP address '{ip}'"
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let whitelist_ips = match parse_ngx_str(self.whitelist_ip_str)? {
+            Some(list) => list
+                .split_whitespace()
+                .map(|range| {
+                    range
+                        .parse::<IpNet>()
+                        .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                        .map_err(|_| {
+                            X402Error::Config(format!(
+                                "x402_whitelist_ip: invalid IP address or CIDR range '{range}'"
+                            ))
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let method_override_header = parse_method_override_header(self.method_override_header_str)?;
+        let method_override_methods =
+            parse_method_override_methods(self.method_override_methods_str)?;
+
+        let response_buffer_size = match parse_ngx_str(self.response_buffer_size_str)? {
+            Some(s) => {
+                let kb = s.parse::<usize>().map_err(|e| {
+                    X402Error::Config(format!("Invalid x402_response_buffer_size_kb: {e}"))
+                })?;
+                if !(1..=1024).contains(&kb) {
+                    return Err(X402Error::Config(
+                        "x402_response_buffer_size_kb must be between 1 and 1024".into(),
+                    ));
+                }
+                kb * 1024
+            }
+            None => DEFAULT_RESPONSE_BUFFER_SIZE,
+        };
+
+        let cors_origin = match parse_ngx_str(self.cors_origin_str)? {
+            Some(origin) => {
+                crate::config::validation::validate_cors_origin(&origin)
+                    .map_err(|e| X402Error::Config(format!("x402_cors_origin: {e}")))?;
+                Some(origin)
+            }
+            None => None,
+        };
+        let cors_max_age = match parse_ngx_str(self.cors_max_age_str)? {
+            Some(s) => {
+                let secs = s.parse::<u32>().map_err(|e| {
+                    X402Error::Config(format!("Invalid x402_cors_max_age_secs: {e}"))
+                })?;
+                if secs > 86400 {
+                    return Err(X402Error::Config(
+                        "x402_cors_max_age_secs must be between 0 and 86400".into(),
+                    ));
+                }
+                secs
+            }
+            None => DEFAULT_CORS_MAX_AGE,
+        };
+
+        let payment_info = parse_flag(self.payment_info_str, "x402_payment_info", false)?;
+        let payment_info_path = match parse_ngx_str(self.payment_info_path_str)? {
+            Some(path) if path.starts_with('/') => path,
+            Some(path) => {
+                return Err(X402Error::Config(format!(
+                    "x402_payment_info_path must start with '/', got '{path}'"
+                )))
+            }
+            None => DEFAULT_PAYMENT_INFO_PATH.to_string(),
+        };
+        let access_log = parse_flag(self.access_log_str, "x402_access_log", false)?;
+
+        let skip_user_agents = match parse_ngx_str(self.skip_user_agents_str)? {
+            Some(list) => list
+                .split_whitespace()
+                .map(|pattern| {
+                    crate::config::validation::validate_user_agent_pattern(pattern)
+                        .map(|()| pattern.to_string())
+                        .map_err(|e| X402Error::Config(format!("x402_skip_user_agents: {e}")))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        let scheme_version = match parse_ngx_str(self.scheme_version_str)?.as_deref() {
+            None | Some("2") => Some(X402Version::V2),
+            Some("1") => Some(X402Version::V1),
+            Some(v) if v.eq_ignore_ascii_case("auto") => None,
+            Some(v) => {
+                return Err(X402Error::Config(format!(
+                    "x402_scheme_version must be 1, 2 or auto, got '{v}'"
+                )))
+            }
+        };
+
+        let paywall_include_meta = parse_flag(
+            self.paywall_include_meta_str,
+            "x402_paywall_include_meta",
+            true,
+        )?;
+        let paywall_script = self.paywall_script()?;
+        let passthrough_methods = parse_passthrough_methods(self.passthrough_methods_str)?;
+
+        let facilitator_preflight = parse_flag(
+            self.facilitator_preflight_str,
+            "x402_facilitator_preflight",
+            false,
+        )?;
+        let facilitator_preflight_path = parse_preflight_path(self.facilitator_preflight_path_str)?;
+
+        let expose_payer_header = parse_flag(
+            self.expose_payer_header_str,
+            "x402_expose_payer_header",
+            false,
+        )?;
+        let forward_payer = parse_flag(self.forward_payer_str, "x402_forward_payer", false)?;
+        let upstream_payment_pass = parse_flag(
+            self.upstream_payment_pass_str,
+            "x402_upstream_payment_pass",
+            false,
+        )?;
+        let upstream_payment_hmac_secret = self.parse_env_str(
+            self.upstream_payment_hmac_secret_str,
+            "x402_upstream_payment_hmac_secret",
+        )?;
+        if upstream_payment_hmac_secret.is_some() && !upstream_payment_pass {
+            log_warn(
+                None,
+                "x402_upstream_payment_hmac_secret has no effect without x402_upstream_payment_pass on",
+            );
+        }
+        let log_request_id = parse_flag(self.log_request_id_str, "x402_log_request_id", true)?;
+
+        Ok(ParsedX402Config {
+            enabled: self.enabled != 0,
+            amount,
+            pay_to,
+            facilitator_url,
+            description,
+            network,
+            network_id,
             resource,
             asset,
             asset_decimals,
-            timeout,
+            verify_timeout,
+            settle_timeout,
             facilitator_fallback,
             ttl,
             redis_url,
             replay_ttl,
+            max_payment_size,
+            ens_rpc_url,
+            rpc_url,
+            pay_to_any,
+            pay_to_any_requires_all,
+            facilitator_headers,
+            allowed_networks,
+            asset_symbol,
+            asset_symbol_auto,
+            payment_window_start,
+            payment_window_end,
+            outside_window_status,
+            free_quota,
+            free_quota_window,
+            trust_forwarded_headers,
+            description_auto,
+            scheme,
+            dynamic_price_prefix_match,
+            resource_include_query,
+            metrics_format,
+            scheme_version,
+            paywall_include_meta,
+            facilitator_preflight,
+            facilitator_preflight_path,
+            expose_payer_header,
+            dry_run,
+            log_request_id,
+            response_format,
+            metrics_endpoint,
+            metrics_auth_token,
+            metrics_allowed_ips,
+            redis_storage_mode,
+            forward_payer,
+            total_deadline,
+            facilitator_file_delay,
+            browser_detect,
+            nonce,
+            nonce_ttl_ms,
+            whitelist_ips,
+            amount_expr,
+            resource_prefix,
+            resource_suffix,
+            facilitator_response_max_size,
+            upstream_payment_pass,
+            upstream_payment_hmac_secret,
+            fallback_reject_message,
+            fallback_reject_retry_after,
+            amount_bps,
+            amount_bps_source,
+            redis_init_timeout,
+            local_cache_max_entries,
+            skip_user_agents,
+            resource_allowed_schemes,
+            resource_max_length,
+            skip_head,
+            pay_to_blocked_addresses,
+            facilitator_protocol,
+            method_override_header,
+            method_override_methods,
+            response_buffer_size,
+            cors_origin,
+            cors_max_age,
+            payment_info,
+            payment_info_path,
+            access_log,
+            redis_error_policy,
+            paywall_script,
+            passthrough_methods,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ngx_str(s: &'static str) -> ngx_str_t {
+        ngx_str_t {
+            len: s.len(),
+            data: s.as_ptr() as *mut u8,
+        }
+    }
+
+    const SERVER_PAY_TO: &str = "0x1234567890abcdef1234567890abcdef12345678";
+    const OTHER_PAY_TO: &str = "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd";
+
+    #[test]
+    fn test_merge_inherits_unset_fields() {
+        let server = X402Config {
+            enabled: 1,
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            amount_str: ngx_str("0.001"),
+            ..Default::default()
+        };
+        let mut location = X402Config {
+            amount_str: ngx_str("0.002"),
+            ..Default::default()
+        };
+
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(location.enabled, 1);
+        assert_eq!(
+            ngx_str_bytes(&location.pay_to_str),
+            SERVER_PAY_TO.as_bytes()
+        );
+        assert_eq!(ngx_str_bytes(&location.amount_str), b"0.002");
+    }
+
+    #[test]
+    fn test_merge_server_level_reaches_location() {
+        let http = X402Config {
+            facilitator_url_str: ngx_str("https://x402.org/facilitator"),
+            ..Default::default()
+        };
+        let mut server = X402Config {
+            enabled: 1,
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            network_str: ngx_str("base-sepolia"),
+            ..Default::default()
+        };
+        let mut location = X402Config {
+            amount_str: ngx_str("0.001"),
+            ..Default::default()
+        };
+
+        assert!(server.merge(&http).is_ok());
+        assert!(location.merge(&server).is_ok());
+        let parsed = location.parse().unwrap();
+        assert!(parsed.enabled);
+        assert_eq!(parsed.pay_to.as_deref(), Some(SERVER_PAY_TO));
+        assert_eq!(parsed.network.as_deref(), Some("base-sepolia"));
+        assert_eq!(
+            parsed.facilitator_url.as_deref(),
+            Some("https://x402.org/facilitator")
+        );
+        assert_eq!(parsed.amount, Some(Decimal::new(1, 3)));
+    }
+
+    #[test]
+    fn test_merge_inherit_keyword() {
+        let server = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            amount_str: ngx_str("0.001"),
+            network_id_str: ngx_str("8453"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            amount_str: INHERIT,
+            pay_to_str: INHERIT,
+            network_str: INHERIT,
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(ngx_str_bytes(&location.amount_str), b"0.001");
+        assert_eq!(
+            ngx_str_bytes(&location.pay_to_str),
+            SERVER_PAY_TO.as_bytes()
+        );
+        assert_eq!(location.network_str.len, 0);
+        assert_eq!(ngx_str_bytes(&location.network_id_str), b"8453");
+    }
+
+    #[test]
+    fn test_merge_inherit_keyword_overrides_exclusions() {
+        let server = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            amount_str: ngx_str("0.001"),
+            ..Default::default()
+        };
+
+        // The block's own `x402_amount_bps` gives way to the parent's amount.
+        let mut location = X402Config {
+            amount_str: INHERIT,
+            amount_bps_str: ngx_str("50"),
+            merge_except_str: ngx_str("amount pay_to"),
+            pay_to_str: INHERIT,
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(ngx_str_bytes(&location.amount_str), b"0.001");
+        assert_eq!(location.amount_bps_str.len, 0);
+        assert_eq!(
+            ngx_str_bytes(&location.pay_to_str),
+            SERVER_PAY_TO.as_bytes()
+        );
+
+        let mut location = X402Config {
+            merge_enabled: 0,
+            pay_to_str: INHERIT,
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(
+            ngx_str_bytes(&location.pay_to_str),
+            SERVER_PAY_TO.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_merge_inherit_keyword_without_parent_value() {
+        let server = X402Config::default();
+        let mut location = X402Config {
+            amount_str: ngx_str("0.002"),
+            pay_to_str: INHERIT,
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(location.pay_to_str.len, 0);
+        assert!(!is_inherit(&location.pay_to_str));
+        assert_eq!(location.parse().unwrap().pay_to, None);
+
+        // An unmerged sentinel reads as unset rather than a null string.
+        let unmerged = X402Config {
+            amount_str: ngx_str("0.002"),
+            pay_to_str: INHERIT,
+            ..Default::default()
+        };
+        assert_eq!(unmerged.parse().unwrap().pay_to, None);
+    }
+
+    #[test]
+    fn test_merge_location_override_allowed_by_default() {
+        let server = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            ..Default::default()
+        };
+        let mut location = X402Config {
+            pay_to_str: ngx_str(OTHER_PAY_TO),
+            ..Default::default()
+        };
+
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(ngx_str_bytes(&location.pay_to_str), OTHER_PAY_TO.as_bytes());
+    }
+
+    #[test]
+    fn test_merge_location_override_rejected_when_off() {
+        let mut server = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            allow_location_override_str: ngx_str("off"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            pay_to_str: ngx_str(OTHER_PAY_TO),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+
+        let mut location = X402Config {
+            facilitator_url_str: ngx_str("https://evil.example.com"),
+            ..Default::default()
+        };
+        server.facilitator_url_str = ngx_str("https://x402.org/facilitator");
+        assert!(location.merge(&server).is_err());
+
+        let mut location = X402Config {
+            vault_addr_str: ngx_str("https://vault.evil.example"),
+            ..Default::default()
+        };
+        server.vault_addr_str = ngx_str("https://vault.example");
+        assert!(location.merge(&server).is_err());
+    }
+
+    #[test]
+    fn test_merge_location_override_off_locks_flags() {
+        let server = X402Config {
+            allow_location_override_str: ngx_str("off"),
+            ..Default::default()
+        };
+
+        // A locked block cannot unlock its own nested locations.
+        let mut location = X402Config {
+            allow_location_override_str: ngx_str("on"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+        let mut location = X402Config {
+            allow_location_override_str: ngx_str("off"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+
+        let mut location = X402Config {
+            dry_run_str: ngx_str("on"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+        let dry_run_server = X402Config {
+            dry_run_str: ngx_str("on"),
+            ..server
+        };
+        let mut location = X402Config {
+            dry_run_str: ngx_str("on"),
+            ..Default::default()
+        };
+        assert!(location.merge(&dry_run_server).is_ok());
+
+        // `x402_merge off` does not lift the lock for the blocks below.
+        let mut location = X402Config {
+            merge_enabled: 0,
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        let mut nested = X402Config {
+            pay_to_str: ngx_str(OTHER_PAY_TO),
+            ..Default::default()
+        };
+        location.pay_to_str = ngx_str(SERVER_PAY_TO);
+        assert!(nested.merge(&location).is_err());
+    }
+
+    #[test]
+    fn test_merge_location_override_off_locks_pay_to_any_with_pay_to() {
+        let server = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            allow_location_override_str: ngx_str("off"),
+            ..Default::default()
+        };
+
+        // pay_to_any would replace the server's pay_to in the requirements.
+        let mut location = X402Config {
+            pay_to_any_str: ngx_str(OTHER_PAY_TO),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+
+        let server = X402Config {
+            pay_to_any_str: ngx_str(SERVER_PAY_TO),
+            allow_location_override_str: ngx_str("off"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            pay_to_str: ngx_str(OTHER_PAY_TO),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+
+        let mut location = X402Config {
+            pay_to_any_str: ngx_str(SERVER_PAY_TO),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+    }
+
+    #[test]
+    fn test_merge_location_override_off_accepts_same_or_unset_value() {
+        let server = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            allow_location_override_str: ngx_str("off"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+
+        let mut location = X402Config {
+            amount_str: ngx_str("0.01"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(
+            ngx_str_bytes(&location.pay_to_str),
+            SERVER_PAY_TO.as_bytes()
+        );
+        assert_eq!(ngx_str_bytes(&location.allow_location_override_str), b"off");
+    }
+
+    #[test]
+    fn test_merge_rejects_invalid_override_flag() {
+        let server = X402Config {
+            allow_location_override_str: ngx_str("maybe"),
+            ..Default::default()
+        };
+        let mut location = X402Config::default();
+        assert!(location.merge(&server).is_err());
+    }
+
+    #[test]
+    fn test_parse_network_id_hex() {
+        let mut conf = X402Config {
+            network_id_str: ngx_str("0x2105"),
+            ..Default::default()
+        };
+        assert_eq!(conf.parse().unwrap().network_id, Some(8453));
+
+        conf.network_id_str = ngx_str("0xGG");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_network_and_network_id_exclusive() {
+        let conf = X402Config {
+            network_str: ngx_str("base"),
+            network_id_str: ngx_str("8453"),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.parse(),
+            Err(X402Error::Config(
+                "x402_network and x402_network_id are mutually exclusive; use only one".into()
+            ))
+        );
+
+        let conf = X402Config {
+            network_id_str: ngx_str("999999999"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err(), "unknown chain ID");
+        let conf = X402Config {
+            network_str: ngx_str("not-a-network"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err(), "unknown network name");
+    }
+
+    #[test]
+    fn test_merge_network_overrides_network_id() {
+        let server = X402Config {
+            network_id_str: ngx_str("8453"),
+            ..Default::default()
+        };
+
+        // A location naming its network does not also inherit the chain ID.
+        let mut location = X402Config {
+            network_str: ngx_str("polygon"),
+            ..Default::default()
+        };
+        location.merge(&server).unwrap();
+        assert_eq!(location.network_id_str.len, 0);
+        assert_eq!(
+            location.parse().unwrap().network.as_deref(),
+            Some("polygon")
+        );
+
+        let server = X402Config {
+            network_str: ngx_str("base"),
+            ..Default::default()
+        };
+        let mut location = X402Config {
+            network_id_str: ngx_str("137"),
+            ..Default::default()
+        };
+        location.merge(&server).unwrap();
+        assert_eq!(location.network_str.len, 0);
+        assert_eq!(location.parse().unwrap().network_id, Some(137));
+
+        let mut location = X402Config::default();
+        location.merge(&server).unwrap();
+        assert_eq!(ngx_str_bytes(&location.network_str), b"base");
+    }
+
+    #[test]
+    fn test_parse_max_payment_size_kb() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().max_payment_size,
+            MAX_PAYMENT_HEADER_SIZE
+        );
+
+        conf.max_payment_size_kb_str = ngx_str("8");
+        assert_eq!(conf.parse().unwrap().max_payment_size, 8 * 1024);
+
+        conf.max_payment_size_kb_str = ngx_str("1024");
+        assert_eq!(conf.parse().unwrap().max_payment_size, 1024 * 1024);
+
+        for invalid in ["0", "1025", "-1", "big"] {
+            conf.max_payment_size_kb_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_response_buffer_size_kb() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().response_buffer_size,
+            DEFAULT_RESPONSE_BUFFER_SIZE
+        );
+
+        conf.response_buffer_size_str = ngx_str("4");
+        assert_eq!(conf.parse().unwrap().response_buffer_size, 4 * 1024);
+
+        for invalid in ["0", "1025", "-1", "big"] {
+            conf.response_buffer_size_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_cors() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.cors_origin, None);
+        assert_eq!(parsed.cors_max_age, DEFAULT_CORS_MAX_AGE);
+
+        conf.cors_origin_str = ngx_str("https://app.example.com");
+        conf.cors_max_age_str = ngx_str("600");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(
+            parsed.cors_origin.as_deref(),
+            Some("https://app.example.com")
+        );
+        assert_eq!(parsed.cors_max_age, 600);
+
+        conf.cors_max_age_str = ngx_str("86401");
+        assert!(conf.parse().is_err());
+        conf.cors_max_age_str = ngx_str_t::default();
+        conf.cors_origin_str = ngx_str("https://app.example.com/pay");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_pay_to_ens_requires_rpc_url() {
+        let conf = X402Config {
+            pay_to_str: ngx_str("alice.eth"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_merge_resolves_ens_pay_to() {
+        use crate::ngx_module::test_support::MockServer;
+
+        let server = MockServer::start(|req: &str| {
+            // The registry answers with a resolver, the resolver with the address.
+            let word = if req.contains("\"0x0178b8bf") {
+                "4976fb03c32e5b8cfe2b6ccb31c09ba78ebaba41"
+            } else {
+                &SERVER_PAY_TO[2..]
+            };
+            (
+                200,
+                format!(r#"{{"jsonrpc":"2.0","id":1,"result":"0x{word:0>64}"}}"#),
+            )
+        });
+        let rpc_url: &'static str = Box::leak(server.url.clone().into_boxed_str());
+
+        let mut conf = X402Config {
+            enabled: 1,
+            pay_to_str: ngx_str("merge-test.eth"),
+            ..Default::default()
+        };
+        assert!(conf.merge(&X402Config::default()).is_err(), "no RPC URL");
+
+        conf.ens_rpc_url_str = ngx_str(rpc_url);
+        assert!(conf.merge(&X402Config::default()).is_ok());
+        assert_eq!(server.requests().len(), 2);
+
+        // Requests are served from what the load resolved.
+        assert_eq!(conf.parse().unwrap().pay_to.as_deref(), Some(SERVER_PAY_TO));
+        assert_eq!(server.requests().len(), 2);
+
+        // A name that fails to resolve fails the load.
+        conf.pay_to_str = ngx_str("unresolvable.invalid.eth");
+        conf.ens_rpc_url_str = ngx_str("http://127.0.0.1:1");
+        assert!(conf.merge(&X402Config::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_replay_ttl_below_ttl_rejected() {
+        let conf = X402Config {
+            ttl_str: ngx_str("60"),
+            replay_ttl_str: ngx_str("30"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_replay_ttl_equal_ttl_needs_multiplier_one() {
+        let mut conf = X402Config {
+            ttl_str: ngx_str("60"),
+            replay_ttl_str: ngx_str("60"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err());
+
+        conf.replay_ttl_min_multiplier_str = ngx_str("1");
+        assert_eq!(conf.parse().unwrap().replay_ttl, Some(60));
+    }
+
+    #[test]
+    fn test_replay_ttl_auto_from_ttl() {
+        for (ttl, expected) in [("1", 2), ("60", 120), ("300", 600), ("3600", 7200)] {
+            let conf = X402Config {
+                ttl_str: ngx_str(ttl),
+                ..Default::default()
+            };
+            assert_eq!(
+                conf.parse().unwrap().replay_ttl,
+                Some(expected),
+                "ttl {ttl}"
+            );
+        }
+        assert_eq!(
+            auto_replay_ttl(u32::MAX, 100).unwrap(),
+            u64::from(u32::MAX) * 100
+        );
+    }
+
+    #[test]
+    fn test_replay_ttl_auto_follows_min_multiplier() {
+        let conf = X402Config {
+            ttl_str: ngx_str("60"),
+            replay_ttl_min_multiplier_str: ngx_str("5"),
+            ..Default::default()
+        };
+        assert_eq!(conf.parse().unwrap().replay_ttl, Some(300));
+    }
+
+    #[test]
+    fn test_replay_ttl_auto_keeps_explicit_and_default() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().replay_ttl, None, "neither set");
+
+        conf.ttl_str = ngx_str("60");
+        conf.replay_ttl_str = ngx_str("600");
+        assert_eq!(conf.parse().unwrap().replay_ttl, Some(600));
+
+        conf.replay_ttl_str = ngx_str_t::default();
+        conf.replay_ttl_auto_str = ngx_str("off");
+        assert_eq!(conf.parse().unwrap().replay_ttl, None);
+
+        conf.replay_ttl_auto_str = ngx_str("maybe");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_replay_ttl_double_ttl_accepted() {
+        let mut conf = X402Config {
+            ttl_str: ngx_str("60"),
+            replay_ttl_str: ngx_str("120"),
+            ..Default::default()
+        };
+        assert_eq!(conf.parse().unwrap().replay_ttl, Some(120));
+
+        // Unset ttl falls back to the 60s default.
+        conf.ttl_str = ngx_str_t::default();
+        conf.replay_ttl_str = ngx_str("100");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_merge_inherit_off_keeps_fields_unset() {
+        let server = X402Config {
+            enabled: 1,
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            amount_str: ngx_str("0.001"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            inherit_str: ngx_str("off"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(location.enabled, 1);
+        assert_eq!(location.pay_to_str.len, 0);
+        assert_eq!(location.amount_str.len, 0);
+
+        let mut location = X402Config {
+            inherit_str: ngx_str("on"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(
+            ngx_str_bytes(&location.pay_to_str),
+            SERVER_PAY_TO.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_merge_off_inherits_nothing() {
+        let server = X402Config {
+            enabled: 1,
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            amount_str: ngx_str("0.001"),
+            allowed_networks_str: ngx_str("base"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            merge_enabled: 0,
+            amount_str: ngx_str("0.002"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(location.enabled, 0);
+        assert_eq!(location.pay_to_str.len, 0);
+        assert_eq!(location.allowed_networks_str.len, 0);
+        assert_eq!(ngx_str_bytes(&location.amount_str), b"0.002");
+    }
+
+    #[test]
+    fn test_merge_except_skips_listed_fields() {
+        let server = X402Config {
+            enabled: 1,
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            facilitator_url_str: ngx_str("https://facilitator.example.com"),
+            amount_str: ngx_str("0.001"),
+            description_str: ngx_str("Weather"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            merge_except_str: ngx_str("pay_to x402_facilitator_url"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(location.enabled, 1);
+        assert_eq!(location.pay_to_str.len, 0);
+        assert_eq!(location.facilitator_url_str.len, 0);
+        assert_eq!(ngx_str_bytes(&location.amount_str), b"0.001");
+        assert_eq!(ngx_str_bytes(&location.description_str), b"Weather");
+    }
+
+    #[test]
+    fn test_merge_except_exclusive_pairs() {
+        let server = X402Config {
+            amount_str: ngx_str("0.001"),
+            network_id_str: ngx_str("8453"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            merge_except_str: ngx_str("amount_bps network"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(location.amount_str.len, 0);
+        assert_eq!(location.network_id_str.len, 0);
+    }
+
+    #[test]
+    fn test_merge_except_rejects_unknown_field() {
+        let server = X402Config::default();
+        let mut location = X402Config {
+            merge_except_str: ngx_str("pay_to payto"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+    }
+
+    #[test]
+    fn test_parse_facilitator_headers() {
+        let mut conf = X402Config::default();
+        assert!(conf.parse().unwrap().facilitator_headers.is_empty());
+
+        conf.facilitator_headers_str = ngx_str("X-Api-Key: secret\nX-Tenant: acme\n");
+        assert_eq!(
+            conf.parse().unwrap().facilitator_headers,
+            vec![
+                ("X-Api-Key".to_string(), "secret".to_string()),
+                ("X-Tenant".to_string(), "acme".to_string()),
+            ]
+        );
+
+        conf.facilitator_headers_str = ngx_str("Bad Name: value\n");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_facilitator_header_file() {
+        let path = std::env::temp_dir().join(format!("x402-headers-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# facilitator credentials\n\nAuthorization: Bearer abc\n",
+        )
+        .unwrap();
+        let path: &'static str = Box::leak(path.to_string_lossy().into_owned().into_boxed_str());
+
+        let mut conf = X402Config {
+            facilitator_headers_str: ngx_str("X-Api-Key: secret\n"),
+            facilitator_header_file_str: ngx_str(path),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.parse().unwrap().facilitator_headers,
+            vec![
+                ("X-Api-Key".to_string(), "secret".to_string()),
+                ("Authorization".to_string(), "Bearer abc".to_string()),
+            ]
+        );
+        std::fs::remove_file(path).ok();
+
+        conf.facilitator_header_file_str = ngx_str("/nonexistent/x402-headers");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_allowed_networks() {
+        let mut conf = X402Config::default();
+        assert!(conf.parse().unwrap().allowed_networks.is_none());
+
+        conf.allowed_networks_str = ngx_str("base base-sepolia");
+        let allowed = conf.parse().unwrap().allowed_networks.unwrap();
+        assert!(allowed.contains("eip155:8453"));
+        assert!(allowed.contains("eip155:84532"));
+
+        conf.allowed_networks_str = ngx_str("base nowhere");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_merge_allowed_networks() {
+        let server = X402Config {
+            allowed_networks_str: ngx_str("base"),
+            ..Default::default()
+        };
+
+        let mut location = X402Config {
+            network_str: ngx_str("base"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_ok());
+        assert_eq!(ngx_str_bytes(&location.allowed_networks_str), b"base");
+
+        let mut location = X402Config {
+            network_id_str: ngx_str("0x89"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+
+        // Not even `x402_inherit off` escapes the allowlist.
+        let mut location = X402Config {
+            inherit_str: ngx_str("off"),
+            network_str: ngx_str("polygon"),
+            ..Default::default()
+        };
+        assert!(location.merge(&server).is_err());
+
+        let mut location = X402Config {
+            network_str: ngx_str("polygon"),
+            ..Default::default()
+        };
+        assert!(location.merge(&X402Config::default()).is_ok());
+    }
+
+    const BASE_USDC: &str = "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913";
+    const OTHER_ASSET: &str = "0x1111111111111111111111111111111111111111";
+
+    #[test]
+    fn test_parse_asset_lists_unset_allows_any() {
+        let conf = X402Config {
+            asset_str: ngx_str(OTHER_ASSET),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_ok());
+    }
+
+    #[test]
+    fn test_parse_allowed_assets() {
+        let mut conf = X402Config {
+            allowed_assets_str: ngx_str("0x833589FCD6EDB6E08F4C7C32D4F71B54BDA02913"),
+            ..Default::default()
+        };
+        // Default Base USDC matches case-insensitively.
+        assert!(conf.parse().is_ok());
+
+        conf.asset_str = ngx_str(OTHER_ASSET);
+        assert!(conf.parse().is_err());
+
+        conf.allowed_assets_str = ngx_str("0xnothex");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_denied_assets() {
+        let mut conf = X402Config {
+            denied_assets_str: ngx_str(OTHER_ASSET),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_ok());
+
+        conf.asset_str = ngx_str(OTHER_ASSET);
+        assert!(conf.parse().is_err());
+
+        conf.denied_assets_str = ngx_str("0x123");
+        conf.asset_str = ngx_str_t::default();
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_allowed_and_denied_assets() {
+        let mut conf = X402Config {
+            allowed_assets_str: ngx_str(BASE_USDC),
+            denied_assets_str: ngx_str(OTHER_ASSET),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_ok());
+
+        // Denylist wins even when the asset is also allowlisted.
+        conf.denied_assets_str = ngx_str(BASE_USDC);
+        assert!(conf.parse().is_err());
+
+        conf.denied_assets_str = ngx_str(OTHER_ASSET);
+        conf.asset_str = ngx_str(OTHER_ASSET);
+        assert!(conf.parse().is_err());
+
+        // Networks without a default USDC have no effective asset to check.
+        conf.asset_str = ngx_str_t::default();
+        conf.network_str = ngx_str("eip155:56");
+        assert!(conf.parse().is_ok());
+    }
+
+    #[test]
+    fn test_parse_asset_symbol() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.asset_symbol.is_none());
+        assert!(!parsed.asset_symbol_auto);
+
+        conf.asset_symbol_str = ngx_str("USDC");
+        conf.asset_symbol_auto_str = ngx_str("on");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.asset_symbol.as_deref(), Some("USDC"));
+        assert!(parsed.asset_symbol_auto);
+
+        conf.asset_symbol_str = ngx_str("   ");
+        assert!(conf.parse().is_err());
+        conf.asset_symbol_str = ngx_str("USDC");
+        conf.asset_symbol_auto_str = ngx_str("maybe");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_unix_facilitator_url() {
+        let mut conf = X402Config {
+            facilitator_url_str: ngx_str("unix:/run/x402/facilitator.sock"),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.parse().unwrap().facilitator_url.as_deref(),
+            Some("unix:/run/x402/facilitator.sock")
+        );
+
+        conf.facilitator_url_str = ngx_str("unix:facilitator.sock");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_payment_window() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.payment_window_start.is_none());
+        assert!(parsed.payment_window_end.is_none());
+        assert_eq!(parsed.outside_window_status, 503);
+
+        conf.payment_window_start_str = ngx_str("2026-01-01T00:00:00Z");
+        conf.payment_window_end_str = ngx_str("2026-02-01T00:00:00Z");
+        conf.outside_window_status_str = ngx_str("403");
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.payment_window_start < parsed.payment_window_end);
+        assert_eq!(parsed.outside_window_status, 403);
+
+        conf.outside_window_status_str = ngx_str("200");
+        assert!(conf.parse().is_err());
+        conf.outside_window_status_str = ngx_str_t::default();
+
+        conf.payment_window_end_str = ngx_str("2025-12-31T00:00:00Z");
+        assert!(conf.parse().is_err());
+
+        conf.payment_window_end_str = ngx_str_t::default();
+        conf.payment_window_start_str = ngx_str("next tuesday");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_free_quota() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.free_quota.is_none());
+        assert_eq!(parsed.free_quota_window, Duration::from_secs(86400));
+        assert!(!parsed.trust_forwarded_headers);
+
+        conf.free_quota_str = ngx_str("5");
+        assert!(conf.parse().is_err(), "free quota needs Redis");
+
+        conf.redis_url_str = ngx_str("redis://127.0.0.1:6379");
+        conf.free_quota_window_secs_str = ngx_str("3600");
+        conf.trust_forwarded_headers_str = ngx_str("on");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.free_quota, Some(5));
+        assert_eq!(parsed.free_quota_window, Duration::from_secs(3600));
+        assert!(parsed.trust_forwarded_headers);
+
+        conf.free_quota_str = ngx_str("0");
+        assert!(conf.parse().is_err());
+        conf.free_quota_str = ngx_str("5");
+        conf.free_quota_window_secs_str = ngx_str("0");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_resolve_redis_url() {
+        // Unique names keep this test independent of the real environment and
+        // of other tests running in parallel.
+        const PRIMARY: &str = "X402_TEST_RESOLVE_PRIMARY_REDIS_URL";
+        const FALLBACK: &str = "X402_TEST_RESOLVE_FALLBACK_REDIS_URL";
+        std::env::remove_var(PRIMARY);
+        std::env::remove_var(FALLBACK);
+        assert_eq!(resolve_redis_url(None, &[PRIMARY, FALLBACK]), None);
+
+        std::env::set_var(FALLBACK, "redis://fallback:6379");
+        assert_eq!(
+            resolve_redis_url(None, &[PRIMARY, FALLBACK]).as_deref(),
+            Some("redis://fallback:6379")
+        );
+
+        std::env::set_var(PRIMARY, "redis://primary:6379");
+        assert_eq!(
+            resolve_redis_url(None, &[PRIMARY, FALLBACK]).as_deref(),
+            Some("redis://primary:6379")
+        );
+
+        assert_eq!(
+            resolve_redis_url(Some("redis://directive:6379".into()), &[PRIMARY, FALLBACK])
+                .as_deref(),
+            Some("redis://directive:6379")
+        );
+
+        // An empty variable is treated as unset.
+        std::env::set_var(PRIMARY, "");
+        assert_eq!(
+            resolve_redis_url(None, &[PRIMARY, FALLBACK]).as_deref(),
+            Some("redis://fallback:6379")
+        );
+
+        std::env::remove_var(PRIMARY);
+        std::env::remove_var(FALLBACK);
+    }
+
+    #[test]
+    fn test_parse_redis_url_env() {
+        const VAR: &str = "X402_TEST_PARSE_CUSTOM_REDIS_URL";
+        std::env::set_var(VAR, "redis://:secret@custom:6379");
+        let mut conf = X402Config {
+            redis_url_env_str: ngx_str(VAR),
+            ..Default::default()
+        };
+        if std::env::var_os("X402_REDIS_URL").is_none() {
+            assert_eq!(
+                conf.parse().unwrap().redis_url.as_deref(),
+                Some("redis://:secret@custom:6379")
+            );
+        }
+
+        conf.redis_url_str = ngx_str("redis://127.0.0.1:6379");
+        assert_eq!(
+            conf.parse().unwrap().redis_url.as_deref(),
+            Some("redis://127.0.0.1:6379")
+        );
+        std::env::remove_var(VAR);
+    }
+
+    #[test]
+    fn test_resolve_service_url() {
+        std::env::set_var("X402_TEST_FAC_SERVICE_HOST", "10.0.0.5");
+        std::env::set_var("X402_TEST_FAC_SERVICE_PORT", "8080");
+        assert_eq!(
+            resolve_service_url("X402_TEST_FAC", "http").unwrap(),
+            "http://10.0.0.5:8080"
+        );
+        // Kubernetes-style service names map to the variable prefix.
+        assert_eq!(
+            resolve_service_url("x402-test-fac", "https").unwrap(),
+            "https://10.0.0.5:8080"
+        );
+
+        std::env::set_var("X402_TEST_FAC6_SERVICE_HOST", "fd00::5");
+        std::env::set_var("X402_TEST_FAC6_SERVICE_PORT", "443");
+        assert_eq!(
+            resolve_service_url("X402_TEST_FAC6", "https").unwrap(),
+            "https://[fd00::5]:443"
+        );
+
+        std::env::set_var("X402_TEST_FACBAD_SERVICE_HOST", "10.0.0.5");
+        for port in ["0", "http", "70000"] {
+            std::env::set_var("X402_TEST_FACBAD_SERVICE_PORT", port);
+            assert!(resolve_service_url("X402_TEST_FACBAD", "http").is_err());
+        }
+
+        let err = resolve_service_url("X402_TEST_MISSING", "http").unwrap_err();
+        assert!(
+            err.to_string().contains("X402_TEST_MISSING_SERVICE_HOST"),
+            "{err}"
+        );
+        std::env::set_var("X402_TEST_NOPORT_SERVICE_HOST", "10.0.0.5");
+        let err = resolve_service_url("X402_TEST_NOPORT", "http").unwrap_err();
+        assert!(
+            err.to_string().contains("X402_TEST_NOPORT_SERVICE_PORT"),
+            "{err}"
+        );
+        assert!(resolve_service_url("", "http").is_err());
+    }
+
+    #[test]
+    fn test_parse_facilitator_url_resolve() {
+        std::env::set_var("X402_TEST_RESOLVE_SERVICE_HOST", "facilitator.internal");
+        std::env::set_var("X402_TEST_RESOLVE_SERVICE_PORT", "8443");
+
+        let mut conf = X402Config {
+            facilitator_url_str: ngx_str("resolve:X402_TEST_RESOLVE"),
+            facilitator_service_scheme_str: ngx_str("https"),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.parse().unwrap().facilitator_url.as_deref(),
+            Some("https://facilitator.internal:8443")
+        );
+
+        // Plain HTTP still needs x402_facilitator_https_required off.
+        conf.facilitator_service_scheme_str = ngx_str_t::default();
+        assert!(conf.parse().is_err());
+        conf.facilitator_https_required_str = ngx_str("off");
+        assert_eq!(
+            conf.parse().unwrap().facilitator_url.as_deref(),
+            Some("http://facilitator.internal:8443")
+        );
+
+        conf.facilitator_service_scheme_str = ngx_str("grpc");
+        assert!(conf.parse().is_err());
+
+        conf.facilitator_service_scheme_str = ngx_str_t::default();
+        conf.facilitator_url_str = ngx_str("resolve:X402_TEST_RESOLVE_MISSING");
+        let err = conf.parse().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("X402_TEST_RESOLVE_MISSING_SERVICE_HOST"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_parse_env_references() {
+        const PAY_TO_VAR: &str = "X402_TEST_ENV_REF_PAY_TO";
+        const FACILITATOR_VAR: &str = "X402_TEST_ENV_REF_FACILITATOR";
+        std::env::set_var(PAY_TO_VAR, SERVER_PAY_TO);
+        std::env::set_var(FACILITATOR_VAR, "https://facilitator.example");
+
+        let mut conf = X402Config {
+            pay_to_str: ngx_str("env:X402_TEST_ENV_REF_PAY_TO"),
+            facilitator_url_str: ngx_str("env:X402_TEST_ENV_REF_FACILITATOR"),
+            redis_url_str: ngx_str("env:X402_TEST_ENV_REF_PAY_TO"),
+            ..Default::default()
+        };
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.pay_to.as_deref(), Some(SERVER_PAY_TO));
+        assert_eq!(
+            parsed.facilitator_url.as_deref(),
+            Some("https://facilitator.example")
+        );
+        assert_eq!(parsed.redis_url.as_deref(), Some(SERVER_PAY_TO));
+
+        // With a custom sigil, `env:` is taken literally.
+        conf.env_prefix_str = ngx_str("$ENV:");
+        assert!(conf.parse().is_err(), "env:... is not an address");
+        conf.pay_to_str = ngx_str("$ENV:X402_TEST_ENV_REF_PAY_TO");
+        conf.facilitator_url_str = ngx_str("$ENV:X402_TEST_ENV_REF_FACILITATOR");
+        conf.redis_url_str = ngx_str_t::default();
+        assert_eq!(conf.parse().unwrap().pay_to.as_deref(), Some(SERVER_PAY_TO));
+
+        std::env::remove_var(PAY_TO_VAR);
+        std::env::remove_var(FACILITATOR_VAR);
+        let err = conf.parse().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("X402_TEST_ENV_REF_PAY_TO is not set"));
+
+        conf.pay_to_str = ngx_str("$ENV:");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_description_limits() {
+        let mut conf = X402Config {
+            description_str: ngx_str("Weather\tAPI"),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.parse().unwrap().description.as_deref(),
+            Some("Weather\tAPI")
+        );
+
+        conf.description_str = ngx_str("Weather\nAPI");
+        assert!(conf.parse().is_err());
+        conf.description_sanitize_str = ngx_str("on");
+        assert_eq!(
+            conf.parse().unwrap().description.as_deref(),
+            Some("Weather API")
+        );
+
+        conf.description_max_length_str = ngx_str("7");
+        assert_eq!(
+            conf.parse().unwrap().description.as_deref(),
+            Some("Weather")
+        );
+        conf.description_sanitize_str = ngx_str_t::default();
+        assert!(
+            conf.parse().is_err(),
+            "longer than x402_description_max_length"
+        );
+
+        for invalid in ["0", "4097", "long"] {
+            conf.description_max_length_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_description_auto() {
+        let mut conf = X402Config::default();
+        assert!(conf.parse().unwrap().description_auto);
+        conf.description_auto_str = ngx_str("off");
+        assert!(!conf.parse().unwrap().description_auto);
+    }
+
+    #[test]
+    fn test_parse_scheme() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().scheme, Scheme::Exact);
+
+        conf.scheme_str = ngx_str("range");
+        assert!(conf.parse().is_err(), "range needs a maximum");
+        conf.amount_str = ngx_str("0.01");
+        conf.amount_max_str = ngx_str("0.10");
+        assert_eq!(
+            conf.parse().unwrap().scheme,
+            Scheme::Range(Decimal::new(10, 2))
+        );
+        conf.amount_max_str = ngx_str("0.001");
+        assert!(conf.parse().is_err(), "max below min");
+
+        conf.scheme_str = ngx_str("subscription");
+        assert!(conf.parse().is_err(), "subscription needs a period");
+        conf.subscription_period_secs_str = ngx_str("2592000");
+        assert_eq!(
+            conf.parse().unwrap().scheme,
+            Scheme::Subscription(2_592_000)
+        );
+
+        conf.scheme_str = ngx_str("upto");
+        assert!(conf.parse().is_err());
+
+        conf.scheme_str = ngx_str("free");
+        assert!(conf.parse().is_err(), "free needs a zero amount");
+        conf.amount_str = ngx_str("0");
+        assert_eq!(conf.parse().unwrap().scheme, Scheme::Free);
+    }
+
+    #[test]
+    fn test_parse_free_amount() {
+        let mut conf = X402Config::default();
+        for amount in ["free", "FREE", "0", "0.00"] {
+            conf.amount_str = ngx_str(amount);
+            let parsed = conf.parse().unwrap();
+            assert_eq!(parsed.amount, Some(Decimal::ZERO), "{amount}");
+            assert_eq!(parsed.scheme, Scheme::Free, "{amount}");
+        }
+
+        // An explicit scheme is kept; range may start at zero.
+        conf.scheme_str = ngx_str("range");
+        conf.amount_max_str = ngx_str("0.10");
+        assert_eq!(
+            conf.parse().unwrap().scheme,
+            Scheme::Range(Decimal::new(10, 2))
+        );
+
+        conf.amount_str = ngx_str("freely");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_dynamic_price_prefix_match() {
+        let mut conf = X402Config::default();
+        assert!(!conf.parse().unwrap().dynamic_price_prefix_match);
+        conf.dynamic_price_prefix_match_str = ngx_str("on");
+        assert!(conf.parse().unwrap().dynamic_price_prefix_match);
+    }
+
+    #[test]
+    fn test_parse_verify_and_settle_timeouts() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.verify_timeout.is_none());
+        assert!(parsed.settle_timeout.is_none());
+
+        conf.verify_timeout_str = ngx_str("5");
+        conf.settle_timeout_str = ngx_str("120");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.verify_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(parsed.settle_timeout, Some(Duration::from_secs(120)));
+
+        for invalid in ["0", "301", "soon"] {
+            conf.settle_timeout_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_human_readable_durations() {
+        let mut conf = X402Config {
+            ttl_str: ngx_str("5m"),
+            replay_ttl_str: ngx_str("1d"),
+            verify_timeout_str: ngx_str("1m30s"),
+            settle_timeout_str: ngx_str("30s"),
+            ..Default::default()
+        };
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.ttl, Some(300));
+        assert_eq!(parsed.replay_ttl, Some(86_400));
+        assert_eq!(parsed.verify_timeout, Some(Duration::from_secs(90)));
+        assert_eq!(parsed.settle_timeout, Some(Duration::from_secs(30)));
+
+        // The existing ranges still apply to the parsed seconds.
+        conf.ttl_str = ngx_str("2h");
+        assert!(conf.parse().is_err());
+        conf.ttl_str = ngx_str("0s");
+        assert!(conf.parse().is_err());
+        conf.ttl_str = ngx_str("1h");
+        conf.replay_ttl_str = ngx_str("1h");
+        assert!(conf.parse().is_err(), "below 2 x x402_ttl");
+        conf.replay_ttl_str = ngx_str("999999h");
+        assert_eq!(conf.parse().unwrap().replay_ttl, Some(3_599_996_400));
+        conf.replay_ttl_str = ngx_str_t::default();
+        conf.verify_timeout_str = ngx_str("5m1s");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_total_deadline() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().total_deadline, None);
+
+        conf.total_deadline_str = ngx_str("15");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.total_deadline, Some(Duration::from_secs(15)));
+        // Independent of the facilitator-only timeouts.
+        assert_eq!(parsed.verify_timeout, None);
+
+        for invalid in ["0", "301", "-1"] {
+            conf.total_deadline_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_file_facilitator_url() {
+        let mut conf = X402Config {
+            facilitator_url_str: ngx_str("file:///tmp/x402-facilitator.json"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err(), "file:// needs x402_dry_run on");
+
+        conf.dry_run_str = ngx_str("on");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(
+            parsed.facilitator_url.as_deref(),
+            Some("file:///tmp/x402-facilitator.json")
+        );
+        assert_eq!(parsed.facilitator_file_delay, Duration::ZERO);
+
+        conf.facilitator_file_delay_str = ngx_str("250");
+        assert_eq!(
+            conf.parse().unwrap().facilitator_file_delay,
+            Duration::from_millis(250)
+        );
+        for invalid in ["60001", "-1", "fast"] {
+            conf.facilitator_file_delay_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_browser_detect() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().browser_detect, BrowserDetect::Auto);
+        conf.browser_detect_str = ngx_str("Sec-Fetch");
+        assert_eq!(
+            conf.parse().unwrap().browser_detect,
+            BrowserDetect::SecFetch
+        );
+        conf.browser_detect_str = ngx_str("legacy");
+        assert_eq!(conf.parse().unwrap().browser_detect, BrowserDetect::Legacy);
+        conf.browser_detect_str = ngx_str("user-agent");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_nonce() {
+        let mut conf = X402Config {
+            nonce_str: ngx_str("on"),
+            nonce_ttl_str: ngx_str("15000"),
+            ..Default::default()
+        };
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.nonce);
+        assert_eq!(parsed.nonce_ttl_ms, 15_000);
+
+        for invalid in ["999", "3600001", "1s"] {
+            conf.nonce_ttl_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_whitelist_ip() {
+        let mut conf = X402Config::default();
+        assert!(conf.parse().unwrap().whitelist_ips.is_empty());
+
+        conf.whitelist_ip_str = ngx_str("10.0.0.0/8 127.0.0.1 fd00::/8");
+        assert_eq!(
+            conf.parse().unwrap().whitelist_ips,
+            vec![
+                "10.0.0.0/8".parse::<IpNet>().unwrap(),
+                "127.0.0.1/32".parse().unwrap(),
+                "fd00::/8".parse().unwrap(),
+            ]
+        );
+
+        for invalid in ["10.0.0.0/33", "localhost", "10.0.0"] {
+            conf.whitelist_ip_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_amount_expr() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().amount_expr, None);
+
+        conf.amount_expr_str = ngx_str("req_size_kb * 0.001");
+        assert_eq!(
+            conf.parse().unwrap().amount_expr,
+            Some(Expr::parse("req_size_kb * 0.001").unwrap())
+        );
+
+        conf.amount_expr_str = ngx_str("req_size * 0.001");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_resource_prefix_and_suffix() {
+        let mut conf = X402Config {
+            resource_prefix_str: ngx_str("/service/v1"),
+            resource_suffix_str: ngx_str(".json"),
+            ..Default::default()
+        };
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.resource_prefix.as_deref(), Some("/service/v1"));
+        assert_eq!(parsed.resource_suffix.as_deref(), Some(".json"));
+
+        conf.resource_prefix_str = ngx_str("/service/v1/");
+        assert!(conf.parse().is_err());
+        conf.resource_prefix_str = ngx_str("service");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_resource_allowed_schemes_and_max_length() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.resource_allowed_schemes, ["https", "http"]);
+        assert_eq!(parsed.resource_max_length, 2048);
+
+        conf.resource_allowed_schemes_str = ngx_str("HTTPS unix");
+        conf.resource_max_length_str = ngx_str("512");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.resource_allowed_schemes, ["https", "unix"]);
+        assert_eq!(parsed.resource_max_length, 512);
+
+        conf.resource_allowed_schemes_str = ngx_str("https://");
+        assert!(conf.parse().is_err());
+        conf.resource_allowed_schemes_str = ngx_str_t::default();
+        for invalid in ["0", "65537", "long"] {
+            conf.resource_max_length_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_facilitator_user_agent() {
+        let mut conf = X402Config {
+            facilitator_headers_str: ngx_str("User-Agent: old\nX-Api-Key: secret\n"),
+            facilitator_user_agent_str: ngx_str("acme-gateway/2"),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.parse().unwrap().facilitator_headers,
+            vec![
+                ("X-Api-Key".to_string(), "secret".to_string()),
+                ("User-Agent".to_string(), "acme-gateway/2".to_string()),
+            ]
+        );
+
+        conf.facilitator_headers_str = ngx_str_t::default();
+        conf.facilitator_user_agent_str = ngx_str("default");
+        assert!(conf.parse().unwrap().facilitator_headers.is_empty());
+    }
+
+    #[test]
+    fn test_parse_facilitator_response_max_size() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().facilitator_response_max_size,
+            64 * 1024
+        );
+
+        conf.facilitator_response_max_size_str = ngx_str("8");
+        assert_eq!(conf.parse().unwrap().facilitator_response_max_size, 8192);
+
+        for invalid in ["0", "16385", "-1", "64k"] {
+            conf.facilitator_response_max_size_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_facilitator_fallback_reject() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.facilitator_fallback, FacilitatorFallback::Error);
+        assert_eq!(parsed.fallback_reject_message, None);
+        assert_eq!(parsed.fallback_reject_retry_after, None);
+
+        conf.facilitator_fallback_str = ngx_str("reject");
+        conf.fallback_reject_message_str = ngx_str("Payments paused, try again shortly");
+        conf.fallback_reject_retry_after_str = ngx_str("30");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.facilitator_fallback, FacilitatorFallback::Reject);
+        assert_eq!(
+            parsed.fallback_reject_message.as_deref(),
+            Some("Payments paused, try again shortly")
+        );
+        assert_eq!(parsed.fallback_reject_retry_after, Some(30));
+
+        conf.facilitator_fallback_str = ngx_str("402");
+        assert_eq!(
+            conf.parse().unwrap().facilitator_fallback,
+            FacilitatorFallback::Reject
+        );
+
+        for invalid in ["0", "86401", "soon"] {
+            conf.fallback_reject_retry_after_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+        conf.fallback_reject_retry_after_str = ngx_str_t::default();
+        conf.facilitator_fallback_str = ngx_str("retry");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_facilitator_fallback_local_cache() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().local_cache_max_entries,
+            crate::ngx_module::verify_cache::DEFAULT_LOCAL_CACHE_MAX_ENTRIES
+        );
+
+        for value in ["local_cache", "cache_only"] {
+            conf.facilitator_fallback_str = ngx_str(value);
+            assert_eq!(
+                conf.parse().unwrap().facilitator_fallback,
+                FacilitatorFallback::LocalCache
+            );
+        }
+
+        conf.local_cache_max_entries_str = ngx_str("50");
+        assert_eq!(conf.parse().unwrap().local_cache_max_entries, 50);
+        for invalid in ["0", "1000001", "many"] {
+            conf.local_cache_max_entries_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_amount_bps() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().amount_bps, None);
+
+        conf.amount_bps_str = ngx_str("250");
+        assert!(conf.parse().is_err(), "x402_amount_bps without a source");
+
+        conf.amount_bps_source_str = ngx_str("$content_price");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.amount_bps, Some(250));
+        assert_eq!(parsed.amount_bps_source.as_deref(), Some("content_price"));
+
+        for invalid in [
+            "content_price",
+            "$",
+            "$content-price",
+            "$http_x_content_price",
+        ] {
+            conf.amount_bps_source_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+        conf.amount_bps_source_str = ngx_str("$content_price");
+
+        for invalid in ["0", "10001", "1.5"] {
+            conf.amount_bps_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid} should be rejected");
+        }
+
+        conf.amount_bps_str = ngx_str("250");
+        conf.amount_str = ngx_str("0.001");
+        assert!(
+            conf.parse().is_err(),
+            "x402_amount and x402_amount_bps together"
+        );
+    }
+
+    #[test]
+    fn test_merge_amount_bps_replaces_amount() {
+        let server = X402Config {
+            amount_str: ngx_str("0.001"),
+            ..Default::default()
+        };
+        let mut location = X402Config {
+            amount_bps_str: ngx_str("100"),
+            amount_bps_source_str: ngx_str("$content_price"),
+            ..Default::default()
+        };
+        location.merge(&server).unwrap();
+        assert_eq!(location.amount_str.len, 0);
+        let parsed = location.parse().unwrap();
+        assert_eq!(parsed.amount, None);
+        assert_eq!(parsed.amount_bps, Some(100));
+    }
+
+    #[test]
+    fn test_parse_redis_init_timeout() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().redis_init_timeout, None);
+
+        conf.redis_init_timeout_str = ngx_str("500");
+        assert_eq!(
+            conf.parse().unwrap().redis_init_timeout,
+            Some(Duration::from_millis(500))
+        );
+
+        for invalid in ["0", "60001", "-1", "1s"] {
+            conf.redis_init_timeout_str = ngx_str(invalid);
+            assert!(conf.parse().is_err(), "{invalid:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_skip_user_agents() {
+        let mut conf = X402Config::default();
+        assert!(conf.parse().unwrap().skip_user_agents.is_empty());
+
+        conf.skip_user_agents_str = ngx_str("kube-probe GoogleHC ELB-HealthChecker");
+        assert_eq!(
+            conf.parse().unwrap().skip_user_agents,
+            ["kube-probe", "GoogleHC", "ELB-HealthChecker"]
+        );
+
+        conf.skip_user_agents_str = ngx_str("kube-probe kube-*");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_amount_precision() {
+        let mut conf = X402Config {
+            amount_str: ngx_str("0.001"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_ok());
+
+        conf.amount_str = ngx_str("0.0000001");
+        assert!(
+            conf.parse().is_err(),
+            "7 places with the default 6 decimals"
+        );
+
+        conf.asset_decimals_str = ngx_str("9");
+        conf.amount_str = ngx_str("0.000000001");
+        assert!(conf.parse().is_ok());
+
+        conf.amount_max_str = ngx_str("0.0000000001");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_metrics_format() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().metrics_format,
+            MetricsFormat::Prometheus
+        );
+        conf.metrics_format_str = ngx_str("OpenMetrics");
+        assert_eq!(
+            conf.parse().unwrap().metrics_format,
+            MetricsFormat::OpenMetrics
+        );
+        conf.metrics_format_str = ngx_str("json");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_asset_decimals_auto() {
+        let mut conf = X402Config {
+            asset_str: ngx_str("0x4444444444444444444444444444444444444444"),
+            asset_decimals_auto_str: ngx_str("on"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err(), "x402_rpc_url is required");
+        assert!(conf.merge(&X402Config::default()).is_err());
+
+        // An unreachable RPC endpoint falls back to 6 decimals, at load time.
+        conf.rpc_url_str = ngx_str("http://127.0.0.1:1");
+        assert!(conf.merge(&X402Config::default()).is_ok());
+        assert_eq!(conf.parse().unwrap().asset_decimals, Some(6));
+
+        // An explicit value is never overridden.
+        conf.asset_decimals_str = ngx_str("18");
+        assert_eq!(conf.parse().unwrap().asset_decimals, Some(18));
+
+        // Without an explicit asset there is nothing to query.
+        let conf = X402Config {
+            asset_decimals_auto_str: ngx_str("on"),
+            ..Default::default()
+        };
+        assert_eq!(conf.parse().unwrap().asset_decimals, None);
+    }
+
+    #[test]
+    fn test_parse_asset_ticker() {
+        let mut conf = X402Config {
+            asset_str: ngx_str("WETH"),
+            ..Default::default()
+        };
+        let parsed = conf.parse().unwrap();
+        assert_eq!(
+            parsed.asset.as_deref(),
+            Some("0x4200000000000000000000000000000000000006")
+        );
+        assert_eq!(parsed.asset_decimals, Some(18));
+
+        // The configured network picks between deployments of the same symbol.
+        conf.asset_str = ngx_str("usdc");
+        conf.network_str = ngx_str("polygon");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(
+            parsed.asset.as_deref(),
+            Some("0x3c499c542cef5e3811e1192ce70d8cc03d5c3359")
+        );
+        assert_eq!(parsed.asset_decimals, Some(6));
+
+        conf.network_str = ngx_str_t::default();
+        conf.network_id_str = ngx_str("84532");
+        assert_eq!(
+            conf.parse().unwrap().asset.as_deref(),
+            Some("0x036cbd53842c5426634e7929541ec2318f3dcf7e")
+        );
+
+        // An explicit x402_asset_decimals wins over the registry.
+        conf.asset_decimals_str = ngx_str("8");
+        assert_eq!(conf.parse().unwrap().asset_decimals, Some(8));
+
+        conf.asset_str = ngx_str("DAI");
+        assert!(conf.parse().is_err(), "no DAI on base-sepolia");
+        conf.asset_str = ngx_str("DOGE");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_pay_to_any() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.pay_to_any.is_empty());
+        assert!(!parsed.pay_to_any_requires_all);
+
+        conf.pay_to_any_str = ngx_str(
+            "0x1234567890abcdef1234567890abcdef12345678 0xabcdefabcdefabcdefabcdefabcdefabcdefabcd 0x1234567890ABCDEF1234567890ABCDEF12345678",
+        );
+        conf.pay_to_any_requires_all_str = ngx_str("on");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.pay_to_any, vec![SERVER_PAY_TO, OTHER_PAY_TO]);
+        assert!(parsed.pay_to_any_requires_all);
+
+        conf.pay_to_any_str = ngx_str("0x1234 0xabcdefabcdefabcdefabcdefabcdefabcdefabcd");
+        assert!(conf.parse().is_err());
+
+        let conf = X402Config {
+            pay_to_any_requires_all_str: ngx_str("on"),
+            ..Default::default()
+        };
+        assert!(conf.parse().is_err(), "requires_all needs an address list");
+    }
+
+    #[test]
+    fn test_parse_scheme_version() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().scheme_version, Some(X402Version::V2));
+        conf.scheme_version_str = ngx_str("1");
+        assert_eq!(conf.parse().unwrap().scheme_version, Some(X402Version::V1));
+        conf.scheme_version_str = ngx_str("auto");
+        assert_eq!(conf.parse().unwrap().scheme_version, None);
+        conf.scheme_version_str = ngx_str("3");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_facilitator_preflight() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(!parsed.facilitator_preflight);
+        assert_eq!(parsed.facilitator_preflight_path, None);
+
+        conf.facilitator_preflight_str = ngx_str("on");
+        conf.facilitator_preflight_path_str = ngx_str("/health");
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.facilitator_preflight);
+        assert_eq!(
+            parsed.facilitator_preflight_path.as_deref(),
+            Some("/health")
+        );
+
+        conf.facilitator_preflight_path_str = ngx_str("health");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_expose_payer_header() {
+        let mut conf = X402Config::default();
+        assert!(!conf.parse().unwrap().expose_payer_header);
+        conf.expose_payer_header_str = ngx_str("on");
+        assert!(conf.parse().unwrap().expose_payer_header);
+        conf.expose_payer_header_str = ngx_str("yes");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_forward_payer() {
+        let mut conf = X402Config::default();
+        assert!(!conf.parse().unwrap().forward_payer);
+        conf.forward_payer_str = ngx_str("on");
+        assert!(conf.parse().unwrap().forward_payer);
+        conf.forward_payer_str = ngx_str("1");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_upstream_payment_pass() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(!parsed.upstream_payment_pass);
+        assert_eq!(parsed.upstream_payment_hmac_secret, None);
+
+        conf.upstream_payment_pass_str = ngx_str("on");
+        conf.upstream_payment_hmac_secret_str = ngx_str("backend-secret");
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.upstream_payment_pass);
+        assert_eq!(
+            parsed.upstream_payment_hmac_secret.as_deref(),
+            Some("backend-secret")
+        );
+
+        conf.upstream_payment_pass_str = ngx_str("yes");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_dry_run() {
+        let mut conf = X402Config::default();
+        assert!(!conf.parse().unwrap().dry_run);
+        conf.dry_run_str = ngx_str("on");
+        assert!(conf.parse().unwrap().dry_run);
+
+        conf.register_globals().unwrap();
+        assert!(take_dry_run_configured());
+        assert!(!take_dry_run_configured());
+    }
+
+    #[test]
+    fn test_parse_payment_info() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(!parsed.payment_info);
+        assert_eq!(parsed.payment_info_path, "/x402/info");
+
+        conf.payment_info_str = ngx_str("on");
+        conf.payment_info_path_str = ngx_str("/.well-known/x402");
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.payment_info);
+        assert_eq!(parsed.payment_info_path, "/.well-known/x402");
+
+        conf.payment_info_path_str = ngx_str("x402/info");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_redis_error_policy() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().redis_error_policy,
+            RedisErrorPolicy::Allow
+        );
+        for (value, policy) in [
+            ("allow", RedisErrorPolicy::Allow),
+            ("deny", RedisErrorPolicy::Deny),
+            ("error", RedisErrorPolicy::Error),
+        ] {
+            conf.redis_error_policy_str = ngx_str(value);
+            assert_eq!(conf.parse().unwrap().redis_error_policy, policy);
+        }
+        conf.redis_error_policy_str = ngx_str("ignore");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_access_log() {
+        let mut conf = X402Config::default();
+        assert!(!conf.parse().unwrap().access_log);
+        assert!(!conf.access_log());
+
+        conf.access_log_str = ngx_str("on");
+        assert!(conf.parse().unwrap().access_log);
+        assert!(conf.access_log());
+
+        conf.access_log_str = ngx_str("yes please");
+        assert!(conf.parse().is_err());
+        assert!(!conf.access_log());
+    }
+
+    #[test]
+    fn test_replay_hmac_secret() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.replay_hmac_secret().unwrap(), None);
+
+        conf.replay_hmac_secret_str = ngx_str("short");
+        assert!(conf.replay_hmac_secret().is_err());
+        assert!(conf.register_globals().is_err());
+
+        // Redis shared between instances needs an explicit secret.
+        conf.replay_hmac_secret_str = ngx_str_t::default();
+        conf.redis_url_str = ngx_str("redis://127.0.0.1:6379");
+        assert!(conf.register_globals().is_err());
+
+        conf.replay_hmac_secret_str = ngx_str("0123456789abcdef");
+        assert_eq!(
+            conf.replay_hmac_secret().unwrap().as_deref(),
+            Some("0123456789abcdef")
+        );
+        assert!(conf.register_globals().is_ok());
+        conf.redis_url_str = ngx_str_t::default();
+
+        // So does Redis found in the environment, where payments are on.
+        const VAR: &str = "X402_TEST_REPLAY_HMAC_REDIS_URL";
+        std::env::set_var(VAR, "redis://127.0.0.1:6379");
+        let mut from_env = X402Config {
+            redis_url_env_str: ngx_str(VAR),
+            ..Default::default()
+        };
+        assert!(from_env.register_globals().is_ok(), "x402 is off");
+        from_env.enabled = 1;
+        assert!(from_env.register_globals().is_err());
+        from_env.replay_hmac_secret_str = ngx_str("0123456789abcdef");
+        assert!(from_env.register_globals().is_ok());
+        std::env::remove_var(VAR);
+
+        std::env::set_var("X402_TEST_REPLAY_HMAC_SECRET", "from-the-environment");
+        conf.replay_hmac_secret_str = ngx_str("env:X402_TEST_REPLAY_HMAC_SECRET");
+        assert_eq!(
+            conf.replay_hmac_secret().unwrap().as_deref(),
+            Some("from-the-environment")
+        );
+        std::env::remove_var("X402_TEST_REPLAY_HMAC_SECRET");
+    }
+
+    #[test]
+    fn test_facilitator_keepalive() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.facilitator_keepalive().unwrap(),
+            FacilitatorKeepalive::default()
+        );
+
+        conf.facilitator_keepalive_requests_str = ngx_str("0");
+        conf.facilitator_keepalive_timeout_str = ngx_str("1500");
+        assert_eq!(
+            conf.facilitator_keepalive().unwrap(),
+            FacilitatorKeepalive {
+                max_idle: 0,
+                idle_timeout: Duration::from_millis(1500),
+            }
+        );
+
+        for (requests, timeout) in [("-1", "1500"), ("10001", "1500"), ("8", "0"), ("8", "1s")] {
+            conf.facilitator_keepalive_requests_str = ngx_str(requests);
+            conf.facilitator_keepalive_timeout_str = ngx_str(timeout);
+            assert!(
+                conf.facilitator_keepalive().is_err(),
+                "{requests} {timeout}"
+            );
+            assert!(conf.register_globals().is_err());
+        }
+    }
+
+    #[test]
+    fn test_testnets_allowed() {
+        let refused = vec![("eip155:84532".to_string(), false)];
+        let allowed = vec![("eip155:84532".to_string(), true)];
+        assert!(!testnets_allowed(&refused, false));
+        assert!(testnets_allowed(&allowed, false));
+        assert!(testnets_allowed(&[], false));
+        // Debug builds never refuse.
+        assert!(testnets_allowed(&refused, true));
+
+        let mut conf = X402Config::default();
+        assert_eq!(conf.explicit_network().unwrap(), None);
+        conf.network_str = ngx_str("base-sepolia");
+        assert!(crate::config::validation::is_testnet(
+            &conf.explicit_network().unwrap().unwrap()
+        ));
+        conf.network_id_str = ngx_str("8453");
+        assert!(!crate::config::validation::is_testnet(
+            &conf.explicit_network().unwrap().unwrap()
+        ));
+        conf.allow_testnet_str = ngx_str("maybe");
+        conf.network_id_str = ngx_str("84532");
+        assert!(conf.register_globals().is_err());
+    }
+
+    #[test]
+    fn test_parse_log_request_id() {
+        let mut conf = X402Config::default();
+        assert!(conf.parse().unwrap().log_request_id);
+        conf.log_request_id_str = ngx_str("off");
+        assert!(!conf.parse().unwrap().log_request_id);
+    }
+
+    #[test]
+    fn test_parse_response_format() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.parse().unwrap().response_format, ResponseFormat::Auto);
+        conf.response_format_str = ngx_str("JSON");
+        assert_eq!(conf.parse().unwrap().response_format, ResponseFormat::Json);
+        conf.response_format_str = ngx_str("html");
+        assert_eq!(conf.parse().unwrap().response_format, ResponseFormat::Html);
+        conf.response_format_str = ngx_str("xml");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_skip_head() {
+        let mut conf = X402Config::default();
+        assert!(!conf.skip_head());
+        assert!(!conf.parse().unwrap().skip_head);
+
+        conf.skip_head_str = ngx_str("on");
+        assert!(conf.skip_head());
+        assert!(conf.parse().unwrap().skip_head);
+
+        conf.skip_head_str = ngx_str("yes please");
+        assert!(!conf.skip_head());
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_metrics_endpoint() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert!(!conf.metrics_endpoint());
+        assert!(!parsed.metrics_endpoint);
+        assert_eq!(parsed.metrics_auth_token, None);
+        assert!(parsed.metrics_allowed_ips.is_empty());
+
+        conf.metrics_str = ngx_str("on");
+        conf.metrics_auth_token_str = ngx_str("s3cret");
+        conf.metrics_allowed_ips_str = ngx_str("127.0.0.1 ::1");
+        let parsed = conf.parse().unwrap();
+        assert!(conf.metrics_endpoint());
+        assert!(parsed.metrics_endpoint);
+        assert_eq!(parsed.metrics_auth_token.as_deref(), Some("s3cret"));
+        assert_eq!(
+            parsed.metrics_allowed_ips,
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+
+        conf.metrics_allowed_ips_str = ngx_str("127.0.0.1 localhost");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_default_parsed() {
+        let parsed = X402Config::default_parsed();
+        assert!(!parsed.enabled);
+        assert_eq!(parsed.amount, None);
+        assert_eq!(parsed.ttl, None);
+        assert_eq!(parsed.replay_ttl, None);
+        assert_eq!(parsed.asset_decimals, None);
+        assert_eq!(parsed.verify_timeout, None);
+        assert_eq!(parsed.settle_timeout, None);
+        assert_eq!(parsed.free_quota_window, Duration::from_secs(86400));
+        assert_eq!(parsed.response_format, ResponseFormat::Auto);
+        assert_eq!(parsed.metrics_format, MetricsFormat::Prometheus);
+        assert!(parsed.log_request_id);
+        assert!(!parsed.dry_run);
+
+        assert_eq!(DEFAULT_TTL_SECONDS, 60);
+        assert_eq!(DEFAULT_TIMEOUT_SECONDS, 10);
+        assert_eq!(DEFAULT_ASSET_DECIMALS, 6);
+        assert_eq!(DEFAULT_FACILITATOR_TIMEOUT_SECS, 10);
+        assert_eq!(DEFAULT_REPLAY_TTL_SECONDS, 86400);
+        assert!(!parsed.nonce);
+        assert_eq!(parsed.nonce_ttl_ms, DEFAULT_NONCE_TTL_MS);
+        assert_eq!(
+            crate::ngx_module::runtime::DEFAULT_FACILITATOR_TIMEOUT,
+            Duration::from_secs(DEFAULT_FACILITATOR_TIMEOUT_SECS)
+        );
+
+        // The defaults reach the advertised requirements.
+        let req = crate::ngx_module::requirements::create_requirements(
+            &ParsedX402Config {
+                amount: Some(Decimal::new(1, 2)),
+                pay_to: Some("0x1234567890abcdef1234567890abcdef12345678".into()),
+                ..parsed
+            },
+            "/api",
+        )
+        .unwrap();
+        assert_eq!(req.max_timeout_seconds, u64::from(DEFAULT_TTL_SECONDS));
+        assert_eq!(req.amount, "10000");
+    }
+
+    #[test]
+    fn test_facilitator_https_required() {
+        let mut conf = X402Config::default();
+        for url in ["https://x402.org/facilitator", "unix:/run/x402.sock"] {
+            conf.facilitator_url_str = ngx_str(url);
+            assert!(conf.parse().is_ok(), "{url}");
+        }
+
+        // Required by default, loopback included.
+        conf.facilitator_url_str = ngx_str("http://x402.org/facilitator");
+        assert!(conf.parse().is_err());
+        conf.facilitator_url_str = ngx_str("http://127.0.0.1:8080");
+        assert!(conf.parse().is_err());
+
+        // The loopback override covers loopback only.
+        conf.facilitator_allow_loopback_http_str = ngx_str("on");
+        assert_eq!(
+            conf.check_facilitator_https("http://127.0.0.1:8080"),
+            Ok(true)
+        );
+        assert_eq!(conf.check_facilitator_https("http://localhost"), Ok(true));
+        assert_eq!(conf.check_facilitator_https("https://localhost"), Ok(false));
+        assert!(conf.parse().is_ok());
+        conf.facilitator_url_str = ngx_str("http://x402.org/facilitator");
+        assert!(conf.parse().is_err());
+
+        // Off accepts any HTTP URL without the loopback warning.
+        conf.facilitator_https_required_str = ngx_str("off");
+        assert!(conf.parse().is_ok());
+        assert_eq!(conf.check_facilitator_https("http://127.0.0.1"), Ok(false));
+        conf.facilitator_allow_loopback_http_str = ngx_str_t::default();
+        assert!(conf.parse().is_ok());
+    }
+
+    #[test]
+    fn test_pay_to_rejections() {
+        let parse = |pay_to: &'static str, pay_to_any: &'static str, asset: &'static str| {
+            let conf = X402Config {
+                pay_to_str: ngx_str(pay_to),
+                pay_to_any_str: ngx_str(pay_to_any),
+                asset_str: ngx_str(asset),
+                ..Default::default()
+            };
+            conf.parse()
+        };
+        assert!(parse(SERVER_PAY_TO, "", "").is_ok());
+
+        // Zero address.
+        assert!(parse("0x0000000000000000000000000000000000000000", "", "").is_err());
+        assert!(parse("", "0x0000000000000000000000000000000000000000", "").is_err());
+
+        // Bad EIP-55 checksum (one letter's case flipped).
+        assert!(parse("0xABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCd", "", "").is_err());
+        assert!(parse("", "0xABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCd", "").is_err());
+
+        // The asset's own contract, explicit or the network's default USDC.
+        assert!(parse(
+            "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+            "",
+            "0x036cbd53842c5426634e7929541ec2318f3dcf7e"
+        )
+        .is_err());
+        assert!(parse("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", "", "").is_err());
+        assert!(parse("", "0x833589fcd6edb6e08f4c7c32d4f71b54bda02913", "usdc").is_err());
+        // Any network's USDC contract, even when charging in another token.
+        assert!(parse(
+            "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913",
+            "",
+            "0x036CbD53842c5426634e7929541eC2318f3dCF7e"
+        )
+        .is_err());
+        // The burn address.
+        assert!(parse("0x000000000000000000000000000000000000dEaD", "", "").is_err());
+    }
+
+    #[test]
+    fn test_parse_facilitator_protocol() {
+        let mut conf = X402Config {
+            facilitator_url_str: ngx_str("https://facilitator.example"),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.parse().unwrap().facilitator_protocol,
+            FacilitatorProtocol::Rest
+        );
+        conf.facilitator_protocol_str = ngx_str("REST");
+        assert_eq!(
+            conf.parse().unwrap().facilitator_protocol,
+            FacilitatorProtocol::Rest
+        );
+
+        conf.facilitator_protocol_str = ngx_str("grpc");
+        if cfg!(feature = "grpc") {
+            assert_eq!(
+                conf.parse().unwrap().facilitator_protocol,
+                FacilitatorProtocol::Grpc
+            );
+            conf.facilitator_url_str = ngx_str("unix:/run/facilitator.sock");
+            let err = conf.parse().unwrap_err();
+            assert!(err.to_string().contains("http(s)"), "{err}");
+        } else {
+            let err = conf.parse().unwrap_err();
+            assert!(err.to_string().contains("grpc feature"), "{err}");
+        }
+
+        conf.facilitator_protocol_str = ngx_str("soap");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_method_override() {
+        let mut conf = X402Config::default();
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.method_override_header, None);
+        assert_eq!(
+            parsed.method_override_methods,
+            ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        );
+
+        conf.method_override_header_str = ngx_str("X-HTTP-Method-Override");
+        conf.method_override_methods_str = ngx_str("put delete");
+        let parsed = conf.parse().unwrap();
+        assert_eq!(
+            parsed.method_override_header.as_deref(),
+            Some("X-HTTP-Method-Override")
+        );
+        assert_eq!(parsed.method_override_methods, ["PUT", "DELETE"]);
+        assert_eq!(
+            conf.method_override_header().as_deref(),
+            Some("X-HTTP-Method-Override")
+        );
+        assert_eq!(conf.method_override_methods(), ["PUT", "DELETE"]);
+
+        conf.method_override_methods_str = ngx_str("PUT OPTIONS");
+        let err = conf.parse().unwrap_err();
+        assert!(
+            err.to_string().contains("x402_method_override_methods"),
+            "{err}"
+        );
+        conf.method_override_methods_str = ngx_str_t::default();
+
+        conf.method_override_header_str = ngx_str("X Method");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_pay_to_blocked_addresses() {
+        let mut conf = X402Config {
+            pay_to_str: ngx_str(SERVER_PAY_TO),
+            ..Default::default()
+        };
+        assert!(conf.parse().unwrap().pay_to_blocked_addresses.is_empty());
+
+        conf.pay_to_blocked_addresses_str = ngx_str(
+            "0xABCDEFABCDEFABCDEFABCDEFABCDEFABCDEFABCD 0x1234567890ABCDEF1234567890ABCDEF12345678",
+        );
+        let err = conf.parse().unwrap_err();
+        assert!(err.to_string().contains("blocked"), "{err}");
+
+        conf.pay_to_str = ngx_str(OTHER_PAY_TO);
+        let err = conf.parse().unwrap_err();
+        assert!(err.to_string().contains("blocked"), "{err}");
+
+        conf.pay_to_str = ngx_str_t::default();
+        conf.pay_to_any_str = ngx_str(
+            "0x1111111111111111111111111111111111111111 0xabcdefabcdefabcdefabcdefabcdefabcdefabcd",
+        );
+        assert!(conf.parse().is_err());
+        conf.pay_to_any_str = ngx_str("0x1111111111111111111111111111111111111111");
+        assert_eq!(
+            conf.parse().unwrap().pay_to_blocked_addresses,
+            vec![
+                "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd",
+                "0x1234567890abcdef1234567890abcdef12345678",
+            ]
+        );
+
+        conf.pay_to_blocked_addresses_str = ngx_str("0x1234");
+        let err = conf.parse().unwrap_err();
+        assert!(
+            err.to_string().contains("x402_pay_to_blocked_addresses"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_pay_to_and_asset_lowercased() {
+        let configure = |pay_to: &'static str, pay_to_any: &'static str, asset: &'static str| {
+            let conf = X402Config {
+                pay_to_str: ngx_str(pay_to),
+                pay_to_any_str: ngx_str(pay_to_any),
+                asset_str: ngx_str(asset),
+                ..Default::default()
+            };
+            conf.parse().unwrap()
+        };
+        let checksummed = configure(
+            "0xABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD",
+            "0xABCDEFABCDEFABCDEFABCDEFABCDEFABCDEFABCD 0xabcdefabcdefabcdefabcdefabcdefabcdefabcd",
+            "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913",
+        );
+        let lower = configure(
+            "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd",
+            "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd",
+            "0x833589fcd6edb6e08f4c7c32d4f71b54bda02913",
+        );
+        assert_eq!(checksummed, lower);
+        assert_eq!(lower.pay_to.as_deref(), Some(OTHER_PAY_TO));
+        assert_eq!(lower.pay_to_any, vec![OTHER_PAY_TO]);
+        assert_eq!(
+            lower.asset.as_deref(),
+            Some("0x833589fcd6edb6e08f4c7c32d4f71b54bda02913")
+        );
+    }
+
+    #[test]
+    fn test_parse_redis_storage_mode() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().redis_storage_mode,
+            ReplayStorageMode::Keyspace
+        );
+        conf.redis_storage_mode_str = ngx_str("HashBucket");
+        assert_eq!(
+            conf.parse().unwrap().redis_storage_mode,
+            ReplayStorageMode::HashBucket
+        );
+        conf.redis_storage_mode_str = ngx_str("keyspace");
+        assert_eq!(
+            conf.parse().unwrap().redis_storage_mode,
+            ReplayStorageMode::Keyspace
+        );
+        conf.redis_storage_mode_str = ngx_str("zset");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_amount_buckets() {
+        assert_eq!(parse_amount_buckets(ngx_str_t::default()).unwrap(), None);
+        assert_eq!(
+            parse_amount_buckets(ngx_str("1000 10000 1e6")).unwrap(),
+            Some(vec![1_000.0, 10_000.0, 1_000_000.0])
+        );
+        assert!(parse_amount_buckets(ngx_str("1000 100")).is_err());
+        assert!(parse_amount_buckets(ngx_str("0 100")).is_err());
+        assert!(parse_amount_buckets(ngx_str("10 lots")).is_err());
+    }
+
+    #[test]
+    fn test_parse_vault_references() {
+        use crate::ngx_module::test_support::MockServer;
+
+        let server = MockServer::start(|req: &str| {
+            if !req.to_ascii_lowercase().contains("x-vault-token: s.config") {
+                return (403, r#"{"errors":["permission denied"]}"#.to_string());
+            }
+            (
+                200,
+                format!(
+                    r#"{{"data":{{"data":{{"pay_to":"{SERVER_PAY_TO}","url":"https://vault-facilitator.example"}}}}}}"#
+                ),
+            )
+        });
+        let token = std::env::temp_dir().join(format!("x402-config-vault-{}", std::process::id()));
+        std::fs::write(&token, "s.config\n").unwrap();
+        let leak = |s: String| -> &'static str { Box::leak(s.into_boxed_str()) };
+
+        let mut conf = X402Config {
+            vault_addr_str: ngx_str(leak(server.url.clone())),
+            vault_token_file_str: ngx_str(leak(token.to_string_lossy().into_owned())),
+            pay_to_str: ngx_str("vault:secret/data/x402#pay_to"),
+            facilitator_url_str: ngx_str("vault:secret/data/x402#url"),
+            ..Default::default()
+        };
+        let parsed = conf.parse().unwrap();
+        assert_eq!(parsed.pay_to.as_deref(), Some(SERVER_PAY_TO));
+        assert_eq!(
+            parsed.facilitator_url.as_deref(),
+            Some("https://vault-facilitator.example")
+        );
+
+        // A missing field, or a reference without one, rejects the config.
+        conf.pay_to_str = ngx_str("vault:secret/data/x402#missing");
+        let err = conf.parse().unwrap_err();
+        assert!(err.to_string().starts_with("x402_pay_to: "), "{err}");
+        assert!(
+            conf.merge(&X402Config::default()).is_err(),
+            "read when the configuration loads"
+        );
+        conf.pay_to_str = ngx_str("vault:secret/data/x402");
+        assert!(conf.parse().is_err());
+        std::fs::remove_file(token).ok();
+    }
+
+    #[test]
+    fn test_parse_passthrough_methods() {
+        let mut conf = X402Config::default();
+        assert!(conf.parse().unwrap().passthrough_methods.is_empty());
+
+        conf.passthrough_methods_str = ngx_str("get HEAD");
+        let parsed = conf.parse().unwrap();
+        assert!(parsed.passthrough_methods.contains("GET"));
+        assert!(parsed.passthrough_methods.contains("HEAD"));
+        assert!(!parsed.passthrough_methods.contains("POST"));
+
+        conf.passthrough_methods_str = ngx_str("GET FETCH");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_asset_network_mismatch() {
+        let mut conf = X402Config {
+            network_id_str: ngx_str("8453"),
+            asset_str: ngx_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e"),
+            ..Default::default()
+        };
+        let err = conf.parse().unwrap_err();
+        assert!(
+            err.to_string().contains("is USDC on base-sepolia (84532)"),
+            "{err}"
+        );
+
+        conf.network_id_str = ngx_str("84532");
+        assert!(conf.parse().is_ok());
+
+        conf.network_id_str = ngx_str("8453");
+        conf.asset_str = ngx_str(OTHER_ASSET);
+        assert!(conf.parse().is_ok());
+    }
+
+    #[test]
+    fn test_parse_paywall_script() {
+        let mut conf = X402Config::default();
+        assert_eq!(
+            conf.parse().unwrap().paywall_script,
+            Some(PaywallScript::default())
+        );
+
+        conf.paywall_script_url_str = ngx_str("https://cdn.example/x402.js");
+        conf.paywall_script_integrity_str = ngx_str("sha384-abc123");
+        conf.paywall_auto_connect_str = ngx_str("on");
+        assert_eq!(
+            conf.parse().unwrap().paywall_script,
+            Some(PaywallScript {
+                url: "https://cdn.example/x402.js".to_string(),
+                integrity: Some("sha384-abc123".to_string()),
+                auto_connect: true,
+            })
+        );
+
+        // `off` drops the script, and its options with it.
+        conf.paywall_script_url_str = ngx_str("off");
+        assert!(conf.parse().is_err());
+        conf.paywall_script_integrity_str = ngx_str_t::default();
+        conf.paywall_auto_connect_str = ngx_str_t::default();
+        assert_eq!(conf.parse().unwrap().paywall_script, None);
+
+        conf.paywall_script_url_str = ngx_str("javascript:alert(1)");
+        assert!(conf.parse().is_err());
+        conf.paywall_script_url_str = ngx_str_t::default();
+        conf.paywall_script_integrity_str = ngx_str("md5-abc");
+        assert!(conf.parse().is_err());
+    }
+
+    #[test]
+    fn test_vault_secret_ttl() {
+        let mut conf = X402Config::default();
+        assert_eq!(conf.vault_secret_ttl().unwrap(), None);
+        conf.vault_secret_ttl_secs_str = ngx_str("300");
+        assert_eq!(
+            conf.vault_secret_ttl().unwrap(),
+            Some(Duration::from_secs(300))
+        );
+        for bad in ["0", "86401", "5m"] {
+            conf.vault_secret_ttl_secs_str = ngx_str(bad);
+            assert!(conf.vault_secret_ttl().is_err(), "{bad}");
+        }
+    }
+}
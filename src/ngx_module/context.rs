@@ -0,0 +1,285 @@
+use crate::ngx_module::error::{Result, X402Error};
+use crate::ngx_module::ngx_http_x402_module;
+use crate::ngx_module::request::get_header_value;
+use ngx::ffi::{ngx_http_request_t, ngx_module_t};
+use ngx::http::Request;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+/// Values of [`X402RequestContext::payment_status`], exposed as `$x402_payment_status`.
+pub mod status {
+    /// The handler has not reached a decision yet.
+    pub const NONE: &str = "none";
+    /// A 402 was sent because the request carried no payment.
+    pub const REQUIRED: &str = "required";
+    /// Served from the per-IP free quota.
+    pub const FREE: &str = "free";
+    /// The client is in `x402_whitelist_ip` or matches `x402_skip_user_agents`.
+    pub const WHITELISTED: &str = "whitelisted";
+    /// The method is in `x402_passthrough_on_methods`.
+    pub const PASSTHROUGH: &str = "passthrough";
+    /// Let through by `x402_facilitator_fallback pass`.
+    pub const BYPASSED: &str = "bypassed";
+    /// The payment was invalid, replayed or failed to settle.
+    pub const REJECTED: &str = "rejected";
+    /// The payment was verified and settled.
+    pub const SETTLED: &str = "settled";
+    /// The facilitator was down and the payment was accepted, unsettled, on
+    /// a verification cached by `x402_facilitator_fallback local_cache`.
+    pub const CACHED: &str = "cached";
+    /// The payment terms were served by `x402_payment_info`.
+    pub const INFO: &str = "info";
+    /// The request fell outside the payment window, or `x402_amount_bps` had
+    /// no content price to charge a share of.
+    pub const UNAVAILABLE: &str = "unavailable";
+    /// The facilitator failed and the request was answered with an error (a
+    /// 402 under `x402_facilitator_fallback reject`).
+    pub const ERROR: &str = "error";
+}
+
+/// Index passed as `data` to the variable get handler.
+pub const VAR_REQUEST_ID: usize = 0;
+pub const VAR_PAYER: usize = 1;
+pub const VAR_TX_HASH: usize = 2;
+pub const VAR_PAYMENT_STATUS: usize = 3;
+
+/// Longest `X-Request-ID` reused as the request ID; longer values are replaced.
+const MAX_INCOMING_REQUEST_ID_LEN: usize = 128;
+
+/// Per-request x402 state, stored as the module's request context.
+#[derive(Debug)]
+pub struct X402RequestContext {
+    pub request_id: String,
+    pub payer: Option<String>,
+    pub tx_hash: Option<String>,
+    /// Total of the paid (or else the first offered) requirements, in the
+    /// asset's smallest unit.
+    pub amount: Option<String>,
+    pub network: Option<String>,
+    pub resource: Option<String>,
+    pub payment_status: &'static str,
+    pub start_time: Instant,
+    /// Prefix this request's log lines with `[reqid=<request_id>]`.
+    pub log_request_id: bool,
+}
+
+impl Default for X402RequestContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl X402RequestContext {
+    pub fn new() -> Self {
+        Self::with_request_id(uuid::Uuid::new_v4().to_string())
+    }
+
+    fn with_request_id(request_id: String) -> Self {
+        Self {
+            request_id,
+            payer: None,
+            tx_hash: None,
+            amount: None,
+            network: None,
+            resource: None,
+            payment_status: status::NONE,
+            start_time: Instant::now(),
+            log_request_id: false,
+        }
+    }
+
+    /// A context for `r`, reusing its `X-Request-ID` header as the request ID
+    /// when it is usable, so IDs assigned by an upstream proxy carry through.
+    pub fn for_request(r: &Request) -> Self {
+        match get_header_value(r, "X-Request-ID").and_then(incoming_request_id) {
+            Some(id) => Self::with_request_id(id),
+            None => Self::new(),
+        }
+    }
+
+    /// Value of the nginx variable identified by `index`, if set.
+    pub fn variable(&self, index: usize) -> Option<&str> {
+        match index {
+            VAR_REQUEST_ID => Some(&self.request_id),
+            VAR_PAYER => self.payer.as_deref(),
+            VAR_TX_HASH => self.tx_hash.as_deref(),
+            VAR_PAYMENT_STATUS => Some(self.payment_status),
+            _ => None,
+        }
+    }
+
+    /// The `x402_access_log` line for a request that took `latency`; unset
+    /// fields are logged as `-`.
+    pub fn access_log_line(&self, latency: Duration) -> String {
+        let field = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".into());
+        format!(
+            "[x402] status={} payer={} amount={} network={} resource={} latency_ms={} tx_hash={}",
+            self.payment_status,
+            field(&self.payer),
+            field(&self.amount),
+            field(&self.network),
+            field(&self.resource),
+            latency.as_millis(),
+            field(&self.tx_hash),
+        )
+    }
+
+    /// Move `self` into the request pool and register it as the module context.
+    ///
+    /// The context is dropped by a pool cleanup when the request is finalized.
+    pub fn store_in_request(self, r: &mut Request) -> Option<&mut X402RequestContext> {
+        let ctx = r.pool().allocate(self);
+        if ctx.is_null() {
+            return None;
+        }
+        r.set_module_ctx(ctx.cast(), module());
+        // SAFETY: just allocated and initialized from the request pool.
+        unsafe { ctx.as_mut() }
+    }
+
+    /// The context previously stored with [`store_in_request`](Self::store_in_request).
+    pub fn get_from_request(r: &mut Request) -> Option<&mut X402RequestContext> {
+        // SAFETY: the slot is either null or set by `store_in_request`.
+        unsafe { context_slot(r.as_ref()).as_mut() }
+    }
+
+    /// Shared access to the stored context, for callers holding `&Request`.
+    pub fn from_request(r: &Request) -> Option<&X402RequestContext> {
+        // SAFETY: as in `get_from_request`.
+        unsafe { context_slot(r.as_ref()).as_ref() }
+    }
+}
+
+fn context_slot(raw: &ngx_http_request_t) -> *mut X402RequestContext {
+    if raw.ctx.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: `ctx` has one slot per HTTP module, indexed by `ctx_index`.
+    unsafe { (*raw.ctx.add(module().ctx_index)).cast() }
+}
+
+fn module() -> &'static ngx_module_t {
+    unsafe { &*ptr::addr_of!(ngx_http_x402_module) }
+}
+
+/// Record the payment status on the request context, if one is attached.
+pub fn set_payment_status(r: &mut Request, payment_status: &'static str) {
+    if let Some(ctx) = X402RequestContext::get_from_request(r) {
+        ctx.payment_status = payment_status;
+    }
+}
+
+/// Set `X-Request-ID` on the response so clients can quote it when reporting problems.
+pub fn inject_request_id(r: &mut Request, id: &str) -> Result<()> {
+    r.add_header_out("X-Request-ID", id)
+        .ok_or_else(|| X402Error::Runtime("Failed to set X-Request-ID header".into()))
+}
+
+/// An incoming `X-Request-ID` worth keeping: short, printable and free of spaces
+/// so it cannot break log lines or the echoed response header.
+fn incoming_request_id(value: String) -> Option<String> {
+    let value = value.trim();
+    let usable = !value.is_empty()
+        && value.len() <= MAX_INCOMING_REQUEST_ID_LEN
+        && value.bytes().all(|b| b.is_ascii_graphic());
+    usable.then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_context() {
+        let ctx = X402RequestContext::new();
+        let id = uuid::Uuid::parse_str(&ctx.request_id).unwrap();
+        assert_eq!(id.get_version(), Some(uuid::Version::Random));
+        assert_eq!(ctx.payment_status, status::NONE);
+        assert!(!ctx.log_request_id);
+        assert!(ctx.payer.is_none());
+        assert!(ctx.tx_hash.is_none());
+        assert_ne!(ctx.request_id, X402RequestContext::new().request_id);
+    }
+
+    #[test]
+    fn test_incoming_request_id() {
+        assert_eq!(
+            incoming_request_id(" abc-123 ".into()).as_deref(),
+            Some("abc-123")
+        );
+        assert_eq!(incoming_request_id(String::new()), None);
+        assert_eq!(incoming_request_id("has space".into()), None);
+        assert_eq!(incoming_request_id("bad\u{7f}".into()), None);
+        assert_eq!(incoming_request_id("x".repeat(129)), None);
+        assert!(incoming_request_id("x".repeat(128)).is_some());
+    }
+
+    #[test]
+    fn test_variable_values() {
+        let mut ctx = X402RequestContext::new();
+        assert_eq!(ctx.variable(VAR_REQUEST_ID), Some(ctx.request_id.as_str()));
+        assert_eq!(ctx.variable(VAR_PAYER), None);
+        assert_eq!(ctx.variable(VAR_TX_HASH), None);
+        assert_eq!(ctx.variable(VAR_PAYMENT_STATUS), Some("none"));
+        assert_eq!(ctx.variable(99), None);
+
+        ctx.payer = Some("0xabc".into());
+        ctx.tx_hash = Some("0xdef".into());
+        ctx.payment_status = status::SETTLED;
+        assert_eq!(ctx.variable(VAR_PAYER), Some("0xabc"));
+        assert_eq!(ctx.variable(VAR_TX_HASH), Some("0xdef"));
+        assert_eq!(ctx.variable(VAR_PAYMENT_STATUS), Some("settled"));
+    }
+
+    #[test]
+    fn test_access_log_line() {
+        let mut ctx = X402RequestContext::new();
+        ctx.payment_status = status::REQUIRED;
+        assert_eq!(
+            ctx.access_log_line(Duration::from_millis(3)),
+            "[x402] status=required payer=- amount=- network=- resource=- latency_ms=3 tx_hash=-"
+        );
+
+        ctx.payment_status = status::SETTLED;
+        ctx.payer = Some("0xABC".into());
+        ctx.amount = Some("1000".into());
+        ctx.network = Some("eip155:8453".into());
+        ctx.resource = Some("https://api.example.com/weather".into());
+        ctx.tx_hash = Some("0xdef".into());
+        assert_eq!(
+            ctx.access_log_line(Duration::from_millis(120)),
+            "[x402] status=settled payer=0xABC amount=1000 network=eip155:8453 \
+             resource=https://api.example.com/weather latency_ms=120 tx_hash=0xdef"
+        );
+    }
+
+    #[test]
+    fn test_context_lifecycle() {
+        // nginx assigns `ctx_index` when it counts the modules at startup.
+        unsafe { (*ptr::addr_of_mut!(ngx_http_x402_module)).ctx_index = 0 };
+        let mut slots = [ptr::null_mut::<std::ffi::c_void>(); 8];
+        let mut raw: ngx::ffi::ngx_http_request_t = unsafe { std::mem::zeroed() };
+        raw.ctx = slots.as_mut_ptr();
+        let r = unsafe { Request::from_ngx_http_request(&mut raw) };
+
+        assert!(X402RequestContext::get_from_request(r).is_none());
+        set_payment_status(r, status::FREE);
+
+        let ctx = Box::into_raw(Box::new(X402RequestContext::new()));
+        r.set_module_ctx(ctx.cast(), module());
+        set_payment_status(r, status::SETTLED);
+        X402RequestContext::get_from_request(r).unwrap().tx_hash = Some("0x1".into());
+
+        assert_eq!(
+            X402RequestContext::from_request(r)
+                .unwrap()
+                .tx_hash
+                .as_deref(),
+            Some("0x1")
+        );
+
+        let ctx = unsafe { Box::from_raw(ctx) };
+        assert_eq!(ctx.payment_status, status::SETTLED);
+        assert_eq!(ctx.tx_hash.as_deref(), Some("0x1"));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::ngx_module::chain::eth_call;
+use crate::ngx_module::logging::log_info;
+use sha3::{Digest, Keccak256};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// ENS registry, deployed at the same address on mainnet and the public testnets.
+const ENS_REGISTRY: &str = "0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e";
+/// Selector of `resolver(bytes32)` on the registry.
+const RESOLVER_SELECTOR: &str = "0178b8bf";
+/// Selector of `addr(bytes32)` on a public resolver.
+const ADDR_SELECTOR: &str = "3b3b57de";
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/// Outcome of each `(rpc_url, name)` lookup, failures included, so a name is
+/// only ever queried once per configuration load.
+type EnsCache = HashMap<(String, String), Result<String, String>>;
+
+static ENS_CACHE: OnceLock<Mutex<EnsCache>> = OnceLock::new();
+
+/// Whether `s` looks like an ENS name (`alice.eth`) rather than a hex address.
+pub fn is_ens_name(s: &str) -> bool {
+    let s = s.trim();
+    !s.starts_with("0x")
+        && !s.starts_with("0X")
+        && s.contains('.')
+        && s.split('.').all(|label| !label.is_empty())
+}
+
+/// EIP-137 namehash of a (lowercased) ENS name.
+pub(crate) fn namehash(name: &str) -> [u8; 32] {
+    let mut node = [0u8; 32];
+    if name.is_empty() {
+        return node;
+    }
+    for label in name.rsplit('.') {
+        let label_hash = Keccak256::digest(label.as_bytes());
+        let mut hasher = Keccak256::new();
+        hasher.update(node);
+        hasher.update(label_hash);
+        node = hasher.finalize().into();
+    }
+    node
+}
+
+/// Extract the address from a 32-byte ABI-encoded `eth_call` result.
+fn decode_address(word: &str) -> Result<String, String> {
+    let hex = word.strip_prefix("0x").unwrap_or(word);
+    if hex.len() < 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Unexpected eth_call result: {word}"));
+    }
+    Ok(format!("0x{}", &hex[24..64]).to_lowercase())
+}
+
+/// Resolve an ENS name to an address via `eth_call`s against `rpc_url`.
+///
+/// Called while merging configs, before nginx forks, so workers inherit the
+/// result; the outcome is cached either way and later calls never reach the
+/// RPC endpoint. [`clear_ens_cache`] starts over on reload.
+pub fn resolve_ens_blocking(name: &str, rpc_url: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+    let key = (rpc_url.to_string(), name.clone());
+    let cache = ENS_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(result) = cache
+        .lock()
+        .map_err(|_| "ENS cache lock poisoned".to_string())?
+        .get(&key)
+    {
+        return result.clone();
+    }
+
+    let result = lookup(&name, rpc_url);
+    if let Ok(address) = &result {
+        log_info(None, &format!("Resolved ENS name {name} to {address}"));
+    }
+    cache
+        .lock()
+        .map_err(|_| "ENS cache lock poisoned".to_string())?
+        .insert(key, result.clone());
+    result
+}
+
+/// Forget every resolved name, so the next configuration load queries again.
+pub fn clear_ens_cache() {
+    if let Some(Ok(mut cache)) = ENS_CACHE.get().map(Mutex::lock) {
+        cache.clear();
+    }
+}
+
+fn lookup(name: &str, rpc_url: &str) -> Result<String, String> {
+    let node = hex::encode(namehash(name));
+    // Config is read before the shared runtime exists (and before nginx
+    // forks), so the lookup uses a throwaway one.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to create tokio runtime: {e}"))?;
+    let address = runtime.block_on(async {
+        let client = reqwest::Client::new();
+        let resolver = decode_address(
+            &eth_call(
+                &client,
+                rpc_url,
+                ENS_REGISTRY,
+                format!("0x{RESOLVER_SELECTOR}{node}"),
+            )
+            .await?,
+        )?;
+        if resolver == ZERO_ADDRESS {
+            return Err(format!("ENS name {name} has no resolver"));
+        }
+        decode_address(
+            &eth_call(
+                &client,
+                rpc_url,
+                &resolver,
+                format!("0x{ADDR_SELECTOR}{node}"),
+            )
+            .await?,
+        )
+    })?;
+    if address == ZERO_ADDRESS {
+        return Err(format!("ENS name {name} resolves to the zero address"));
+    }
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngx_module::test_support::MockServer;
+
+    const RESOLVER: &str = "0x4976fb03c32e5b8cfe2b6ccb31c09ba78ebaba41";
+
+    fn mock_rpc(address: &'static str) -> MockServer {
+        MockServer::start(move |request| {
+            let word = if request.contains(&format!("\"0x{RESOLVER_SELECTOR}")) {
+                RESOLVER
+            } else {
+                address
+            };
+            let result = format!("0x{:0>64}", word.trim_start_matches("0x"));
+            (
+                200,
+                serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string(),
+            )
+        })
+    }
+
+    #[test]
+    fn test_is_ens_name() {
+        assert!(is_ens_name("alice.eth"));
+        assert!(is_ens_name("pay.alice.eth"));
+        assert!(!is_ens_name("0x1234567890abcdef1234567890abcdef12345678"));
+        assert!(!is_ens_name("alice"));
+        assert!(!is_ens_name("alice..eth"));
+    }
+
+    #[test]
+    fn test_namehash_eip137_vectors() {
+        assert_eq!(namehash(""), [0u8; 32]);
+        assert_eq!(
+            hex::encode(namehash("eth")),
+            "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+        );
+        assert_eq!(
+            hex::encode(namehash("foo.eth")),
+            "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+        );
+    }
+
+    #[test]
+    fn test_resolve_ens_blocking() {
+        let server = mock_rpc("0xAbCdEfabcdefabcdefabcdefabcdefabcdefabcd");
+        let address = resolve_ens_blocking("resolved-test.eth", &server.url).unwrap();
+        assert_eq!(address, "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd");
+        assert_eq!(server.requests().len(), 2);
+
+        // Second lookup is served from the cache.
+        resolve_ens_blocking("Resolved-Test.eth", &server.url).unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_ens_zero_address() {
+        let server = mock_rpc(ZERO_ADDRESS);
+        assert!(resolve_ens_blocking("unset-test.eth", &server.url).is_err());
+        assert_eq!(server.requests().len(), 2);
+
+        // The failure is cached too.
+        assert!(resolve_ens_blocking("unset-test.eth", &server.url).is_err());
+        assert_eq!(server.requests().len(), 2);
+    }
+}
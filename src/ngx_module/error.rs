@@ -1,39 +1,81 @@
 use std::fmt;
 
-#[derive(Debug)]
-pub struct ConfigError {
-    message: String,
+/// Errors raised while configuring the module or handling a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum X402Error {
+    /// Missing or invalid x402 directive values.
+    Config(String),
+    /// Internal failure while handling a request (nginx API, serialization, locks).
+    Runtime(String),
+    /// A facilitator call did not complete in time.
+    Timeout(String),
+    /// The payment signature has already been used.
+    Replay,
+    /// The client sent a payment payload that could not be decoded.
+    InvalidPayment(String),
+    /// The facilitator was unreachable or returned an unusable response.
+    FacilitatorError(String),
 }
 
-impl ConfigError {
-    pub fn new(msg: impl Into<String>) -> Self {
-        Self {
-            message: msg.into(),
+#[deprecated(note = "use X402Error and pick the matching variant")]
+pub type ConfigError = X402Error;
+
+impl X402Error {
+    /// Client-facing message for this error, from [`user_errors`].
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            X402Error::Config(_) | X402Error::Runtime(_) => user_errors::CONFIGURATION_ERROR,
+            X402Error::Timeout(_) => user_errors::TIMEOUT,
+            X402Error::Replay => user_errors::REPLAY_DETECTED,
+            X402Error::InvalidPayment(_) => user_errors::INVALID_PAYMENT,
+            X402Error::FacilitatorError(_) => user_errors::PAYMENT_VERIFICATION_FAILED,
         }
     }
 }
 
-impl fmt::Display for ConfigError {
+impl fmt::Display for X402Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message)
+        match self {
+            X402Error::Config(msg)
+            | X402Error::Runtime(msg)
+            | X402Error::Timeout(msg)
+            | X402Error::InvalidPayment(msg)
+            | X402Error::FacilitatorError(msg) => write!(f, "{msg}"),
+            X402Error::Replay => write!(f, "{}", user_errors::REPLAY_DETECTED),
+        }
     }
 }
 
-impl std::error::Error for ConfigError {}
+impl std::error::Error for X402Error {}
 
-impl From<&str> for ConfigError {
+impl From<&str> for X402Error {
     fn from(s: &str) -> Self {
-        Self::new(s)
+        Self::Config(s.to_string())
     }
 }
 
-impl From<String> for ConfigError {
+impl From<String> for X402Error {
     fn from(s: String) -> Self {
-        Self::new(s)
+        Self::Config(s)
+    }
+}
+
+/// Status returned from the phase handler when `x402_handler_impl` fails.
+impl From<X402Error> for ngx::ffi::ngx_int_t {
+    fn from(e: X402Error) -> Self {
+        let status = match e {
+            X402Error::Config(_) | X402Error::Runtime(_) => {
+                ngx::ffi::NGX_HTTP_INTERNAL_SERVER_ERROR
+            }
+            X402Error::Timeout(_) => ngx::ffi::NGX_HTTP_GATEWAY_TIME_OUT,
+            X402Error::FacilitatorError(_) => ngx::ffi::NGX_HTTP_BAD_GATEWAY,
+            X402Error::Replay | X402Error::InvalidPayment(_) => ngx::ffi::NGX_HTTP_BAD_REQUEST,
+        };
+        status as ngx::ffi::ngx_int_t
     }
 }
 
-pub type Result<T> = std::result::Result<T, ConfigError>;
+pub type Result<T> = std::result::Result<T, X402Error>;
 
 pub mod user_errors {
     pub const INVALID_PAYMENT: &str = "Invalid payment payload";
@@ -41,4 +83,61 @@ pub mod user_errors {
     pub const CONFIGURATION_ERROR: &str = "Server configuration error";
     pub const TIMEOUT: &str = "Payment verification timed out";
     pub const REPLAY_DETECTED: &str = "Payment replay detected";
+    pub const REPLAY_CHECK_UNAVAILABLE: &str = "Payment replay check unavailable";
+    pub const NONCE_EXPIRED: &str = "Payment nonce missing or expired";
+    pub const FACILITATOR_UNAVAILABLE: &str = "Facilitator temporarily unavailable";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_variants() -> Vec<X402Error> {
+        vec![
+            X402Error::Config("bad directive".into()),
+            X402Error::Runtime("alloc failed".into()),
+            X402Error::Timeout("verify timed out".into()),
+            X402Error::Replay,
+            X402Error::InvalidPayment("bad base64".into()),
+            X402Error::FacilitatorError("HTTP 503".into()),
+        ]
+    }
+
+    #[test]
+    fn test_user_message_per_variant() {
+        for e in all_variants() {
+            let expected = match e {
+                X402Error::Config(_) | X402Error::Runtime(_) => user_errors::CONFIGURATION_ERROR,
+                X402Error::Timeout(_) => user_errors::TIMEOUT,
+                X402Error::Replay => user_errors::REPLAY_DETECTED,
+                X402Error::InvalidPayment(_) => user_errors::INVALID_PAYMENT,
+                X402Error::FacilitatorError(_) => user_errors::PAYMENT_VERIFICATION_FAILED,
+            };
+            assert_eq!(e.user_message(), expected);
+        }
+    }
+
+    #[test]
+    fn test_ngx_status_per_variant() {
+        for e in all_variants() {
+            let expected = match e {
+                X402Error::Config(_) | X402Error::Runtime(_) => 500,
+                X402Error::Timeout(_) => 504,
+                X402Error::FacilitatorError(_) => 502,
+                X402Error::Replay | X402Error::InvalidPayment(_) => 400,
+            };
+            assert_eq!(ngx::ffi::ngx_int_t::from(e), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_keeps_message() {
+        assert_eq!(X402Error::Config("x".into()).to_string(), "x");
+        assert_eq!(X402Error::Replay.to_string(), user_errors::REPLAY_DETECTED);
+    }
+
+    #[test]
+    fn test_from_str_is_config() {
+        assert_eq!(X402Error::from("oops"), X402Error::Config("oops".into()));
+    }
 }
@@ -0,0 +1,327 @@
+//! gRPC facilitator client (`x402_facilitator_protocol grpc`, `grpc` feature).
+//!
+//! Speaks the `x402.facilitator.Facilitator` service from
+//! `proto/x402_facilitator.proto`. Payloads and requirements travel as JSON
+//! strings, so both clients send the facilitator exactly the same data.
+
+use crate::ngx_module::error::{Result, X402Error};
+use crate::ngx_module::runtime::{
+    default_user_agent, FacilitatorRequestBody, SettleResponseBody, VerifyResponseBody,
+};
+use std::time::Duration;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+pub mod proto {
+    tonic::include_proto!("x402.facilitator");
+}
+
+use proto::facilitator_client::FacilitatorClient as ProtoClient;
+
+/// The request bodies' `x402_version`.
+const X402_VERSION: u32 = 2;
+
+pub struct GrpcFacilitatorClient {
+    channel: Channel,
+}
+
+impl GrpcFacilitatorClient {
+    /// `base_url` is the facilitator's `http(s)://host:port`. The connection
+    /// is made on the first call; must be called within a tokio runtime.
+    pub fn new(base_url: &str) -> Result<Self> {
+        let base_url = base_url.trim_end_matches('/');
+        let mut endpoint = Endpoint::from_shared(base_url.to_string())
+            .and_then(|endpoint| endpoint.user_agent(default_user_agent()))
+            .map_err(|e| X402Error::Runtime(format!("Invalid gRPC facilitator URL: {e}")))?;
+        if base_url.starts_with("https://") {
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().with_native_roots())
+                .map_err(|e| X402Error::Runtime(format!("Failed to configure gRPC TLS: {e}")))?;
+        }
+        Ok(Self {
+            channel: endpoint.connect_lazy(),
+        })
+    }
+
+    pub async fn verify(
+        &self,
+        body: &FacilitatorRequestBody,
+        timeout: Duration,
+        headers: &[(String, String)],
+        max_response_size: usize,
+    ) -> Result<VerifyResponseBody> {
+        let (payment_payload, payment_requirements) = encode_body(body)?;
+        let request = build_request(
+            proto::VerifyRequest {
+                x402_version: X402_VERSION,
+                payment_payload,
+                payment_requirements,
+            },
+            timeout,
+            headers,
+        )?;
+        let resp = self
+            .client(max_response_size)
+            .verify(request)
+            .await
+            .map_err(|status| {
+                X402Error::FacilitatorError(format!(
+                    "Facilitator verify request failed: {}: {}",
+                    status.code(),
+                    status.message()
+                ))
+            })?
+            .into_inner();
+        Ok(VerifyResponseBody {
+            is_valid: resp.is_valid,
+            invalid_reason: resp.invalid_reason,
+            payer: resp.payer,
+        })
+    }
+
+    pub async fn settle(
+        &self,
+        body: &FacilitatorRequestBody,
+        timeout: Duration,
+        headers: &[(String, String)],
+        max_response_size: usize,
+    ) -> Result<SettleResponseBody> {
+        let (payment_payload, payment_requirements) = encode_body(body)?;
+        let request = build_request(
+            proto::SettleRequest {
+                x402_version: X402_VERSION,
+                payment_payload,
+                payment_requirements,
+            },
+            timeout,
+            headers,
+        )?;
+        let resp = self
+            .client(max_response_size)
+            .settle(request)
+            .await
+            .map_err(|status| {
+                X402Error::FacilitatorError(format!(
+                    "Facilitator settle request failed: {}: {}",
+                    status.code(),
+                    status.message()
+                ))
+            })?
+            .into_inner();
+        Ok(SettleResponseBody {
+            success: resp.success,
+            tx_hash: resp.tx_hash,
+            error_reason: resp.error_reason,
+            error_message: resp.error_message,
+            payer: None,
+        })
+    }
+
+    fn client(&self, max_response_size: usize) -> ProtoClient<Channel> {
+        // Same cap as `read_body_limited` on the REST client.
+        ProtoClient::new(self.channel.clone()).max_decoding_message_size(max_response_size)
+    }
+}
+
+fn encode_body(body: &FacilitatorRequestBody) -> Result<(String, String)> {
+    let encode = |value: &serde_json::Value| {
+        serde_json::to_string(value).map_err(|e| {
+            X402Error::FacilitatorError(format!("Failed to encode facilitator request: {e}"))
+        })
+    };
+    Ok((
+        encode(&body.payment_payload)?,
+        encode(&body.payment_requirements)?,
+    ))
+}
+
+/// Wrap `message` with the deadline and `x402_facilitator_headers`, sent as
+/// gRPC metadata (names lowercased, as HTTP/2 requires).
+fn build_request<T>(
+    message: T,
+    timeout: Duration,
+    headers: &[(String, String)],
+) -> Result<tonic::Request<T>> {
+    let mut request = tonic::Request::new(message);
+    request.set_timeout(timeout);
+    for (name, value) in headers {
+        let key = MetadataKey::from_bytes(name.to_ascii_lowercase().as_bytes()).map_err(|_| {
+            X402Error::FacilitatorError(format!("Invalid facilitator header name: {name}"))
+        })?;
+        let value = MetadataValue::try_from(value.as_str()).map_err(|_| {
+            X402Error::FacilitatorError(format!("Invalid value for facilitator header {name}"))
+        })?;
+        request.metadata_mut().insert(key, value);
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::facilitator_server::{Facilitator, FacilitatorServer};
+    use super::*;
+    use crate::ngx_module::runtime::{get_runtime, spawn_on_runtime};
+    use tonic::{Request, Response, Status};
+    use x402_types::proto::v2::X402Version2;
+
+    /// Accepts requests carrying `x-api-key: secret`; the payer echoes the
+    /// payload's `from` field.
+    struct MockFacilitator;
+
+    // `Status` is what the service methods return anyway.
+    #[allow(clippy::result_large_err)]
+    fn authorize(metadata: &tonic::metadata::MetadataMap) -> std::result::Result<(), Status> {
+        match metadata.get("x-api-key").and_then(|v| v.to_str().ok()) {
+            Some("secret") => Ok(()),
+            _ => Err(Status::unauthenticated("missing api key")),
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Facilitator for MockFacilitator {
+        async fn verify(
+            &self,
+            request: Request<proto::VerifyRequest>,
+        ) -> std::result::Result<Response<proto::VerifyResponse>, Status> {
+            authorize(request.metadata())?;
+            let body = request.into_inner();
+            assert_eq!(body.x402_version, 2);
+            let payload: serde_json::Value = serde_json::from_str(&body.payment_payload)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            Ok(Response::new(proto::VerifyResponse {
+                is_valid: true,
+                invalid_reason: None,
+                payer: payload["from"].as_str().map(String::from),
+            }))
+        }
+
+        async fn settle(
+            &self,
+            request: Request<proto::SettleRequest>,
+        ) -> std::result::Result<Response<proto::SettleResponse>, Status> {
+            authorize(request.metadata())?;
+            Ok(Response::new(proto::SettleResponse {
+                success: true,
+                tx_hash: Some("0xabc".to_string()),
+                error_reason: None,
+                error_message: None,
+            }))
+        }
+    }
+
+    /// Serve [`MockFacilitator`] on an ephemeral port; returns its URL.
+    fn start_mock_server() -> String {
+        let runtime = get_runtime().unwrap();
+        let listener = runtime
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        spawn_on_runtime(
+            tonic::transport::Server::builder()
+                .add_service(FacilitatorServer::new(MockFacilitator))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        )
+        .unwrap();
+        url
+    }
+
+    fn request_body() -> FacilitatorRequestBody {
+        FacilitatorRequestBody {
+            x402_version: X402Version2,
+            payment_payload: serde_json::json!({"from": "0xpayer"}),
+            payment_requirements: serde_json::json!({}),
+        }
+    }
+
+    fn api_key() -> Vec<(String, String)> {
+        vec![("X-Api-Key".to_string(), "secret".to_string())]
+    }
+
+    #[test]
+    fn test_grpc_verify_and_settle() {
+        let url = start_mock_server();
+        let runtime = get_runtime().unwrap();
+        let timeout = Duration::from_secs(5);
+
+        let (verify, settle) = runtime.block_on(async {
+            let client = GrpcFacilitatorClient::new(&url).unwrap();
+            let verify = client
+                .verify(&request_body(), timeout, &api_key(), 64 * 1024)
+                .await;
+            let settle = client
+                .settle(&request_body(), timeout, &api_key(), 64 * 1024)
+                .await;
+            (verify, settle)
+        });
+        assert_eq!(
+            verify.unwrap(),
+            VerifyResponseBody {
+                is_valid: true,
+                invalid_reason: None,
+                payer: Some("0xpayer".to_string()),
+            }
+        );
+        let settle = settle.unwrap();
+        assert!(settle.success);
+        assert_eq!(settle.tx_hash.as_deref(), Some("0xabc"));
+    }
+
+    #[test]
+    fn test_grpc_errors() {
+        let url = start_mock_server();
+        let runtime = get_runtime().unwrap();
+        let timeout = Duration::from_secs(5);
+
+        let err = runtime
+            .block_on(async {
+                let client = GrpcFacilitatorClient::new(&url).unwrap();
+                client
+                    .verify(&request_body(), timeout, &[], 64 * 1024)
+                    .await
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("missing api key"), "{err}");
+
+        // A response larger than the cap is refused.
+        let err = runtime
+            .block_on(async {
+                let client = GrpcFacilitatorClient::new(&url).unwrap();
+                client.verify(&request_body(), timeout, &api_key(), 4).await
+            })
+            .unwrap_err();
+        assert!(matches!(err, X402Error::FacilitatorError(_)));
+
+        let bad_header = vec![("X Bad".to_string(), "v".to_string())];
+        assert!(build_request((), timeout, &bad_header).is_err());
+        assert!(GrpcFacilitatorClient::new("not a url").is_err());
+    }
+
+    #[test]
+    fn test_get_facilitator_client_dispatches_on_protocol() {
+        use crate::ngx_module::config::FacilitatorProtocol;
+        use crate::ngx_module::runtime::{
+            get_facilitator_client, register_facilitator_protocol, FacilitatorClient,
+        };
+
+        let url = start_mock_server();
+        assert!(register_facilitator_protocol(
+            &url,
+            FacilitatorProtocol::Grpc
+        ));
+
+        let runtime = get_runtime().unwrap();
+        let verify = runtime.block_on(async {
+            let client = get_facilitator_client(&url).unwrap();
+            assert!(matches!(*client, FacilitatorClient::Grpc(_)));
+            client
+                .verify(
+                    &request_body(),
+                    Duration::from_secs(5),
+                    &api_key(),
+                    64 * 1024,
+                )
+                .await
+        });
+        assert!(verify.unwrap().is_valid);
+    }
+}
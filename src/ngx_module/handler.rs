@@ -1,15 +1,38 @@
-use crate::ngx_module::config::{FacilitatorFallback, ParsedX402Config};
-use crate::ngx_module::error::{user_errors, ConfigError, Result};
+use crate::ngx_module::amount_expr::EvalContext;
+use crate::ngx_module::config::{
+    FacilitatorFallback, ParsedX402Config, DEFAULT_ASSET_DECIMALS, DEFAULT_REPLAY_TTL_SECONDS,
+};
+use crate::ngx_module::context::{set_payment_status, status, X402RequestContext};
+use crate::ngx_module::error::{user_errors, Result, X402Error};
 use crate::ngx_module::logging::{log_debug, log_error, log_info, log_warn};
-use crate::ngx_module::metrics::X402Metrics;
-use crate::ngx_module::redis;
-use crate::ngx_module::request::{build_full_url, get_header_value, infer_mime_type};
-use crate::ngx_module::requirements::create_requirements;
-use crate::ngx_module::response::{send_402_response, send_response_body};
-use crate::ngx_module::runtime::{get_runtime, settle_payment, verify_payment};
-use ngx::http::{HTTPStatus, Request};
-use rust_decimal::prelude::ToPrimitive;
-use std::time::Instant;
+use crate::ngx_module::metrics::{render_metrics, X402Metrics};
+use crate::ngx_module::redis::{self, RedisErrorPolicy};
+use crate::ngx_module::request::{
+    build_full_url, client_ip, decode_payment_payload, extract_payer_from_payload,
+    get_effective_method, get_header_value, get_variable_value, infer_mime_type,
+    is_passthrough_method, is_payment_info_request, is_skipped_user_agent, is_whitelisted,
+    set_header_in, strip_resource_path,
+};
+use crate::ngx_module::requirements::{
+    create_requirements_list, generate_auto_description, match_payments, nonce_is_current,
+    now_millis, payment_nonce, payment_pay_to, PaymentRequirements,
+};
+use crate::ngx_module::response::{
+    send_402_response, send_chunked_response_body, send_payment_info_response, send_response_body,
+};
+use crate::ngx_module::runtime::{
+    file_facilitator_path, get_runtime, settle_payment, verify_payment, VerifyResponseBody,
+};
+use crate::ngx_module::verify_cache::{
+    cache_verification, mark_verification_used, take_cached_verification,
+};
+use hmac::{Hmac, Mac};
+use ngx::http::{HTTPStatus, Method, Request};
+use rust_decimal::{Decimal, RoundingStrategy};
+use sha2::Sha256;
+use std::future::Future;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HandlerResult {
@@ -18,6 +41,392 @@ pub enum HandlerResult {
     Error,
 }
 
+pub(crate) fn exceeds_max_payment_size(payment_b64: &str, max_size: usize) -> bool {
+    payment_b64.len() > max_size
+}
+
+/// Where a request falls relative to `x402_payment_window_start`/`_end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PaymentWindow {
+    Open,
+    /// Payments are not accepted yet; `retry_after` is in whole seconds.
+    NotYetOpen {
+        retry_after: u64,
+        available_at: String,
+    },
+    Closed,
+}
+
+pub(crate) fn payment_window(
+    now: SystemTime,
+    start: Option<SystemTime>,
+    end: Option<SystemTime>,
+) -> PaymentWindow {
+    if let Some(start) = start {
+        if let Ok(wait) = start.duration_since(now) {
+            if !wait.is_zero() {
+                let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                let available_at = chrono::DateTime::<chrono::Utc>::from(start)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+                return PaymentWindow::NotYetOpen {
+                    retry_after,
+                    available_at,
+                };
+            }
+        }
+    }
+    match end {
+        Some(end) if now >= end => PaymentWindow::Closed,
+        _ => PaymentWindow::Open,
+    }
+}
+
+/// When the payment flow has to be finished by (`x402_total_deadline_secs`).
+/// Redis calls are blocking, so they get the remaining time as their socket
+/// timeouts rather than being raced against a timer.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline(Option<Instant>);
+
+impl Deadline {
+    pub(crate) fn new(start: Instant, total: Option<Duration>) -> Self {
+        Self(total.map(|total| start + total))
+    }
+
+    /// Time left, zero once passed; `None` without a deadline.
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.0
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    pub(crate) fn expired(&self) -> bool {
+        self.remaining().is_some_and(|left| left.is_zero())
+    }
+}
+
+/// Run `fut` on `runtime`, or `None` if the deadline passes first.
+pub(crate) fn block_on_within<F: Future>(
+    runtime: &tokio::runtime::Runtime,
+    deadline: Deadline,
+    fut: F,
+) -> Option<F::Output> {
+    runtime.block_on(async {
+        match deadline.remaining() {
+            Some(left) => tokio::time::timeout(left, fut).await.ok(),
+            None => Some(fut.await),
+        }
+    })
+}
+
+/// Answer a request whose payment flow ran past `x402_total_deadline_secs`.
+fn send_deadline_exceeded(
+    r: &mut Request,
+    requirements: &[PaymentRequirements],
+    config: &ParsedX402Config,
+    resource: &str,
+    mime_type: &str,
+    stage: &str,
+) -> Result<HandlerResult> {
+    log_warn(
+        Some(r),
+        &format!("x402_total_deadline_secs exceeded during {stage}"),
+    );
+    let metrics = X402Metrics::get();
+    metrics.record_verification_failed();
+    metrics.record_402_response();
+    set_payment_status(r, status::REJECTED);
+    send_402_response(
+        r,
+        requirements,
+        config,
+        resource,
+        mime_type,
+        Some(user_errors::TIMEOUT),
+    )?;
+    Ok(HandlerResult::ResponseSent)
+}
+
+/// `x402_dry_run` lets a request through, unverified, when it carries any
+/// `Payment-Signature`; without one the normal 402 is sent. With a `file://`
+/// facilitator nothing real is contacted anyway, so the payment goes through
+/// the full flow against the file instead.
+pub(crate) fn dry_run_bypass(config: &ParsedX402Config, payment_header: Option<&str>) -> bool {
+    config.dry_run
+        && payment_header.is_some()
+        && config
+            .facilitator_url
+            .as_deref()
+            .is_none_or(|url| file_facilitator_path(url).is_none())
+}
+
+/// A computed price rounded up to the asset's decimals, so sub-unit precision
+/// never undercharges.
+fn round_up_to_asset_decimals(amount: Decimal, config: &ParsedX402Config) -> Decimal {
+    let decimals = config.asset_decimals.unwrap_or(DEFAULT_ASSET_DECIMALS);
+    amount
+        .round_dp_with_strategy(decimals.into(), RoundingStrategy::AwayFromZero)
+        .normalize()
+}
+
+/// `x402_amount_bps` of the content price read from `x402_amount_bps_source`.
+/// A missing, zero or invalid price is an error: nothing can be charged for it.
+pub(crate) fn bps_amount(
+    content_price: Option<&str>,
+    bps: u32,
+) -> std::result::Result<Decimal, String> {
+    let content_price = content_price.ok_or("content price is not set")?;
+    let price = crate::config::validation::parse_amount(content_price)
+        .and_then(|price| crate::config::validation::validate_amount(price).map(|()| price))
+        .map_err(|e| format!("invalid content price: {e}"))?;
+    if price.is_zero() {
+        return Err("content price is zero".into());
+    }
+    price
+        .checked_mul(Decimal::from(bps))
+        .map(|scaled| scaled / Decimal::from(10_000))
+        .ok_or_else(|| "content price is too large".into())
+}
+
+/// Outcome of the replay check once `x402_redis_error_policy` is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReplayCheck {
+    /// Not seen before, or Redis failed under `allow`.
+    Fresh,
+    Replayed,
+    /// Redis failed under `deny`: answer with a 402.
+    Denied,
+    /// Redis failed under `error`: fail the request.
+    Failed,
+}
+
+pub(crate) fn replay_check_outcome(used: &Result<bool>, policy: RedisErrorPolicy) -> ReplayCheck {
+    match (used, policy) {
+        (Ok(true), _) => ReplayCheck::Replayed,
+        (Ok(false), _) | (Err(_), RedisErrorPolicy::Allow) => ReplayCheck::Fresh,
+        (Err(_), RedisErrorPolicy::Deny) => ReplayCheck::Denied,
+        (Err(_), RedisErrorPolicy::Error) => ReplayCheck::Failed,
+    }
+}
+
+/// What `x402_facilitator_fallback` does with a request whose verification
+/// failed to reach the facilitator.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FacilitatorDown {
+    /// Answer 500 (`error`, or `local_cache` without a usable entry).
+    Error,
+    /// Let the request through unpaid (`pass`).
+    Pass,
+    /// Answer 402 (`reject`).
+    Reject,
+    /// `local_cache`: this worker verified the same signature earlier. The
+    /// request is served without settling, as the facilitator is down, and
+    /// the signature is marked used so it is served this way only once.
+    Cached(Vec<VerifyResponseBody>),
+}
+
+pub(crate) fn facilitator_down_outcome(
+    fallback: FacilitatorFallback,
+    signature: &str,
+) -> FacilitatorDown {
+    match fallback {
+        FacilitatorFallback::Error => FacilitatorDown::Error,
+        FacilitatorFallback::Pass => FacilitatorDown::Pass,
+        FacilitatorFallback::Reject => FacilitatorDown::Reject,
+        FacilitatorFallback::LocalCache => take_cached_verification(signature)
+            .map_or(FacilitatorDown::Error, FacilitatorDown::Cached),
+    }
+}
+
+/// Note the network and total amount of `reqs` on the request context, for
+/// `x402_access_log`.
+fn record_payment_terms(r: &mut Request, reqs: &[&PaymentRequirements]) {
+    let [_, (_, network), (_, amount)] = payment_headers(None, reqs);
+    if let Some(ctx) = X402RequestContext::get_from_request(r) {
+        ctx.network = (!network.is_empty()).then_some(network);
+        ctx.amount = (!reqs.is_empty()).then_some(amount);
+    }
+}
+
+/// Whether a settle response names another payer than verification did.
+/// Addresses compare case-insensitively, as checksum casing may differ.
+pub(crate) fn is_payer_mismatch(verified: Option<&str>, settled: &str) -> bool {
+    verified.is_some_and(|verified| !verified.eq_ignore_ascii_case(settled))
+}
+
+/// Headers added by `x402_expose_payer_header` once `paid` (the requirements the
+/// payments matched) have settled. The amount is the total in smallest units.
+pub(crate) fn payment_headers(
+    payer: Option<&str>,
+    paid: &[&PaymentRequirements],
+) -> [(&'static str, String); 3] {
+    let network = paid
+        .first()
+        .map(|req| req.network.to_string())
+        .unwrap_or_default();
+    let amount: u128 = paid
+        .iter()
+        .filter_map(|req| req.amount.parse::<u128>().ok())
+        .sum();
+    [
+        ("X-Payment-Payer", payer.unwrap_or("unknown").to_string()),
+        ("X-Payment-Network", network),
+        ("X-Payment-Amount", amount.to_string()),
+    ]
+}
+
+/// Every request header [`upstream_payment_headers`] can set. Under
+/// `x402_upstream_payment_pass` they are stripped from every request first,
+/// so the upstream only ever sees the module's own values.
+pub(crate) const UPSTREAM_PAYMENT_HEADERS: &[&str] = &[
+    "X-Payment-Verified",
+    "X-Payment-Payer",
+    "X-Payment-Amount",
+    "X-Payment-Network",
+    "X-Payment-Tx-Hash",
+    "X-Payment-Asset",
+    "X-Payment-Timestamp",
+    "X-Payment-Hmac",
+];
+
+/// Request headers set by `x402_upstream_payment_pass` once `paid` has
+/// settled for the request `method uri` at `timestamp` (Unix seconds). With a
+/// secret, `X-Payment-Hmac` is the hex HMAC-SHA256 of `method uri` followed by
+/// the other headers as `Name: value` lines, all joined by `\n` in this
+/// order, so a signed block is only valid for the request it was made for.
+pub(crate) fn upstream_payment_headers(
+    payer: Option<&str>,
+    paid: &[&PaymentRequirements],
+    tx_hashes: &[String],
+    request: (&str, &str),
+    timestamp: u64,
+    hmac_secret: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let [payer, network, amount] = payment_headers(payer, paid);
+    let asset = paid
+        .first()
+        .map(|req| req.asset.to_string())
+        .unwrap_or_default();
+    let mut headers = vec![
+        ("X-Payment-Verified", "true".to_string()),
+        payer,
+        amount,
+        network,
+        ("X-Payment-Tx-Hash", tx_hashes.join(",")),
+        ("X-Payment-Asset", asset),
+        ("X-Payment-Timestamp", timestamp.to_string()),
+    ];
+    if let Some(secret) = hmac_secret {
+        let (method, uri) = request;
+        let signed = std::iter::once(format!("{method} {uri}"))
+            .chain(
+                headers
+                    .iter()
+                    .map(|(name, value)| format!("{name}: {value}")),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(signed.as_bytes());
+        headers.push(("X-Payment-Hmac", hex::encode(mac.finalize().into_bytes())));
+    }
+    headers
+}
+
+fn inject_payment_headers(
+    r: &mut Request,
+    payer: Option<&str>,
+    paid: &[&PaymentRequirements],
+) -> Result<()> {
+    for (name, value) in payment_headers(payer, paid) {
+        r.add_header_out(name, &value)
+            .ok_or_else(|| X402Error::Runtime(format!("Failed to set {name} header")))?;
+    }
+    Ok(())
+}
+
+/// Outcome of the `x402_metrics_allowed_ips` and `x402_metrics_auth_token`
+/// checks for a scrape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetricsAccess {
+    Allowed,
+    /// Client IP not in `x402_metrics_allowed_ips`.
+    Forbidden,
+    /// Missing or wrong `Authorization: Bearer` token.
+    Unauthorized,
+}
+
+pub(crate) fn metrics_access(
+    config: &ParsedX402Config,
+    client_ip: Option<&str>,
+    authorization: Option<&str>,
+) -> MetricsAccess {
+    if !config.metrics_allowed_ips.is_empty() {
+        let allowed = client_ip
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .is_some_and(|ip| config.metrics_allowed_ips.contains(&ip));
+        if !allowed {
+            return MetricsAccess::Forbidden;
+        }
+    }
+    if let Some(ref token) = config.metrics_auth_token {
+        let presented = authorization
+            .and_then(|value| value.trim().split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, presented)| presented.trim());
+        if !presented.is_some_and(|p| constant_time_eq(p.as_bytes(), token.as_bytes())) {
+            return MetricsAccess::Unauthorized;
+        }
+    }
+    MetricsAccess::Allowed
+}
+
+/// Compares without an early exit, so timing does not leak the token prefix.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Answers a GET on an `x402_metrics on` location with the metrics exposition.
+pub fn serve_metrics(r: &mut Request, config: &ParsedX402Config) -> Result<HandlerResult> {
+    let denied = if r.method() != Method::GET {
+        r.add_header_out("Allow", "GET")
+            .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
+        Some((405, "Method not allowed"))
+    } else {
+        let ip = client_ip(r, config.trust_forwarded_headers);
+        let authorization = get_header_value(r, "Authorization");
+        match metrics_access(config, ip.as_deref(), authorization.as_deref()) {
+            MetricsAccess::Allowed => None,
+            MetricsAccess::Forbidden => Some((403, "Forbidden")),
+            MetricsAccess::Unauthorized => {
+                r.add_header_out("WWW-Authenticate", "Bearer")
+                    .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
+                Some((401, "Unauthorized"))
+            }
+        }
+    };
+
+    let (status, content_type, body) = match denied {
+        Some((status, error)) => {
+            log_debug(Some(r), &format!("Metrics request rejected: {error}"));
+            (
+                status,
+                "application/json; charset=utf-8",
+                serde_json::json!({ "error": error }).to_string(),
+            )
+        }
+        None => {
+            let accept = get_header_value(r, "Accept");
+            let (content_type, body) = render_metrics(config.metrics_format, accept.as_deref())?;
+            (200, content_type, body)
+        }
+    };
+    r.set_status(HTTPStatus(status));
+    r.add_header_out("Content-Type", content_type)
+        .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
+    send_chunked_response_body(r, body.as_bytes(), config.response_buffer_size)?;
+    Ok(HandlerResult::ResponseSent)
+}
+
 pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<HandlerResult> {
     let metrics = X402Metrics::get();
     metrics.record_request();
@@ -26,18 +435,184 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
         return Ok(HandlerResult::PaymentValid);
     }
 
-    // Initialize Redis if configured and not yet initialized
-    if let Some(ref redis_url) = config.redis_url {
+    // Trusted clients (health checks, internal services) never pay.
+    if !config.whitelist_ips.is_empty() {
+        if let Some(ip) = client_ip(r, config.trust_forwarded_headers)
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+            .filter(|ip| is_whitelisted(ip, &config.whitelist_ips))
+        {
+            log_info(
+                Some(r),
+                &format!("Payment bypassed for whitelisted client {ip}"),
+            );
+            metrics.record_whitelist_bypass();
+            set_payment_status(r, status::WHITELISTED);
+            return Ok(HandlerResult::PaymentValid);
+        }
+    }
+    // The client picks its own `User-Agent`, so this only suits locations
+    // with nothing to sell (see the README); `x402_whitelist_ip` is the safe
+    // way to let probes through.
+    if !config.skip_user_agents.is_empty() {
+        if let Some(user_agent) = get_header_value(r, "User-Agent")
+            .filter(|ua| is_skipped_user_agent(ua, &config.skip_user_agents))
+        {
+            log_debug(
+                Some(r),
+                &format!("Payment bypassed for user agent {user_agent}"),
+            );
+            metrics.record_skip_user_agent();
+            set_payment_status(r, status::WHITELISTED);
+            return Ok(HandlerResult::PaymentValid);
+        }
+    }
+    // Public reads on an endpoint whose writes are paid: the access phase
+    // passes, unlike skipped methods, which this module declines.
+    if !config.passthrough_methods.is_empty() {
+        let method = get_effective_method(
+            r,
+            config.method_override_header.as_deref(),
+            &config.method_override_methods,
+        );
+        if is_passthrough_method(&r.method(), &method, &config.passthrough_methods) {
+            log_debug(
+                Some(r),
+                &format!("Payment not required for {method} (x402_passthrough_on_methods)"),
+            );
+            metrics.record_passthrough(method.as_str());
+            set_payment_status(r, status::PASSTHROUGH);
+            return Ok(HandlerResult::PaymentValid);
+        }
+    }
+
+    let deadline = Deadline::new(Instant::now(), config.total_deadline);
+
+    // Outside the payment window nothing is purchasable, so skip Redis and the
+    // facilitator entirely.
+    let window = payment_window(
+        SystemTime::now(),
+        config.payment_window_start,
+        config.payment_window_end,
+    );
+    if window != PaymentWindow::Open {
+        let body = match &window {
+            PaymentWindow::NotYetOpen {
+                retry_after,
+                available_at,
+            } => {
+                r.add_header_out("Retry-After", &retry_after.to_string())
+                    .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
+                serde_json::json!({ "error": "Not yet available", "available_at": available_at })
+            }
+            _ => serde_json::json!({ "error": "No longer available" }),
+        };
+        log_debug(
+            Some(r),
+            &format!("Request outside payment window: {window:?}"),
+        );
+        set_payment_status(r, status::UNAVAILABLE);
+        r.set_status(HTTPStatus(config.outside_window_status.into()));
+        r.add_header_out("Content-Type", "application/json; charset=utf-8")
+            .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
+        send_chunked_response_body(r, body.to_string().as_bytes(), config.response_buffer_size)?;
+        return Ok(HandlerResult::ResponseSent);
+    }
+
+    // Dry run never touches Redis or the facilitator.
+    if config.dry_run {
+        if dry_run_bypass(config, get_header_value(r, "Payment-Signature").as_deref()) {
+            log_warn(Some(r), "[x402 DRY-RUN] payment bypassed");
+            metrics.record_dry_run_bypass();
+            set_payment_status(r, status::BYPASSED);
+            return Ok(HandlerResult::PaymentValid);
+        }
+    } else if let Some(ref redis_url) = config.redis_url {
+        // Initialize Redis if configured and not yet initialized
         if !redis::is_redis_configured() {
-            redis::init_redis(redis_url).ok();
+            if let Err(e) = redis::init_redis(redis_url, config.redis_init_timeout) {
+                log_warn(Some(r), &format!("Redis unavailable: {e}"));
+            }
+        }
+    }
+    let use_redis = !config.dry_run && redis::is_redis_configured();
+
+    // Asking for the terms is not a use of the resource.
+    let payment_info = config.payment_info && is_payment_info_request(r, &config.payment_info_path);
+
+    // Free per-IP quota: serve without payment until the window's quota is used up
+    if let Some(quota) = config
+        .free_quota
+        .filter(|_| !config.dry_run && !payment_info)
+    {
+        if let Some(ip) = client_ip(r, config.trust_forwarded_headers) {
+            let start = Instant::now();
+            let consumed = redis::consume_free_quota(
+                &ip,
+                quota,
+                config.free_quota_window.as_secs(),
+                deadline.remaining(),
+            );
+            metrics.record_redis_duration("quota_check", start.elapsed().as_secs_f64());
+            match consumed {
+                Some(true) => {
+                    log_debug(Some(r), &format!("Free quota request for {ip}"));
+                    metrics.record_quota_used();
+                    set_payment_status(r, status::FREE);
+                    return Ok(HandlerResult::PaymentValid);
+                }
+                Some(false) => metrics.record_quota_exhausted(),
+                None => log_warn(Some(r), "Free quota unavailable: Redis not reachable"),
+            }
         }
     }
 
-    // Resolve effective amount (Redis override or config default)
+    // Resolve effective amount (Redis override, then x402_amount_bps or
+    // x402_amount_expr, then config default)
     let mut effective_config_amount = config.amount;
     let request_path = r.path().to_str().unwrap_or("/").to_string();
-    if redis::is_redis_configured() {
-        if let Some(price_str) = redis::get_dynamic_price(&request_path) {
+    if let Some(ref expr) = config.amount_expr {
+        let content_length =
+            get_header_value(r, "Content-Length").and_then(|v| v.trim().parse::<u64>().ok());
+        let ctx = EvalContext::new(content_length, &request_path);
+        let amount = expr.amount(&ctx).map_err(|e| {
+            log_error(
+                Some(r),
+                &format!("Failed to evaluate x402_amount_expr: {e}"),
+            );
+            e
+        })?;
+        effective_config_amount = Some(round_up_to_asset_decimals(amount, config));
+    }
+    if let (Some(bps), Some(source)) = (config.amount_bps, config.amount_bps_source.as_deref()) {
+        let content_price = get_variable_value(r, source);
+        match bps_amount(content_price.as_deref(), bps) {
+            Ok(amount) => {
+                effective_config_amount = Some(round_up_to_asset_decimals(amount, config))
+            }
+            Err(e) => {
+                // Without a price there are no terms to offer, but the client
+                // still gets a 402 rather than a server error.
+                log_warn(Some(r), &format!("x402_amount_bps: ${source}: {e}"));
+                metrics.record_402_response();
+                set_payment_status(r, status::UNAVAILABLE);
+                r.set_status(HTTPStatus(402));
+                r.add_header_out("Content-Type", "application/json; charset=utf-8")
+                    .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
+                let body = serde_json::json!({ "error": "Price unavailable" }).to_string();
+                send_chunked_response_body(r, body.as_bytes(), config.response_buffer_size)?;
+                return Ok(HandlerResult::ResponseSent);
+            }
+        }
+    }
+    if use_redis {
+        let start = Instant::now();
+        let dynamic_price = if config.dynamic_price_prefix_match {
+            redis::get_dynamic_price_with_fallback(&request_path, deadline.remaining())
+        } else {
+            redis::get_dynamic_price(&request_path, deadline.remaining())
+        };
+        metrics.record_redis_duration("price_lookup", start.elapsed().as_secs_f64());
+        if let Some(price_str) = dynamic_price {
             if let Ok(price) = crate::config::validation::parse_amount(&price_str) {
                 log_debug(
                     Some(r),
@@ -48,33 +623,31 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
         }
     }
 
-    let working_config = ParsedX402Config {
+    let mut working_config = ParsedX402Config {
         amount: effective_config_amount,
-        enabled: config.enabled,
-        pay_to: config.pay_to.clone(),
-        facilitator_url: config.facilitator_url.clone(),
-        description: config.description.clone(),
-        network: config.network.clone(),
-        network_id: config.network_id,
-        resource: config.resource.clone(),
-        asset: config.asset.clone(),
-        asset_decimals: config.asset_decimals,
-        timeout: config.timeout,
-        facilitator_fallback: config.facilitator_fallback,
-        ttl: config.ttl,
-        redis_url: config.redis_url.clone(),
-        replay_ttl: config.replay_ttl,
+        ..config.clone()
     };
 
-    let full_url = build_full_url(r);
+    let resource_path = strip_resource_path(
+        &request_path,
+        working_config.resource_prefix.as_deref(),
+        working_config.resource_suffix.as_deref(),
+    );
+    let full_url = build_full_url(r, resource_path, working_config.resource_include_query);
     let resource = if let Some(ref configured) = working_config.resource {
         configured.clone()
     } else if let Some(ref url) = full_url {
         url.clone()
+    } else if resource_path.is_empty() {
+        "/".to_string()
     } else {
-        r.path().to_str().unwrap_or("/").to_string()
+        resource_path.to_string()
     };
 
+    if working_config.description.is_none() && working_config.description_auto {
+        working_config.description = Some(generate_auto_description(&resource));
+    }
+
     let mime_type = infer_mime_type(r);
 
     log_debug(
@@ -82,14 +655,28 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
         &format!("x402 handler processing: resource={resource}, mime={mime_type}"),
     );
 
-    let requirements = create_requirements(&working_config, &resource).map_err(|e| {
+    let requirements = create_requirements_list(&working_config, &resource).map_err(|e| {
         log_error(Some(r), &format!("Failed to create requirements: {e}"));
         e
     })?;
-    let requirements_slice = std::slice::from_ref(&requirements);
+    let requirements_slice = requirements.as_slice();
+    if let Some(ctx) = X402RequestContext::get_from_request(r) {
+        ctx.resource = Some(resource.clone());
+    }
+    record_payment_terms(r, &requirements.iter().take(1).collect::<Vec<_>>());
+
+    if payment_info {
+        log_debug(Some(r), "Serving payment terms (x402_payment_info)");
+        set_payment_status(r, status::INFO);
+        send_payment_info_response(r, requirements_slice, &working_config)?;
+        return Ok(HandlerResult::ResponseSent);
+    }
 
-    if let Some(amount_f64) = working_config.amount.and_then(|a| a.to_f64()) {
-        metrics.record_payment_amount(amount_f64);
+    if let Some(units) = requirements
+        .first()
+        .and_then(|req| req.amount.parse::<u128>().ok())
+    {
+        metrics.record_payment_amount_units(units);
     }
 
     // Check for PAYMENT-SIGNATURE header (x402 v2)
@@ -99,10 +686,30 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
         log_debug(Some(r), "Payment header found, verifying...");
         metrics.record_verification_attempt();
 
-        if payment_b64.len() > crate::ngx_module::runtime::MAX_PAYMENT_HEADER_SIZE {
-            log_warn(Some(r), "Payment header too large");
+        if deadline.expired() {
+            return send_deadline_exceeded(
+                r,
+                requirements_slice,
+                &working_config,
+                &resource,
+                &mime_type,
+                "Redis lookups",
+            );
+        }
+
+        if exceeds_max_payment_size(&payment_b64, working_config.max_payment_size) {
+            log_warn(
+                Some(r),
+                &format!(
+                    "Payment header too large: {} bytes (max {})",
+                    payment_b64.len(),
+                    working_config.max_payment_size
+                ),
+            );
+            metrics.record_payment_size_rejected();
             metrics.record_verification_failed();
             metrics.record_402_response();
+            set_payment_status(r, status::REJECTED);
             send_402_response(
                 r,
                 requirements_slice,
@@ -114,111 +721,424 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
             return Ok(HandlerResult::ResponseSent);
         }
 
+        // In all-of-N mode the header carries one comma-separated payment per address
+        let payments: Vec<&str> = if working_config.pay_to_any_requires_all {
+            payment_b64.split(',').map(str::trim).collect()
+        } else {
+            vec![payment_b64.as_str()]
+        };
+
         // Replay prevention
-        if redis::is_redis_configured() && redis::is_payment_used(&payment_b64) {
-            log_warn(Some(r), "Payment replay detected");
+        let replay_ttl = working_config
+            .replay_ttl
+            .unwrap_or(DEFAULT_REPLAY_TTL_SECONDS);
+        metrics.record_replay_ttl(replay_ttl);
+        let replay_check = if redis::is_redis_configured() {
+            let start = Instant::now();
+            let mut used = Ok(false);
+            for p in &payments {
+                used = redis::is_payment_used(
+                    p,
+                    working_config.redis_storage_mode,
+                    replay_ttl,
+                    deadline.remaining(),
+                );
+                if !matches!(used, Ok(false)) {
+                    break;
+                }
+            }
+            metrics.record_redis_duration("replay_check", start.elapsed().as_secs_f64());
+            if let Err(e) = &used {
+                metrics.record_redis_error("replay_check");
+                log_warn(Some(r), &format!("Replay check failed: {e}"));
+            }
+            replay_check_outcome(&used, working_config.redis_error_policy)
+        } else {
+            ReplayCheck::Fresh
+        };
+        match replay_check {
+            ReplayCheck::Fresh => {}
+            ReplayCheck::Replayed | ReplayCheck::Denied => {
+                let reason = if replay_check == ReplayCheck::Replayed {
+                    log_warn(Some(r), "Payment replay detected");
+                    user_errors::REPLAY_DETECTED
+                } else {
+                    log_warn(Some(r), "Payment refused: x402_redis_error_policy deny");
+                    user_errors::REPLAY_CHECK_UNAVAILABLE
+                };
+                metrics.record_verification_failed();
+                metrics.record_402_response();
+                set_payment_status(r, status::REJECTED);
+                send_402_response(
+                    r,
+                    requirements_slice,
+                    &working_config,
+                    &resource,
+                    &mime_type,
+                    Some(reason),
+                )?;
+                return Ok(HandlerResult::ResponseSent);
+            }
+            ReplayCheck::Failed => {
+                set_payment_status(r, status::ERROR);
+                return Err(X402Error::Runtime(
+                    "Replay check failed (x402_redis_error_policy error)".into(),
+                ));
+            }
+        }
+
+        if deadline.expired() {
+            return send_deadline_exceeded(
+                r,
+                requirements_slice,
+                &working_config,
+                &resource,
+                &mime_type,
+                "the replay check",
+            );
+        }
+
+        let decoded: Vec<Option<serde_json::Value>> =
+            payments.iter().map(|p| decode_payment_payload(p)).collect();
+        let payees: Vec<Option<String>> = decoded
+            .iter()
+            .map(|p| p.as_ref().and_then(payment_pay_to))
+            .collect();
+        let Some(matched) = match_payments(
+            &payees,
+            requirements_slice,
+            working_config.pay_to_any_requires_all,
+        ) else {
+            log_warn(
+                Some(r),
+                &format!("Payment recipients {payees:?} do not match x402_pay_to_any"),
+            );
             metrics.record_verification_failed();
             metrics.record_402_response();
+            set_payment_status(r, status::REJECTED);
             send_402_response(
                 r,
                 requirements_slice,
                 &working_config,
                 &resource,
                 &mime_type,
-                Some(user_errors::REPLAY_DETECTED),
+                Some(user_errors::INVALID_PAYMENT),
             )?;
             return Ok(HandlerResult::ResponseSent);
+        };
+
+        // Each payment must echo a nonce recently issued for its requirement.
+        if working_config.nonce {
+            let now_ms = now_millis();
+            let fresh = decoded.iter().zip(&matched).all(|(payload, &i)| {
+                payload
+                    .as_ref()
+                    .and_then(payment_nonce)
+                    .is_some_and(|nonce| {
+                        nonce_is_current(
+                            nonce,
+                            &requirements[i],
+                            now_ms,
+                            working_config.nonce_ttl_ms,
+                        )
+                    })
+            });
+            if !fresh {
+                log_warn(Some(r), "Payment nonce missing or expired");
+                metrics.record_verification_failed();
+                metrics.record_402_response();
+                set_payment_status(r, status::REJECTED);
+                send_402_response(
+                    r,
+                    requirements_slice,
+                    &working_config,
+                    &resource,
+                    &mime_type,
+                    Some(user_errors::NONCE_EXPIRED),
+                )?;
+                return Ok(HandlerResult::ResponseSent);
+            }
+        }
+
+        // Claimed by the payload until the facilitator confirms it.
+        let claimed_payer = decoded
+            .iter()
+            .flatten()
+            .find_map(extract_payer_from_payload);
+        if let Some(ref payer) = claimed_payer {
+            log_info(Some(r), &format!("Payment from payer={payer}"));
+            if let Some(ctx) = X402RequestContext::get_from_request(r) {
+                ctx.payer = Some(payer.clone());
+            }
         }
 
         let facilitator_url = working_config.facilitator_url.as_deref().ok_or_else(|| {
             log_error(Some(r), "Facilitator URL not configured");
-            ConfigError::new("Facilitator URL not configured")
+            X402Error::Config("Facilitator URL not configured".into())
         })?;
 
-        let requirements_json = serde_json::to_value(&requirements)
-            .map_err(|e| ConfigError::new(format!("Failed to serialize requirements: {e}")))?;
+        let paid: Vec<&PaymentRequirements> = matched.iter().map(|&i| &requirements[i]).collect();
+        record_payment_terms(r, &paid);
+        let selected = payments
+            .iter()
+            .zip(matched)
+            .map(|(payment, i)| {
+                serde_json::to_value(&requirements[i])
+                    .map(|json| (*payment, json))
+                    .map_err(|e| {
+                        X402Error::Runtime(format!("Failed to serialize requirements: {e}"))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        let timeout = working_config.timeout;
+        let verify_timeout = working_config.verify_timeout;
+        let settle_timeout = working_config.settle_timeout;
+        let headers = &working_config.facilitator_headers;
         let runtime = get_runtime()?;
         let verification_start = Instant::now();
-        let verification_result = runtime.block_on(async {
-            verify_payment(&payment_b64, &requirements_json, facilitator_url, timeout).await
+        let verification_result = block_on_within(runtime, deadline, async {
+            let mut responses = Vec::with_capacity(selected.len());
+            for (payment, requirements_json) in &selected {
+                let start = Instant::now();
+                let response = verify_payment(
+                    payment,
+                    requirements_json,
+                    facilitator_url,
+                    verify_timeout,
+                    headers,
+                    working_config.facilitator_file_delay,
+                    working_config.facilitator_response_max_size,
+                )
+                .await;
+                metrics.record_facilitator_duration("verify", start.elapsed().as_secs_f64());
+                responses.push(response?);
+            }
+            Ok::<_, X402Error>(responses)
         });
         let duration = verification_start.elapsed().as_secs_f64();
         metrics.record_verification_duration(duration);
+        let Some(verification_result) = verification_result else {
+            return send_deadline_exceeded(
+                r,
+                requirements_slice,
+                &working_config,
+                &resource,
+                &mime_type,
+                "verify",
+            );
+        };
 
-        let response = match verification_result {
-            Ok(resp) => {
+        // Set when the facilitator is down and `local_cache` vouches for the
+        // payment instead; there is then nothing to settle with.
+        let mut from_local_cache = false;
+        let responses = match verification_result {
+            Ok(responses) => {
                 log_debug(
                     Some(r),
                     &format!(
-                        "Verify result: is_valid={}, duration={duration:.3}s",
-                        resp.is_valid
+                        "Verify result: is_valid={:?}, duration={duration:.3}s",
+                        responses
+                            .iter()
+                            .map(|resp| resp.is_valid)
+                            .collect::<Vec<_>>()
                     ),
                 );
-                resp
+                if working_config.facilitator_fallback == FacilitatorFallback::LocalCache
+                    && responses.iter().all(|resp| resp.is_valid)
+                {
+                    let ttl = paid.iter().map(|req| req.max_timeout_seconds).min();
+                    let evicted = cache_verification(
+                        &payment_b64,
+                        responses.clone(),
+                        Duration::from_secs(ttl.unwrap_or(0)),
+                        working_config.local_cache_max_entries,
+                    );
+                    metrics.record_local_cache_evictions(evicted);
+                }
+                responses
             }
             Err(e) => {
                 log_error(Some(r), &format!("Facilitator error: {e}"));
                 metrics.record_facilitator_error();
-                match working_config.facilitator_fallback {
-                    FacilitatorFallback::Error => {
+                match facilitator_down_outcome(working_config.facilitator_fallback, &payment_b64) {
+                    FacilitatorDown::Error => {
+                        set_payment_status(r, status::ERROR);
                         r.set_status(HTTPStatus(500));
                         r.add_header_out("Content-Type", "text/plain; charset=utf-8")
-                            .ok_or_else(|| ConfigError::new("Failed to set header"))?;
+                            .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
                         send_response_body(r, b"Internal server error")?;
                         return Ok(HandlerResult::ResponseSent);
                     }
-                    FacilitatorFallback::Pass => {
+                    FacilitatorDown::Pass => {
                         log_info(Some(r), "Facilitator error, passing through");
+                        set_payment_status(r, status::BYPASSED);
                         return Ok(HandlerResult::PaymentValid);
                     }
+                    FacilitatorDown::Reject => {
+                        set_payment_status(r, status::ERROR);
+                        metrics.record_402_response();
+                        if let Some(secs) = working_config.fallback_reject_retry_after {
+                            r.add_header_out("Retry-After", &secs.to_string())
+                                .ok_or_else(|| X402Error::Runtime("Failed to set header".into()))?;
+                        }
+                        let message = working_config
+                            .fallback_reject_message
+                            .as_deref()
+                            .unwrap_or(user_errors::FACILITATOR_UNAVAILABLE);
+                        send_402_response(
+                            r,
+                            requirements_slice,
+                            &working_config,
+                            &resource,
+                            &mime_type,
+                            Some(message),
+                        )?;
+                        return Ok(HandlerResult::ResponseSent);
+                    }
+                    FacilitatorDown::Cached(cached) => {
+                        log_info(
+                            Some(r),
+                            "Facilitator error, using cached verification without settlement",
+                        );
+                        metrics.record_local_cache_hit();
+                        from_local_cache = true;
+                        cached
+                    }
                 }
             }
         };
 
-        if response.is_valid {
-            log_info(Some(r), "Payment verified successfully");
+        if responses.iter().all(|resp| resp.is_valid) {
+            // Only a payer the facilitator named is forwarded upstream; the
+            // payload's claim is just for logging.
+            let mut confirmed_payer = responses.iter().find_map(|resp| resp.payer.clone());
+            let mut payer = confirmed_payer.clone().or(claimed_payer);
+            log_info(
+                Some(r),
+                &format!(
+                    "Payment verified successfully, payer={}",
+                    payer.as_deref().unwrap_or("unknown")
+                ),
+            );
             metrics.record_verification_success();
 
             // Settle payment on-chain (execute the actual USDC transfer)
-            let settle_result = runtime.block_on(async {
-                settle_payment(&payment_b64, &requirements_json, facilitator_url, timeout).await
-            });
+            let mut tx_hashes = Vec::with_capacity(selected.len());
+            let to_settle: &[_] = if from_local_cache { &[] } else { &selected };
+            for (payment, requirements_json) in to_settle {
+                let settlement_start = Instant::now();
+                metrics.settle_started();
+                let settle_result = block_on_within(
+                    runtime,
+                    deadline,
+                    settle_payment(
+                        payment,
+                        requirements_json,
+                        facilitator_url,
+                        settle_timeout,
+                        headers,
+                        working_config.facilitator_file_delay,
+                        working_config.facilitator_response_max_size,
+                    ),
+                );
+                metrics.settle_finished();
+                let settlement_duration = settlement_start.elapsed().as_secs_f64();
+                metrics.record_settlement_duration(settlement_duration);
+                if settle_result.is_some() {
+                    metrics.record_facilitator_duration("settle", settlement_duration);
+                    metrics.record_settlement_lag(verification_start.elapsed().as_secs_f64());
+                }
+                let Some(settle_result) = settle_result else {
+                    metrics.record_settle_failure();
+                    return send_deadline_exceeded(
+                        r,
+                        requirements_slice,
+                        &working_config,
+                        &resource,
+                        &mime_type,
+                        "settle",
+                    );
+                };
 
-            match settle_result {
-                Ok(settle) => {
-                    if !settle.success {
-                        let err_info = [
-                            settle.error_reason.as_deref().unwrap_or(""),
-                            settle.error_message.as_deref().unwrap_or(""),
-                        ]
-                        .iter()
-                        .filter(|s| !s.is_empty())
-                        .cloned()
-                        .collect::<Vec<_>>()
-                        .join("; ");
-                        log_error(
+                match settle_result {
+                    Ok(settle) => {
+                        if !settle.success {
+                            metrics.record_settle_failure();
+                            let err_info = [
+                                settle.error_reason.as_deref().unwrap_or(""),
+                                settle.error_message.as_deref().unwrap_or(""),
+                            ]
+                            .iter()
+                            .filter(|s| !s.is_empty())
+                            .cloned()
+                            .collect::<Vec<_>>()
+                            .join("; ");
+                            log_error(
+                                Some(r),
+                                &format!(
+                                    "Payment settle failed: success=false txHash={:?} {}",
+                                    settle.tx_hash.as_deref().unwrap_or("none"),
+                                    if err_info.is_empty() {
+                                        "".to_string()
+                                    } else {
+                                        format!("errorReason/Message: {err_info}")
+                                    }
+                                ),
+                            );
+                            metrics.record_verification_failed();
+                            set_payment_status(r, status::REJECTED);
+                            let err_msg = if err_info.is_empty() {
+                                user_errors::PAYMENT_VERIFICATION_FAILED.to_string()
+                            } else {
+                                format!(
+                                    "{} (Facilitator: {err_info})",
+                                    user_errors::PAYMENT_VERIFICATION_FAILED
+                                )
+                            };
+                            send_402_response(
+                                r,
+                                requirements_slice,
+                                &working_config,
+                                &resource,
+                                &mime_type,
+                                Some(&err_msg),
+                            )?;
+                            return Ok(HandlerResult::ResponseSent);
+                        }
+                        log_info(
                             Some(r),
                             &format!(
-                                "Payment settle failed: success=false txHash={:?} {}",
-                                settle.tx_hash.as_deref().unwrap_or("none"),
-                                if err_info.is_empty() {
-                                    "".to_string()
-                                } else {
-                                    format!("errorReason/Message: {err_info}")
-                                }
+                                "Payment settled on-chain, txHash={:?}",
+                                settle.tx_hash.as_deref().unwrap_or("none")
                             ),
                         );
+                        metrics.record_settle_success();
+                        tx_hashes.extend(settle.tx_hash);
+                        // The settle response is authoritative for the payer.
+                        if let Some(settled_payer) = settle.payer {
+                            if is_payer_mismatch(payer.as_deref(), &settled_payer) {
+                                log_warn(
+                                    Some(r),
+                                    &format!(
+                                        "Payer discrepancy: verified {} but settled {settled_payer}",
+                                        payer.as_deref().unwrap_or("unknown")
+                                    ),
+                                );
+                                metrics.record_settle_payer_mismatch();
+                            }
+                            payer = Some(settled_payer.clone());
+                            confirmed_payer = Some(settled_payer);
+                        }
+                    }
+                    Err(e) => {
+                        metrics.record_settle_failure();
+                        log_error(Some(r), &format!("Payment settlement failed: {e}"));
                         metrics.record_verification_failed();
-                        let err_msg = if err_info.is_empty() {
-                            user_errors::PAYMENT_VERIFICATION_FAILED.to_string()
-                        } else {
-                            format!(
-                                "{} (Facilitator: {err_info})",
-                                user_errors::PAYMENT_VERIFICATION_FAILED
-                            )
-                        };
+                        set_payment_status(r, status::REJECTED);
+                        let err_msg = format!(
+                            "{} (Facilitator error: {e})",
+                            user_errors::PAYMENT_VERIFICATION_FAILED
+                        );
                         send_402_response(
                             r,
                             requirements_slice,
@@ -229,37 +1149,87 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
                         )?;
                         return Ok(HandlerResult::ResponseSent);
                     }
-                    log_info(
-                        Some(r),
-                        &format!(
-                            "Payment settled on-chain, txHash={:?}",
-                            settle.tx_hash.as_deref().unwrap_or("none")
-                        ),
-                    );
                 }
-                Err(e) => {
-                    log_error(Some(r), &format!("Payment settlement failed: {e}"));
-                    metrics.record_verification_failed();
-                    let err_msg = format!(
-                        "{} (Facilitator error: {e})",
-                        user_errors::PAYMENT_VERIFICATION_FAILED
-                    );
-                    send_402_response(
-                        r,
-                        requirements_slice,
-                        &working_config,
-                        &resource,
-                        &mime_type,
-                        Some(&err_msg),
-                    )?;
-                    return Ok(HandlerResult::ResponseSent);
+            }
+            // A settled signature must not be served from `local_cache` later.
+            if working_config.facilitator_fallback == FacilitatorFallback::LocalCache
+                && !from_local_cache
+            {
+                let ttl = paid.iter().map(|req| req.max_timeout_seconds).min();
+                let evicted = mark_verification_used(
+                    &payment_b64,
+                    Duration::from_secs(ttl.unwrap_or(0)),
+                    working_config.local_cache_max_entries,
+                );
+                metrics.record_local_cache_evictions(evicted);
+            }
+            if let Some(ctx) = X402RequestContext::get_from_request(r) {
+                ctx.payment_status = if from_local_cache {
+                    status::CACHED
+                } else {
+                    status::SETTLED
+                };
+                ctx.payer = payer.clone();
+                ctx.tx_hash = (!tx_hashes.is_empty()).then(|| tx_hashes.join(","));
+            }
+            if working_config.expose_payer_header {
+                inject_payment_headers(r, payer.as_deref(), &paid)?;
+            }
+            if let Some(payer) = confirmed_payer
+                .as_deref()
+                .filter(|_| working_config.forward_payer)
+            {
+                set_header_in(r, "X-Payment-Payer", payer).ok_or_else(|| {
+                    X402Error::Runtime("Failed to set X-Payment-Payer request header".into())
+                })?;
+            }
+            if working_config.upstream_payment_pass {
+                let method = r.method();
+                let uri = r.unparsed_uri().to_string_lossy().into_owned();
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                for (name, value) in upstream_payment_headers(
+                    confirmed_payer.as_deref(),
+                    &paid,
+                    &tx_hashes,
+                    (method.as_str(), &uri),
+                    timestamp,
+                    working_config.upstream_payment_hmac_secret.as_deref(),
+                ) {
+                    set_header_in(r, name, &value).ok_or_else(|| {
+                        X402Error::Runtime(format!("Failed to set {name} request header"))
+                    })?;
                 }
             }
 
-            // Store as used for replay prevention
+            // Store as used for replay prevention. The payment is settled by
+            // now, so running out of time here must not fail the request; the
+            // spent authorization nonce still stops it settling twice.
             if redis::is_redis_configured() {
-                let ttl = working_config.replay_ttl.unwrap_or(86400);
-                redis::store_payment_as_used(&payment_b64, ttl).ok();
+                if deadline.expired() {
+                    log_warn(
+                        Some(r),
+                        "x402_total_deadline_secs exceeded, payment not stored as used",
+                    );
+                } else {
+                    for payment in &payments {
+                        let start = Instant::now();
+                        let stored = redis::store_payment_as_used(
+                            payment,
+                            replay_ttl,
+                            working_config.redis_storage_mode,
+                            deadline.remaining(),
+                        );
+                        metrics.record_redis_duration("store_used", start.elapsed().as_secs_f64());
+                        // Whatever `x402_redis_error_policy` says, the
+                        // payment has settled and the request is served.
+                        if let Err(e) = stored {
+                            metrics.record_redis_error("store_used");
+                            log_error(Some(r), &format!("Failed to store payment as used: {e}"));
+                        }
+                    }
+                }
             }
 
             Ok(HandlerResult::PaymentValid)
@@ -267,6 +1237,7 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
             log_warn(Some(r), "Payment verification failed (is_valid=false)");
             metrics.record_verification_failed();
             metrics.record_402_response();
+            set_payment_status(r, status::REJECTED);
             send_402_response(
                 r,
                 requirements_slice,
@@ -280,6 +1251,7 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
     } else {
         log_debug(Some(r), "No payment header found, sending 402");
         metrics.record_402_response();
+        set_payment_status(r, status::REQUIRED);
         send_402_response(
             r,
             requirements_slice,
@@ -291,3 +1263,391 @@ pub fn x402_handler_impl(r: &mut Request, config: &ParsedX402Config) -> Result<H
         Ok(HandlerResult::ResponseSent)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requirement(network: &str, amount: &str) -> PaymentRequirements {
+        PaymentRequirements {
+            scheme: "exact".into(),
+            network: network.parse().unwrap(),
+            amount: amount.into(),
+            pay_to: "0x1234567890abcdef1234567890abcdef12345678".into(),
+            max_timeout_seconds: 60,
+            asset: "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913".into(),
+            extra: None,
+        }
+    }
+
+    #[test]
+    fn test_replay_check_outcome() {
+        let down: Result<bool> = Err(X402Error::Runtime("Redis unavailable".into()));
+        for policy in [
+            RedisErrorPolicy::Allow,
+            RedisErrorPolicy::Deny,
+            RedisErrorPolicy::Error,
+        ] {
+            assert_eq!(replay_check_outcome(&Ok(false), policy), ReplayCheck::Fresh);
+            assert_eq!(
+                replay_check_outcome(&Ok(true), policy),
+                ReplayCheck::Replayed
+            );
+        }
+        assert_eq!(
+            replay_check_outcome(&down, RedisErrorPolicy::Allow),
+            ReplayCheck::Fresh
+        );
+        assert_eq!(
+            replay_check_outcome(&down, RedisErrorPolicy::Deny),
+            ReplayCheck::Denied
+        );
+        assert_eq!(
+            replay_check_outcome(&down, RedisErrorPolicy::Error),
+            ReplayCheck::Failed
+        );
+        let status: ngx::ffi::ngx_int_t = X402Error::Runtime(String::new()).into();
+        assert_eq!(
+            status,
+            ngx::ffi::NGX_HTTP_INTERNAL_SERVER_ERROR as ngx::ffi::ngx_int_t
+        );
+    }
+
+    #[test]
+    fn test_deadline_unset_never_expires() {
+        let deadline = Deadline::new(Instant::now(), None);
+        assert_eq!(deadline.remaining(), None);
+        assert!(!deadline.expired());
+    }
+
+    #[test]
+    fn test_deadline_counts_time_spent_in_redis() {
+        let deadline = Deadline::new(Instant::now(), Some(Duration::from_millis(50)));
+        assert!(!deadline.expired());
+        assert!(deadline.remaining().unwrap() <= Duration::from_millis(50));
+
+        // A slow Redis lookup uses up the budget before the facilitator is called.
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(deadline.expired());
+        assert_eq!(deadline.remaining(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_block_on_within() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        let unbounded = Deadline::new(Instant::now(), None);
+        assert_eq!(block_on_within(&runtime, unbounded, async { 1 }), Some(1));
+
+        let deadline = Deadline::new(Instant::now(), Some(Duration::from_millis(50)));
+        let start = Instant::now();
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            1
+        };
+        assert_eq!(block_on_within(&runtime, deadline, slow), None);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Time already spent on Redis shortens what the facilitator gets.
+        let spent = Deadline::new(
+            Instant::now() - Duration::from_millis(100),
+            Some(Duration::from_millis(100)),
+        );
+        let quick = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            1
+        };
+        assert_eq!(block_on_within(&runtime, spent, quick), None);
+    }
+
+    #[test]
+    fn test_metrics_access() {
+        let mut config = crate::ngx_module::config::X402Config::default_parsed();
+        assert_eq!(metrics_access(&config, None, None), MetricsAccess::Allowed);
+
+        config.metrics_allowed_ips = vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+        assert_eq!(
+            metrics_access(&config, Some("127.0.0.1"), None),
+            MetricsAccess::Allowed
+        );
+        assert_eq!(
+            metrics_access(&config, Some("::1"), None),
+            MetricsAccess::Allowed
+        );
+        assert_eq!(
+            metrics_access(&config, Some("10.0.0.1"), None),
+            MetricsAccess::Forbidden
+        );
+        assert_eq!(
+            metrics_access(&config, None, None),
+            MetricsAccess::Forbidden
+        );
+
+        config.metrics_auth_token = Some("s3cret".into());
+        assert_eq!(
+            metrics_access(&config, Some("127.0.0.1"), Some("Bearer s3cret")),
+            MetricsAccess::Allowed
+        );
+        assert_eq!(
+            metrics_access(&config, Some("127.0.0.1"), Some("bearer  s3cret ")),
+            MetricsAccess::Allowed
+        );
+        assert_eq!(
+            metrics_access(&config, Some("127.0.0.1"), Some("Bearer s3cre")),
+            MetricsAccess::Unauthorized
+        );
+        assert_eq!(
+            metrics_access(&config, Some("127.0.0.1"), Some("Basic s3cret")),
+            MetricsAccess::Unauthorized
+        );
+        assert_eq!(
+            metrics_access(&config, Some("127.0.0.1"), None),
+            MetricsAccess::Unauthorized
+        );
+        // The IP check comes first: a right token from elsewhere is still refused.
+        assert_eq!(
+            metrics_access(&config, Some("10.0.0.1"), Some("Bearer s3cret")),
+            MetricsAccess::Forbidden
+        );
+    }
+
+    #[test]
+    fn test_facilitator_down_outcome() {
+        let verified = vec![VerifyResponseBody {
+            is_valid: true,
+            invalid_reason: None,
+            payer: Some("0x9999999999999999999999999999999999999999".into()),
+        }];
+        let ttl = Duration::from_secs(60);
+
+        assert_eq!(
+            facilitator_down_outcome(FacilitatorFallback::Error, "sig-down-a"),
+            FacilitatorDown::Error
+        );
+        assert_eq!(
+            facilitator_down_outcome(FacilitatorFallback::Pass, "sig-down-a"),
+            FacilitatorDown::Pass
+        );
+        assert_eq!(
+            facilitator_down_outcome(FacilitatorFallback::Reject, "sig-down-a"),
+            FacilitatorDown::Reject
+        );
+
+        // Never verified here: nothing to fall back on.
+        assert_eq!(
+            facilitator_down_outcome(FacilitatorFallback::LocalCache, "sig-down-a"),
+            FacilitatorDown::Error
+        );
+
+        // Verified but not settled: served once, then replays get a 500.
+        cache_verification("sig-down-b", verified.clone(), ttl, 10);
+        assert_eq!(
+            facilitator_down_outcome(FacilitatorFallback::LocalCache, "sig-down-b"),
+            FacilitatorDown::Cached(verified.clone())
+        );
+        assert_eq!(
+            facilitator_down_outcome(FacilitatorFallback::LocalCache, "sig-down-b"),
+            FacilitatorDown::Error
+        );
+
+        // Verified and settled: never served from the cache.
+        cache_verification("sig-down-c", verified, ttl, 10);
+        mark_verification_used("sig-down-c", ttl, 10);
+        assert_eq!(
+            facilitator_down_outcome(FacilitatorFallback::LocalCache, "sig-down-c"),
+            FacilitatorDown::Error
+        );
+    }
+
+    #[test]
+    fn test_dry_run_bypass() {
+        let mut config = crate::ngx_module::config::X402Config::default_parsed();
+        assert!(!dry_run_bypass(&config, Some("anything")));
+
+        config.dry_run = true;
+        assert!(dry_run_bypass(&config, Some("not even base64")));
+        assert!(!dry_run_bypass(&config, None));
+
+        config.facilitator_url = Some("file:///tmp/x402-facilitator.json".into());
+        assert!(!dry_run_bypass(&config, Some("anything")));
+
+        let metrics = X402Metrics::get();
+        let before = metrics.dry_run_bypasses.get();
+        metrics.record_dry_run_bypass();
+        assert_eq!(metrics.dry_run_bypasses.get(), before + 1);
+    }
+
+    #[test]
+    fn test_payment_headers() {
+        let req = requirement("eip155:8453", "1000");
+        let headers = payment_headers(Some("0xabc"), &[&req]);
+        assert_eq!(
+            headers,
+            [
+                ("X-Payment-Payer", "0xabc".to_string()),
+                ("X-Payment-Network", "eip155:8453".to_string()),
+                ("X-Payment-Amount", "1000".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_payer_mismatch() {
+        const PAYER: &str = "0x9999999999999999999999999999999999999999";
+        assert!(!is_payer_mismatch(Some(PAYER), PAYER));
+        assert!(!is_payer_mismatch(
+            Some("0xABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD"),
+            "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd"
+        ));
+        assert!(is_payer_mismatch(
+            Some(PAYER),
+            "0x1111111111111111111111111111111111111111"
+        ));
+        // Nothing to contradict when verification named no payer.
+        assert!(!is_payer_mismatch(None, PAYER));
+    }
+
+    #[test]
+    fn test_payment_headers_unknown_payer_and_total_amount() {
+        let first = requirement("eip155:84532", "1000");
+        let second = requirement("eip155:84532", "2500");
+        let headers = payment_headers(None, &[&first, &second]);
+        assert_eq!(headers[0], ("X-Payment-Payer", "unknown".to_string()));
+        assert_eq!(
+            headers[1],
+            ("X-Payment-Network", "eip155:84532".to_string())
+        );
+        assert_eq!(headers[2], ("X-Payment-Amount", "3500".to_string()));
+    }
+
+    #[test]
+    fn test_upstream_payment_headers() {
+        let req = requirement("eip155:8453", "1000");
+        let tx_hashes = vec!["0xfeed".to_string()];
+        let request = ("GET", "/api/weather?city=nyc");
+        let at = 1_700_000_000;
+        let headers =
+            upstream_payment_headers(Some("0xabc"), &[&req], &tx_hashes, request, at, None);
+        assert_eq!(
+            headers,
+            vec![
+                ("X-Payment-Verified", "true".to_string()),
+                ("X-Payment-Payer", "0xabc".to_string()),
+                ("X-Payment-Amount", "1000".to_string()),
+                ("X-Payment-Network", "eip155:8453".to_string()),
+                ("X-Payment-Tx-Hash", "0xfeed".to_string()),
+                (
+                    "X-Payment-Asset",
+                    "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913".to_string()
+                ),
+                ("X-Payment-Timestamp", "1700000000".to_string()),
+            ]
+        );
+        assert!(headers
+            .iter()
+            .all(|(name, _)| UPSTREAM_PAYMENT_HEADERS.contains(name)));
+
+        let sign = |request, at, secret| {
+            upstream_payment_headers(
+                Some("0xabc"),
+                &[&req],
+                &tx_hashes,
+                request,
+                at,
+                Some(secret),
+            )
+        };
+        let signed = sign(request, at, "backend-secret");
+        assert_eq!(signed[..7], headers[..]);
+        assert_eq!(
+            signed[7],
+            (
+                "X-Payment-Hmac",
+                "6c36a017ac047ef2657dfdab0a3a218efdc6b913a511d93e800444410b88f37b".to_string()
+            )
+        );
+        assert_ne!(sign(request, at, "other-secret")[7], signed[7]);
+        // The signature is bound to the request and the time it was made.
+        assert_ne!(
+            sign(("POST", request.1), at, "backend-secret")[7],
+            signed[7]
+        );
+        assert_ne!(
+            sign(("GET", "/api/other"), at, "backend-secret")[7],
+            signed[7]
+        );
+        assert_ne!(sign(request, at + 1, "backend-secret")[7], signed[7]);
+    }
+
+    #[test]
+    fn test_bps_amount() {
+        let dec = |s: &str| s.parse::<Decimal>().unwrap();
+        assert_eq!(bps_amount(Some("10"), 250).unwrap(), dec("0.25"));
+        assert_eq!(bps_amount(Some("$1.00"), 1).unwrap(), dec("0.0001"));
+        assert_eq!(bps_amount(Some("3"), 10_000).unwrap(), dec("3"));
+        assert!(bps_amount(None, 250).is_err());
+        assert!(bps_amount(Some("free"), 250).is_err());
+        assert!(bps_amount(Some("-10"), 250).is_err());
+        assert!(bps_amount(Some("0"), 250).is_err());
+        assert!(bps_amount(Some("$0.00"), 250).is_err());
+
+        let mut config = crate::ngx_module::config::X402Config::default_parsed();
+        config.asset_decimals = Some(2);
+        assert_eq!(
+            round_up_to_asset_decimals(dec("0.001"), &config),
+            dec("0.01")
+        );
+        assert_eq!(
+            round_up_to_asset_decimals(dec("0.25"), &config),
+            dec("0.25")
+        );
+    }
+
+    #[test]
+    fn test_exceeds_max_payment_size() {
+        let max = 1024;
+        assert!(!exceeds_max_payment_size(&"a".repeat(max - 1), max));
+        assert!(!exceeds_max_payment_size(&"a".repeat(max), max));
+        assert!(exceeds_max_payment_size(&"a".repeat(max + 1), max));
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_payment_window_unset_is_open() {
+        assert_eq!(payment_window(at(1_000), None, None), PaymentWindow::Open);
+    }
+
+    #[test]
+    fn test_payment_window_not_yet_open() {
+        let start = at(1_767_225_600);
+        assert_eq!(
+            payment_window(at(1_767_225_600 - 90), Some(start), None),
+            PaymentWindow::NotYetOpen {
+                retry_after: 90,
+                available_at: "2026-01-01T00:00:00Z".to_string(),
+            }
+        );
+
+        // Partial seconds round up so clients never retry too early.
+        let now = start - std::time::Duration::from_millis(1_500);
+        assert!(matches!(
+            payment_window(now, Some(start), None),
+            PaymentWindow::NotYetOpen { retry_after: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn test_payment_window_open_and_closed() {
+        let (start, end) = (Some(at(1_000)), Some(at(2_000)));
+        assert_eq!(payment_window(at(1_000), start, end), PaymentWindow::Open);
+        assert_eq!(payment_window(at(1_999), start, end), PaymentWindow::Open);
+        assert_eq!(payment_window(at(2_000), start, end), PaymentWindow::Closed);
+        assert_eq!(payment_window(at(5_000), None, end), PaymentWindow::Closed);
+    }
+}
@@ -1,17 +1,64 @@
+use crate::ngx_module::context::X402RequestContext;
 use ngx::http::Request;
+use std::borrow::Cow;
 
-pub fn log_debug(_r: Option<&Request>, msg: &str) {
-    log::debug!("{msg}");
+/// Prefix `msg` with `[reqid=<id>]` when `r` carries an x402 context with
+/// `x402_log_request_id on`, so interleaved log lines can be correlated.
+fn with_request_id<'a>(r: Option<&Request>, msg: &'a str) -> Cow<'a, str> {
+    match r.and_then(X402RequestContext::from_request) {
+        Some(ctx) if ctx.log_request_id => Cow::Owned(format!("[reqid={}] {msg}", ctx.request_id)),
+        _ => Cow::Borrowed(msg),
+    }
 }
 
-pub fn log_info(_r: Option<&Request>, msg: &str) {
-    log::info!("{msg}");
+pub fn log_debug(r: Option<&Request>, msg: &str) {
+    log::debug!("{}", with_request_id(r, msg));
 }
 
-pub fn log_warn(_r: Option<&Request>, msg: &str) {
-    log::warn!("{msg}");
+pub fn log_info(r: Option<&Request>, msg: &str) {
+    log::info!("{}", with_request_id(r, msg));
 }
 
-pub fn log_error(_r: Option<&Request>, msg: &str) {
-    log::error!("{msg}");
+pub fn log_warn(r: Option<&Request>, msg: &str) {
+    log::warn!("{}", with_request_id(r, msg));
+}
+
+pub fn log_error(r: Option<&Request>, msg: &str) {
+    log::error!("{}", with_request_id(r, msg));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngx_module::ngx_http_x402_module;
+    use std::ptr;
+
+    #[test]
+    fn test_with_request_id() {
+        assert_eq!(with_request_id(None, "msg"), "msg");
+
+        // nginx assigns `ctx_index` when it counts the modules at startup.
+        unsafe { (*ptr::addr_of_mut!(ngx_http_x402_module)).ctx_index = 0 };
+        let mut slots = [ptr::null_mut::<std::ffi::c_void>(); 8];
+        let mut raw: ngx::ffi::ngx_http_request_t = unsafe { std::mem::zeroed() };
+        raw.ctx = slots.as_mut_ptr();
+        let r = unsafe { Request::from_ngx_http_request(&mut raw) };
+        assert_eq!(with_request_id(Some(r), "msg"), "msg");
+
+        let ctx = Box::into_raw(Box::new(X402RequestContext::new()));
+        r.set_module_ctx(ctx.cast(), unsafe { &*ptr::addr_of!(ngx_http_x402_module) });
+        assert_eq!(with_request_id(Some(r), "msg"), "msg");
+
+        let id = {
+            let ctx = X402RequestContext::get_from_request(r).unwrap();
+            ctx.log_request_id = true;
+            ctx.request_id.clone()
+        };
+        assert_eq!(
+            with_request_id(Some(r), "Payment verified"),
+            format!("[reqid={id}] Payment verified")
+        );
+
+        drop(unsafe { Box::from_raw(ctx) });
+    }
 }
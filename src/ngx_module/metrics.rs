@@ -1,7 +1,47 @@
-use prometheus::{Histogram, HistogramOpts, IntCounter, Registry, TextEncoder};
-use std::sync::OnceLock;
+use prometheus::{
+    Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ngx_module::error::{Result, X402Error};
 
 static METRICS: OnceLock<X402Metrics> = OnceLock::new();
+/// Unix time at which the metrics were created, reported as `_created` in OpenMetrics.
+static METRICS_CREATED: OnceLock<f64> = OnceLock::new();
+/// Buckets for `x402_payment_amount_units` from `x402_metrics_amount_buckets`.
+static AMOUNT_UNIT_BUCKETS: OnceLock<Vec<f64>> = OnceLock::new();
+
+/// Held while encoding so concurrent scrapes in one worker do not interleave.
+static ENCODE_LOCK: Mutex<()> = Mutex::new(());
+
+/// First line of a Prometheus scrape. Metrics live in each worker process and
+/// are not aggregated, so consecutive scrapes may hit different workers.
+pub const PER_WORKER_WARNING: &str =
+    "# WARNING: per-worker metrics (each nginx worker reports its own counters)\n";
+
+/// 0.0001 to 100 tokens at 6 decimals.
+const DEFAULT_AMOUNT_UNIT_BUCKETS: [f64; 7] = [
+    100.0,
+    1_000.0,
+    10_000.0,
+    100_000.0,
+    1_000_000.0,
+    10_000_000.0,
+    100_000_000.0,
+];
+
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Exposition format selected by `x402_metrics_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Prometheus,
+    OpenMetrics,
+}
 
 pub struct X402Metrics {
     pub requests_total: IntCounter,
@@ -10,75 +50,445 @@ pub struct X402Metrics {
     pub verification_failed: IntCounter,
     pub responses_402: IntCounter,
     pub facilitator_errors: IntCounter,
+    pub payment_size_rejected: IntCounter,
+    pub quota_used: IntCounter,
+    pub quota_exhausted: IntCounter,
+    pub dry_run_bypasses: IntCounter,
+    pub file_facilitator_hits: IntCounter,
+    pub whitelist_bypasses: IntCounter,
+    pub skip_user_agents: IntCounter,
+    pub facilitator_response_oversized: IntCounter,
+    pub local_cache_hits: IntCounter,
+    pub local_cache_evictions: IntCounter,
     pub verification_duration: Histogram,
+    pub settlement_duration: Histogram,
+    /// Time from the start of verification until a settle completes: what
+    /// synchronous settlement adds before the request is let through.
+    pub settlement_lag: Histogram,
+    pub settle_success: IntCounter,
+    /// Settles that failed, were refused by the facilitator or timed out.
+    pub settle_failure: IntCounter,
+    /// Settles whose response named another payer than verification did.
+    pub settle_payer_mismatch: IntCounter,
+    /// Settle calls in flight in this worker.
+    pub settle_pending: IntGauge,
+    /// Facilitator connections used within their keep-alive idle timeout:
+    /// an estimate of the HTTP client pools' size.
+    pub facilitator_connections: IntGauge,
+    /// Facilitator requests sent on an already open connection.
+    pub facilitator_connection_reuse: IntCounter,
+    /// Latency of each Redis call, by `operation`: `replay_check`,
+    /// `price_lookup`, `store_used` or `quota_check`.
+    pub redis_operation_duration: HistogramVec,
+    /// Failed replay checks and stores, by `operation`: `replay_check` or
+    /// `store_used`.
+    pub redis_errors: IntCounterVec,
+    /// Requests let through by `x402_passthrough_on_methods`, by `method`.
+    pub passthrough: IntCounterVec,
+    /// Latency of each facilitator call, by `operation`: `verify` or `settle`.
+    pub facilitator_call_duration: HistogramVec,
     pub payment_amount: Histogram,
+    pub payment_amount_units: Histogram,
+    pub facilitator_preflight_ok: IntGauge,
+    pub replay_ttl_computed: IntGauge,
+    /// Caught panics, labelled by whether a backtrace was captured.
+    pub panics: IntCounterVec,
+    /// Set to 1 for the context of the most recent panic only.
+    pub panic_context_last: GaugeVec,
+    /// 1 once the metrics are registered, 0 after [`X402Metrics::mark_down`].
+    pub up: Gauge,
+    /// Always 1; the labels carry the module, nginx and rustc versions.
+    pub build_info: GaugeVec,
+    pub config_reloads: IntCounter,
+    /// Unix time of the last [`X402Metrics::reset`], 0 if never reset.
+    pub last_metrics_reset: Gauge,
     registry: Registry,
 }
 
+/// Labels of `x402_build_info`: module, nginx and rustc versions.
+fn build_info_labels() -> [&'static str; 3] {
+    let embedded = |v: Option<&'static str>| v.filter(|v| !v.is_empty()).unwrap_or("unknown");
+    [
+        env!("CARGO_PKG_VERSION"),
+        embedded(option_env!("X402_NGINX_VERSION")),
+        embedded(option_env!("X402_RUSTC_VERSION")),
+    ]
+}
+
 impl X402Metrics {
     pub fn get() -> &'static Self {
-        METRICS.get_or_init(|| {
-            let registry = Registry::new();
-
-            let requests_total =
-                IntCounter::new("x402_requests_total", "Total requests processed").unwrap();
-            let verification_attempts =
-                IntCounter::new("x402_payment_verifications_total", "Verification attempts")
-                    .unwrap();
-            let verification_success = IntCounter::new(
-                "x402_payment_verifications_success_total",
-                "Successful verifications",
+        METRICS.get_or_init(Self::new)
+    }
+
+    fn new() -> Self {
+        METRICS_CREATED.get_or_init(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0)
+        });
+        let registry = Registry::new();
+
+        let requests_total =
+            IntCounter::new("x402_requests_total", "Total requests processed").unwrap();
+        let verification_attempts =
+            IntCounter::new("x402_payment_verifications_total", "Verification attempts").unwrap();
+        let verification_success = IntCounter::new(
+            "x402_payment_verifications_success_total",
+            "Successful verifications",
+        )
+        .unwrap();
+        let verification_failed = IntCounter::new(
+            "x402_payment_verifications_failed_total",
+            "Failed verifications",
+        )
+        .unwrap();
+        let responses_402 =
+            IntCounter::new("x402_responses_402_total", "402 responses sent").unwrap();
+        let facilitator_errors =
+            IntCounter::new("x402_facilitator_errors_total", "Facilitator errors").unwrap();
+        let payment_size_rejected = IntCounter::new(
+            "x402_payment_size_rejected_total",
+            "Payment headers rejected for exceeding the size limit",
+        )
+        .unwrap();
+        let quota_used = IntCounter::new(
+            "x402_quota_used_total",
+            "Requests served from the free per-IP quota",
+        )
+        .unwrap();
+        let quota_exhausted = IntCounter::new(
+            "x402_quota_exhausted_total",
+            "Requests that exceeded the free per-IP quota",
+        )
+        .unwrap();
+        let dry_run_bypasses = IntCounter::new(
+            "x402_dry_run_bypasses_total",
+            "Requests let through unverified by x402_dry_run",
+        )
+        .unwrap();
+        let file_facilitator_hits = IntCounter::new(
+            "x402_file_facilitator_hits_total",
+            "Verify and settle calls answered from a file:// facilitator",
+        )
+        .unwrap();
+        let whitelist_bypasses = IntCounter::new(
+            "x402_whitelist_bypass_total",
+            "Requests let through without payment by x402_whitelist_ip",
+        )
+        .unwrap();
+        let skip_user_agents = IntCounter::new(
+            "x402_skip_user_agents_total",
+            "Requests let through without payment by x402_skip_user_agents",
+        )
+        .unwrap();
+        let facilitator_response_oversized = IntCounter::new(
+            "x402_facilitator_response_oversized_total",
+            "Facilitator responses rejected for exceeding x402_facilitator_response_max_size_kb",
+        )
+        .unwrap();
+        let local_cache_hits = IntCounter::new(
+            "x402_local_cache_hits_total",
+            "Payments accepted from the local verification cache while the facilitator failed",
+        )
+        .unwrap();
+        let local_cache_evictions = IntCounter::new(
+            "x402_local_cache_evictions_total",
+            "Local verification cache entries evicted by x402_local_cache_max_entries",
+        )
+        .unwrap();
+        let verification_duration = Histogram::with_opts(
+            HistogramOpts::new("x402_verification_duration_seconds", "Verification latency")
+                .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        )
+        .unwrap();
+        let settlement_duration = Histogram::with_opts(
+            HistogramOpts::new("x402_settlement_duration_seconds", "Settlement latency")
+                .buckets(vec![0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0]),
+        )
+        .unwrap();
+        let settlement_lag = Histogram::with_opts(
+            HistogramOpts::new(
+                "x402_settlement_lag_seconds",
+                "Time from verification start to settlement",
             )
-            .unwrap();
-            let verification_failed = IntCounter::new(
-                "x402_payment_verifications_failed_total",
-                "Failed verifications",
+            .buckets(vec![0.01, 0.05, 0.1, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0]),
+        )
+        .unwrap();
+        let settle_success =
+            IntCounter::new("x402_settle_success_total", "Successful settlements").unwrap();
+        let settle_failure =
+            IntCounter::new("x402_settle_failure_total", "Failed settlements").unwrap();
+        let settle_payer_mismatch = IntCounter::new(
+            "x402_settle_payer_mismatch_total",
+            "Settlements confirming a different payer than verification",
+        )
+        .unwrap();
+        let settle_pending =
+            IntGauge::new("x402_settle_pending", "Settle calls in flight").unwrap();
+        let facilitator_connections = IntGauge::new(
+            "x402_facilitator_connections_total",
+            "Open keep-alive connections to facilitators",
+        )
+        .unwrap();
+        let facilitator_connection_reuse = IntCounter::new(
+            "x402_facilitator_connection_reuse_total",
+            "Facilitator requests sent on a reused connection",
+        )
+        .unwrap();
+        let redis_operation_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "x402_redis_operation_duration_seconds",
+                "Redis call latency",
             )
-            .unwrap();
-            let responses_402 =
-                IntCounter::new("x402_responses_402_total", "402 responses sent").unwrap();
-            let facilitator_errors =
-                IntCounter::new("x402_facilitator_errors_total", "Facilitator errors").unwrap();
-            let verification_duration = Histogram::with_opts(
-                HistogramOpts::new("x402_verification_duration_seconds", "Verification latency")
-                    .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ]),
+            &["operation"],
+        )
+        .unwrap();
+        let facilitator_call_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "x402_facilitator_call_duration_seconds",
+                "Facilitator call latency",
             )
-            .unwrap();
-            let payment_amount = Histogram::with_opts(
-                HistogramOpts::new("x402_payment_amount", "Payment amount")
-                    .buckets(vec![0.0001, 0.001, 0.01, 0.1, 1.0, 10.0, 100.0]),
+            .buckets(vec![
+                0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+            ]),
+            &["operation"],
+        )
+        .unwrap();
+        let payment_amount = Histogram::with_opts(
+            HistogramOpts::new("x402_payment_amount", "Payment amount")
+                .buckets(vec![0.0001, 0.001, 0.01, 0.1, 1.0, 10.0, 100.0]),
+        )
+        .unwrap();
+        let payment_amount_units = Histogram::with_opts(
+            HistogramOpts::new(
+                "x402_payment_amount_units",
+                "Payment amount in the asset's smallest unit",
             )
-            .unwrap();
-
-            registry.register(Box::new(requests_total.clone())).ok();
-            registry
-                .register(Box::new(verification_attempts.clone()))
-                .ok();
-            registry
-                .register(Box::new(verification_success.clone()))
-                .ok();
-            registry
-                .register(Box::new(verification_failed.clone()))
-                .ok();
-            registry.register(Box::new(responses_402.clone())).ok();
-            registry.register(Box::new(facilitator_errors.clone())).ok();
-            registry
-                .register(Box::new(verification_duration.clone()))
-                .ok();
-            registry.register(Box::new(payment_amount.clone())).ok();
-
-            Self {
-                requests_total,
-                verification_attempts,
-                verification_success,
-                verification_failed,
-                responses_402,
-                facilitator_errors,
-                verification_duration,
-                payment_amount,
-                registry,
-            }
-        })
+            .buckets(
+                AMOUNT_UNIT_BUCKETS
+                    .get()
+                    .cloned()
+                    .unwrap_or_else(|| DEFAULT_AMOUNT_UNIT_BUCKETS.to_vec()),
+            ),
+        )
+        .unwrap();
+        let facilitator_preflight_ok = IntGauge::new(
+            "x402_facilitator_last_preflight_ok",
+            "Whether the last startup facilitator preflight succeeded",
+        )
+        .unwrap();
+        let replay_ttl_computed = IntGauge::new(
+            "x402_replay_ttl_computed",
+            "Effective replay TTL in seconds of the last payment checked",
+        )
+        .unwrap();
+
+        let redis_errors = IntCounterVec::new(
+            Opts::new("x402_redis_errors_total", "Failed Redis replay calls"),
+            &["operation"],
+        )
+        .unwrap();
+        let passthrough = IntCounterVec::new(
+            Opts::new(
+                "x402_passthrough_total",
+                "Requests let through without payment by x402_passthrough_on_methods",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let panics = IntCounterVec::new(
+            Opts::new("x402_panics_total", "Panics caught in the module"),
+            &["backtrace"],
+        )
+        .unwrap();
+        let panic_context_last = GaugeVec::new(
+            Opts::new(
+                "x402_panic_context_last",
+                "Context of the most recent caught panic",
+            ),
+            &["context"],
+        )
+        .unwrap();
+        let up = Gauge::new("x402_up", "Whether the x402 module is initialized").unwrap();
+        let build_info = GaugeVec::new(
+            Opts::new("x402_build_info", "Build information of the x402 module"),
+            &["version", "nginx_version", "rust_version"],
+        )
+        .unwrap();
+        build_info.with_label_values(&build_info_labels()).set(1.0);
+        let config_reloads = IntCounter::new(
+            "x402_config_reload_total",
+            "Configurations loaded, counting the initial load and each reload",
+        )
+        .unwrap();
+        let last_metrics_reset = Gauge::new(
+            "x402_last_metrics_reset_timestamp_seconds",
+            "Unix time the counters were last reset, 0 if never",
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests_total.clone())).ok();
+        registry
+            .register(Box::new(verification_attempts.clone()))
+            .ok();
+        registry
+            .register(Box::new(verification_success.clone()))
+            .ok();
+        registry
+            .register(Box::new(verification_failed.clone()))
+            .ok();
+        registry.register(Box::new(responses_402.clone())).ok();
+        registry.register(Box::new(facilitator_errors.clone())).ok();
+        registry
+            .register(Box::new(payment_size_rejected.clone()))
+            .ok();
+        registry.register(Box::new(quota_used.clone())).ok();
+        registry.register(Box::new(quota_exhausted.clone())).ok();
+        registry.register(Box::new(dry_run_bypasses.clone())).ok();
+        registry
+            .register(Box::new(file_facilitator_hits.clone()))
+            .ok();
+        registry.register(Box::new(whitelist_bypasses.clone())).ok();
+        registry.register(Box::new(skip_user_agents.clone())).ok();
+        registry
+            .register(Box::new(facilitator_response_oversized.clone()))
+            .ok();
+        registry.register(Box::new(local_cache_hits.clone())).ok();
+        registry
+            .register(Box::new(local_cache_evictions.clone()))
+            .ok();
+        registry
+            .register(Box::new(verification_duration.clone()))
+            .ok();
+        registry
+            .register(Box::new(settlement_duration.clone()))
+            .ok();
+        registry.register(Box::new(settlement_lag.clone())).ok();
+        registry.register(Box::new(settle_success.clone())).ok();
+        registry.register(Box::new(settle_failure.clone())).ok();
+        registry
+            .register(Box::new(settle_payer_mismatch.clone()))
+            .ok();
+        registry.register(Box::new(settle_pending.clone())).ok();
+        registry
+            .register(Box::new(facilitator_connections.clone()))
+            .ok();
+        registry
+            .register(Box::new(facilitator_connection_reuse.clone()))
+            .ok();
+        registry
+            .register(Box::new(redis_operation_duration.clone()))
+            .ok();
+        registry.register(Box::new(redis_errors.clone())).ok();
+        registry.register(Box::new(passthrough.clone())).ok();
+        registry
+            .register(Box::new(facilitator_call_duration.clone()))
+            .ok();
+        registry.register(Box::new(payment_amount.clone())).ok();
+        registry
+            .register(Box::new(payment_amount_units.clone()))
+            .ok();
+        registry
+            .register(Box::new(facilitator_preflight_ok.clone()))
+            .ok();
+        registry
+            .register(Box::new(replay_ttl_computed.clone()))
+            .ok();
+        registry.register(Box::new(panics.clone())).ok();
+        registry.register(Box::new(panic_context_last.clone())).ok();
+        registry.register(Box::new(up.clone())).ok();
+        registry.register(Box::new(build_info.clone())).ok();
+        registry.register(Box::new(config_reloads.clone())).ok();
+        registry.register(Box::new(last_metrics_reset.clone())).ok();
+        up.set(1.0);
+
+        Self {
+            requests_total,
+            verification_attempts,
+            verification_success,
+            verification_failed,
+            responses_402,
+            facilitator_errors,
+            payment_size_rejected,
+            quota_used,
+            quota_exhausted,
+            dry_run_bypasses,
+            file_facilitator_hits,
+            whitelist_bypasses,
+            skip_user_agents,
+            facilitator_response_oversized,
+            local_cache_hits,
+            local_cache_evictions,
+            verification_duration,
+            settlement_duration,
+            settlement_lag,
+            settle_success,
+            settle_failure,
+            settle_payer_mismatch,
+            settle_pending,
+            facilitator_connections,
+            facilitator_connection_reuse,
+            redis_operation_duration,
+            redis_errors,
+            passthrough,
+            facilitator_call_duration,
+            payment_amount,
+            payment_amount_units,
+            facilitator_preflight_ok,
+            replay_ttl_computed,
+            panics,
+            panic_context_last,
+            up,
+            build_info,
+            config_reloads,
+            last_metrics_reset,
+            registry,
+        }
+    }
+
+    /// Zero every counter, e.g. after a reload made their values stale, and
+    /// record the time in `x402_last_metrics_reset_timestamp_seconds`.
+    /// Gauges report current state and histograms cannot be reset, so both
+    /// are left alone.
+    pub fn reset(&self) {
+        for counter in [
+            &self.requests_total,
+            &self.verification_attempts,
+            &self.verification_success,
+            &self.verification_failed,
+            &self.responses_402,
+            &self.facilitator_errors,
+            &self.payment_size_rejected,
+            &self.quota_used,
+            &self.quota_exhausted,
+            &self.dry_run_bypasses,
+            &self.file_facilitator_hits,
+            &self.whitelist_bypasses,
+            &self.skip_user_agents,
+            &self.facilitator_response_oversized,
+            &self.local_cache_hits,
+            &self.local_cache_evictions,
+            &self.settle_success,
+            &self.settle_failure,
+            &self.settle_payer_mismatch,
+            &self.facilitator_connection_reuse,
+            &self.config_reloads,
+        ] {
+            counter.reset();
+        }
+        self.redis_errors.reset();
+        self.passthrough.reset();
+        self.panics.reset();
+        self.last_metrics_reset.set(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0),
+        );
     }
 
     pub fn record_request(&self) {
@@ -105,16 +515,184 @@ impl X402Metrics {
         self.facilitator_errors.inc();
     }
 
+    pub fn record_payment_size_rejected(&self) {
+        self.payment_size_rejected.inc();
+    }
+
+    pub fn record_quota_used(&self) {
+        self.quota_used.inc();
+    }
+
+    pub fn record_quota_exhausted(&self) {
+        self.quota_exhausted.inc();
+    }
+
+    pub fn record_dry_run_bypass(&self) {
+        self.dry_run_bypasses.inc();
+    }
+
+    pub fn record_file_facilitator_hit(&self) {
+        self.file_facilitator_hits.inc();
+    }
+
+    pub fn record_whitelist_bypass(&self) {
+        self.whitelist_bypasses.inc();
+    }
+
+    pub fn record_skip_user_agent(&self) {
+        self.skip_user_agents.inc();
+    }
+
+    pub fn record_facilitator_response_oversized(&self) {
+        self.facilitator_response_oversized.inc();
+    }
+
+    pub fn record_local_cache_hit(&self) {
+        self.local_cache_hits.inc();
+    }
+
+    pub fn record_local_cache_evictions(&self, count: usize) {
+        self.local_cache_evictions.inc_by(count as u64);
+    }
+
     pub fn record_verification_duration(&self, duration: f64) {
         self.verification_duration.observe(duration);
     }
 
+    pub fn record_settlement_duration(&self, duration: f64) {
+        self.settlement_duration.observe(duration);
+    }
+
+    pub fn record_settlement_lag(&self, duration: f64) {
+        self.settlement_lag.observe(duration);
+    }
+
+    pub fn record_settle_success(&self) {
+        self.settle_success.inc();
+    }
+
+    pub fn record_settle_failure(&self) {
+        self.settle_failure.inc();
+    }
+
+    pub fn record_settle_payer_mismatch(&self) {
+        self.settle_payer_mismatch.inc();
+    }
+
+    /// A settle call starts; pair with [`Self::settle_finished`].
+    pub fn settle_started(&self) {
+        self.settle_pending.inc();
+    }
+
+    pub fn settle_finished(&self) {
+        self.settle_pending.dec();
+    }
+
+    /// Adjust the facilitator connection estimate by `delta`.
+    pub fn record_facilitator_connections(&self, delta: i64) {
+        self.facilitator_connections.add(delta);
+    }
+
+    pub fn record_facilitator_connection_reuse(&self) {
+        self.facilitator_connection_reuse.inc();
+    }
+
+    /// Observe one Redis call, e.g. `record_redis_duration("replay_check", secs)`.
+    pub fn record_redis_duration(&self, op: &str, secs: f64) {
+        self.redis_operation_duration
+            .with_label_values(&[op])
+            .observe(secs);
+    }
+
+    /// Count a failed Redis call: `replay_check` or `store_used`.
+    pub fn record_redis_error(&self, op: &str) {
+        self.redis_errors.with_label_values(&[op]).inc();
+    }
+
+    /// Count a request let through by `x402_passthrough_on_methods`.
+    pub fn record_passthrough(&self, method: &str) {
+        self.passthrough.with_label_values(&[method]).inc();
+    }
+
+    /// Observe one facilitator call: `verify` or `settle`.
+    pub fn record_facilitator_duration(&self, op: &str, secs: f64) {
+        self.facilitator_call_duration
+            .with_label_values(&[op])
+            .observe(secs);
+    }
+
+    #[deprecated(note = "use record_payment_amount_units; f64 token amounts lose precision")]
     pub fn record_payment_amount(&self, amount: f64) {
         self.payment_amount.observe(amount);
     }
+
+    /// Observe an amount in the asset's smallest unit. Prometheus samples are
+    /// f64, so values above 2^53 are rounded.
+    pub fn record_payment_amount_units(&self, amount: u128) {
+        self.payment_amount_units.observe(amount as f64);
+    }
+
+    /// Use `buckets` for `x402_payment_amount_units`. Only the first call
+    /// before the metrics are created has an effect; returns whether
+    /// `buckets` are the ones in use.
+    pub fn set_amount_unit_buckets(buckets: Vec<f64>) -> bool {
+        if METRICS.get().is_none() {
+            let _ = AMOUNT_UNIT_BUCKETS.set(buckets.clone());
+        }
+        AMOUNT_UNIT_BUCKETS.get().is_some_and(|b| *b == buckets)
+    }
+
+    pub fn record_panic(&self, context: &str, backtrace: bool) {
+        self.panics
+            .with_label_values(&[if backtrace { "true" } else { "false" }])
+            .inc();
+        self.panic_context_last.reset();
+        self.panic_context_last
+            .with_label_values(&[context])
+            .set(1.0);
+    }
+
+    pub fn record_facilitator_preflight(&self, ok: bool) {
+        self.facilitator_preflight_ok.set(i64::from(ok));
+    }
+
+    /// Report the module as unavailable (`x402_up 0`), e.g. after a failed
+    /// postconfiguration.
+    pub fn mark_down(&self) {
+        self.up.set(0.0);
+    }
+
+    pub fn record_config_reload(&self) {
+        self.config_reloads.inc();
+    }
+
+    pub fn record_replay_ttl(&self, secs: u64) {
+        self.replay_ttl_computed
+            .set(i64::try_from(secs).unwrap_or(i64::MAX));
+    }
 }
 
 pub fn collect_metrics() -> String {
+    let _guard = ENCODE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    encode_metrics()
+}
+
+/// [`collect_metrics`] for the scrape endpoint: prefixed with
+/// [`PER_WORKER_WARNING`], and an error rather than a best effort if an
+/// earlier scrape panicked while encoding.
+pub fn collect_metrics_safe() -> Result<String> {
+    let _guard = ENCODE_LOCK
+        .lock()
+        .map_err(|_| X402Error::Runtime("Metrics lock poisoned".into()))?;
+    Ok(format!("{PER_WORKER_WARNING}{}", encode_metrics()))
+}
+
+/// Reset this worker's counters; see [`X402Metrics::reset`].
+pub fn x402_metrics_reset() {
+    X402Metrics::get().reset();
+}
+
+fn encode_metrics() -> String {
     let metrics = X402Metrics::get();
     let encoder = TextEncoder::new();
     let metric_families = metrics.registry.gather();
@@ -125,3 +703,337 @@ pub fn collect_metrics() -> String {
     }
     buffer
 }
+
+/// Same metrics as [`collect_metrics`] in OpenMetrics text format: counter
+/// families drop the `_total` suffix, each counter gets a `_created` sample
+/// and the exposition ends with `# EOF`.
+pub fn collect_metrics_openmetrics() -> String {
+    let text = collect_metrics();
+    // Counters restart from zero at a reset, so that is their creation time.
+    let created = METRICS_CREATED
+        .get()
+        .copied()
+        .unwrap_or(0.0)
+        .max(X402Metrics::get().last_metrics_reset.get());
+    to_openmetrics(&text, created)
+}
+
+fn to_openmetrics(text: &str, created: f64) -> String {
+    let counters: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("# TYPE "))
+        .filter_map(|rest| rest.strip_suffix(" counter"))
+        .collect();
+    let family = |name: &str| name.strip_suffix("_total").unwrap_or(name).to_string();
+
+    let mut out = String::with_capacity(text.len() + 64 * counters.len() + 6);
+    for line in text.lines() {
+        if let Some(rest) = line
+            .strip_prefix("# HELP ")
+            .or_else(|| line.strip_prefix("# TYPE "))
+        {
+            let (name, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            if counters.contains(&name) {
+                out.push_str(&format!("{} {} {tail}\n", &line[..6], family(name)));
+                continue;
+            }
+        } else if let Some(name) = line
+            .split(['{', ' '])
+            .next()
+            .filter(|name| counters.contains(name))
+        {
+            let labels = line[name.len()..].split_once(' ').map_or("", |(l, _)| l);
+            out.push_str(line);
+            out.push('\n');
+            out.push_str(&format!("{}_created{labels} {created}\n", family(name)));
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Content type and body for a metrics scrape. A client asking for
+/// `application/openmetrics-text` gets OpenMetrics regardless of `format`.
+/// OpenMetrics allows no free-form comments, so only the Prometheus format
+/// carries [`PER_WORKER_WARNING`].
+pub fn render_metrics(
+    format: MetricsFormat,
+    accept: Option<&str>,
+) -> Result<(&'static str, String)> {
+    let wants_openmetrics = accept.is_some_and(|a| a.contains("application/openmetrics-text"));
+    if wants_openmetrics || format == MetricsFormat::OpenMetrics {
+        Ok((OPENMETRICS_CONTENT_TYPE, collect_metrics_openmetrics()))
+    } else {
+        Ok((PROMETHEUS_CONTENT_TYPE, collect_metrics_safe()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_payment_amount_units() {
+        let metrics = X402Metrics::get();
+        let before = metrics.payment_amount_units.get_sample_count();
+        metrics.record_payment_amount_units(1_000);
+        metrics.record_payment_amount_units(u128::from(u64::MAX) + 1);
+        assert_eq!(metrics.payment_amount_units.get_sample_count(), before + 2);
+        assert!(collect_metrics().contains("x402_payment_amount_units_bucket"));
+        // Too late once the histogram exists.
+        assert!(!X402Metrics::set_amount_unit_buckets(vec![1.0]));
+    }
+
+    #[test]
+    fn test_record_redis_and_facilitator_durations() {
+        let metrics = X402Metrics::get();
+        let redis = |op: &str| {
+            metrics
+                .redis_operation_duration
+                .with_label_values(&[op])
+                .get_sample_count()
+        };
+        let facilitator = |op: &str| {
+            metrics
+                .facilitator_call_duration
+                .with_label_values(&[op])
+                .get_sample_count()
+        };
+        let (replay, price, verify, settle) = (
+            redis("replay_check"),
+            redis("price_lookup"),
+            facilitator("verify"),
+            facilitator("settle"),
+        );
+
+        metrics.record_redis_duration("replay_check", 0.002);
+        metrics.record_redis_duration("replay_check", 0.004);
+        metrics.record_facilitator_duration("verify", 0.3);
+        assert_eq!(redis("replay_check"), replay + 2);
+        assert_eq!(redis("price_lookup"), price);
+        assert_eq!(facilitator("verify"), verify + 1);
+        assert_eq!(facilitator("settle"), settle);
+
+        let text = collect_metrics();
+        assert!(text.contains(
+            "x402_redis_operation_duration_seconds_bucket{operation=\"replay_check\",le=\"0.005\"}"
+        ));
+        assert!(text.contains("x402_facilitator_call_duration_seconds_count{operation=\"verify\"}"));
+    }
+
+    #[test]
+    fn test_reset_zeroes_counters() {
+        // A private instance, so tests using the shared one are unaffected.
+        let metrics = X402Metrics::new();
+        metrics.record_request();
+        metrics.record_402_response();
+        metrics.record_settle_success();
+        metrics.record_redis_error("replay_check");
+        metrics.settle_started();
+        metrics.record_settlement_lag(0.5);
+        assert_eq!(metrics.last_metrics_reset.get(), 0.0);
+
+        metrics.reset();
+        assert_eq!(metrics.requests_total.get(), 0);
+        assert_eq!(metrics.responses_402.get(), 0);
+        assert_eq!(metrics.settle_success.get(), 0);
+        assert_eq!(
+            metrics
+                .redis_errors
+                .with_label_values(&["replay_check"])
+                .get(),
+            0
+        );
+        assert!(metrics.last_metrics_reset.get() > 0.0);
+        // State and distributions are kept.
+        assert_eq!(metrics.settle_pending.get(), 1);
+        assert_eq!(metrics.settlement_lag.get_sample_count(), 1);
+        assert_eq!(metrics.up.get(), 1.0);
+
+        metrics.record_request();
+        assert_eq!(metrics.requests_total.get(), 1);
+    }
+
+    #[test]
+    fn test_redis_errors() {
+        let metrics = X402Metrics::get();
+        let before = metrics
+            .redis_errors
+            .with_label_values(&["replay_check"])
+            .get();
+        metrics.record_redis_error("replay_check");
+        assert_eq!(
+            metrics
+                .redis_errors
+                .with_label_values(&["replay_check"])
+                .get(),
+            before + 1
+        );
+        assert!(collect_metrics().contains("x402_redis_errors_total{operation=\"replay_check\"}"));
+    }
+
+    #[test]
+    fn test_passthrough_metric() {
+        let metrics = X402Metrics::get();
+        let before = metrics.passthrough.with_label_values(&["GET"]).get();
+        metrics.record_passthrough("GET");
+        assert_eq!(
+            metrics.passthrough.with_label_values(&["GET"]).get(),
+            before + 1
+        );
+        assert!(collect_metrics().contains("x402_passthrough_total{method=\"GET\"}"));
+    }
+
+    #[test]
+    fn test_settle_metrics() {
+        let metrics = X402Metrics::get();
+        let lag_count = metrics.settlement_lag.get_sample_count();
+        let lag_sum = metrics.settlement_lag.get_sample_sum();
+        let (success, failure) = (metrics.settle_success.get(), metrics.settle_failure.get());
+
+        metrics.record_settlement_lag(0.75);
+        assert_eq!(metrics.settlement_lag.get_sample_count(), lag_count + 1);
+        assert!((metrics.settlement_lag.get_sample_sum() - lag_sum - 0.75).abs() < 1e-9);
+
+        metrics.record_settle_success();
+        metrics.record_settle_failure();
+        metrics.record_settle_failure();
+        assert_eq!(metrics.settle_success.get(), success + 1);
+        assert_eq!(metrics.settle_failure.get(), failure + 2);
+        let mismatch = metrics.settle_payer_mismatch.get();
+        metrics.record_settle_payer_mismatch();
+        assert_eq!(metrics.settle_payer_mismatch.get(), mismatch + 1);
+
+        // Other tests may run settles concurrently, so only check this
+        // test's own increment is visible and undone.
+        metrics.settle_started();
+        assert!(metrics.settle_pending.get() >= 1);
+        metrics.settle_finished();
+
+        let text = collect_metrics();
+        assert!(text.contains("x402_settlement_lag_seconds_bucket{le=\"1\"}"));
+        assert!(text.contains("x402_settle_success_total"));
+        assert!(text.contains("x402_settle_failure_total"));
+        assert!(text.contains("x402_settle_payer_mismatch_total"));
+        assert!(text.contains("x402_settle_pending"));
+    }
+
+    #[test]
+    fn test_up_and_build_info_after_init() {
+        let metrics = X402Metrics::get();
+        assert_eq!(metrics.up.get(), 1.0);
+        let text = collect_metrics();
+        assert!(text.contains("\nx402_up 1\n"));
+        assert!(text.contains(&format!(
+            "x402_build_info{{nginx_version=\"{}\",rust_version=\"{}\",version=\"{}\"}} 1",
+            build_info_labels()[1],
+            build_info_labels()[2],
+            env!("CARGO_PKG_VERSION"),
+        )));
+
+        metrics.mark_down();
+        assert_eq!(metrics.up.get(), 0.0);
+        assert!(collect_metrics().contains("\nx402_up 0\n"));
+        metrics.up.set(1.0);
+    }
+
+    #[test]
+    fn test_record_config_reload() {
+        let metrics = X402Metrics::get();
+        let before = metrics.config_reloads.get();
+        metrics.record_config_reload();
+        assert_eq!(metrics.config_reloads.get(), before + 1);
+        assert!(collect_metrics().contains("x402_config_reload_total"));
+    }
+
+    #[test]
+    fn test_openmetrics_eof_and_created() {
+        X402Metrics::get().record_request();
+        let text = collect_metrics_openmetrics();
+        assert!(text.ends_with("# EOF\n"));
+        assert_eq!(text.matches("# EOF").count(), 1);
+        assert!(text.contains("# TYPE x402_requests counter"));
+        assert!(text.contains("\nx402_requests_total "));
+        assert!(text.contains("\nx402_requests_created "));
+    }
+
+    #[test]
+    fn test_to_openmetrics_rewrites_counters_only() {
+        let text = "# HELP x402_requests_total Total requests\n\
+                    # TYPE x402_requests_total counter\n\
+                    x402_requests_total 3\n\
+                    # HELP x402_latency Latency\n\
+                    # TYPE x402_latency histogram\n\
+                    x402_latency_sum 1.5\n";
+        assert_eq!(
+            to_openmetrics(text, 1700000000.0),
+            "# HELP x402_requests Total requests\n\
+             # TYPE x402_requests counter\n\
+             x402_requests_total 3\n\
+             x402_requests_created 1700000000\n\
+             # HELP x402_latency Latency\n\
+             # TYPE x402_latency histogram\n\
+             x402_latency_sum 1.5\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn test_prometheus_text_format() {
+        X402Metrics::get().record_request();
+        let text = collect_metrics();
+        assert!(text.contains("# HELP x402_requests_total "));
+        assert!(text.contains("# TYPE x402_requests_total counter\n"));
+        // Every sample line is `name[{labels}] value`.
+        for line in text
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            let (_, value) = line.rsplit_once(' ').expect(line);
+            assert!(value.parse::<f64>().is_ok(), "{line}");
+            assert!(line.starts_with("x402_"), "{line}");
+        }
+    }
+
+    #[test]
+    fn test_render_metrics_negotiation() {
+        let (ct, body) = render_metrics(MetricsFormat::Prometheus, None).unwrap();
+        assert_eq!(ct, PROMETHEUS_CONTENT_TYPE);
+        assert!(!body.contains("# EOF"));
+        assert!(body.starts_with(PER_WORKER_WARNING));
+
+        let (ct, body) = render_metrics(
+            MetricsFormat::Prometheus,
+            Some("application/openmetrics-text; version=1.0.0"),
+        )
+        .unwrap();
+        assert_eq!(ct, OPENMETRICS_CONTENT_TYPE);
+        assert!(body.ends_with("# EOF\n"));
+        assert!(!body.contains("# WARNING"));
+
+        let (ct, _) = render_metrics(MetricsFormat::OpenMetrics, Some("text/plain")).unwrap();
+        assert_eq!(ct, OPENMETRICS_CONTENT_TYPE);
+    }
+
+    #[test]
+    fn test_collect_metrics_safe_concurrently() {
+        let scrapers: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..50 {
+                        X402Metrics::get().record_request();
+                        let text = collect_metrics_safe().expect("lock not poisoned");
+                        assert!(text.starts_with(PER_WORKER_WARNING));
+                        assert!(text.contains("\nx402_requests_total "));
+                    }
+                })
+            })
+            .collect();
+        for scraper in scrapers {
+            scraper.join().expect("scraper thread panicked");
+        }
+    }
+}
@@ -1,6 +1,12 @@
+pub mod amount_expr;
+pub mod chain;
 pub mod commands;
 pub mod config;
+pub mod context;
+pub mod ens;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod handler;
 pub mod logging;
 pub mod metrics;
@@ -12,6 +18,10 @@ pub mod request;
 pub mod requirements;
 pub mod response;
 pub mod runtime;
+pub mod secrets;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub mod verify_cache;
 #[cfg(test)]
 pub mod module {
     use ngx::ffi::ngx_module_t;
@@ -22,8 +32,14 @@ pub mod module {
     pub static mut ngx_http_x402_module: ngx_module_t = ngx_module_t::default();
 }
 
-pub use config::{FacilitatorFallback, ParsedX402Config, X402Config};
-pub use error::{ConfigError, Result};
+pub use config::{
+    BrowserDetect, FacilitatorFallback, FacilitatorProtocol, ParsedX402Config, ResponseFormat,
+    Scheme, X402Config,
+};
+pub use context::X402RequestContext;
+#[allow(deprecated)]
+pub use error::ConfigError;
+pub use error::{Result, X402Error};
 pub use handler::{x402_handler_impl, HandlerResult};
-pub use metrics::X402Metrics;
+pub use metrics::{x402_metrics_reset, MetricsFormat, X402Metrics};
 pub use module::ngx_http_x402_module;
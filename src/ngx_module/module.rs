@@ -1,17 +1,27 @@
 use ngx::ffi::{
-    ngx_array_push, ngx_conf_t, ngx_http_handler_pt, ngx_http_module_t,
-    ngx_http_phases_NGX_HTTP_ACCESS_PHASE, ngx_int_t, ngx_module_t, NGX_HTTP_MODULE,
+    ngx_array_push, ngx_conf_t, ngx_core_conf_t, ngx_core_module, ngx_cycle_t,
+    ngx_http_add_variable, ngx_http_handler_pt, ngx_http_module_t,
+    ngx_http_phases_NGX_HTTP_ACCESS_PHASE, ngx_http_phases_NGX_HTTP_LOG_PHASE, ngx_http_variable_t,
+    ngx_int_t, ngx_module_t, ngx_pnalloc, ngx_str_t, ngx_uint_t, ngx_variable_value_t,
+    NGX_HTTP_MODULE, NGX_HTTP_VAR_NOCACHEABLE,
 };
 use ngx::http::{
-    HttpModule, HttpModuleLocationConf, HttpModuleMainConf, Merge, MergeConfigError,
+    HttpModule, HttpModuleLocationConf, HttpModuleMainConf, HttpModuleServerConf, Merge,
     NgxHttpCoreModule, Request,
 };
+use ngx::{core, http_variable_get, ngx_string};
 use std::ffi::c_char;
 use std::os::raw::c_void;
 use std::ptr;
 
 use crate::ngx_module::commands::NGX_HTTP_X402_COMMANDS;
 use crate::ngx_module::config::X402Config;
+use crate::ngx_module::context::{
+    inject_request_id, X402RequestContext, VAR_PAYER, VAR_PAYMENT_STATUS, VAR_REQUEST_ID,
+    VAR_TX_HASH,
+};
+use crate::ngx_module::logging::{log_error, log_warn};
+use crate::ngx_module::metrics::X402Metrics;
 
 pub struct X402Module;
 
@@ -21,48 +31,97 @@ impl HttpModule for X402Module {
     }
 }
 
+unsafe impl HttpModuleServerConf for X402Module {
+    type ServerConf = X402Config;
+}
+
 unsafe impl HttpModuleLocationConf for X402Module {
     type LocationConf = X402Config;
 }
 
-impl Merge for X402Config {
-    fn merge(&mut self, prev: &X402Config) -> Result<(), MergeConfigError> {
-        if prev.enabled != 0 && self.enabled == 0 {
-            self.enabled = prev.enabled;
+pub fn get_loc_conf(r: &Request) -> Option<&'static X402Config> {
+    X402Module::location_conf(r)
+}
+
+/// `$x402_*` variables, evaluated from the request's [`X402RequestContext`].
+const X402_VARIABLES: [(ngx_str_t, usize); 4] = [
+    (ngx_string!("x402_request_id"), VAR_REQUEST_ID),
+    (ngx_string!("x402_payer"), VAR_PAYER),
+    (ngx_string!("x402_tx_hash"), VAR_TX_HASH),
+    (ngx_string!("x402_payment_status"), VAR_PAYMENT_STATUS),
+];
+
+http_variable_get!(
+    ngx_http_x402_variable,
+    |r: &mut Request, v: *mut ngx_variable_value_t, data: usize| {
+        let value = X402RequestContext::from_request(r).and_then(|ctx| {
+            let value = ctx.variable(data)?;
+            let p = ngx_pnalloc(r.pool().as_ptr(), value.len()) as *mut u8;
+            if p.is_null() {
+                return None;
+            }
+            ptr::copy_nonoverlapping(value.as_ptr(), p, value.len());
+            Some((p, value.len()))
+        });
+        match value {
+            Some((p, len)) => {
+                (*v).set_len(len as u32);
+                (*v).set_valid(1);
+                (*v).set_no_cacheable(0);
+                (*v).set_not_found(0);
+                (*v).data = p;
+            }
+            None => (*v).set_not_found(1),
         }
-        macro_rules! merge_str {
-            ($field:ident) => {
-                if self.$field.len == 0 && prev.$field.len > 0 {
-                    self.$field = prev.$field;
-                }
-            };
+        core::Status::NGX_OK
+    }
+);
+
+unsafe fn add_variables(cf: *mut ngx_conf_t) -> core::Status {
+    for (mut name, index) in X402_VARIABLES {
+        let var: *mut ngx_http_variable_t =
+            ngx_http_add_variable(cf, &mut name, NGX_HTTP_VAR_NOCACHEABLE as ngx_uint_t);
+        if var.is_null() {
+            return core::Status::NGX_ERROR;
         }
-        merge_str!(amount_str);
-        merge_str!(pay_to_str);
-        merge_str!(facilitator_url_str);
-        merge_str!(description_str);
-        merge_str!(network_str);
-        merge_str!(network_id_str);
-        merge_str!(resource_str);
-        merge_str!(asset_str);
-        merge_str!(asset_decimals_str);
-        merge_str!(timeout_str);
-        merge_str!(facilitator_fallback_str);
-        merge_str!(ttl_str);
-        merge_str!(redis_url_str);
-        merge_str!(replay_ttl_str);
-        Ok(())
+        (*var).get_handler = Some(ngx_http_x402_variable);
+        (*var).data = index;
     }
+    core::Status::NGX_OK
 }
 
-pub fn get_loc_conf(r: &Request) -> Option<&'static X402Config> {
-    X402Module::location_conf(r)
+/// Runs before the `http` block is read, on every (re)load: lookups cached
+/// by the previous configuration are dropped so this one queries afresh.
+unsafe extern "C" fn preconfiguration(_cf: *mut ngx_conf_t) -> ngx_int_t {
+    crate::ngx_module::ens::clear_ens_cache();
+    crate::ngx_module::chain::clear_decimals_cache();
+    crate::ngx_module::secrets::clear_vault_cache();
+    ngx::ffi::NGX_OK as ngx_int_t
 }
 
 unsafe extern "C" fn postconfiguration(cf: *mut ngx_conf_t) -> ngx_int_t {
+    let metrics = X402Metrics::get();
+    metrics.record_config_reload();
+    if add_variables(cf) != core::Status::NGX_OK {
+        metrics.mark_down();
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+
+    if !crate::ngx_module::config::check_configured_testnets() {
+        metrics.mark_down();
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+
+    // Locations were merged before postconfiguration, so every facilitator
+    // with `x402_facilitator_preflight on` is registered by now.
+    crate::ngx_module::runtime::run_facilitator_preflights();
+
     let cmcf = match NgxHttpCoreModule::main_conf_mut(&*cf) {
         Some(c) => c,
-        None => return ngx::ffi::NGX_ERROR as ngx_int_t,
+        None => {
+            metrics.mark_down();
+            return ngx::ffi::NGX_ERROR as ngx_int_t;
+        }
     };
 
     let phase_idx = ngx_http_phases_NGX_HTTP_ACCESS_PHASE as usize;
@@ -70,18 +129,112 @@ unsafe extern "C" fn postconfiguration(cf: *mut ngx_conf_t) -> ngx_int_t {
     let h = ngx_array_push(handlers_ptr) as *mut ngx_http_handler_pt;
 
     if h.is_null() {
+        metrics.mark_down();
         return ngx::ffi::NGX_ERROR as ngx_int_t;
     }
 
     *h = Some(x402_phase_handler);
+
+    let log_handlers =
+        ptr::addr_of_mut!(cmcf.phases[ngx_http_phases_NGX_HTTP_LOG_PHASE as usize].handlers);
+    let h = ngx_array_push(log_handlers) as *mut ngx_http_handler_pt;
+    if h.is_null() {
+        metrics.mark_down();
+        return ngx::ffi::NGX_ERROR as ngx_int_t;
+    }
+    *h = Some(x402_log_handler);
     ngx::ffi::NGX_OK as ngx_int_t
 }
 
+/// `x402_access_log`: log the payment outcome of requests the access
+/// handler saw, at `notice` in the error log.
+unsafe extern "C" fn x402_log_handler(r: *mut ngx::ffi::ngx_http_request_t) -> ngx_int_t {
+    use crate::ngx_module::panic_handler::catch_panic_or_default;
+
+    if r.is_null() {
+        return ngx::ffi::NGX_OK as ngx_int_t;
+    }
+    catch_panic_or_default(
+        || {
+            let req = unsafe { Request::from_ngx_http_request(r) };
+            if !get_loc_conf(req).is_some_and(|conf| conf.access_log()) {
+                return ngx::ffi::NGX_OK as ngx_int_t;
+            }
+            let Some(ctx) = X402RequestContext::from_request(req) else {
+                return ngx::ffi::NGX_OK as ngx_int_t;
+            };
+            let line = ctx.access_log_line(ctx.start_time.elapsed());
+            let log = req.log();
+            // nginx logs a level when `log_level >= level`.
+            if !log.is_null()
+                && unsafe { (*log).log_level } >= ngx::ffi::NGX_LOG_NOTICE as ngx_uint_t
+            {
+                unsafe {
+                    ngx::log::log_error(
+                        ngx::ffi::NGX_LOG_NOTICE as ngx_uint_t,
+                        log,
+                        0,
+                        line.as_bytes(),
+                    )
+                };
+            }
+            ngx::ffi::NGX_OK as ngx_int_t
+        },
+        "x402_log_handler",
+        ngx::ffi::NGX_OK as ngx_int_t,
+    )
+}
+
+/// Runs in the master once the whole configuration is read, unlike
+/// postconfiguration, so `worker_processes` is final here.
+unsafe extern "C" fn init_module(cycle: *mut ngx_cycle_t) -> ngx_int_t {
+    if crate::ngx_module::config::take_dry_run_configured() {
+        let index = (*ptr::addr_of!(ngx_core_module)).index;
+        let ccf = *(*cycle).conf_ctx.add(index) as *const ngx_core_conf_t;
+        // More than one worker is taken as a sign this is not a development box.
+        if !ccf.is_null() && (*ccf).worker_processes > 1 {
+            log_error(
+                None,
+                "x402_dry_run is on with worker_processes > 1: payments are NOT verified",
+            );
+        }
+    }
+    ngx::ffi::NGX_OK as ngx_int_t
+}
+
+/// Runs in each worker after the fork; background tasks start here so they
+/// live on the worker's own runtime.
+unsafe extern "C" fn init_process(_cycle: *mut ngx_cycle_t) -> ngx_int_t {
+    crate::ngx_module::secrets::start_vault_refresh();
+    ngx::ffi::NGX_OK as ngx_int_t
+}
+
+unsafe extern "C" fn create_srv_conf(cf: *mut ngx_conf_t) -> *mut c_void {
+    let pool = ngx::core::Pool::from_ngx_pool((*cf).pool);
+    pool.allocate::<X402Config>(Default::default()) as *mut c_void
+}
+
+unsafe extern "C" fn merge_srv_conf(
+    _cf: *mut ngx_conf_t,
+    prev: *mut c_void,
+    conf: *mut c_void,
+) -> *mut c_char {
+    let prev = &*(prev as *const X402Config);
+    let conf = &mut *(conf as *mut X402Config);
+    match conf.merge(prev) {
+        Ok(_) => ptr::null_mut(),
+        Err(_) => ngx::core::NGX_CONF_ERROR as *mut c_char,
+    }
+}
+
 unsafe extern "C" fn create_loc_conf(cf: *mut ngx_conf_t) -> *mut c_void {
     let pool = ngx::core::Pool::from_ngx_pool((*cf).pool);
     pool.allocate::<X402Config>(Default::default()) as *mut c_void
 }
 
+/// Directives store into `NGX_HTTP_LOC_CONF_OFFSET`, so a value set in a
+/// `server` block lands in that server's location conf and reaches nested
+/// locations through this merge (http -> server -> location).
 unsafe extern "C" fn merge_loc_conf(
     _cf: *mut ngx_conf_t,
     prev: *mut c_void,
@@ -96,12 +249,12 @@ unsafe extern "C" fn merge_loc_conf(
 }
 
 pub static NGX_HTTP_X402_MODULE_CTX: ngx_http_module_t = ngx_http_module_t {
-    preconfiguration: None,
+    preconfiguration: Some(preconfiguration),
     postconfiguration: Some(postconfiguration),
     create_main_conf: None,
     init_main_conf: None,
-    create_srv_conf: None,
-    merge_srv_conf: None,
+    create_srv_conf: Some(create_srv_conf),
+    merge_srv_conf: Some(merge_srv_conf),
     create_loc_conf: Some(create_loc_conf),
     merge_loc_conf: Some(merge_loc_conf),
 };
@@ -117,6 +270,8 @@ pub static mut ngx_http_x402_module: ngx_module_t = {
     m.ctx = &NGX_HTTP_X402_MODULE_CTX as *const _ as *mut c_void;
     m.commands = unsafe { &NGX_HTTP_X402_COMMANDS[0] as *const _ as *mut _ };
     m.type_ = NGX_HTTP_MODULE as usize;
+    m.init_module = Some(init_module);
+    m.init_process = Some(init_process);
     m
 };
 
@@ -138,26 +293,62 @@ pub unsafe extern "C" fn x402_phase_handler(
         || {
             let req = unsafe { Request::from_ngx_http_request(r) };
 
-            use crate::ngx_module::handler::HandlerResult;
-            use crate::ngx_module::request::{is_websocket_request, should_skip_method};
+            use crate::ngx_module::handler::{HandlerResult, UPSTREAM_PAYMENT_HEADERS};
+            use crate::ngx_module::request::{
+                is_websocket_request, remove_headers_in, should_skip_method,
+            };
 
-            if should_skip_method(req) {
+            // Skip subrequests
+            if !req.is_main() {
                 return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
             }
 
+            let conf = match get_loc_conf(req) {
+                Some(c) => c,
+                None => return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t,
+            };
+
+            // Whatever happens next, the upstream must not trust payment
+            // headers the client sent; a settled payment sets them afresh.
+            let mut client_headers = Vec::new();
+            if conf.upstream_payment_pass() {
+                client_headers.extend_from_slice(UPSTREAM_PAYMENT_HEADERS);
+            }
+            if conf.forward_payer() {
+                client_headers.push("X-Payment-Payer");
+            }
+            if !client_headers.is_empty() && remove_headers_in(req, &client_headers).is_none() {
+                log::error!("Failed to strip client-sent X-Payment-* headers");
+                return ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t;
+            }
+
             if is_websocket_request(req) {
                 return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
             }
 
-            // Skip subrequests
-            if !req.is_main() {
+            if should_skip_method(
+                req,
+                conf.skip_head(),
+                conf.method_override_header().as_deref(),
+                &conf.method_override_methods(),
+            ) {
                 return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
             }
 
-            let conf = match get_loc_conf(req) {
-                Some(c) => c,
-                None => return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t,
-            };
+            // Metrics locations are served, never gated, even under `x402 on`.
+            if conf.metrics_endpoint() {
+                let parsed = match conf.parse() {
+                    Ok(c) => c,
+                    Err(e) => {
+                        log::error!("Failed to parse x402 config: {e}");
+                        return ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t;
+                    }
+                };
+                return match crate::ngx_module::handler::serve_metrics(req, &parsed) {
+                    Ok(_) => ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t,
+                    Err(e) => e.into(),
+                };
+            }
 
             if conf.enabled == 0 {
                 return ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t;
@@ -171,12 +362,28 @@ pub unsafe extern "C" fn x402_phase_handler(
                 }
             };
 
+            if X402RequestContext::get_from_request(req).is_none() {
+                let mut ctx = X402RequestContext::for_request(req);
+                ctx.log_request_id = parsed.log_request_id;
+                let request_id = ctx.request_id.clone();
+                if ctx.store_in_request(req).is_none() {
+                    log::error!("Failed to allocate x402 request context");
+                    return ngx::ffi::NGX_ERROR as ngx::ffi::ngx_int_t;
+                }
+                if parsed.log_request_id {
+                    if let Err(e) = inject_request_id(req, &request_id) {
+                        log_warn(Some(req), &e.to_string());
+                    }
+                }
+            }
+
             match crate::ngx_module::handler::x402_handler_impl(req, &parsed) {
                 Ok(HandlerResult::PaymentValid) => ngx::ffi::NGX_DECLINED as ngx::ffi::ngx_int_t,
                 Ok(HandlerResult::ResponseSent) => ngx::ffi::NGX_OK as ngx::ffi::ngx_int_t,
-                Ok(HandlerResult::Error) | Err(_) => {
+                Ok(HandlerResult::Error) => {
                     ngx::ffi::NGX_HTTP_INTERNAL_SERVER_ERROR as ngx::ffi::ngx_int_t
                 }
+                Err(e) => e.into(),
             }
         },
         "x402_phase_handler",
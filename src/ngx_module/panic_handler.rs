@@ -1,14 +1,66 @@
+use crate::ngx_module::metrics::X402Metrics;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
 use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+static INSTALL_HOOK: Once = Once::new();
+
+thread_local! {
+    /// Backtrace captured by the panic hook, taken when the panic is caught.
+    static LAST_BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Whether `RUST_BACKTRACE` asks for backtraces.
+fn backtrace_enabled() -> bool {
+    std::env::var("RUST_BACKTRACE").is_ok_and(|v| v != "0")
+}
+
+/// The stack is gone once `catch_unwind` returns, so the backtrace has to be
+/// captured in a panic hook. The previous hook still runs.
+fn install_backtrace_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if backtrace_enabled() {
+                let backtrace = Backtrace::force_capture().to_string();
+                LAST_BACKTRACE.with(|b| *b.borrow_mut() = Some(backtrace));
+            }
+            previous(info);
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
+}
+
+/// Build the log line for a caught panic and record it in the metrics.
+fn report_panic(context: &str, payload: &(dyn Any + Send)) -> String {
+    let backtrace = LAST_BACKTRACE.with(|b| b.borrow_mut().take());
+    X402Metrics::get().record_panic(context, backtrace.is_some());
+    let message = panic_message(payload);
+    match backtrace {
+        Some(backtrace) => format!("Panic caught in {context}: {message}\nBacktrace:\n{backtrace}"),
+        None => format!("Panic caught in {context}: {message}"),
+    }
+}
 
 /// Catch panics and return a default value on panic.
 pub fn catch_panic_or_default<F, T>(f: F, context: &str, default: T) -> T
 where
     F: FnOnce() -> T,
 {
+    install_backtrace_hook();
     match panic::catch_unwind(AssertUnwindSafe(f)) {
         Ok(result) => result,
-        Err(_) => {
-            log::error!("Panic caught in {context}, returning default");
+        Err(payload) => {
+            log::error!("{}, returning default", report_panic(context, &*payload));
             default
         }
     }
@@ -19,11 +71,37 @@ pub fn catch_panic<F, T>(f: F, context: &str) -> Option<T>
 where
     F: FnOnce() -> T,
 {
+    install_backtrace_hook();
     match panic::catch_unwind(AssertUnwindSafe(f)) {
         Ok(result) => Some(result),
-        Err(_) => {
-            log::error!("Panic caught in {context}");
+        Err(payload) => {
+            log::error!("{}", report_panic(context, &*payload));
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic_returns_default() {
+        assert_eq!(catch_panic_or_default(|| panic!("boom"), "test", 7), 7);
+        assert_eq!(catch_panic(|| 1, "test"), Some(1));
+        assert_eq!(catch_panic(|| -> i32 { panic!("boom") }, "test"), None);
+    }
+
+    #[test]
+    fn test_report_panic_includes_backtrace() {
+        std::env::set_var("RUST_BACKTRACE", "1");
+        install_backtrace_hook();
+        let payload = panic::catch_unwind(|| panic!("induced panic")).unwrap_err();
+        let report = report_panic("backtrace_test", &*payload);
+        assert!(report.starts_with("Panic caught in backtrace_test: induced panic"));
+        assert!(report.contains("\nBacktrace:\n"));
+        assert!(report.len() > "Panic caught in backtrace_test: induced panic\nBacktrace:\n".len());
+
+        assert!(X402Metrics::get().panics.with_label_values(&["true"]).get() >= 1);
+    }
+}
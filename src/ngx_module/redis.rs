@@ -1,68 +1,354 @@
-use crate::ngx_module::error::{ConfigError, Result};
-use redis::Commands;
-use sha2::{Digest, Sha256};
+use crate::ngx_module::error::{Result, X402Error};
+use hmac::{Hmac, Mac};
+use redis::{Commands, ConnectionLike};
+use sha2::Sha256;
 use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 static REDIS_CLIENT: OnceLock<Mutex<redis::Client>> = OnceLock::new();
+static REDIS_INIT: RedisInitGuard = RedisInitGuard::new(INIT_RETRY_INTERVAL);
 
-pub fn init_redis(url: &str) -> Result<()> {
-    match redis::Client::open(url) {
-        Ok(client) => {
-            if REDIS_CLIENT.set(Mutex::new(client)).is_err() {
-                log::warn!("Redis client already initialized");
-            } else {
-                log::info!("Connected to Redis at {url}");
+/// Key of the replay hashes (`x402_replay_hmac_secret`), so they cannot be
+/// precomputed from a known payment.
+static REPLAY_HMAC_KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// How long a failed init is reported to later callers before it is retried.
+const INIT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Serializes Redis initialization. Callers that arrive while an init is
+/// running wait for it and share its outcome: a failure is handed to every
+/// caller for `retry_interval` instead of each of them retrying in turn.
+struct RedisInitGuard {
+    retry_interval: Duration,
+    error: Mutex<Option<(X402Error, Instant)>>,
+}
+
+impl RedisInitGuard {
+    const fn new(retry_interval: Duration) -> Self {
+        Self {
+            retry_interval,
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Fill `cell` with `init()` unless it is already set. Only one `init`
+    /// runs at a time and `cell` is set at most once.
+    fn init<T>(&self, cell: &OnceLock<T>, init: impl FnOnce() -> Result<T>) -> Result<()> {
+        if cell.get().is_some() {
+            return Ok(());
+        }
+        let mut error = self
+            .error
+            .lock()
+            .map_err(|_| X402Error::Runtime("Redis init lock poisoned".into()))?;
+        if cell.get().is_some() {
+            return Ok(());
+        }
+        if let Some((e, at)) = error.as_ref() {
+            if at.elapsed() < self.retry_interval {
+                return Err(e.clone());
             }
-            Ok(())
         }
-        Err(e) => Err(ConfigError::new(format!(
-            "Failed to create Redis client: {e}"
-        ))),
+        match init() {
+            Ok(value) => {
+                *error = None;
+                let _ = cell.set(value);
+                Ok(())
+            }
+            Err(e) => {
+                *error = Some((e.clone(), Instant::now()));
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Create the shared Redis client. Safe to call from any number of requests
+/// at once; calls after the first success are no-ops. With `init_timeout`
+/// (`x402_redis_init_timeout_ms`), Redis must also answer a `PING` within that
+/// time, otherwise init fails instead of deferring the error to the first lookup.
+pub fn init_redis(url: &str, init_timeout: Option<Duration>) -> Result<()> {
+    REDIS_INIT.init(&REDIS_CLIENT, || {
+        open_client(url, init_timeout).map(Mutex::new)
+    })
+}
+
+fn open_client(url: &str, init_timeout: Option<Duration>) -> Result<redis::Client> {
+    let redacted = crate::config::validation::redact_url_credentials(url);
+    let client = redis::Client::open(url)
+        .map_err(|e| X402Error::Config(format!("Failed to create Redis client: {e}")))?;
+    if let Some(timeout) = init_timeout {
+        let reachable = connect(&client, Some(timeout))
+            .is_some_and(|mut conn| redis::cmd("PING").query::<String>(&mut conn).is_ok());
+        if !reachable {
+            return Err(X402Error::Runtime(format!(
+                "Redis at {redacted} not reachable within {}ms",
+                timeout.as_millis()
+            )));
+        }
     }
+    log::info!("Connected to Redis at {redacted}");
+    Ok(client)
 }
 
-fn get_connection() -> Option<redis::Connection> {
+/// `timeout` bounds connecting and each command, so a slow Redis cannot hold
+/// the request past its `x402_total_deadline_secs`.
+fn get_connection(timeout: Option<Duration>) -> Option<redis::Connection> {
     let client = REDIS_CLIENT.get()?;
     let guard = client.lock().ok()?;
-    guard.get_connection().ok()
+    connect(&guard, timeout)
+}
+
+fn connect(client: &redis::Client, timeout: Option<Duration>) -> Option<redis::Connection> {
+    let conn = match timeout {
+        Some(timeout) => client.get_connection_with_timeout(timeout).ok()?,
+        None => client.get_connection().ok()?,
+    };
+    conn.set_read_timeout(timeout).ok()?;
+    conn.set_write_timeout(timeout).ok()?;
+    Some(conn)
 }
 
 /// Get a dynamic price override from Redis for the given path.
 /// Returns None if Redis is not configured or no override exists.
-pub fn get_dynamic_price(path: &str) -> Option<String> {
-    let mut conn = get_connection()?;
+pub fn get_dynamic_price(path: &str, timeout: Option<Duration>) -> Option<String> {
+    let mut conn = get_connection(timeout)?;
     conn.get(path).ok()
 }
 
+/// Keys tried by [`get_dynamic_price_with_fallback`], highest priority first:
+/// the exact path, then `x402:price:<prefix>/*` for each ancestor prefix from
+/// longest to shortest, then the global `x402:price:*`.
+pub(crate) fn price_key_candidates(path: &str) -> Vec<String> {
+    let mut keys = vec![path.to_string()];
+    let mut prefix = path.trim_end_matches('/');
+    while !prefix.is_empty() {
+        keys.push(format!("x402:price:{prefix}/*"));
+        prefix = &prefix[..prefix.rfind('/').unwrap_or(0)];
+    }
+    keys.push("x402:price:/*".to_string());
+    keys.push("x402:price:*".to_string());
+    keys
+}
+
+fn first_price(path: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> Option<String> {
+    price_key_candidates(path)
+        .iter()
+        .find_map(|key| lookup(key))
+}
+
+/// Like [`get_dynamic_price`], falling back to prefix and global keys.
+/// Uses one `GET` per candidate rather than `KEYS`/`SCAN`, which would block Redis.
+pub fn get_dynamic_price_with_fallback(path: &str, timeout: Option<Duration>) -> Option<String> {
+    let mut conn = get_connection(timeout)?;
+    first_price(path, |key| {
+        conn.get::<_, Option<String>>(key).ok().flatten()
+    })
+}
+
+/// How used payment signatures are laid out in Redis (`x402_redis_storage_mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayStorageMode {
+    /// One `x402:payment_sig:<hash>` key per payment, each with its own TTL.
+    Keyspace,
+    /// One `x402:payments:<replay_ttl>:<bucket>` hash per `replay_ttl`
+    /// seconds, expiring as a whole instead of key by key.
+    HashBucket,
+}
+
+/// What a Redis failure during the replay check does (`x402_redis_error_policy`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedisErrorPolicy {
+    /// Log and treat the payment as unused.
+    Allow,
+    /// Refuse the payment with a 402.
+    Deny,
+    /// Fail the request with a 500.
+    Error,
+}
+
+/// Replay-prevention record of payment signature hashes.
+pub trait ReplayStore {
+    fn is_used(&mut self, hash: &str) -> Result<bool>;
+    fn mark_used(&mut self, hash: &str) -> Result<()>;
+}
+
+pub struct KeyspaceStore<'a, C> {
+    conn: &'a mut C,
+    ttl_seconds: u64,
+}
+
+impl<C: ConnectionLike> ReplayStore for KeyspaceStore<'_, C> {
+    fn is_used(&mut self, hash: &str) -> Result<bool> {
+        self.conn
+            .exists(format!("x402:payment_sig:{hash}"))
+            .map_err(|e| X402Error::Runtime(format!("Failed to check payment in Redis: {e}")))
+    }
+
+    fn mark_used(&mut self, hash: &str) -> Result<()> {
+        self.conn
+            .set_ex::<_, _, ()>(format!("x402:payment_sig:{hash}"), "used", self.ttl_seconds)
+            .map_err(|e| X402Error::Runtime(format!("Failed to store payment in Redis: {e}")))
+    }
+}
+
+/// Buckets are `replay_ttl` seconds wide. A payment marked during bucket `b`
+/// must be seen for `replay_ttl` more seconds, i.e. from buckets `b` and
+/// `b + 1`, so lookups check the current and previous bucket and bucket `b`
+/// expires when `b + 1` ends.
+pub struct HashBucketStore<'a, C> {
+    conn: &'a mut C,
+    ttl_seconds: u64,
+    now_secs: u64,
+}
+
+impl<C> HashBucketStore<'_, C> {
+    fn bucket(&self) -> u64 {
+        self.now_secs / self.ttl_seconds.max(1)
+    }
+}
+
+/// The replay TTL is part of the key so that locations with different TTLs
+/// never share a bucket.
+pub(crate) fn bucket_key(ttl_seconds: u64, bucket: u64) -> String {
+    format!("x402:payments:{ttl_seconds}:{bucket}")
+}
+
+impl<C: ConnectionLike> ReplayStore for HashBucketStore<'_, C> {
+    fn is_used(&mut self, hash: &str) -> Result<bool> {
+        let bucket = self.bucket();
+        for b in [bucket, bucket.saturating_sub(1)] {
+            let used: bool = self
+                .conn
+                .hexists(bucket_key(self.ttl_seconds, b), hash)
+                .map_err(|e| {
+                    X402Error::Runtime(format!("Failed to check payment in Redis: {e}"))
+                })?;
+            if used {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn mark_used(&mut self, hash: &str) -> Result<()> {
+        let bucket = self.bucket();
+        let key = bucket_key(self.ttl_seconds, bucket);
+        let expires_at = (bucket + 2) * self.ttl_seconds.max(1);
+        self.conn
+            .hset::<_, _, _, ()>(&key, hash, "used")
+            .and_then(|()| self.conn.expire_at::<_, ()>(&key, expires_at as i64))
+            .map_err(|e| X402Error::Runtime(format!("Failed to store payment in Redis: {e}")))
+    }
+}
+
+/// The [`ReplayStore`] for `mode` over `conn`.
+pub fn replay_store<'a, C: ConnectionLike>(
+    mode: ReplayStorageMode,
+    conn: &'a mut C,
+    ttl_seconds: u64,
+    now_secs: u64,
+) -> Box<dyn ReplayStore + 'a> {
+    match mode {
+        ReplayStorageMode::Keyspace => Box::new(KeyspaceStore { conn, ttl_seconds }),
+        ReplayStorageMode::HashBucket => Box::new(HashBucketStore {
+            conn,
+            ttl_seconds,
+            now_secs,
+        }),
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 /// Check if a payment signature has been used before (replay prevention).
-pub fn is_payment_used(payment_b64: &str) -> bool {
-    let mut conn = match get_connection() {
-        Some(c) => c,
-        None => return false,
-    };
+/// `ttl_seconds` is the replay TTL the payment would have been stored with.
+/// Fails when Redis cannot be reached or answers with an error; the caller
+/// applies `x402_redis_error_policy`.
+pub fn is_payment_used(
+    payment_b64: &str,
+    mode: ReplayStorageMode,
+    ttl_seconds: u64,
+    timeout: Option<Duration>,
+) -> Result<bool> {
+    let mut conn = get_connection(timeout).ok_or_else(redis_unavailable)?;
+    let hash = payment_hash(payment_b64)?;
+    let mut store = replay_store(mode, &mut conn, ttl_seconds, now_secs());
+    store.is_used(&hash)
+}
 
-    let hash = payment_hash(payment_b64);
-    let key = format!("x402:payment_sig:{hash}");
-    conn.exists::<_, bool>(&key).unwrap_or(false)
+fn redis_unavailable() -> X402Error {
+    X402Error::Runtime("Redis unavailable".into())
 }
 
 /// Store a payment signature as used with TTL.
-pub fn store_payment_as_used(payment_b64: &str, ttl_seconds: u64) -> Result<()> {
-    let mut conn = get_connection().ok_or_else(|| ConfigError::new("Redis not configured"))?;
+pub fn store_payment_as_used(
+    payment_b64: &str,
+    ttl_seconds: u64,
+    mode: ReplayStorageMode,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let mut conn = get_connection(timeout).ok_or_else(redis_unavailable)?;
 
-    let hash = payment_hash(payment_b64);
-    let key = format!("x402:payment_sig:{hash}");
-    conn.set_ex::<_, _, ()>(&key, "used", ttl_seconds as u64)
-        .map_err(|e| ConfigError::new(format!("Failed to store payment in Redis: {e}")))?;
+    let hash = payment_hash(payment_b64)?;
+    replay_store(mode, &mut conn, ttl_seconds, now_secs()).mark_used(&hash)?;
 
-    log::debug!("Stored payment signature as used: {hash} (TTL: {ttl_seconds}s)");
+    log::debug!("Stored payment signature as used: {hash} (TTL: {ttl_seconds}s, {mode:?})");
     Ok(())
 }
 
-pub(crate) fn payment_hash(payment_b64: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(payment_b64.as_bytes());
-    hex::encode(hasher.finalize())
+/// Key for the free-quota counter of `ip` in the fixed window containing `now_secs`.
+pub(crate) fn quota_key(ip: &str, window_secs: u64, now_secs: u64) -> String {
+    let window_epoch = now_secs / window_secs.max(1);
+    format!("x402:quota:{ip}:{window_epoch}")
+}
+
+/// Count one request against the free quota of `ip`.
+///
+/// Returns `Some(true)` while the client is within `quota` requests for the
+/// current window, `Some(false)` once exhausted, and `None` if Redis is
+/// unavailable (the caller should then require payment).
+pub fn consume_free_quota(
+    ip: &str,
+    quota: u64,
+    window_secs: u64,
+    timeout: Option<Duration>,
+) -> Option<bool> {
+    let mut conn = get_connection(timeout)?;
+    let key = quota_key(ip, window_secs, now_secs());
+    let used: u64 = conn.incr(&key, 1).ok()?;
+    if used == 1 {
+        conn.expire::<_, ()>(&key, window_secs as i64).ok()?;
+    }
+    Some(used <= quota)
+}
+
+/// Set the replay hash key. Returns false if a different key is already in
+/// use; it cannot change without a restart.
+pub fn set_replay_hmac_key(key: &[u8]) -> bool {
+    REPLAY_HMAC_KEY.get_or_init(|| key.to_vec()) == key
+}
+
+/// Hash under which `payment_b64` is recorded. The configuration requires
+/// `x402_replay_hmac_secret` wherever Redis is in use, so a missing key means
+/// Redis was set up without it.
+pub(crate) fn payment_hash(payment_b64: &str) -> Result<String> {
+    let key = REPLAY_HMAC_KEY.get().ok_or_else(|| {
+        X402Error::Config("Redis replay protection requires x402_replay_hmac_secret".into())
+    })?;
+    Ok(keyed_payment_hash(key, payment_b64))
+}
+
+/// Hex HMAC-SHA256 of `payment_b64` under `key`.
+fn keyed_payment_hash(key: &[u8], payment_b64: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(payment_b64.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
 }
 
 pub fn is_redis_configured() -> bool {
@@ -72,20 +358,357 @@ pub fn is_redis_configured() -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use redis::{Arg, Cmd, RedisResult, Value};
+    use std::collections::{HashMap, HashSet};
+
+    /// In-memory stand-in for the few commands the replay stores send.
+    #[derive(Default)]
+    struct FakeRedis {
+        keys: HashMap<String, u64>,
+        hashes: HashMap<String, HashSet<String>>,
+        expire_at: HashMap<String, i64>,
+        /// Fail every command, like a Redis that went away.
+        down: bool,
+    }
+
+    impl ConnectionLike for FakeRedis {
+        fn req_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+            if self.down {
+                return Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into());
+            }
+            let args: Vec<String> = cmd
+                .args_iter()
+                .filter_map(|arg| match arg {
+                    Arg::Simple(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+                    Arg::Cursor => None,
+                })
+                .collect();
+            let int = |b: bool| Value::Int(i64::from(b));
+            Ok(match args[0].as_str() {
+                "SETEX" => {
+                    self.keys.insert(args[1].clone(), args[2].parse().unwrap());
+                    Value::Okay
+                }
+                "EXISTS" => int(self.keys.contains_key(&args[1])),
+                "HSET" => int(self
+                    .hashes
+                    .entry(args[1].clone())
+                    .or_default()
+                    .insert(args[2].clone())),
+                "HEXISTS" => int(self
+                    .hashes
+                    .get(&args[1])
+                    .is_some_and(|h| h.contains(&args[2]))),
+                "EXPIREAT" => {
+                    self.expire_at
+                        .insert(args[1].clone(), args[2].parse().unwrap());
+                    int(true)
+                }
+                other => panic!("unexpected command {other}"),
+            })
+        }
+
+        fn req_packed_command(&mut self, _cmd: &[u8]) -> RedisResult<Value> {
+            unreachable!("req_command is overridden")
+        }
+
+        fn req_packed_commands(
+            &mut self,
+            _cmd: &[u8],
+            _offset: usize,
+            _count: usize,
+        ) -> RedisResult<Vec<Value>> {
+            unreachable!("pipelines are not used")
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            true
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_keyspace_store() {
+        let mut redis = FakeRedis::default();
+        let mut store = replay_store(ReplayStorageMode::Keyspace, &mut redis, 600, 1_000);
+        assert!(!store.is_used("abc").unwrap());
+        store.mark_used("abc").unwrap();
+        assert!(store.is_used("abc").unwrap());
+        assert!(!store.is_used("def").unwrap());
+        drop(store);
+        assert_eq!(redis.keys.get("x402:payment_sig:abc"), Some(&600));
+        assert!(redis.hashes.is_empty());
+    }
+
+    #[test]
+    fn test_hash_bucket_store() {
+        let mut redis = FakeRedis::default();
+        // 1_250 falls in bucket 2 of 500 seconds.
+        let mut store = replay_store(ReplayStorageMode::HashBucket, &mut redis, 500, 1_250);
+        assert!(!store.is_used("abc").unwrap());
+        store.mark_used("abc").unwrap();
+        assert!(store.is_used("abc").unwrap());
+        drop(store);
+        assert!(redis.keys.is_empty());
+        assert!(redis.hashes["x402:payments:500:2"].contains("abc"));
+        // Bucket 2 lives until bucket 3 ends.
+        assert_eq!(redis.expire_at["x402:payments:500:2"], 2_000);
+
+        // Still seen from the next bucket, via the previous-bucket lookup.
+        let mut store = replay_store(ReplayStorageMode::HashBucket, &mut redis, 500, 1_749);
+        assert!(store.is_used("abc").unwrap());
+        drop(store);
+        // Two buckets on, Redis would have expired it; it is not looked up either.
+        let mut store = replay_store(ReplayStorageMode::HashBucket, &mut redis, 500, 2_000);
+        assert!(!store.is_used("abc").unwrap());
+        drop(store);
+        // Another replay TTL uses its own buckets.
+        let mut store = replay_store(ReplayStorageMode::HashBucket, &mut redis, 600, 1_250);
+        assert!(!store.is_used("abc").unwrap());
+    }
+
+    #[test]
+    fn test_stores_surface_redis_errors() {
+        for mode in [ReplayStorageMode::Keyspace, ReplayStorageMode::HashBucket] {
+            let mut redis = FakeRedis {
+                down: true,
+                ..FakeRedis::default()
+            };
+            let mut store = replay_store(mode, &mut redis, 600, 1_000);
+            assert!(
+                matches!(store.is_used("abc"), Err(X402Error::Runtime(_))),
+                "{mode:?}"
+            );
+            assert!(store.mark_used("abc").is_err(), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn test_is_payment_used_without_redis_is_an_error() {
+        // No test initializes the shared client, so there is no connection.
+        assert!(REDIS_CLIENT.get().is_none());
+        let err = is_payment_used("payment", ReplayStorageMode::Keyspace, 600, None).unwrap_err();
+        assert_eq!(err, redis_unavailable());
+        assert!(store_payment_as_used("payment", 600, ReplayStorageMode::Keyspace, None).is_err());
+    }
+
+    #[test]
+    fn test_bucket_key() {
+        assert_eq!(bucket_key(86400, 20_000), "x402:payments:86400:20000");
+    }
 
     #[test]
     fn test_payment_hash_deterministic() {
-        let hash = payment_hash("abc");
+        // The key is process-wide; any test may set it first.
+        set_replay_hmac_key(b"0123456789abcdef");
+        assert_eq!(payment_hash("abc").unwrap(), payment_hash("abc").unwrap());
+        assert_ne!(payment_hash("abc").unwrap(), payment_hash("abd").unwrap());
+        // Published HMAC-SHA256 test vector.
         assert_eq!(
-            hash,
+            keyed_payment_hash(b"key", "The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_payment_hash_depends_on_key() {
+        let payment = "eyJ4NDAyVmVyc2lvbiI6Mn0=";
+        assert_ne!(
+            keyed_payment_hash(b"secret-one-0123456789", payment),
+            keyed_payment_hash(b"secret-two-0123456789", payment)
+        );
+        // Not the bare SHA-256 an attacker could precompute.
+        assert_ne!(
+            keyed_payment_hash(b"", "abc"),
             "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
         );
     }
 
     #[test]
     fn test_payment_hash_format() {
-        let hash = payment_hash("test");
+        set_replay_hmac_key(b"0123456789abcdef");
+        let hash = payment_hash("test").unwrap();
         assert_eq!(hash.len(), 64);
         assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
     }
+
+    #[test]
+    fn test_quota_key_fixed_window() {
+        assert_eq!(
+            quota_key("203.0.113.7", 3600, 7200),
+            "x402:quota:203.0.113.7:2"
+        );
+        assert_eq!(
+            quota_key("203.0.113.7", 3600, 10799),
+            "x402:quota:203.0.113.7:2"
+        );
+        assert_eq!(
+            quota_key("203.0.113.7", 3600, 10800),
+            "x402:quota:203.0.113.7:3"
+        );
+        assert_eq!(quota_key("::1", 0, 5), "x402:quota:::1:5");
+    }
+
+    #[test]
+    fn test_price_key_candidates_order() {
+        assert_eq!(
+            price_key_candidates("/api/weather/today"),
+            vec![
+                "/api/weather/today",
+                "x402:price:/api/weather/today/*",
+                "x402:price:/api/weather/*",
+                "x402:price:/api/*",
+                "x402:price:/*",
+                "x402:price:*",
+            ]
+        );
+        assert_eq!(
+            price_key_candidates("/"),
+            vec!["/", "x402:price:/*", "x402:price:*"]
+        );
+    }
+
+    #[test]
+    fn test_first_price_prefers_exact_then_longest_prefix() {
+        let prices: std::collections::HashMap<&str, &str> = [
+            ("/api/weather", "0.05"),
+            ("x402:price:/api/*", "0.02"),
+            ("x402:price:*", "0.01"),
+        ]
+        .into_iter()
+        .collect();
+        let lookup = |key: &str| prices.get(key).map(|p| p.to_string());
+
+        assert_eq!(first_price("/api/weather", lookup).as_deref(), Some("0.05"));
+        assert_eq!(first_price("/api/news", lookup).as_deref(), Some("0.02"));
+        assert_eq!(
+            first_price("/static/logo.png", lookup).as_deref(),
+            Some("0.01")
+        );
+        assert_eq!(first_price("/api/weather", |_| None), None);
+    }
+
+    #[test]
+    fn test_connect_timeout_bounds_slow_redis() {
+        // Accepts connections but never answers, like a stalled Redis.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut held = Vec::new();
+            for stream in listener.incoming() {
+                held.push(stream);
+            }
+        });
+        let client = redis::Client::open(url).unwrap();
+
+        let start = std::time::Instant::now();
+        let price = connect(&client, Some(Duration::from_millis(200)))
+            .and_then(|mut conn| conn.get::<_, Option<String>>("/api").ok().flatten());
+        assert_eq!(price, None);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    fn run_concurrently(threads: usize, f: impl Fn() -> Result<()> + Sync) -> Vec<Result<()>> {
+        let barrier = std::sync::Barrier::new(threads);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        f()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    #[test]
+    fn test_init_guard_runs_init_once_under_contention() {
+        let guard = RedisInitGuard::new(INIT_RETRY_INTERVAL);
+        let cell = OnceLock::new();
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+
+        let results = run_concurrently(16, || {
+            guard.init(&cell, || {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                Ok(42)
+            })
+        });
+
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(cell.get(), Some(&42));
+        assert!(guard.init(&cell, || Ok(7)).is_ok());
+        assert_eq!(cell.get(), Some(&42), "set only once");
+    }
+
+    #[test]
+    fn test_init_guard_shares_error_with_waiting_callers() {
+        let guard = RedisInitGuard::new(Duration::from_secs(60));
+        let cell = OnceLock::<u32>::new();
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+
+        let results = run_concurrently(16, || {
+            guard.init(&cell, || {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                Err(X402Error::Config("boom".into()))
+            })
+        });
+
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        for result in results {
+            assert_eq!(result, Err(X402Error::Config("boom".into())));
+        }
+        assert!(cell.get().is_none());
+    }
+
+    #[test]
+    fn test_init_guard_retries_after_interval() {
+        let guard = RedisInitGuard::new(Duration::ZERO);
+        let cell = OnceLock::new();
+        assert!(guard
+            .init(&cell, || Err(X402Error::Config("down".into())))
+            .is_err());
+        assert!(guard.init(&cell, || Ok(1)).is_ok());
+        assert_eq!(cell.get(), Some(&1));
+    }
+
+    #[test]
+    fn test_open_client_rejects_invalid_url() {
+        assert!(matches!(
+            open_client("not a url", None),
+            Err(X402Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_open_client_init_timeout() {
+        // Accepts connections but never answers, like a stalled Redis.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut held = Vec::new();
+            for stream in listener.incoming() {
+                held.push(stream);
+            }
+        });
+
+        // Without a timeout the client is created lazily, as before.
+        assert!(open_client(&url, None).is_ok());
+
+        let start = std::time::Instant::now();
+        let result = open_client(&url, Some(Duration::from_millis(200)));
+        assert!(matches!(result, Err(X402Error::Runtime(_))));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
 }
@@ -1,4 +1,15 @@
+use crate::ngx_module::config::BrowserDetect;
+use crate::ngx_module::requirements::X402Version;
+use ipnet::IpNet;
+use ngx::ffi::{
+    ngx_hash_key, ngx_http_get_variable, ngx_list_part_t, ngx_list_push, ngx_palloc, ngx_pnalloc,
+    ngx_str_t, ngx_table_elt_t,
+};
 use ngx::http::{Method, Request};
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+pub const DEFAULT_MIME_TYPE: &str = "application/json";
 
 pub fn get_header_value(r: &Request, name: &str) -> Option<String> {
     if name.trim().is_empty() {
@@ -14,16 +25,301 @@ pub fn get_header_value(r: &Request, name: &str) -> Option<String> {
     None
 }
 
-pub fn is_browser_request(r: &Request) -> bool {
-    let content_type = get_header_value(r, "Content-Type");
-    if let Some(ref ct) = content_type {
+/// Value of the nginx variable `name` (lowercase, without the `$`), if it is
+/// defined for this request and not empty.
+pub fn get_variable_value(r: &mut Request, name: &str) -> Option<String> {
+    let mut bytes = name.as_bytes().to_vec();
+    let mut name = ngx_str_t {
+        len: bytes.len(),
+        data: bytes.as_mut_ptr(),
+    };
+    // SAFETY: `name` outlives both calls; nginx only reads it.
+    let value = unsafe {
+        let key = ngx_hash_key(name.data, name.len);
+        ngx_http_get_variable(r.as_mut(), &mut name, key).as_ref()?
+    };
+    if value.not_found() != 0 || value.len() == 0 || value.data.is_null() {
+        return None;
+    }
+    // SAFETY: a found variable's value is `len` bytes at `data`.
+    let bytes = unsafe { std::slice::from_raw_parts(value.data, value.len() as usize) };
+    std::str::from_utf8(bytes).ok().map(str::to_string)
+}
+
+/// Protocol version for the 402 body. A configured version wins; in `auto`
+/// mode (`None`) a client asks for v1 with `X-402-Version: 1` or
+/// `Accept: application/x402+json; version=1`.
+pub fn negotiate_x402_version(
+    configured: Option<X402Version>,
+    version_header: Option<&str>,
+    accept: Option<&str>,
+) -> X402Version {
+    if let Some(version) = configured {
+        return version;
+    }
+    if version_header.is_some_and(|v| v.trim() == "1") {
+        return X402Version::V1;
+    }
+    let wants_v1 = accept.is_some_and(|accept| {
+        accept.split(',').any(|media| {
+            let mut parts = media.split(';').map(str::trim);
+            parts
+                .next()
+                .is_some_and(|t| t.eq_ignore_ascii_case("application/x402+json"))
+                && parts.any(|p| p.replace(' ', "").eq_ignore_ascii_case("version=1"))
+        })
+    });
+    if wants_v1 {
+        X402Version::V1
+    } else {
+        X402Version::V2
+    }
+}
+
+/// Decode a base64 `Payment-Signature` value into its JSON payload.
+pub fn decode_payment_payload(payment_b64: &str) -> Option<serde_json::Value> {
+    let bytes =
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, payment_b64).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Where payment payloads commonly carry the signer: the `exact` scheme's
+/// EIP-3009 authorization, then generic EIP-712 shapes, nested under
+/// `payload` (x402 v1/v2) or at the top level.
+const PAYER_POINTERS: &[&str] = &[
+    "/payload/authorization/from",
+    "/payload/from",
+    "/payload/signer",
+    "/payload/message/from",
+    "/from",
+    "/signer",
+    "/message/from",
+];
+
+/// Payer address claimed by a decoded payment payload. Not verified: the
+/// facilitator's answer is authoritative once it has checked the signature.
+pub fn extract_payer_from_payload(payload: &serde_json::Value) -> Option<String> {
+    PAYER_POINTERS
+        .iter()
+        .filter_map(|pointer| payload.pointer(pointer)?.as_str())
+        .find(|addr| crate::config::validation::validate_ethereum_address(addr).is_ok())
+        .map(str::to_lowercase)
+}
+
+/// Set a request header for the upstream. A value the client sent under the
+/// same name is overwritten rather than forwarded alongside, so it cannot be
+/// spoofed.
+pub fn set_header_in(r: &mut Request, name: &str, value: &str) -> Option<()> {
+    let pool = r.pool();
+    let mut replaced = false;
+    unsafe {
+        let mut part: *mut ngx_list_part_t = &mut r.as_mut().headers_in.headers.part;
+        while !part.is_null() {
+            let elts = (*part).elts as *mut ngx_table_elt_t;
+            for i in 0..(*part).nelts {
+                let header = &mut *elts.add(i);
+                let key = ngx::core::NgxStr::from_ngx_str(header.key);
+                if !key.as_bytes().eq_ignore_ascii_case(name.as_bytes()) {
+                    continue;
+                }
+                let data = ngx_pnalloc(pool.as_ptr(), value.len()) as *mut u8;
+                if data.is_null() {
+                    return None;
+                }
+                std::ptr::copy_nonoverlapping(value.as_ptr(), data, value.len());
+                header.value = ngx_str_t {
+                    len: value.len(),
+                    data,
+                };
+                replaced = true;
+            }
+            part = (*part).next;
+        }
+    }
+    if replaced {
+        Some(())
+    } else {
+        r.add_header_in(name, value)
+    }
+}
+
+/// Drop every request header named in `names`, so the upstream never sees
+/// what the client sent under them. nginx lists cannot remove entries, so the
+/// list is rebuilt from the remaining headers; the `headers_in` shortcuts
+/// (`host`, `user_agent`, ...) keep pointing at the old, unchanged copies.
+pub fn remove_headers_in(r: &mut Request, names: &[&str]) -> Option<()> {
+    let pool = r.pool();
+    let headers = &mut r.as_mut().headers_in.headers;
+    let mut kept = Vec::new();
+    let mut removed = false;
+    unsafe {
+        let mut part: *mut ngx_list_part_t = &mut headers.part;
+        while !part.is_null() {
+            let elts = (*part).elts as *const ngx_table_elt_t;
+            for i in 0..(*part).nelts {
+                let header = *elts.add(i);
+                let key = ngx::core::NgxStr::from_ngx_str(header.key);
+                if names
+                    .iter()
+                    .any(|name| key.as_bytes().eq_ignore_ascii_case(name.as_bytes()))
+                {
+                    removed = true;
+                } else {
+                    kept.push(header);
+                }
+            }
+            part = (*part).next;
+        }
+        if !removed {
+            return Some(());
+        }
+        let elts = ngx_palloc(pool.as_ptr(), headers.size * headers.nalloc);
+        if elts.is_null() {
+            return None;
+        }
+        headers.part = ngx_list_part_t {
+            elts,
+            nelts: 0,
+            next: std::ptr::null_mut(),
+        };
+        headers.last = &mut headers.part;
+        for header in kept {
+            let slot = ngx_list_push(headers) as *mut ngx_table_elt_t;
+            if slot.is_null() {
+                return None;
+            }
+            *slot = header;
+        }
+    }
+    Some(())
+}
+
+/// First (client-most) address in an `X-Forwarded-For` header.
+pub fn forwarded_client_ip(header: &str) -> Option<String> {
+    header
+        .split(',')
+        .map(str::trim)
+        .find(|ip| !ip.is_empty())
+        .map(String::from)
+}
+
+/// Client IP for per-client accounting. `X-Forwarded-For` is only honoured
+/// with `trust_forwarded`, since clients can set it to anything.
+pub fn client_ip(r: &Request, trust_forwarded: bool) -> Option<String> {
+    if trust_forwarded {
+        if let Some(ip) = get_header_value(r, "X-Forwarded-For")
+            .as_deref()
+            .and_then(forwarded_client_ip)
+        {
+            return Some(ip);
+        }
+    }
+    let c = r.connection();
+    if c.is_null() {
+        return None;
+    }
+    let addr = unsafe { ngx::core::NgxStr::from_ngx_str((*c).addr_text) };
+    addr.to_str().ok().map(String::from)
+}
+
+/// Whether `addr` falls in any of the `x402_whitelist_ip` ranges.
+pub fn is_whitelisted(addr: &IpAddr, ranges: &[IpNet]) -> bool {
+    ranges.iter().any(|range| range.contains(addr))
+}
+
+/// Whether `user_agent` starts with any of the `x402_skip_user_agents` prefixes.
+pub fn is_skipped_user_agent(user_agent: &str, prefixes: &[String]) -> bool {
+    prefixes
+        .iter()
+        .any(|prefix| user_agent.starts_with(prefix.as_str()))
+}
+
+/// `Accept` type, and `Content-Type` of the answer, of `x402_payment_info` requests.
+pub const PAYMENT_INFO_MEDIA_TYPE: &str = "application/x402+json";
+
+/// Whether a `GET` asks for the payment terms instead of the resource
+/// (`x402_payment_info`).
+pub fn is_payment_info_request(r: &Request, info_path: &str) -> bool {
+    r.method() == Method::GET
+        && wants_payment_info(
+            r.path().to_str().unwrap_or("/"),
+            info_path,
+            get_header_value(r, "Accept").as_deref(),
+            get_header_value(r, "X-402-Info").as_deref(),
+        )
+}
+
+/// The request targets `x402_payment_info_path`, accepts
+/// [`PAYMENT_INFO_MEDIA_TYPE`], or sends `X-402-Info: true`.
+pub(crate) fn wants_payment_info(
+    path: &str,
+    info_path: &str,
+    accept: Option<&str>,
+    info_header: Option<&str>,
+) -> bool {
+    let accepts_info = accept.is_some_and(|accept| {
+        accept.split(',').any(|media_type| {
+            media_type
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case(PAYMENT_INFO_MEDIA_TYPE)
+        })
+    });
+    path == info_path
+        || accepts_info
+        || info_header.is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+
+pub fn is_browser_request(r: &Request, detect: BrowserDetect) -> bool {
+    let sec_fetch = sec_fetch_navigation(
+        get_header_value(r, "Sec-Fetch-Mode").as_deref(),
+        get_header_value(r, "Sec-Fetch-Dest").as_deref(),
+    );
+    match (detect, sec_fetch) {
+        (BrowserDetect::Legacy, _) | (BrowserDetect::Auto, None) => legacy_is_browser(
+            get_header_value(r, "Content-Type").as_deref(),
+            get_header_value(r, "Accept").as_deref(),
+            get_header_value(r, "User-Agent").as_deref(),
+        ),
+        (_, sec_fetch) => sec_fetch.unwrap_or(false),
+    }
+}
+
+/// What the Fetch Metadata headers say: `Some(true)` for a page load,
+/// `Some(false)` for `fetch()`/XHR-style requests, `None` when the client
+/// sent neither header (or values this doesn't know).
+pub(crate) fn sec_fetch_navigation(mode: Option<&str>, dest: Option<&str>) -> Option<bool> {
+    match mode.map(str::trim) {
+        Some(m) if m.eq_ignore_ascii_case("navigate") => return Some(true),
+        Some(m)
+            if ["cors", "no-cors", "same-origin", "websocket"]
+                .iter()
+                .any(|api| m.eq_ignore_ascii_case(api)) =>
+        {
+            return Some(false)
+        }
+        _ => {}
+    }
+    dest.map(str::trim)
+        .filter(|d| d.eq_ignore_ascii_case("document"))
+        .map(|_| true)
+}
+
+/// Pre-Fetch-Metadata guess from `Content-Type`, `Accept` and `User-Agent`.
+pub(crate) fn legacy_is_browser(
+    content_type: Option<&str>,
+    accept: Option<&str>,
+    user_agent: Option<&str>,
+) -> bool {
+    if let Some(ct) = content_type {
         if ct.to_lowercase().starts_with("application/json") {
             return false;
         }
     }
 
-    let accept = get_header_value(r, "Accept");
-    if let Some(ref accept_header) = accept {
+    if let Some(accept_header) = accept {
         let lower = accept_header.to_lowercase();
         if lower.contains("text/html") {
             return true;
@@ -33,8 +329,7 @@ pub fn is_browser_request(r: &Request) -> bool {
         }
     }
 
-    let user_agent = get_header_value(r, "User-Agent");
-    user_agent.as_ref().is_some_and(|ua| {
+    user_agent.is_some_and(|ua| {
         let lower = ua.to_lowercase();
         let is_browser = lower.contains("mozilla")
             && (lower.contains("chrome")
@@ -62,12 +357,95 @@ pub fn is_websocket_request(r: &Request) -> bool {
     has_upgrade && has_connection
 }
 
-pub fn should_skip_method(r: &Request) -> bool {
-    let method = r.method();
-    method == Method::OPTIONS || method == Method::HEAD || method == Method::TRACE
+/// `OPTIONS` and `TRACE` are never gated; `HEAD` only with `x402_skip_head on`.
+/// The method is the one [`get_effective_method`] sees.
+pub fn should_skip_method(
+    r: &Request,
+    skip_head: bool,
+    override_header: Option<&str>,
+    override_methods: &[String],
+) -> bool {
+    skips_method(
+        &get_effective_method(r, override_header, override_methods),
+        skip_head,
+    )
 }
 
-pub fn build_full_url(r: &Request) -> Option<String> {
+/// The request method, or the one named by `override_header`
+/// (`x402_method_override_header`, e.g. `X-HTTP-Method-Override`) when the
+/// header holds one of `override_methods`. Anything else is ignored.
+pub fn get_effective_method(
+    r: &Request,
+    override_header: Option<&str>,
+    override_methods: &[String],
+) -> Method {
+    override_header
+        .and_then(|name| get_header_value(r, name))
+        .and_then(|value| override_method(&value, override_methods))
+        .unwrap_or_else(|| r.method())
+}
+
+pub(crate) fn override_method(value: &str, allowed: &[String]) -> Option<Method> {
+    let value = value.trim();
+    let name = allowed.iter().find(|m| m.eq_ignore_ascii_case(value))?;
+    parse_method(name)
+}
+
+/// `ngx::http::Method` has no working `FromStr`.
+fn parse_method(name: &str) -> Option<Method> {
+    Some(match name {
+        "GET" => Method::GET,
+        "HEAD" => Method::HEAD,
+        "POST" => Method::POST,
+        "PUT" => Method::PUT,
+        "DELETE" => Method::DELETE,
+        "CONNECT" => Method::CONNECT,
+        "OPTIONS" => Method::OPTIONS,
+        "TRACE" => Method::TRACE,
+        "PATCH" => Method::PATCH,
+        _ => return None,
+    })
+}
+
+pub(crate) fn skips_method(method: &Method, skip_head: bool) -> bool {
+    *method == Method::OPTIONS || *method == Method::TRACE || (skip_head && *method == Method::HEAD)
+}
+
+/// Whether the request is in `x402_passthrough_on_methods`: let through
+/// unpaid, but still by this module, unlike [`skips_method`]. Both the request
+/// `method` and the `effective` one chosen by `x402_method_override_header`
+/// must be listed, since the upstream still sees the request method.
+pub(crate) fn is_passthrough_method(
+    method: &Method,
+    effective: &Method,
+    passthrough: &HashSet<String>,
+) -> bool {
+    !passthrough.is_empty()
+        && passthrough.contains(method.as_str())
+        && passthrough.contains(effective.as_str())
+}
+
+/// `path` without the `x402_resource_prefix`/`x402_resource_suffix`. The prefix
+/// only matches whole segments: `/service/v1` strips `/service/v1/api` and
+/// `/service/v1` itself, not `/service/v10`.
+pub fn strip_resource_path<'a>(
+    path: &'a str,
+    prefix: Option<&str>,
+    suffix: Option<&str>,
+) -> &'a str {
+    let path = prefix
+        .and_then(|prefix| path.strip_prefix(prefix))
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+        .unwrap_or(path);
+    suffix
+        .and_then(|suffix| path.strip_suffix(suffix))
+        .unwrap_or(path)
+}
+
+/// Resource URL for the request: the absolute URL with `uri` as the path. With
+/// `include_query`, the query string is kept so that e.g. `/api?symbol=AAPL`
+/// and `/api?symbol=MSFT` are distinct.
+pub fn build_full_url(r: &Request, uri: &str, include_query: bool) -> Option<String> {
     let scheme = get_header_value(r, "X-Forwarded-Proto")
         .and_then(|p| {
             let lower = p.to_lowercase();
@@ -80,24 +458,47 @@ pub fn build_full_url(r: &Request) -> Option<String> {
         .unwrap_or_else(|| "http".to_string());
 
     let host = get_header_value(r, "Host")?;
-    let uri = r.path().to_str().ok()?;
-    let uri_normalized = if uri.starts_with('/') {
-        uri.to_string()
+    let args = if include_query {
+        let args = r.as_ref().args;
+        unsafe { ngx::core::NgxStr::from_ngx_str(args) }
+            .to_str()
+            .ok()
     } else {
-        format!("/{uri}")
+        None
     };
+    Some(format_full_url(&scheme, &host, uri, args))
+}
 
-    Some(format!("{scheme}://{host}{uri_normalized}"))
+pub(crate) fn format_full_url(scheme: &str, host: &str, uri: &str, args: Option<&str>) -> String {
+    let mut url = if uri.starts_with('/') {
+        format!("{scheme}://{host}{uri}")
+    } else {
+        format!("{scheme}://{host}/{uri}")
+    };
+    if let Some(args) = args.filter(|a| !a.is_empty()) {
+        url.push('?');
+        url.push_str(args);
+    }
+    url
 }
 
 pub fn infer_mime_type(r: &Request) -> String {
-    if let Some(ct) = get_header_value(r, "Content-Type") {
-        let mime = ct.split(';').next().unwrap_or("application/json").trim();
+    mime_type_from_headers(
+        get_header_value(r, "Content-Type").as_deref(),
+        get_header_value(r, "Accept").as_deref(),
+    )
+}
+
+/// MIME type advertised in the 402 `resource`, from the request's
+/// `Content-Type` and `Accept` headers.
+pub fn mime_type_from_headers(content_type: Option<&str>, accept: Option<&str>) -> String {
+    if let Some(ct) = content_type {
+        let mime = ct.split(';').next().unwrap_or(DEFAULT_MIME_TYPE).trim();
         if !mime.is_empty() {
             return mime.to_string();
         }
     }
-    if let Some(accept) = get_header_value(r, "Accept") {
+    if let Some(accept) = accept {
         let lower = accept.to_lowercase();
         if lower.contains("application/json") {
             return "application/json".to_string();
@@ -106,5 +507,332 @@ pub fn infer_mime_type(r: &Request) -> String {
             return "text/html".to_string();
         }
     }
-    "application/json".to_string()
+    DEFAULT_MIME_TYPE.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_whitelisted() {
+        let ranges: Vec<IpNet> = ["10.0.0.0/8", "127.0.0.1/32", "fd00::/8"]
+            .iter()
+            .map(|range| range.parse().unwrap())
+            .collect();
+        let whitelisted = |ip: &str| is_whitelisted(&ip.parse().unwrap(), &ranges);
+
+        assert!(whitelisted("10.1.2.3"));
+        assert!(whitelisted("10.255.255.255"));
+        assert!(whitelisted("127.0.0.1"));
+        assert!(whitelisted("fd12:3456::1"));
+        assert!(!whitelisted("11.0.0.1"));
+        assert!(!whitelisted("127.0.0.2"));
+        assert!(!whitelisted("::1"));
+        assert!(!is_whitelisted(&"10.1.2.3".parse().unwrap(), &[]));
+    }
+
+    #[test]
+    fn test_skips_method() {
+        assert!(skips_method(&Method::OPTIONS, false));
+        assert!(skips_method(&Method::TRACE, false));
+        assert!(
+            !skips_method(&Method::HEAD, false),
+            "HEAD gets a 402 by default"
+        );
+        assert!(skips_method(&Method::HEAD, true));
+        assert!(!skips_method(&Method::GET, true));
+        assert!(!skips_method(&Method::POST, false));
+    }
+
+    #[test]
+    fn test_is_passthrough_method() {
+        let passthrough: HashSet<String> = ["GET", "HEAD"].iter().map(|m| m.to_string()).collect();
+        let get = Method::GET;
+        assert!(is_passthrough_method(&get, &get, &passthrough));
+        assert!(is_passthrough_method(
+            &Method::HEAD,
+            &Method::HEAD,
+            &passthrough
+        ));
+        assert!(!is_passthrough_method(
+            &Method::POST,
+            &Method::POST,
+            &passthrough
+        ));
+        assert!(!is_passthrough_method(&get, &get, &HashSet::new()));
+        // `POST` with `X-HTTP-Method-Override: GET` reaches the upstream as a POST.
+        assert!(!is_passthrough_method(&Method::POST, &get, &passthrough));
+        assert!(!is_passthrough_method(&get, &Method::POST, &passthrough));
+    }
+
+    #[test]
+    fn test_override_method() {
+        let allowed: Vec<String> = crate::config::validation::DEFAULT_METHOD_OVERRIDE_METHODS
+            .iter()
+            .map(|m| m.to_string())
+            .collect();
+        assert_eq!(override_method("DELETE", &allowed), Some(Method::DELETE));
+        assert_eq!(override_method(" put ", &allowed), Some(Method::PUT));
+        // Not allowed or not a method: the actual method stays in effect.
+        assert_eq!(override_method("OPTIONS", &allowed), None);
+        assert_eq!(override_method("TRACE", &allowed), None);
+        assert_eq!(override_method("HEAD", &allowed), None);
+        assert_eq!(override_method("FETCH", &allowed), None);
+        assert_eq!(override_method("", &allowed), None);
+
+        let put_only = vec!["PUT".to_string()];
+        assert_eq!(override_method("PUT", &put_only), Some(Method::PUT));
+        assert_eq!(override_method("DELETE", &put_only), None);
+        assert_eq!(override_method("PUT", &[]), None);
+    }
+
+    #[test]
+    fn test_wants_payment_info() {
+        let info = |path, accept, header| wants_payment_info(path, "/x402/info", accept, header);
+        assert!(info("/x402/info", None, None));
+        assert!(info("/api", Some("application/x402+json"), None));
+        assert!(info(
+            "/api",
+            Some("text/html, Application/X402+JSON;q=0.9"),
+            None
+        ));
+        assert!(info("/api", None, Some("true")));
+        assert!(info("/api", None, Some(" TRUE ")));
+
+        assert!(!info("/api", None, None));
+        assert!(!info("/x402/info/more", None, None));
+        assert!(!info("/api", Some("application/json"), None));
+        assert!(!info("/api", Some("application/x402+jsonx"), None));
+        assert!(!info("/api", None, Some("1")));
+    }
+
+    #[test]
+    fn test_is_skipped_user_agent() {
+        let prefixes = ["kube-probe", "GoogleHC", "ELB-HealthChecker"].map(String::from);
+        assert!(is_skipped_user_agent("kube-probe/1.29", &prefixes));
+        assert!(is_skipped_user_agent("GoogleHC/1.0", &prefixes));
+        assert!(is_skipped_user_agent("ELB-HealthChecker/2.0", &prefixes));
+        assert!(!is_skipped_user_agent(
+            "Mozilla/5.0 kube-probe/1.29",
+            &prefixes
+        ));
+        assert!(!is_skipped_user_agent("Kube-Probe/1.29", &prefixes));
+        assert!(!is_skipped_user_agent("curl/8.5.0", &prefixes));
+        assert!(!is_skipped_user_agent("", &prefixes));
+        assert!(!is_skipped_user_agent("kube-probe/1.29", &[]));
+    }
+
+    #[test]
+    fn test_negotiate_x402_version() {
+        use X402Version::{V1, V2};
+        assert_eq!(negotiate_x402_version(Some(V2), Some("1"), None), V2);
+        assert_eq!(negotiate_x402_version(Some(V1), None, None), V1);
+        assert_eq!(negotiate_x402_version(None, None, None), V2);
+        assert_eq!(negotiate_x402_version(None, Some("1"), None), V1);
+        assert_eq!(negotiate_x402_version(None, Some("2"), None), V2);
+        assert_eq!(
+            negotiate_x402_version(None, None, Some("application/x402+json; version=1")),
+            V1
+        );
+        assert_eq!(
+            negotiate_x402_version(
+                None,
+                None,
+                Some("text/html, application/x402+json;version=2")
+            ),
+            V2
+        );
+    }
+
+    #[test]
+    fn test_decode_payment_payload() {
+        let b64 = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            r#"{"x402Version":2,"payload":{}}"#,
+        );
+        let payload = decode_payment_payload(&b64).unwrap();
+        assert_eq!(payload["x402Version"], 2);
+        assert!(decode_payment_payload("not base64!").is_none());
+        assert!(decode_payment_payload("bm90IGpzb24=").is_none());
+    }
+
+    #[test]
+    fn test_extract_payer_from_payload() {
+        const PAYER: &str = "0x9999999999999999999999999999999999999999";
+        // x402 `exact` payment: EIP-3009 TransferWithAuthorization.
+        let exact = serde_json::json!({
+            "x402Version": 2,
+            "accepted": { "scheme": "exact", "network": "eip155:8453" },
+            "payload": {
+                "signature": "0xabc",
+                "authorization": {
+                    "from": "0x9999999999999999999999999999999999999999",
+                    "to": "0x1234567890abcdef1234567890abcdef12345678",
+                    "value": "1000",
+                },
+            },
+        });
+        assert_eq!(extract_payer_from_payload(&exact).as_deref(), Some(PAYER));
+
+        // Typed data with the signer in the EIP-712 message.
+        let typed = serde_json::json!({
+            "payload": {
+                "domain": { "name": "USD Coin", "version": "2", "chainId": 8453 },
+                "primaryType": "TransferWithAuthorization",
+                "message": { "from": "0x9999999999999999999999999999999999999999" },
+            },
+        });
+        assert_eq!(extract_payer_from_payload(&typed).as_deref(), Some(PAYER));
+
+        let signer = serde_json::json!({
+            "signer": "0x9999999999999999999999999999999999999999",
+        });
+        assert_eq!(extract_payer_from_payload(&signer).as_deref(), Some(PAYER));
+
+        // Checksummed addresses are lowercased; non-addresses are skipped.
+        let mixed = serde_json::json!({
+            "payload": {
+                "from": "not an address\nX-Injected: 1",
+                "signer": "0xABCDEFABCDEFABCDEFABCDEFABCDEFABCDEFABCD",
+            },
+        });
+        assert_eq!(
+            extract_payer_from_payload(&mixed).as_deref(),
+            Some("0xabcdefabcdefabcdefabcdefabcdefabcdefabcd")
+        );
+
+        assert_eq!(extract_payer_from_payload(&serde_json::json!({})), None);
+        assert_eq!(
+            extract_payer_from_payload(&serde_json::json!({ "from": 42 })),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sec_fetch_navigation_modes() {
+        assert_eq!(sec_fetch_navigation(Some("navigate"), None), Some(true));
+        assert_eq!(sec_fetch_navigation(Some("Navigate"), None), Some(true));
+        for api in ["cors", "no-cors", "same-origin", "websocket"] {
+            assert_eq!(sec_fetch_navigation(Some(api), None), Some(false), "{api}");
+            // The mode wins over the destination.
+            assert_eq!(
+                sec_fetch_navigation(Some(api), Some("document")),
+                Some(false),
+                "{api}"
+            );
+        }
+        assert_eq!(sec_fetch_navigation(None, Some("document")), Some(true));
+        assert_eq!(sec_fetch_navigation(None, Some("empty")), None);
+        assert_eq!(sec_fetch_navigation(Some("prefetch"), None), None);
+        assert_eq!(
+            sec_fetch_navigation(Some("prefetch"), Some("document")),
+            Some(true)
+        );
+        assert_eq!(sec_fetch_navigation(None, None), None);
+    }
+
+    #[test]
+    fn test_legacy_is_browser() {
+        const CHROME: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Safari/537.36";
+        assert!(legacy_is_browser(None, Some("text/html,*/*"), None));
+        assert!(!legacy_is_browser(
+            None,
+            Some("application/json"),
+            Some(CHROME)
+        ));
+        assert!(!legacy_is_browser(
+            Some("application/json"),
+            Some("text/html"),
+            Some(CHROME)
+        ));
+        assert!(legacy_is_browser(None, None, Some(CHROME)));
+        assert!(!legacy_is_browser(None, None, Some("curl/8.4.0")));
+        assert!(!legacy_is_browser(None, None, None));
+    }
+
+    #[test]
+    fn test_mime_type_from_headers_browser() {
+        assert_eq!(
+            mime_type_from_headers(None, Some("text/html,application/xhtml+xml,*/*;q=0.8")),
+            "text/html"
+        );
+    }
+
+    #[test]
+    fn test_mime_type_from_headers_api() {
+        assert_eq!(
+            mime_type_from_headers(None, Some("application/json")),
+            "application/json"
+        );
+        assert_eq!(
+            mime_type_from_headers(Some("application/json; charset=utf-8"), None),
+            "application/json"
+        );
+        assert_eq!(mime_type_from_headers(None, None), "application/json");
+        assert_eq!(
+            mime_type_from_headers(Some(""), Some("*/*")),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn test_forwarded_client_ip() {
+        assert_eq!(
+            forwarded_client_ip("203.0.113.7, 10.0.0.1").as_deref(),
+            Some("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded_client_ip("  2001:db8::1 ").as_deref(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(
+            forwarded_client_ip(" , 198.51.100.2").as_deref(),
+            Some("198.51.100.2")
+        );
+        assert_eq!(forwarded_client_ip(""), None);
+    }
+
+    #[test]
+    fn test_strip_resource_path() {
+        let prefix = Some("/service/v1");
+        assert_eq!(
+            strip_resource_path("/service/v1/api/data", prefix, None),
+            "/api/data"
+        );
+        assert_eq!(
+            strip_resource_path("/other/api", prefix, None),
+            "/other/api"
+        );
+        assert_eq!(
+            strip_resource_path("/service/v10/api", prefix, None),
+            "/service/v10/api"
+        );
+        assert_eq!(strip_resource_path("/service/v1", prefix, None), "");
+        assert_eq!(
+            strip_resource_path("/service/v1/api/data.json", prefix, Some(".json")),
+            "/api/data"
+        );
+        assert_eq!(
+            strip_resource_path("/api/data", None, Some(".json")),
+            "/api/data"
+        );
+        assert_eq!(strip_resource_path("/api", None, None), "/api");
+    }
+
+    #[test]
+    fn test_format_full_url_query() {
+        assert_eq!(
+            format_full_url("https", "example.com", "/api", Some("symbol=AAPL")),
+            "https://example.com/api?symbol=AAPL"
+        );
+        assert_eq!(
+            format_full_url("https", "example.com", "/api", None),
+            "https://example.com/api"
+        );
+        assert_eq!(
+            format_full_url("http", "example.com", "api", Some("")),
+            "http://example.com/api"
+        );
+    }
 }
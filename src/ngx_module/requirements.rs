@@ -1,41 +1,73 @@
-use crate::config::validation::chain_id_to_network;
-use crate::ngx_module::config::ParsedX402Config;
-use crate::ngx_module::error::{ConfigError, Result};
+use crate::config::validation::{chain_id_to_network, network_to_chain_id};
+use crate::ngx_module::config::{
+    ParsedX402Config, Scheme, DEFAULT_ASSET_DECIMALS, DEFAULT_TTL_SECONDS,
+};
+use crate::ngx_module::error::{Result, X402Error};
+use crate::ngx_module::request::DEFAULT_MIME_TYPE;
 use rust_decimal::Decimal;
-use std::str::FromStr;
+use sha2::{Digest, Sha256};
 use x402_types::chain::ChainId;
 use x402_types::proto::v2::{PaymentRequired, ResourceInfo, X402Version2};
 
 pub type PaymentRequirements = x402_types::proto::v2::PaymentRequirements;
 pub type PaymentRequiredResponse = PaymentRequired<PaymentRequirements>;
+pub type PaymentRequirementsV1 = x402_types::proto::v1::PaymentRequirements;
+pub type PaymentRequiredV1 = x402_types::proto::v1::PaymentRequired<PaymentRequirementsV1>;
 
-fn amount_to_smallest_unit(amount: Decimal, decimals: u8) -> String {
-    let multiplier = Decimal::from(10u64.pow(decimals as u32));
-    (amount * multiplier).normalize().to_string()
+/// x402 protocol version of the 402 response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum X402Version {
+    V1,
+    V2,
+}
+
+/// `amount` in the asset's smallest unit. Works on the digits rather than
+/// multiplying by `10^decimals`, which would overflow for the up to 28 decimals
+/// an asset may have.
+pub fn amount_to_smallest_unit(amount: Decimal, decimals: u8) -> String {
+    let amount = amount.normalize();
+    let (mantissa, scale) = (amount.mantissa(), amount.scale());
+    let decimals = u32::from(decimals);
+    if mantissa == 0 {
+        "0".to_string()
+    } else if decimals >= scale {
+        format!("{mantissa}{}", "0".repeat((decimals - scale) as usize))
+    } else {
+        Decimal::from_i128_with_scale(mantissa, scale - decimals).to_string()
+    }
 }
 
 fn resolve_network(config: &ParsedX402Config) -> Result<ChainId> {
-    if let Some(chain_id) = config.network_id {
-        chain_id_to_network(chain_id).map_err(ConfigError::new)?;
-        Ok(ChainId::new("eip155", chain_id.to_string()))
+    let network = if let Some(chain_id) = config.network_id {
+        chain_id_to_network(&chain_id.to_string()).map_err(X402Error::Config)?;
+        ChainId::new("eip155", chain_id.to_string())
     } else if let Some(ref net) = config.network {
-        if net.contains(':') {
-            ChainId::from_str(net)
-                .map_err(|_| ConfigError::new(format!("Invalid CAIP-2 network format: {net}")))
-        } else {
-            ChainId::from_network_name(net)
-                .ok_or_else(|| ConfigError::new(format!("Unsupported network name: {net}")))
-        }
+        network_to_chain_id(net).map_err(X402Error::Config)?
     } else {
-        Ok(ChainId::new("eip155", "8453"))
+        ChainId::new("eip155", "8453")
+    };
+    if let Some(ref allowed) = config.allowed_networks {
+        let caip = network.to_string();
+        if !allowed.contains(&caip) {
+            return Err(X402Error::Config(format!(
+                "Network {caip} is not in x402_allowed_networks"
+            )));
+        }
     }
+    Ok(network)
 }
 
-fn default_usdc_address(network: &ChainId) -> Option<&'static str> {
+pub(crate) fn default_usdc_address(network: &ChainId) -> Option<&'static str> {
     match (network.namespace.as_str(), network.reference.as_str()) {
         ("eip155", "8453") => Some("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913"),
         ("eip155", "84532") => Some("0x036CbD53842c5426634e7929541eC2318f3dCF7e"),
         ("eip155", "137") => Some("0x3c499c542cEF5E3811e1192ce70d8cC03d5c3359"),
+        ("eip155", "1") => Some("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+        ("eip155", "10") => Some("0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85"),
+        ("eip155", "42161") => Some("0xaf88d065e77c8cC2239327C5EDb3A432268e5831"),
+        ("eip155", "43114") => Some("0xB97EF9Ef8734C71904D8002F8b6Bc66Dd9c48a6E"),
+        // No default on BNB Chain (Binance-Peg USDC has 18 decimals) or zkSync
+        // Era: set `x402_asset` and `x402_asset_decimals` there.
         _ => None,
     }
 }
@@ -51,6 +83,10 @@ fn eip712_extra_for_asset(asset: &str) -> Option<serde_json::Value> {
     let usdc_addrs = [
         "0x833589fcd6edb6e08f4c7c32d4f71b54bda02913",
         "0x3c499c542cef5e3811e1192ce70d8cc03d5c3359",
+        "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+        "0x0b2c639c533813f4aa9d7837caf62653d097ff85",
+        "0xaf88d065e77c8cc2239327c5edb3a432268e5831",
+        "0xb97ef9ef8734c71904d8002f8b6bc66dd9c48a6e",
     ];
     if usdc_addrs.contains(&normalized.as_str()) {
         Some(serde_json::json!({
@@ -62,22 +98,198 @@ fn eip712_extra_for_asset(asset: &str) -> Option<serde_json::Value> {
     }
 }
 
+/// Ticker symbol for the well-known assets in [`eip712_extra_for_asset`].
+pub fn well_known_symbol(asset: &str) -> Option<&'static str> {
+    eip712_extra_for_asset(asset).map(|_| "USDC")
+}
+
+/// Build a default description such as `Access to Weather Data` from the last
+/// two meaningful path segments of `resource` (a path or full URL). `api` and
+/// version segments like `v1` are skipped.
+pub fn generate_auto_description(resource: &str) -> String {
+    let path = resource.split(['?', '#']).next().unwrap_or("");
+    let path = match path.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+        None => path,
+    };
+    let is_noise = |seg: &str| {
+        seg.eq_ignore_ascii_case("api")
+            || (seg.len() > 1
+                && seg.starts_with(['v', 'V'])
+                && seg[1..].chars().all(|c| c.is_ascii_digit()))
+    };
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|seg| !seg.is_empty() && !is_noise(seg))
+        .collect();
+    let words: Vec<String> = segments[segments.len().saturating_sub(2)..]
+        .iter()
+        .flat_map(|seg| seg.split(['-', '_', '.']))
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    if words.is_empty() {
+        "Access to this resource".to_string()
+    } else {
+        format!("Access to {}", words.join(" "))
+    }
+}
+
+/// Set `key` in the requirements `extra` object, creating it if needed.
+fn insert_extra(extra: &mut Option<serde_json::Value>, key: &str, value: serde_json::Value) {
+    if let Some(map) = extra
+        .get_or_insert_with(|| serde_json::json!({}))
+        .as_object_mut()
+    {
+        map.insert(key.to_string(), value);
+    }
+}
+
+/// Server nonce of `x402_nonce`: `hex(sha256(pay_to || amount || network ||
+/// slot_ms))`. Timestamps are rounded down to the nonce TTL, so every worker
+/// issues the same nonce within a slot and past nonces can be recomputed
+/// rather than stored.
+pub fn requirements_nonce(pay_to: &str, amount: &str, network: &str, slot_ms: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(pay_to.to_lowercase().as_bytes());
+    hasher.update(amount.as_bytes());
+    hasher.update(network.as_bytes());
+    hasher.update(slot_ms.to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Start of the nonce slot containing `now_ms`.
+pub(crate) fn nonce_slot(now_ms: u64, nonce_ttl_ms: u64) -> u64 {
+    let ttl = nonce_ttl_ms.max(1);
+    now_ms / ttl * ttl
+}
+
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Whether `nonce` was issued for `req` in the current slot or one of the
+/// last `ceil(payment_ttl / nonce_ttl)`: a client may take up to
+/// `maxTimeoutSeconds` to pay for requirements it was given.
+pub fn nonce_is_current(
+    nonce: &str,
+    req: &PaymentRequirements,
+    now_ms: u64,
+    nonce_ttl_ms: u64,
+) -> bool {
+    let ttl = nonce_ttl_ms.max(1);
+    let window = (req.max_timeout_seconds * 1000).div_ceil(ttl);
+    let current = nonce_slot(now_ms, ttl);
+    let network = req.network.to_string();
+    (0..=window)
+        .map_while(|i| current.checked_sub(i * ttl))
+        .any(|slot| {
+            requirements_nonce(&req.pay_to, &req.amount, &network, slot)
+                .eq_ignore_ascii_case(nonce.trim())
+        })
+}
+
+/// The nonce a payment echoes back from the requirements it accepted.
+pub fn payment_nonce(payload: &serde_json::Value) -> Option<&str> {
+    payload.pointer("/accepted/extra/nonce")?.as_str()
+}
+
 pub fn create_requirements(
     config: &ParsedX402Config,
     resource: &str,
+) -> Result<PaymentRequirements> {
+    let pay_to = config
+        .pay_to
+        .as_ref()
+        .ok_or_else(|| X402Error::Config("pay_to address not configured".into()))?;
+    build_requirements(config, resource, pay_to)
+}
+
+/// Requirements for every accepted recipient: one per `x402_pay_to_any`
+/// address, or the single `x402_pay_to` requirement.
+pub fn create_requirements_list(
+    config: &ParsedX402Config,
+    resource: &str,
+) -> Result<Vec<PaymentRequirements>> {
+    if config.pay_to_any.is_empty() {
+        return Ok(vec![create_requirements(config, resource)?]);
+    }
+    // Built one by one: with `x402_nonce` each recipient gets its own nonce.
+    config
+        .pay_to_any
+        .iter()
+        .map(|addr| build_requirements(config, resource, addr))
+        .collect()
+}
+
+/// Recipient of a decoded payment payload: the `payTo` of the requirements the
+/// client accepted, or the `to` of an EIP-3009 authorization.
+pub fn payment_pay_to(payload: &serde_json::Value) -> Option<String> {
+    payload
+        .pointer("/accepted/payTo")
+        .or_else(|| payload.pointer("/payload/authorization/to"))
+        .and_then(|v| v.as_str())
+        .map(str::to_lowercase)
+}
+
+/// Pair each payment (by its recipient) with the index of the requirement it
+/// pays. A single requirement accepts any one payment and leaves the recipient
+/// check to the facilitator. Otherwise one payment must go to a listed
+/// address, or with `requires_all` every address must receive exactly one.
+pub fn match_payments(
+    payees: &[Option<String>],
+    accepts: &[PaymentRequirements],
+    requires_all: bool,
+) -> Option<Vec<usize>> {
+    let index_of = |payee: &Option<String>| {
+        let payee = payee.as_deref()?;
+        accepts
+            .iter()
+            .position(|req| req.pay_to.eq_ignore_ascii_case(payee))
+    };
+    if requires_all {
+        if payees.len() != accepts.len() {
+            return None;
+        }
+        let mut matched = Vec::with_capacity(payees.len());
+        for payee in payees {
+            let i = index_of(payee)?;
+            if matched.contains(&i) {
+                return None;
+            }
+            matched.push(i);
+        }
+        return Some(matched);
+    }
+    match (payees, accepts.len()) {
+        ([_], 1) => Some(vec![0]),
+        ([payee], _) => index_of(payee).map(|i| vec![i]),
+        _ => None,
+    }
+}
+
+fn build_requirements(
+    config: &ParsedX402Config,
+    resource: &str,
+    pay_to: &str,
 ) -> Result<PaymentRequirements> {
     let amount = config
         .amount
-        .ok_or_else(|| ConfigError::new("Amount not configured"))?;
+        .ok_or_else(|| X402Error::Config("Amount not configured".into()))?;
     if amount < Decimal::ZERO {
-        return Err(ConfigError::new("Amount cannot be negative"));
+        return Err(X402Error::Config("Amount cannot be negative".into()));
     }
-    let pay_to = config
-        .pay_to
-        .as_ref()
-        .ok_or_else(|| ConfigError::new("pay_to address not configured"))?;
     let network = resolve_network(config)?;
-    let decimals = config.asset_decimals.unwrap_or(6);
+    let decimals = config.asset_decimals.unwrap_or(DEFAULT_ASSET_DECIMALS);
     let amount_str = amount_to_smallest_unit(amount, decimals);
     let asset_address = if let Some(ref custom) = config.asset {
         custom.clone()
@@ -86,43 +298,159 @@ pub fn create_requirements(
             .map(|s| s.to_string())
             .unwrap_or_default()
     };
-    let resource = crate::config::validation::validate_resource_path(resource)
-        .map_err(|e| ConfigError::new(e))?;
+    let allowed_schemes: Vec<&str> = config
+        .resource_allowed_schemes
+        .iter()
+        .map(String::as_str)
+        .collect();
+    let resource = crate::config::validation::validate_resource_path(
+        resource,
+        &allowed_schemes,
+        config.resource_max_length,
+    )
+    .map_err(X402Error::Config)?;
     if resource.is_empty() {
-        return Err(ConfigError::new("Resource path cannot be empty"));
+        return Err(X402Error::Config("Resource path cannot be empty".into()));
+    }
+    let max_timeout_seconds = config.ttl.unwrap_or(DEFAULT_TTL_SECONDS);
+    let mut extra = eip712_extra_for_asset(&asset_address);
+    let symbol = config.asset_symbol.clone().or_else(|| {
+        if !config.asset_symbol_auto {
+            return None;
+        }
+        extra
+            .as_ref()
+            .and_then(|e| e.get("name"))
+            .and_then(|n| n.as_str())
+            .map(String::from)
+    });
+    if let Some(symbol) = symbol {
+        insert_extra(&mut extra, "symbol", symbol.into());
+    }
+    // A zero price (configured, or from Redis or an expression) is advertised
+    // as `free`, and `free` with a non-zero price falls back to `exact`.
+    let scheme = match config.scheme {
+        Scheme::Exact | Scheme::Free if amount.is_zero() => Scheme::Free,
+        Scheme::Free => Scheme::Exact,
+        ref scheme => scheme.clone(),
+    };
+    match scheme {
+        Scheme::Exact | Scheme::Free => {}
+        Scheme::Range(max) => {
+            insert_extra(&mut extra, "min", amount_str.clone().into());
+            insert_extra(
+                &mut extra,
+                "max",
+                amount_to_smallest_unit(max, decimals).into(),
+            );
+        }
+        Scheme::Subscription(period_secs) => {
+            insert_extra(&mut extra, "periodSeconds", period_secs.into());
+        }
+    }
+    let pay_to = pay_to.to_lowercase();
+    if config.nonce {
+        let slot = nonce_slot(now_millis(), config.nonce_ttl_ms);
+        let nonce = requirements_nonce(&pay_to, &amount_str, &network.to_string(), slot);
+        insert_extra(&mut extra, "nonce", nonce.into());
     }
-    let max_timeout_seconds = config.ttl.unwrap_or(60);
-    let extra = eip712_extra_for_asset(&asset_address);
     Ok(PaymentRequirements {
-        scheme: "exact".to_string(),
+        scheme: scheme.as_str().to_string(),
         network,
         amount: amount_str,
-        pay_to: pay_to.to_lowercase(),
+        pay_to,
         max_timeout_seconds: max_timeout_seconds as u64,
         asset: asset_address,
         extra,
     })
 }
 
+/// Build the 402 body. `mime_type` is the type of the gated resource and
+/// defaults to `application/json` when unknown.
 pub fn create_payment_required_response(
     error: &str,
     accepts: Vec<PaymentRequirements>,
     resource_url: &str,
     description: &str,
-    mime_type: &str,
+    mime_type: Option<&str>,
 ) -> PaymentRequiredResponse {
     PaymentRequiredResponse {
         x402_version: X402Version2,
         error: Some(error.to_string()),
         resource: ResourceInfo {
             description: description.to_string(),
-            mime_type: mime_type.to_string(),
+            mime_type: mime_type.unwrap_or(DEFAULT_MIME_TYPE).to_string(),
             url: resource_url.to_string(),
         },
         accepts,
     }
 }
 
+/// Build the 402 body in the v1 shape: `maxAmountRequired`, network names
+/// instead of CAIP-2 IDs, and resource details on each requirement rather
+/// than in a `resource` wrapper.
+pub fn create_payment_required_response_v1(
+    error: &str,
+    accepts: &[PaymentRequirements],
+    resource_url: &str,
+    description: &str,
+    mime_type: Option<&str>,
+) -> PaymentRequiredV1 {
+    let mime_type = mime_type.unwrap_or(DEFAULT_MIME_TYPE);
+    PaymentRequiredV1 {
+        x402_version: x402_types::proto::v1::X402Version1,
+        error: Some(error.to_string()),
+        accepts: accepts
+            .iter()
+            .map(|req| PaymentRequirementsV1 {
+                scheme: req.scheme.clone(),
+                network: req
+                    .network
+                    .as_network_name()
+                    .map(String::from)
+                    .unwrap_or_else(|| req.network.to_string()),
+                max_amount_required: req.amount.clone(),
+                resource: resource_url.to_string(),
+                description: description.to_string(),
+                mime_type: mime_type.to_string(),
+                output_schema: None,
+                pay_to: req.pay_to.clone(),
+                max_timeout_seconds: req.max_timeout_seconds,
+                asset: req.asset.clone(),
+                extra: req.extra.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Serialize the 402 body for the negotiated protocol version.
+pub fn payment_required_json(
+    version: X402Version,
+    error: &str,
+    accepts: &[PaymentRequirements],
+    resource_url: &str,
+    description: &str,
+    mime_type: Option<&str>,
+) -> Result<String> {
+    let json = match version {
+        X402Version::V1 => serde_json::to_string(&create_payment_required_response_v1(
+            error,
+            accepts,
+            resource_url,
+            description,
+            mime_type,
+        )),
+        X402Version::V2 => serde_json::to_string(&create_payment_required_response(
+            error,
+            accepts.to_vec(),
+            resource_url,
+            description,
+            mime_type,
+        )),
+    };
+    json.map_err(|_| X402Error::Runtime("Failed to serialize response".into()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -151,11 +479,85 @@ mod tests {
             resource: None,
             asset,
             asset_decimals,
-            timeout: Some(Duration::from_secs(10)),
+            verify_timeout: Some(Duration::from_secs(10)),
+            settle_timeout: None,
             facilitator_fallback: FacilitatorFallback::Error,
             ttl,
             redis_url: None,
             replay_ttl: None,
+            max_payment_size: crate::ngx_module::runtime::MAX_PAYMENT_HEADER_SIZE,
+            ens_rpc_url: None,
+            rpc_url: None,
+            pay_to_any: Vec::new(),
+            pay_to_any_requires_all: false,
+            facilitator_headers: Vec::new(),
+            allowed_networks: None,
+            asset_symbol: None,
+            asset_symbol_auto: false,
+            payment_window_start: None,
+            payment_window_end: None,
+            outside_window_status: 503,
+            free_quota: None,
+            free_quota_window: Duration::from_secs(86400),
+            trust_forwarded_headers: false,
+            description_auto: true,
+            scheme: Scheme::Exact,
+            dynamic_price_prefix_match: false,
+            resource_include_query: false,
+            metrics_format: crate::ngx_module::metrics::MetricsFormat::Prometheus,
+            scheme_version: Some(X402Version::V2),
+            paywall_include_meta: true,
+            facilitator_preflight: false,
+            facilitator_preflight_path: None,
+            expose_payer_header: false,
+            dry_run: false,
+            log_request_id: true,
+            response_format: crate::ngx_module::config::ResponseFormat::Auto,
+            metrics_endpoint: false,
+            metrics_auth_token: None,
+            metrics_allowed_ips: Vec::new(),
+            redis_storage_mode: crate::ngx_module::redis::ReplayStorageMode::Keyspace,
+            forward_payer: false,
+            total_deadline: None,
+            facilitator_file_delay: Duration::ZERO,
+            browser_detect: crate::ngx_module::config::BrowserDetect::Auto,
+            nonce: false,
+            nonce_ttl_ms: crate::ngx_module::config::DEFAULT_NONCE_TTL_MS,
+            whitelist_ips: Vec::new(),
+            amount_expr: None,
+            resource_prefix: None,
+            resource_suffix: None,
+            facilitator_response_max_size:
+                crate::ngx_module::runtime::DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            upstream_payment_pass: false,
+            upstream_payment_hmac_secret: None,
+            fallback_reject_message: None,
+            fallback_reject_retry_after: None,
+            amount_bps: None,
+            amount_bps_source: None,
+            redis_init_timeout: None,
+            local_cache_max_entries:
+                crate::ngx_module::verify_cache::DEFAULT_LOCAL_CACHE_MAX_ENTRIES,
+            skip_user_agents: Vec::new(),
+            resource_allowed_schemes: crate::config::validation::DEFAULT_RESOURCE_ALLOWED_SCHEMES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            resource_max_length: crate::config::validation::DEFAULT_RESOURCE_MAX_LENGTH,
+            skip_head: false,
+            pay_to_blocked_addresses: Vec::new(),
+            facilitator_protocol: crate::ngx_module::config::FacilitatorProtocol::Rest,
+            method_override_header: None,
+            method_override_methods: Vec::new(),
+            response_buffer_size: crate::ngx_module::response::DEFAULT_RESPONSE_BUFFER_SIZE,
+            cors_origin: None,
+            cors_max_age: crate::ngx_module::response::DEFAULT_CORS_MAX_AGE,
+            payment_info: false,
+            payment_info_path: "/x402/info".to_string(),
+            access_log: false,
+            redis_error_policy: crate::ngx_module::redis::RedisErrorPolicy::Allow,
+            paywall_script: None,
+            passthrough_methods: std::collections::HashSet::new(),
         }
     }
 
@@ -163,6 +565,15 @@ mod tests {
     fn test_amount_to_smallest_unit() {
         assert_eq!(amount_to_smallest_unit(Decimal::new(1, 3), 6), "1000");
         assert_eq!(amount_to_smallest_unit(Decimal::new(1, 0), 6), "1000000");
+        assert_eq!(amount_to_smallest_unit(Decimal::ZERO, 6), "0");
+        assert_eq!(
+            amount_to_smallest_unit(Decimal::new(15, 1), 24),
+            "1500000000000000000000000"
+        );
+        assert_eq!(
+            amount_to_smallest_unit(Decimal::new(100000, 0), 28),
+            format!("1{}", "0".repeat(33))
+        );
     }
 
     #[test]
@@ -217,6 +628,101 @@ mod tests {
         assert_eq!(req.network.to_string(), "eip155:8453");
     }
 
+    fn allowlisted(network: &str, allowed: Option<&str>) -> ParsedX402Config {
+        let mut config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            Some(network.to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        config.allowed_networks =
+            allowed.map(|list| crate::config::validation::parse_allowed_networks(list).unwrap());
+        config
+    }
+
+    #[test]
+    fn test_create_requirements_allowed_network_passes() {
+        let config = allowlisted("base", Some("base base-sepolia"));
+        let req = create_requirements(&config, "/api").unwrap();
+        assert_eq!(req.network.to_string(), "eip155:8453");
+
+        let config = allowlisted("eip155:84532", Some("base-sepolia"));
+        assert!(create_requirements(&config, "/api").is_ok());
+    }
+
+    #[test]
+    fn test_create_requirements_disallowed_network_fails() {
+        let config = allowlisted("polygon", Some("base base-sepolia"));
+        assert!(matches!(
+            create_requirements(&config, "/api"),
+            Err(X402Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_requirements_no_allowlist_allows_all() {
+        let config = allowlisted("polygon", None);
+        assert!(create_requirements(&config, "/api").is_ok());
+    }
+
+    #[test]
+    fn test_create_requirements_alias_networks() {
+        let cases = [
+            (
+                "mainnet",
+                "eip155:1",
+                "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            ),
+            (
+                "optimism",
+                "eip155:10",
+                "0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85",
+            ),
+            (
+                "arbitrum",
+                "eip155:42161",
+                "0xaf88d065e77c8cC2239327C5EDb3A432268e5831",
+            ),
+            (
+                "avalanche",
+                "eip155:43114",
+                "0xB97EF9Ef8734C71904D8002F8b6Bc66Dd9c48a6E",
+            ),
+        ];
+        for (alias, caip, usdc) in cases {
+            let config = test_config(
+                Some(Decimal::from_str("0.001").unwrap()),
+                Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+                Some(alias.to_string()),
+                None,
+                None,
+                None,
+                None,
+                None,
+            );
+            let req = create_requirements(&config, "/api").unwrap();
+            assert_eq!(req.network.to_string(), caip);
+            assert_eq!(req.asset, usdc);
+            assert_eq!(req.amount, "1000");
+            assert!(req.extra.is_some(), "{alias}");
+        }
+    }
+
+    #[test]
+    fn test_default_usdc_addresses_are_blocked_pay_to() {
+        for reference in ["8453", "84532", "137", "1", "10", "42161", "43114"] {
+            let usdc = default_usdc_address(&ChainId::new("eip155", reference)).unwrap();
+            assert!(
+                crate::config::validation::KNOWN_INVALID_PAY_TO.contains(&usdc),
+                "{usdc} missing from KNOWN_INVALID_PAY_TO"
+            );
+        }
+    }
+
     #[test]
     fn test_create_requirements_default_usdc() {
         let config = test_config(
@@ -339,7 +845,7 @@ mod tests {
             vec![req],
             "/api",
             "desc",
-            "application/json",
+            Some("application/json"),
         );
         let json = serde_json::to_value(&resp).unwrap();
         assert_eq!(
@@ -362,12 +868,503 @@ mod tests {
             None,
         );
         let req = create_requirements(&config, "/api").unwrap();
-        let resp =
-            create_payment_required_response("Pay", vec![req], "/api", "desc", "application/json");
+        let resp = create_payment_required_response(
+            "Pay",
+            vec![req],
+            "/api",
+            "desc",
+            Some("application/json"),
+        );
         let json = serde_json::to_value(&resp).unwrap();
         assert_eq!(json["x402Version"], 2);
         let accept = &json["accepts"][0];
         assert!(accept.get("amount").is_some());
         assert!(accept["network"].as_str().unwrap().starts_with("eip155:"));
     }
+
+    #[test]
+    fn test_payment_required_response_mime_type() {
+        let config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let req = create_requirements(&config, "/page").unwrap();
+
+        let html = create_payment_required_response(
+            "Pay",
+            vec![req.clone()],
+            "/page",
+            "desc",
+            Some("text/html"),
+        );
+        let json = serde_json::to_value(&html).unwrap();
+        assert_eq!(json["resource"]["mimeType"], "text/html");
+
+        let api = create_payment_required_response("Pay", vec![req], "/page", "desc", None);
+        let json = serde_json::to_value(&api).unwrap();
+        assert_eq!(json["resource"]["mimeType"], "application/json");
+    }
+
+    #[test]
+    fn test_create_requirements_asset_symbol_in_extra() {
+        let mut config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            Some("base".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let req = create_requirements(&config, "/api").unwrap();
+        assert!(req.extra.as_ref().unwrap().get("symbol").is_none());
+
+        config.asset_symbol = Some("USDC".to_string());
+        let req = create_requirements(&config, "/api").unwrap();
+        let extra = req.extra.unwrap();
+        assert_eq!(extra["symbol"], "USDC");
+        assert_eq!(extra["name"], "USD Coin");
+
+        // Unknown assets get an `extra` object holding just the symbol.
+        config.asset = Some("0x1111111111111111111111111111111111111111".to_string());
+        config.asset_symbol = Some("TKN".to_string());
+        let req = create_requirements(&config, "/api").unwrap();
+        assert_eq!(req.extra.unwrap(), serde_json::json!({ "symbol": "TKN" }));
+    }
+
+    #[test]
+    fn test_create_requirements_asset_symbol_auto() {
+        let mut config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            Some("base-sepolia".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        config.asset_symbol_auto = true;
+        let req = create_requirements(&config, "/api").unwrap();
+        assert_eq!(req.extra.unwrap()["symbol"], "USDC");
+
+        config.asset = Some("0x1111111111111111111111111111111111111111".to_string());
+        let req = create_requirements(&config, "/api").unwrap();
+        assert!(req.extra.is_none());
+    }
+
+    #[test]
+    fn test_generate_auto_description() {
+        assert_eq!(
+            generate_auto_description("/api/weather-data"),
+            "Access to Weather Data"
+        );
+        assert_eq!(
+            generate_auto_description("/reports/annual_summary/"),
+            "Access to Reports Annual Summary"
+        );
+        assert_eq!(
+            generate_auto_description("/api/v2/news/latest?page=2"),
+            "Access to News Latest"
+        );
+        assert_eq!(
+            generate_auto_description("/blog/posts/my-article#intro"),
+            "Access to Posts My Article"
+        );
+        assert_eq!(generate_auto_description("/premium"), "Access to Premium");
+        assert_eq!(
+            generate_auto_description("https://example.com/files/data.csv"),
+            "Access to Files Data Csv"
+        );
+        assert_eq!(generate_auto_description("/"), "Access to this resource");
+        assert_eq!(
+            generate_auto_description("https://example.com"),
+            "Access to this resource"
+        );
+    }
+
+    fn scheme_config(scheme: Scheme) -> ParsedX402Config {
+        let mut config = test_config(
+            Some(Decimal::from_str("0.01").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            None,
+            None,
+            Some("0x1111111111111111111111111111111111111111".to_string()),
+            None,
+            None,
+            None,
+        );
+        config.scheme = scheme;
+        config
+    }
+
+    #[test]
+    fn test_create_requirements_exact_scheme() {
+        let req = create_requirements(&scheme_config(Scheme::Exact), "/api").unwrap();
+        assert_eq!(req.scheme, "exact");
+        assert!(req.extra.is_none());
+    }
+
+    #[test]
+    fn test_create_requirements_range_scheme() {
+        let config = scheme_config(Scheme::Range(Decimal::from_str("0.10").unwrap()));
+        let req = create_requirements(&config, "/api").unwrap();
+        assert_eq!(req.scheme, "range");
+        assert_eq!(req.amount, "10000");
+        assert_eq!(
+            req.extra.unwrap(),
+            serde_json::json!({ "min": "10000", "max": "100000" })
+        );
+    }
+
+    #[test]
+    fn test_create_requirements_subscription_scheme() {
+        let req = create_requirements(&scheme_config(Scheme::Subscription(86400)), "/api").unwrap();
+        assert_eq!(req.scheme, "subscription");
+        assert_eq!(
+            req.extra.unwrap(),
+            serde_json::json!({ "periodSeconds": 86400 })
+        );
+    }
+
+    #[test]
+    fn test_create_requirements_free_scheme() {
+        let mut config = scheme_config(Scheme::Free);
+        config.amount = Some(Decimal::ZERO);
+        let req = create_requirements(&config, "/api").unwrap();
+        assert_eq!(req.scheme, "free");
+        assert_eq!(req.amount, "0");
+        assert!(req.extra.is_none());
+
+        // A zero price under the default scheme is free too...
+        config.scheme = Scheme::Exact;
+        assert_eq!(create_requirements(&config, "/api").unwrap().scheme, "free");
+        // ...and a non-zero one under `free` is charged as exact.
+        config.scheme = Scheme::Free;
+        config.amount = Some(Decimal::from_str("0.01").unwrap());
+        let req = create_requirements(&config, "/api").unwrap();
+        assert_eq!(req.scheme, "exact");
+        assert_eq!(req.amount, "10000");
+    }
+
+    #[test]
+    fn test_free_tier_402_body() {
+        let mut config = scheme_config(Scheme::Free);
+        config.amount = Some(Decimal::ZERO);
+        let req = create_requirements(&config, "/api").unwrap();
+
+        for version in [X402Version::V2, X402Version::V1] {
+            let body = payment_required_json(
+                version,
+                "Payment required",
+                std::slice::from_ref(&req),
+                "/api",
+                "",
+                None,
+            )
+            .unwrap();
+            let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+            let accept = &json["accepts"][0];
+            assert_eq!(accept["scheme"], "free", "{version:?}");
+            assert!(body.contains("\"scheme\":\"free\""), "{body}");
+        }
+    }
+
+    #[test]
+    fn test_create_requirements_resource_query_string() {
+        let config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let with_query = crate::ngx_module::request::format_full_url(
+            "https",
+            "example.com",
+            "/api",
+            Some("symbol=AAPL"),
+        );
+        let without_query =
+            crate::ngx_module::request::format_full_url("https", "example.com", "/api", None);
+        assert!(create_requirements(&config, &with_query).is_ok());
+        assert!(create_requirements(&config, &without_query).is_ok());
+
+        let response = create_payment_required_response(
+            "Payment required",
+            vec![create_requirements(&config, &with_query).unwrap()],
+            &with_query,
+            "",
+            None,
+        );
+        assert_eq!(response.resource.url, "https://example.com/api?symbol=AAPL");
+
+        let response = create_payment_required_response(
+            "Payment required",
+            vec![create_requirements(&config, &without_query).unwrap()],
+            &without_query,
+            "",
+            None,
+        );
+        assert!(!response.resource.url.contains('?'));
+    }
+
+    fn pay_to_any_config(requires_all: bool) -> ParsedX402Config {
+        let mut config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        config.pay_to_any = vec![
+            "0xAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+            "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+        ];
+        config.pay_to_any_requires_all = requires_all;
+        config
+    }
+
+    fn payee(addr: &str) -> Option<String> {
+        payment_pay_to(&serde_json::json!({ "accepted": { "payTo": addr } }))
+    }
+
+    #[test]
+    fn test_requirements_nonce_encoding() {
+        let pay_to = "0x1234567890abcdef1234567890abcdef12345678";
+        let nonce = requirements_nonce(pay_to, "1000", "eip155:8453", 1_700_000_000_000);
+        assert_eq!(nonce.len(), 64);
+        assert!(nonce.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{pay_to}1000eip155:84531700000000000"));
+        assert_eq!(nonce, hex::encode(hasher.finalize()));
+
+        // Case of the address does not matter; every other input does.
+        assert_eq!(
+            nonce,
+            requirements_nonce(
+                &pay_to.to_uppercase().replace("0X", "0x"),
+                "1000",
+                "eip155:8453",
+                1_700_000_000_000
+            )
+        );
+        assert_ne!(
+            nonce,
+            requirements_nonce(pay_to, "1001", "eip155:8453", 1_700_000_000_000)
+        );
+        assert_ne!(
+            nonce,
+            requirements_nonce(pay_to, "1000", "eip155:84532", 1_700_000_000_000)
+        );
+        assert_ne!(
+            nonce,
+            requirements_nonce(pay_to, "1000", "eip155:8453", 1_700_000_060_000)
+        );
+    }
+
+    #[test]
+    fn test_nonce_in_requirements_extra() {
+        let mut config = pay_to_any_config(false);
+        config.nonce = true;
+        let accepts = create_requirements_list(&config, "/api").unwrap();
+        let nonces: Vec<&str> = accepts
+            .iter()
+            .map(|req| req.extra.as_ref().unwrap()["nonce"].as_str().unwrap())
+            .collect();
+        assert_ne!(nonces[0], nonces[1], "each recipient has its own nonce");
+        for (req, nonce) in accepts.iter().zip(&nonces) {
+            assert!(nonce_is_current(
+                nonce,
+                req,
+                now_millis(),
+                config.nonce_ttl_ms
+            ));
+        }
+
+        config.nonce = false;
+        let accepts = create_requirements_list(&config, "/api").unwrap();
+        assert!(accepts[0].extra.as_ref().unwrap().get("nonce").is_none());
+    }
+
+    #[test]
+    fn test_nonce_validation_window() {
+        let req = PaymentRequirements {
+            scheme: "exact".into(),
+            network: "eip155:8453".parse().unwrap(),
+            amount: "1000".into(),
+            pay_to: "0x1234567890abcdef1234567890abcdef12345678".into(),
+            max_timeout_seconds: 60,
+            asset: "0x833589fcd6edb6e08f4c7c32d4f71b54bda02913".into(),
+            extra: None,
+        };
+        let ttl = 20_000;
+        let issued_at = 1_700_000_005_000;
+        let slot = nonce_slot(issued_at, ttl);
+        assert_eq!(slot, 1_700_000_000_000);
+        let nonce = requirements_nonce(&req.pay_to, &req.amount, "eip155:8453", slot);
+
+        // Current for its own slot plus ceil(60s / 20s) = 3 more.
+        assert!(nonce_is_current(&nonce, &req, issued_at, ttl));
+        assert!(nonce_is_current(&nonce, &req, slot + 3 * ttl + 19_999, ttl));
+        assert!(!nonce_is_current(&nonce, &req, slot + 4 * ttl, ttl));
+        // Not yet issued.
+        assert!(!nonce_is_current(&nonce, &req, slot - 1, ttl));
+        // Bound to the requirement it was issued for.
+        let other = PaymentRequirements {
+            amount: "1".into(),
+            ..req.clone()
+        };
+        assert!(!nonce_is_current(&nonce, &other, issued_at, ttl));
+        assert!(!nonce_is_current("", &req, issued_at, ttl));
+
+        let payload = serde_json::json!({ "accepted": { "extra": { "nonce": nonce } } });
+        assert_eq!(payment_nonce(&payload), Some(nonce.as_str()));
+        assert_eq!(payment_nonce(&serde_json::json!({ "accepted": {} })), None);
+    }
+
+    #[test]
+    fn test_create_requirements_list_pay_to_any() {
+        let accepts = create_requirements_list(&pay_to_any_config(false), "/api").unwrap();
+        assert_eq!(accepts.len(), 2);
+        assert_eq!(
+            accepts[0].pay_to,
+            "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"
+        );
+        assert_eq!(
+            accepts[1].pay_to,
+            "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"
+        );
+        assert_eq!(accepts[0].amount, accepts[1].amount);
+
+        // Apart from the recipient the requirements are identical.
+        let mut second = accepts[0].clone();
+        second.pay_to = accepts[1].pay_to.clone();
+        assert_eq!(second, accepts[1]);
+        assert_ne!(accepts[0], accepts[1]);
+
+        let config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        assert_eq!(create_requirements_list(&config, "/api").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_payment_pay_to() {
+        let authorization = serde_json::json!({
+            "payload": { "authorization": { "to": "0xABC" } }
+        });
+        assert_eq!(payment_pay_to(&authorization).as_deref(), Some("0xabc"));
+        assert_eq!(payee("0xDEF").as_deref(), Some("0xdef"));
+        assert_eq!(payment_pay_to(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_match_payments_one_of_n() {
+        let accepts = create_requirements_list(&pay_to_any_config(false), "/api").unwrap();
+        let b = payee("0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB");
+        assert_eq!(
+            match_payments(std::slice::from_ref(&b), &accepts, false),
+            Some(vec![1])
+        );
+        assert_eq!(match_payments(&[payee("0xcccc")], &accepts, false), None);
+        assert_eq!(match_payments(&[None], &accepts, false), None);
+        assert_eq!(match_payments(&[b.clone(), b], &accepts, false), None);
+
+        // A single requirement leaves the recipient check to the facilitator.
+        assert_eq!(match_payments(&[None], &accepts[..1], false), Some(vec![0]));
+    }
+
+    #[test]
+    fn test_match_payments_all_of_n() {
+        let accepts = create_requirements_list(&pay_to_any_config(true), "/api").unwrap();
+        let a = payee("0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        let b = payee("0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb");
+        assert_eq!(
+            match_payments(&[b.clone(), a.clone()], &accepts, true),
+            Some(vec![1, 0])
+        );
+        assert_eq!(
+            match_payments(std::slice::from_ref(&a), &accepts, true),
+            None
+        );
+        assert_eq!(match_payments(&[a.clone(), a], &accepts, true), None);
+        assert_eq!(match_payments(&[b, None], &accepts, true), None);
+    }
+
+    #[test]
+    fn test_402_response_json_v1_structure() {
+        let config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            Some("base-sepolia".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let req = create_requirements(&config, "/api").unwrap();
+        let body = payment_required_json(
+            X402Version::V1,
+            "Pay",
+            &[req],
+            "https://example.com/api",
+            "desc",
+            None,
+        )
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["x402Version"], 1);
+        assert!(json.get("resource").is_none());
+        let accept = &json["accepts"][0];
+        assert_eq!(accept["maxAmountRequired"], "1000");
+        assert!(accept.get("amount").is_none());
+        assert_eq!(accept["network"], "base-sepolia");
+        assert_eq!(accept["resource"], "https://example.com/api");
+        assert_eq!(accept["description"], "desc");
+        assert_eq!(accept["mimeType"], "application/json");
+    }
+
+    #[test]
+    fn test_payment_required_json_v2() {
+        let config = test_config(
+            Some(Decimal::from_str("0.001").unwrap()),
+            Some("0x1234567890abcdef1234567890abcdef12345678".to_string()),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let req = create_requirements(&config, "/api").unwrap();
+        let body =
+            payment_required_json(X402Version::V2, "Pay", &[req], "/api", "desc", None).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["x402Version"], 2);
+        assert_eq!(json["accepts"][0]["amount"], "1000");
+        assert!(json["accepts"][0].get("maxAmountRequired").is_none());
+    }
 }
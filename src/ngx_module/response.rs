@@ -1,13 +1,17 @@
-use crate::ngx_module::config::ParsedX402Config;
-use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::config::{ParsedX402Config, ResponseFormat};
+use crate::ngx_module::error::{Result, X402Error};
 #[cfg(not(test))]
-use crate::ngx_module::request::is_browser_request;
-use crate::ngx_module::requirements::{create_payment_required_response, PaymentRequirements};
+use crate::ngx_module::request::{get_header_value, is_browser_request, negotiate_x402_version};
+#[cfg(test)]
+use crate::ngx_module::requirements::X402Version;
+use crate::ngx_module::requirements::{
+    payment_required_json, well_known_symbol, PaymentRequirements,
+};
 #[cfg(not(test))]
 use ngx::core::Status;
 #[cfg(not(test))]
 use ngx::http::HTTPStatus;
-use ngx::http::Request;
+use ngx::http::{Method, Request};
 
 const HTML_PAYWALL_TEMPLATE: &str = r#"<!DOCTYPE html>
 <html lang="en">
@@ -15,6 +19,7 @@ const HTML_PAYWALL_TEMPLATE: &str = r#"<!DOCTYPE html>
 <meta charset="utf-8">
 <meta name="viewport" content="width=device-width, initial-scale=1">
 <title>402 Payment Required</title>
+<meta name="x402-requirements" content="{{REQUIREMENTS_B64}}">
 <style>
 body{font-family:system-ui,-apple-system,sans-serif;display:flex;justify-content:center;align-items:center;min-height:100vh;margin:0;background:#f5f5f5;color:#333}
 .card{background:#fff;border-radius:12px;padding:2rem;max-width:480px;width:90%;box-shadow:0 2px 12px rgba(0,0,0,.1);text-align:center}
@@ -35,26 +40,149 @@ p{color:#666;line-height:1.5}
 <dl>
 <dt>Network</dt><dd>{{NETWORK}}</dd>
 <dt>Amount</dt><dd>{{AMOUNT}}</dd>
+<dt>Asset</dt><dd>{{SYMBOL}}</dd>
 <dt>Pay To</dt><dd>{{PAY_TO}}</dd>
+<dt>Resource</dt><dd>{{RESOURCE_URL}}</dd>
 </dl>
 </div>
 <p style="font-size:.8rem;color:#999">Powered by x402 protocol</p>
 </div>
+<script src="{{SCRIPT_URL}}"{{SCRIPT_INTEGRITY}}></script>
+<script>x402.init({requirements: JSON.parse(atob("{{REQUIREMENTS_B64}}"))})</script>
 </body>
 </html>"#;
 
-pub(crate) fn generate_paywall_html(message: &str, requirements: &[PaymentRequirements]) -> String {
+/// `Access-Control-Max-Age` when `x402_cors_max_age_secs` is unset.
+pub const DEFAULT_CORS_MAX_AGE: u32 = 86400;
+
+/// Largest single response buffer when `x402_response_buffer_size_kb` is unset.
+pub const DEFAULT_RESPONSE_BUFFER_SIZE: usize = 32 * 1024;
+
+const REQUIREMENTS_META: &str =
+    "<meta name=\"x402-requirements\" content=\"{{REQUIREMENTS_B64}}\">\n";
+
+const PAYWALL_SCRIPT: &str = "<script src=\"{{SCRIPT_URL}}\"{{SCRIPT_INTEGRITY}}></script>\n";
+
+const PAYWALL_AUTO_CONNECT: &str =
+    "<script>x402.init({requirements: JSON.parse(atob(\"{{REQUIREMENTS_B64}}\"))})</script>\n";
+
+/// Library loaded by the paywall when `x402_paywall_script_url` is unset.
+pub const DEFAULT_PAYWALL_SCRIPT_URL: &str = "https://unpkg.com/x402@latest/dist/x402.min.js";
+
+/// The x402.js `<script>` of the paywall (`x402_paywall_script_url`,
+/// `x402_paywall_script_integrity`, `x402_paywall_auto_connect`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaywallScript {
+    pub url: String,
+    /// Subresource integrity hash, e.g. `sha384-...`.
+    pub integrity: Option<String>,
+    /// Call `x402.init` with the requirements once the library has loaded.
+    pub auto_connect: bool,
+}
+
+impl Default for PaywallScript {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_PAYWALL_SCRIPT_URL.to_string(),
+            integrity: None,
+            auto_connect: false,
+        }
+    }
+}
+
+/// Escape text for an HTML element body or a double-quoted attribute.
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Render the browser paywall. With `include_meta`, the page carries the
+/// requirements as base64 JSON in `<meta name="x402-requirements">` for
+/// client-side wallets; with `script`, it loads x402.js to pay in-browser.
+pub fn generate_paywall_html(
+    message: &str,
+    requirements: &[PaymentRequirements],
+    resource_url: &str,
+    include_meta: bool,
+    script: Option<&PaywallScript>,
+) -> String {
     let req = requirements.first();
     let network = req
         .map(|r| r.network.to_string())
         .unwrap_or_else(|| "unknown".to_string());
     let amount = req.map(|r| r.amount.as_str()).unwrap_or("0");
     let pay_to = req.map(|r| r.pay_to.as_str()).unwrap_or("unknown");
-    HTML_PAYWALL_TEMPLATE
-        .replace("{{MESSAGE}}", message)
-        .replace("{{NETWORK}}", &network)
-        .replace("{{AMOUNT}}", amount)
-        .replace("{{PAY_TO}}", pay_to)
+    let symbol = req
+        .and_then(|r| {
+            r.extra
+                .as_ref()
+                .and_then(|e| e.get("symbol"))
+                .and_then(|s| s.as_str())
+                .or_else(|| well_known_symbol(&r.asset))
+                .or(Some(r.asset.as_str()))
+        })
+        .unwrap_or("unknown");
+    let mut template = HTML_PAYWALL_TEMPLATE.to_string();
+    if !include_meta {
+        template = template.replace(REQUIREMENTS_META, "");
+    }
+    match script {
+        Some(script) => {
+            if !script.auto_connect {
+                template = template.replace(PAYWALL_AUTO_CONNECT, "");
+            }
+            let integrity = script
+                .integrity
+                .as_deref()
+                .map(|hash| {
+                    format!(
+                        " integrity=\"{}\" crossorigin=\"anonymous\"",
+                        html_escape(hash)
+                    )
+                })
+                .unwrap_or_default();
+            template = template
+                .replace("{{SCRIPT_URL}}", &html_escape(&script.url))
+                .replace("{{SCRIPT_INTEGRITY}}", &integrity);
+        }
+        None => {
+            template = template
+                .replace(PAYWALL_SCRIPT, "")
+                .replace(PAYWALL_AUTO_CONNECT, "");
+        }
+    }
+    if template.contains("{{REQUIREMENTS_B64}}") {
+        let json = serde_json::to_string(requirements).unwrap_or_else(|_| "[]".to_string());
+        let b64 = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, json);
+        template = template.replace("{{REQUIREMENTS_B64}}", &b64);
+    }
+    template
+        .replace("{{RESOURCE_URL}}", &html_escape(resource_url))
+        .replace("{{MESSAGE}}", &html_escape(message))
+        .replace("{{NETWORK}}", &html_escape(&network))
+        .replace("{{AMOUNT}}", &html_escape(amount))
+        .replace("{{PAY_TO}}", &html_escape(pay_to))
+        .replace("{{SYMBOL}}", &html_escape(symbol))
+}
+
+/// Whether a 402 carries the HTML paywall rather than the JSON body.
+/// `is_browser` only matters for `x402_response_format auto`.
+pub(crate) fn serves_html(format: ResponseFormat, is_browser: bool) -> bool {
+    match format {
+        ResponseFormat::Auto => is_browser,
+        ResponseFormat::Json => false,
+        ResponseFormat::Html => true,
+    }
 }
 
 pub fn send_402_response(
@@ -71,106 +199,223 @@ pub fn send_402_response(
         let error_message = error_msg
             .or(config.description.as_deref())
             .unwrap_or("Payment required");
-        let response = create_payment_required_response(
+        let version = config.scheme_version.unwrap_or(X402Version::V2);
+        payment_required_json(
+            version,
             error_message,
-            requirements.to_vec(),
+            requirements,
             resource_url,
             config.description.as_deref().unwrap_or(""),
-            mime_type,
-        );
-        let _ = serde_json::to_string(&response)
-            .map_err(|_| ConfigError::new("Failed to serialize response"))?;
+            Some(mime_type),
+        )?;
         return Ok(());
     }
 
     #[cfg(not(test))]
     {
         r.set_status(HTTPStatus(402));
-        let is_browser = is_browser_request(r);
+        let paywall = serves_html(
+            config.response_format,
+            is_browser_request(r, config.browser_detect),
+        );
         let error_message = error_msg
             .or(config.description.as_deref())
             .unwrap_or("Payment required");
-        let response = create_payment_required_response(
+        let version = negotiate_x402_version(
+            config.scheme_version,
+            get_header_value(r, "X-402-Version").as_deref(),
+            get_header_value(r, "Accept").as_deref(),
+        );
+        let requirements_json = payment_required_json(
+            version,
             error_message,
-            requirements.to_vec(),
+            requirements,
             resource_url,
             config.description.as_deref().unwrap_or(""),
-            mime_type,
-        );
-        let requirements_json = serde_json::to_string(&response).unwrap_or_default();
+            Some(mime_type),
+        )?;
         let requirements_b64 = base64::Engine::encode(
             &base64::engine::general_purpose::STANDARD,
             &requirements_json,
         );
         r.add_header_out("PAYMENT-REQUIRED", &requirements_b64)
-            .ok_or_else(|| ConfigError::new("Failed to set PAYMENT-REQUIRED header"))?;
-        if is_browser {
-            let html = generate_paywall_html(error_message, requirements);
+            .ok_or_else(|| X402Error::Runtime("Failed to set PAYMENT-REQUIRED header".into()))?;
+        for (name, value) in cache_headers_402(config.cors_origin.as_deref(), config.cors_max_age) {
+            r.add_header_out(name, &value)
+                .ok_or_else(|| X402Error::Runtime(format!("Failed to set {name} header")))?;
+        }
+        if paywall {
+            let html = generate_paywall_html(
+                error_message,
+                requirements,
+                resource_url,
+                config.paywall_include_meta,
+                config.paywall_script.as_ref(),
+            );
             r.add_header_out("Content-Type", "text/html; charset=utf-8")
-                .ok_or_else(|| ConfigError::new("Failed to set Content-Type header"))?;
-            send_response_body(r, html.as_bytes())?;
+                .ok_or_else(|| X402Error::Runtime("Failed to set Content-Type header".into()))?;
+            send_chunked_response_body(r, html.as_bytes(), config.response_buffer_size)?;
         } else {
-            let json = serde_json::to_string(&response)
-                .map_err(|_| ConfigError::new("Failed to serialize response"))?;
             r.add_header_out("Content-Type", "application/json; charset=utf-8")
-                .ok_or_else(|| ConfigError::new("Failed to set Content-Type header"))?;
-            send_response_body(r, json.as_bytes())?;
+                .ok_or_else(|| X402Error::Runtime("Failed to set Content-Type header".into()))?;
+            send_chunked_response_body(
+                r,
+                requirements_json.as_bytes(),
+                config.response_buffer_size,
+            )?;
         }
         Ok(())
     }
 }
 
-#[cfg(not(test))]
+/// Send `body` with the default buffer size; see [`send_chunked_response_body`].
 pub fn send_response_body(r: &mut Request, body: &[u8]) -> Result<()> {
-    use ngx::ffi::{ngx_alloc_chain_link, ngx_create_temp_buf};
+    send_chunked_response_body(r, body, DEFAULT_RESPONSE_BUFFER_SIZE)
+}
+
+/// Send `body` as a chain of buffers of at most `chunk_size` bytes
+/// (`x402_response_buffer_size_kb`), so a large paywall page or metrics
+/// scrape never needs one contiguous pool allocation.
+#[cfg(not(test))]
+pub fn send_chunked_response_body(r: &mut Request, body: &[u8], chunk_size: usize) -> Result<()> {
+    use ngx::ffi::{ngx_alloc_chain_link, ngx_chain_t, ngx_create_temp_buf};
     let pool = r.pool();
     let body_len = body.len();
     if body_len == 0 {
-        return Err(ConfigError::new("Cannot send empty response body"));
-    }
-    let buf = unsafe { ngx_create_temp_buf(pool.as_ptr(), body_len) };
-    if buf.is_null() {
-        return Err(ConfigError::new("Failed to allocate buffer"));
+        return Err(X402Error::Runtime("Cannot send empty response body".into()));
     }
-    unsafe {
-        let buf_ref = &mut *buf;
-        if buf_ref.pos.is_null() {
-            return Err(ConfigError::new("Buffer pos is null"));
+    let chunks = body_chunks(body, chunk_size);
+    let last = chunks.len() - 1;
+    let mut head: *mut ngx_chain_t = core::ptr::null_mut();
+    let mut tail: *mut ngx_chain_t = core::ptr::null_mut();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let buf = unsafe { ngx_create_temp_buf(pool.as_ptr(), chunk.len()) };
+        if buf.is_null() {
+            return Err(X402Error::Runtime("Failed to allocate buffer".into()));
         }
-        let buf_slice = core::slice::from_raw_parts_mut(buf_ref.pos, body_len);
-        buf_slice.copy_from_slice(body);
-        buf_ref.last = buf_ref.pos.add(body_len);
-        buf_ref.set_last_buf(1);
-        buf_ref.set_last_in_chain(1);
-    }
-    let chain = unsafe { ngx_alloc_chain_link(pool.as_ptr()) };
-    if chain.is_null() {
-        return Err(ConfigError::new("Failed to allocate chain link"));
-    }
-    unsafe {
-        (*chain).buf = buf;
-        (*chain).next = core::ptr::null_mut();
+        unsafe {
+            let buf_ref = &mut *buf;
+            if buf_ref.pos.is_null() {
+                return Err(X402Error::Runtime("Buffer pos is null".into()));
+            }
+            let buf_slice = core::slice::from_raw_parts_mut(buf_ref.pos, chunk.len());
+            buf_slice.copy_from_slice(chunk);
+            buf_ref.last = buf_ref.pos.add(chunk.len());
+            if i == last {
+                buf_ref.set_last_buf(1);
+                buf_ref.set_last_in_chain(1);
+            }
+        }
+        let link = unsafe { ngx_alloc_chain_link(pool.as_ptr()) };
+        if link.is_null() {
+            return Err(X402Error::Runtime("Failed to allocate chain link".into()));
+        }
+        unsafe {
+            (*link).buf = buf;
+            (*link).next = core::ptr::null_mut();
+            if tail.is_null() {
+                head = link;
+            } else {
+                (*tail).next = link;
+            }
+        }
+        tail = link;
     }
+    // HEAD gets the Content-Length the body would have, but not the body.
     r.set_content_length_n(body_len);
     let status = r.send_header();
     if status != Status::NGX_OK {
-        return Err(ConfigError::new(format!(
+        return Err(X402Error::Runtime(format!(
             "Failed to send header: {status:?}"
         )));
     }
-    let chain_ref = unsafe { &mut *chain };
+    if is_header_only(&r.method()) {
+        return Ok(());
+    }
+    let chain_ref = unsafe { &mut *head };
     let status = r.output_filter(chain_ref);
     if status != Status::NGX_OK {
-        return Err(ConfigError::new(format!("Failed to send body: {status:?}")));
+        return Err(X402Error::Runtime(format!(
+            "Failed to send body: {status:?}"
+        )));
     }
     Ok(())
 }
 
+/// Split `body` into the buffers [`send_chunked_response_body`] sends; only
+/// the last one is marked `last_buf`.
+pub(crate) fn body_chunks(body: &[u8], chunk_size: usize) -> Vec<&[u8]> {
+    body.chunks(chunk_size.max(1)).collect()
+}
+
+/// Body of an `x402_payment_info` answer: the terms a 402 lists under `accepts`.
+pub(crate) fn payment_info_json(requirements: &[PaymentRequirements]) -> Result<String> {
+    serde_json::to_string(&serde_json::json!({
+        "x402Version": 2,
+        "accepts": requirements,
+    }))
+    .map_err(|_| X402Error::Runtime("Failed to serialize response".into()))
+}
+
+/// Answer an `x402_payment_info` request with 200 and the payment terms,
+/// without gating or running the content handler.
+pub fn send_payment_info_response(
+    r: &mut Request,
+    requirements: &[PaymentRequirements],
+    config: &ParsedX402Config,
+) -> Result<()> {
+    let body = payment_info_json(requirements)?;
+    #[cfg(test)]
+    {
+        let _ = config;
+        send_chunked_response_body(r, body.as_bytes(), DEFAULT_RESPONSE_BUFFER_SIZE)
+    }
+
+    #[cfg(not(test))]
+    {
+        r.set_status(HTTPStatus(200));
+        r.add_header_out(
+            "Content-Type",
+            crate::ngx_module::request::PAYMENT_INFO_MEDIA_TYPE,
+        )
+        .ok_or_else(|| X402Error::Runtime("Failed to set Content-Type header".into()))?;
+        send_chunked_response_body(r, body.as_bytes(), config.response_buffer_size)
+    }
+}
+
+/// `Vary` and CORS headers of a 402 response. The body depends on `Accept`
+/// (HTML or JSON) and is compressed per `Accept-Encoding`; `Payment-Signature`
+/// keeps caches from serving the 402 to a client that has since paid, and
+/// with `x402_cors_origin` set, `Origin` keeps a CDN from replaying one
+/// origin's response to another.
+pub(crate) fn cache_headers_402(
+    cors_origin: Option<&str>,
+    cors_max_age: u32,
+) -> Vec<(&'static str, String)> {
+    let Some(origin) = cors_origin else {
+        return vec![("Vary", "Accept, Accept-Encoding, Payment-Signature".into())];
+    };
+    vec![
+        (
+            "Vary",
+            "Origin, Accept, Accept-Encoding, Payment-Signature".into(),
+        ),
+        ("Access-Control-Allow-Origin", origin.to_string()),
+        ("Access-Control-Max-Age", cors_max_age.to_string()),
+    ]
+}
+
+/// Responses to these methods carry headers only.
+pub(crate) fn is_header_only(method: &Method) -> bool {
+    *method == Method::HEAD
+}
+
 #[cfg(test)]
-pub fn send_response_body(_r: &mut Request, body: &[u8]) -> Result<()> {
+pub fn send_chunked_response_body(_r: &mut Request, body: &[u8], chunk_size: usize) -> Result<()> {
     if body.is_empty() {
-        return Err(ConfigError::new("Cannot send empty response body"));
+        return Err(X402Error::Runtime("Cannot send empty response body".into()));
     }
+    body_chunks(body, chunk_size);
     Ok(())
 }
 
@@ -178,6 +423,97 @@ pub fn send_response_body(_r: &mut Request, body: &[u8]) -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_body_chunks() {
+        let body = vec![7u8; 100];
+        let sizes = |chunk_size| {
+            body_chunks(&body, chunk_size)
+                .iter()
+                .map(|c| c.len())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(100), vec![100]);
+        assert_eq!(sizes(1000), vec![100]);
+        assert_eq!(sizes(50), vec![50, 50]);
+        assert_eq!(sizes(30), vec![30, 30, 30, 10]);
+        assert_eq!(sizes(99), vec![99, 1]);
+        assert_eq!(sizes(1).len(), 100);
+        // A zero size can't loop forever.
+        assert_eq!(sizes(0).len(), 100);
+        assert_eq!(body_chunks(&body, 30).concat(), body);
+    }
+
+    #[test]
+    fn test_payment_info_json() {
+        let req = PaymentRequirements {
+            scheme: "exact".to_string(),
+            network: "eip155:8453".parse().unwrap(),
+            amount: "1000".to_string(),
+            pay_to: "0x1234567890abcdef1234567890abcdef12345678".to_string(),
+            max_timeout_seconds: 60,
+            asset: "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913".to_string(),
+            extra: None,
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&payment_info_json(std::slice::from_ref(&req)).unwrap()).unwrap();
+        assert_eq!(json["x402Version"], 2);
+        let accepts = json["accepts"].as_array().unwrap();
+        assert_eq!(accepts.len(), 1);
+        assert_eq!(accepts[0], serde_json::to_value(&req).unwrap());
+        assert_eq!(accepts[0]["amount"], "1000");
+
+        let empty: serde_json::Value =
+            serde_json::from_str(&payment_info_json(&[]).unwrap()).unwrap();
+        assert_eq!(empty["accepts"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_cache_headers_402() {
+        let vary = |headers: &[(&str, String)]| {
+            headers
+                .iter()
+                .find(|(name, _)| *name == "Vary")
+                .map(|(_, value)| value.split(", ").map(String::from).collect::<Vec<_>>())
+                .unwrap()
+        };
+
+        let headers = cache_headers_402(None, DEFAULT_CORS_MAX_AGE);
+        assert_eq!(
+            vary(&headers),
+            ["Accept", "Accept-Encoding", "Payment-Signature"]
+        );
+        assert_eq!(headers.len(), 1, "no CORS headers without x402_cors_origin");
+
+        let headers = cache_headers_402(Some("https://app.example.com"), 600);
+        assert_eq!(
+            vary(&headers),
+            ["Origin", "Accept", "Accept-Encoding", "Payment-Signature"]
+        );
+        assert!(headers.contains(&(
+            "Access-Control-Allow-Origin",
+            "https://app.example.com".to_string()
+        )));
+        assert!(headers.contains(&("Access-Control-Max-Age", "600".to_string())));
+    }
+
+    #[test]
+    fn test_head_is_header_only() {
+        assert!(is_header_only(&Method::HEAD));
+        assert!(!is_header_only(&Method::GET));
+        assert!(!is_header_only(&Method::POST));
+    }
+
+    #[test]
+    fn test_serves_html() {
+        // `is_browser` is what `is_browser_request` reports for a browser
+        // (`Accept: text/html`) versus an API client (`Accept: application/json`).
+        for is_browser in [true, false] {
+            assert_eq!(serves_html(ResponseFormat::Auto, is_browser), is_browser);
+            assert!(!serves_html(ResponseFormat::Json, is_browser));
+            assert!(serves_html(ResponseFormat::Html, is_browser));
+        }
+    }
+
     #[test]
     fn test_generate_paywall_html_with_requirements() {
         let req = PaymentRequirements {
@@ -189,7 +525,7 @@ mod tests {
             asset: "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913".to_string(),
             extra: None,
         };
-        let html = generate_paywall_html("Payment required", &[req]);
+        let html = generate_paywall_html("Payment required", &[req], "/api", true, None);
         assert!(html.contains("Payment required"));
         assert!(html.contains("eip155:8453"));
         assert!(html.contains("1000"));
@@ -198,9 +534,137 @@ mod tests {
 
     #[test]
     fn test_generate_paywall_html_empty_requirements() {
-        let html = generate_paywall_html("Please pay", &[]);
+        let html = generate_paywall_html("Please pay", &[], "/api", true, None);
         assert!(html.contains("Please pay"));
         assert!(html.contains("unknown"));
         assert!(html.contains("0"));
     }
+
+    fn paywall_requirements(asset: &str, extra: Option<serde_json::Value>) -> PaymentRequirements {
+        PaymentRequirements {
+            scheme: "exact".to_string(),
+            network: "eip155:8453".parse().unwrap(),
+            amount: "1000".to_string(),
+            pay_to: "0x1234567890abcdef1234567890abcdef12345678".to_string(),
+            max_timeout_seconds: 60,
+            asset: asset.to_string(),
+            extra,
+        }
+    }
+
+    #[test]
+    fn test_generate_paywall_html_symbol() {
+        let req = paywall_requirements(
+            "0x1111111111111111111111111111111111111111",
+            Some(serde_json::json!({ "symbol": "TKN" })),
+        );
+        let html = generate_paywall_html("Payment required", &[req], "/api", true, None);
+        assert!(html.contains("<dd>TKN</dd>"));
+        assert!(!html.contains("{{SYMBOL}}"));
+
+        // Well-known assets fall back to their ticker.
+        let req = paywall_requirements("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", None);
+        let html = generate_paywall_html("Payment required", &[req], "/api", true, None);
+        assert!(html.contains("<dd>USDC</dd>"));
+
+        // Anything else shows the contract address.
+        let req = paywall_requirements("0x1111111111111111111111111111111111111111", None);
+        let html = generate_paywall_html("Payment required", &[req], "/api", true, None);
+        assert!(html.contains("<dd>0x1111111111111111111111111111111111111111</dd>"));
+    }
+
+    #[test]
+    fn test_generate_paywall_html_escapes_text() {
+        // `x402_description_auto` derives the message from the request path.
+        let path = "/api/<img src=x onerror=alert(1)>";
+        let message = crate::ngx_module::requirements::generate_auto_description(path);
+        let req = paywall_requirements(
+            "0x1111111111111111111111111111111111111111",
+            Some(serde_json::json!({ "symbol": "<b>TKN</b>" })),
+        );
+        let html = generate_paywall_html(&message, &[req], path, true, None);
+        assert!(!html.contains("<img"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains("&lt;img src=x onerror=alert(1)&gt;"));
+        assert!(html.contains("<dd>&lt;b&gt;TKN&lt;/b&gt;</dd>"));
+    }
+
+    #[test]
+    fn test_generate_paywall_html_requirements_meta() {
+        let req = paywall_requirements("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", None);
+        let html = generate_paywall_html(
+            "Payment required",
+            std::slice::from_ref(&req),
+            "https://example.com/api?a=1&b=<2>",
+            true,
+            None,
+        );
+        let start = html
+            .find(r#"<meta name="x402-requirements" content=""#)
+            .unwrap();
+        let b64 = html[start..].split('"').nth(3).unwrap();
+        let bytes =
+            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, b64).unwrap();
+        let decoded: Vec<PaymentRequirements> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, vec![req.clone()]);
+        assert!(html.contains("<dd>https://example.com/api?a=1&amp;b=&lt;2&gt;</dd>"));
+        assert!(!html.contains("{{"));
+
+        let html = generate_paywall_html("Payment required", &[req], "/api", false, None);
+        assert!(!html.contains("x402-requirements"));
+        assert!(!html.contains("{{"));
+    }
+
+    #[test]
+    fn test_generate_paywall_html_script() {
+        let req = paywall_requirements("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", None);
+        let reqs = std::slice::from_ref(&req);
+
+        let html = generate_paywall_html("Pay", reqs, "/api", true, None);
+        assert!(!html.contains("<script"));
+
+        let mut script = PaywallScript::default();
+        let html = generate_paywall_html("Pay", reqs, "/api", true, Some(&script));
+        assert!(html.contains(&format!(
+            "<script src=\"{DEFAULT_PAYWALL_SCRIPT_URL}\"></script>"
+        )));
+        assert!(!html.contains("x402.init"));
+        assert!(!html.contains("{{"));
+
+        script.url = "https://cdn.example/x402.js?v=1&min=1".to_string();
+        script.integrity = Some("sha384-abc+/=".to_string());
+        let html = generate_paywall_html("Pay", reqs, "/api", true, Some(&script));
+        assert!(html.contains(
+            r#"<script src="https://cdn.example/x402.js?v=1&amp;min=1" integrity="sha384-abc+/=" crossorigin="anonymous"></script>"#
+        ));
+    }
+
+    #[test]
+    fn test_generate_paywall_html_auto_connect() {
+        let req = paywall_requirements("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", None);
+        let script = PaywallScript {
+            auto_connect: true,
+            ..PaywallScript::default()
+        };
+        // The init call carries the requirements even without the <meta> tag.
+        let html = generate_paywall_html(
+            "Pay",
+            std::slice::from_ref(&req),
+            "/api",
+            false,
+            Some(&script),
+        );
+        assert!(!html.contains("x402-requirements"));
+        let start = html
+            .find("x402.init({requirements: JSON.parse(atob(\"")
+            .unwrap();
+        let b64 = html[start..].split('"').nth(1).unwrap();
+        let bytes =
+            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, b64).unwrap();
+        let decoded: Vec<PaymentRequirements> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, vec![req]);
+        // The library is imported before the init call.
+        assert!(html.find("x402.min.js").unwrap() < start);
+        assert!(!html.contains("{{"));
+    }
 }
@@ -1,37 +1,174 @@
-use crate::ngx_module::error::{ConfigError, Result};
+use crate::ngx_module::config::FacilitatorProtocol;
+use crate::ngx_module::error::{Result, X402Error};
 use crate::ngx_module::logging::{log_debug, log_error, log_info, log_warn};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex, OnceLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
 use x402_types::proto::v2::{VerifyRequest, X402Version2};
 
 pub static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
 
-pub static FACILITATOR_CLIENTS: OnceLock<Mutex<HashMap<String, Arc<HttpFacilitatorClient>>>> =
+/// Handle to [`RUNTIME`], set alongside it, so tasks can be spawned without
+/// borrowing the runtime itself.
+pub static RUNTIME_HANDLE: OnceLock<tokio::runtime::Handle> = OnceLock::new();
+
+pub static FACILITATOR_CLIENTS: OnceLock<Mutex<HashMap<String, Arc<FacilitatorClient>>>> =
+    OnceLock::new();
+
+/// `x402_facilitator_protocol` of each facilitator URL, registered while
+/// merging configs; unregistered URLs speak REST.
+static FACILITATOR_PROTOCOLS: OnceLock<Mutex<HashMap<String, FacilitatorProtocol>>> =
     OnceLock::new();
 
-pub const DEFAULT_FACILITATOR_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_FACILITATOR_TIMEOUT: Duration =
+    Duration::from_secs(crate::ngx_module::config::DEFAULT_FACILITATOR_TIMEOUT_SECS);
+/// Settlement waits for an on-chain transaction, so it defaults longer than verify.
+pub const DEFAULT_SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
 pub const MAX_PAYMENT_HEADER_SIZE: usize = 64 * 1024;
+/// Largest verify/settle response body read into memory
+/// (`x402_facilitator_response_max_size_kb`).
+pub const DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE: usize = 64 * 1024;
+pub const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `x402_facilitator_keepalive_*` of each facilitator URL, registered while
+/// merging configs; unregistered URLs use [`FacilitatorKeepalive::default`].
+static FACILITATOR_KEEPALIVE: OnceLock<Mutex<HashMap<String, FacilitatorKeepalive>>> =
+    OnceLock::new();
+
+/// Facilitators with `x402_facilitator_preflight on` as `(url, path)`,
+/// collected while merging configs and checked once in `postconfiguration`.
+type PreflightTargets = Vec<(String, Option<String>)>;
+
+static PREFLIGHT_TARGETS: OnceLock<Mutex<PreflightTargets>> = OnceLock::new();
 
 pub fn get_runtime() -> Result<&'static tokio::runtime::Runtime> {
-    RUNTIME.get_or_init(|| {
+    let runtime = RUNTIME.get_or_init(|| {
         tokio::runtime::Runtime::new()
             .unwrap_or_else(|e| panic!("Failed to create tokio runtime: {e}"))
     });
+    RUNTIME_HANDLE.get_or_init(|| runtime.handle().clone());
     RUNTIME
         .get()
-        .ok_or_else(|| ConfigError::new("Runtime not initialized"))
+        .ok_or_else(|| X402Error::Runtime("Runtime not initialized".into()))
+}
+
+/// Handle to the shared runtime, creating the runtime on first use.
+pub fn get_handle() -> Result<&'static tokio::runtime::Handle> {
+    if let Some(handle) = RUNTIME_HANDLE.get() {
+        return Ok(handle);
+    }
+    get_runtime()?;
+    RUNTIME_HANDLE
+        .get()
+        .ok_or_else(|| X402Error::Runtime("Runtime handle not initialized".into()))
+}
+
+/// Run `fut` in the background on the shared runtime, from any thread.
+pub fn spawn_on_runtime<F>(fut: F) -> Result<JoinHandle<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    Ok(get_handle()?.spawn(fut))
+}
+
+/// User-Agent of facilitator requests: `ngx-x402/<version>`, followed by
+/// ` nginx/<version>` when build.rs found the nginx binary.
+pub fn default_user_agent() -> String {
+    let base = concat!("ngx-x402/", env!("CARGO_PKG_VERSION"));
+    match option_env!("X402_NGINX_VERSION").filter(|v| !v.is_empty()) {
+        Some(nginx) => format!("{base} nginx/{nginx}"),
+        None => base.to_string(),
+    }
+}
+
+/// Connection reuse settings of a facilitator's HTTP client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FacilitatorKeepalive {
+    /// Idle connections kept open per host (`x402_facilitator_keepalive_requests`).
+    pub max_idle: usize,
+    /// How long an idle connection is kept (`x402_facilitator_keepalive_timeout_ms`).
+    pub idle_timeout: Duration,
+}
+
+pub const DEFAULT_FACILITATOR_KEEPALIVE_REQUESTS: usize = 100;
+pub const DEFAULT_FACILITATOR_KEEPALIVE_TIMEOUT_MS: u64 = 60_000;
+
+impl Default for FacilitatorKeepalive {
+    fn default() -> Self {
+        Self {
+            max_idle: DEFAULT_FACILITATOR_KEEPALIVE_REQUESTS,
+            idle_timeout: Duration::from_millis(DEFAULT_FACILITATOR_KEEPALIVE_TIMEOUT_MS),
+        }
+    }
+}
+
+/// Recognizes reused connections by their address pair, and estimates the
+/// pool size as the connections used within the idle timeout (after which
+/// the client closes them).
+struct ConnectionTracker {
+    idle_timeout: Duration,
+    last_used: Mutex<HashMap<(SocketAddr, SocketAddr), Instant>>,
+}
+
+impl ConnectionTracker {
+    fn new(idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout,
+            last_used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Note a response received at `now` on the connection `local`-`remote`
+    /// and update the connection metrics. Returns whether it was reused.
+    fn observe(&self, local: SocketAddr, remote: SocketAddr, now: Instant) -> bool {
+        let Ok(mut last_used) = self.last_used.lock() else {
+            return false;
+        };
+        let before = last_used.len() as i64;
+        last_used.retain(|_, used| now.saturating_duration_since(*used) < self.idle_timeout);
+        let reused = last_used.insert((local, remote), now).is_some();
+
+        let metrics = crate::ngx_module::metrics::X402Metrics::get();
+        if reused {
+            metrics.record_facilitator_connection_reuse();
+        }
+        let delta = last_used.len() as i64 - before;
+        if delta != 0 {
+            metrics.record_facilitator_connections(delta);
+        }
+        reused
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.last_used.lock().map_or(0, |last_used| last_used.len())
+    }
+}
+
+impl Drop for ConnectionTracker {
+    fn drop(&mut self) {
+        if let Ok(last_used) = self.last_used.get_mut() {
+            crate::ngx_module::metrics::X402Metrics::get()
+                .record_facilitator_connections(-(last_used.len() as i64));
+        }
+    }
 }
 
 pub struct HttpFacilitatorClient {
     http_client: reqwest::Client,
     base_url: String,
+    connections: ConnectionTracker,
 }
 
-type FacilitatorRequestBody = VerifyRequest<serde_json::Value, serde_json::Value>;
+pub(crate) type FacilitatorRequestBody = VerifyRequest<serde_json::Value, serde_json::Value>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VerifyResponseBody {
     #[serde(rename = "isValid")]
     pub is_valid: bool,
@@ -41,7 +178,7 @@ pub struct VerifyResponseBody {
     pub payer: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SettleResponseBody {
     pub success: bool,
     #[serde(rename = "txHash")]
@@ -50,63 +187,129 @@ pub struct SettleResponseBody {
     pub error_reason: Option<String>,
     #[serde(rename = "errorMessage")]
     pub error_message: Option<String>,
+    /// Payer confirmed by the facilitator, which some report only here.
+    #[serde(rename = "payer")]
+    pub payer: Option<String>,
 }
 
 impl HttpFacilitatorClient {
+    /// `base_url` is either an `http(s)://` URL or `unix:/path/to/socket` for a
+    /// facilitator listening on a local Unix domain socket.
     pub fn new(base_url: &str) -> Result<Self> {
-        let http_client = reqwest::Client::builder()
+        Self::with_keepalive(base_url, facilitator_keepalive(base_url))
+    }
+
+    pub fn with_keepalive(base_url: &str, keepalive: FacilitatorKeepalive) -> Result<Self> {
+        // `x402_facilitator_user_agent` overrides this per request.
+        let mut builder = reqwest::Client::builder()
+            .user_agent(default_user_agent())
+            .pool_max_idle_per_host(keepalive.max_idle)
+            .pool_idle_timeout(keepalive.idle_timeout);
+        let base_url = match base_url.strip_prefix("unix:") {
+            Some(socket_path) => {
+                builder = builder.unix_socket(socket_path.to_string());
+                "http://localhost"
+            }
+            None => base_url.trim_end_matches('/'),
+        };
+        let http_client = builder
             .build()
-            .map_err(|e| ConfigError::new(format!("Failed to create HTTP client: {e}")))?;
+            .map_err(|e| X402Error::Runtime(format!("Failed to create HTTP client: {e}")))?;
         Ok(Self {
             http_client,
-            base_url: base_url.trim_end_matches('/').to_string(),
+            base_url: base_url.to_string(),
+            connections: ConnectionTracker::new(keepalive.idle_timeout),
         })
     }
 
+    /// Update the connection metrics from the connection `resp` arrived on.
+    /// Unix socket connections carry no addresses and are not counted.
+    fn track_connection(&self, resp: &reqwest::Response) {
+        let Some(info) = resp
+            .extensions()
+            .get::<hyper_util::client::legacy::connect::HttpInfo>()
+        else {
+            return;
+        };
+        self.connections
+            .observe(info.local_addr(), info.remote_addr(), Instant::now());
+    }
+
+    /// Check that the facilitator answers: `GET <path>` must succeed, or
+    /// without a path any non-5xx answer to `OPTIONS` on the base URL will do.
+    pub async fn preflight(&self, path: Option<&str>, timeout: Duration) -> Result<()> {
+        let request = match path {
+            Some(path) => self.http_client.get(format!("{}{path}", self.base_url)),
+            None => self
+                .http_client
+                .request(reqwest::Method::OPTIONS, &self.base_url),
+        };
+        let resp = request.timeout(timeout).send().await.map_err(|e| {
+            X402Error::FacilitatorError(format!("Facilitator preflight failed: {e}"))
+        })?;
+        let status = resp.status();
+        let ok = match path {
+            Some(_) => status.is_success(),
+            None => !status.is_server_error(),
+        };
+        if !ok {
+            return Err(X402Error::FacilitatorError(format!(
+                "Facilitator preflight returned status {status}"
+            )));
+        }
+        Ok(())
+    }
+
     pub async fn verify(
         &self,
         body: &FacilitatorRequestBody,
         timeout: Duration,
+        headers: &[(String, String)],
+        max_response_size: usize,
     ) -> Result<VerifyResponseBody> {
         let url = format!("{}/verify", self.base_url);
-        let resp = self
-            .http_client
-            .post(&url)
-            .json(body)
-            .timeout(timeout)
-            .send()
-            .await
-            .map_err(|e| ConfigError::new(format!("Facilitator verify request failed: {e}")))?;
+        let mut request = self.http_client.post(&url).json(body).timeout(timeout);
+        for (name, value) in headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let resp = request.send().await.map_err(|e| {
+            X402Error::FacilitatorError(format!("Facilitator verify request failed: {e}"))
+        })?;
+        self.track_connection(&resp);
 
         if !resp.status().is_success() {
-            return Err(ConfigError::new(format!(
+            return Err(X402Error::FacilitatorError(format!(
                 "Facilitator returned status {}",
                 resp.status()
             )));
         }
 
-        resp.json::<VerifyResponseBody>()
-            .await
-            .map_err(|e| ConfigError::new(format!("Failed to parse verify response: {e}")))
+        let body = read_body_limited(resp, max_response_size).await?;
+        serde_json::from_slice(&body).map_err(|e| {
+            X402Error::FacilitatorError(format!("Failed to parse verify response: {e}"))
+        })
     }
 
     pub async fn settle(
         &self,
         body: &FacilitatorRequestBody,
         timeout: Duration,
+        headers: &[(String, String)],
+        max_response_size: usize,
     ) -> Result<SettleResponseBody> {
         let url = format!("{}/settle", self.base_url);
-        let resp = self
-            .http_client
-            .post(&url)
-            .json(body)
-            .timeout(timeout)
-            .send()
-            .await
-            .map_err(|e| ConfigError::new(format!("Facilitator settle request failed: {e}")))?;
+        let mut request = self.http_client.post(&url).json(body).timeout(timeout);
+        for (name, value) in headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let resp = request.send().await.map_err(|e| {
+            X402Error::FacilitatorError(format!("Facilitator settle request failed: {e}"))
+        })?;
+        self.track_connection(&resp);
 
         let status = resp.status();
-        let body_text = resp.text().await.unwrap_or_default();
+        let body = read_body_limited(resp, max_response_size).await?;
+        let body_text = String::from_utf8_lossy(&body);
 
         if !status.is_success() {
             log_error(
@@ -117,7 +320,7 @@ impl HttpFacilitatorClient {
                     body_text.chars().take(500).collect::<String>()
                 ),
             );
-            return Err(ConfigError::new(format!(
+            return Err(X402Error::FacilitatorError(format!(
                 "Facilitator settle returned status {status}"
             )));
         }
@@ -127,62 +330,281 @@ impl HttpFacilitatorClient {
                 None,
                 &format!("Failed to parse settle response: {e}, body: {}", body_text),
             );
-            ConfigError::new(format!("Failed to parse settle response: {e}"))
+            X402Error::FacilitatorError(format!("Failed to parse settle response: {e}"))
         })
     }
 }
 
-pub fn get_facilitator_client(url: &str) -> Result<Arc<HttpFacilitatorClient>> {
+/// A facilitator client speaking the protocol set by `x402_facilitator_protocol`.
+pub enum FacilitatorClient {
+    Http(HttpFacilitatorClient),
+    #[cfg(feature = "grpc")]
+    Grpc(crate::ngx_module::grpc::GrpcFacilitatorClient),
+}
+
+impl FacilitatorClient {
+    pub async fn verify(
+        &self,
+        body: &FacilitatorRequestBody,
+        timeout: Duration,
+        headers: &[(String, String)],
+        max_response_size: usize,
+    ) -> Result<VerifyResponseBody> {
+        match self {
+            Self::Http(client) => {
+                client
+                    .verify(body, timeout, headers, max_response_size)
+                    .await
+            }
+            #[cfg(feature = "grpc")]
+            Self::Grpc(client) => {
+                client
+                    .verify(body, timeout, headers, max_response_size)
+                    .await
+            }
+        }
+    }
+
+    pub async fn settle(
+        &self,
+        body: &FacilitatorRequestBody,
+        timeout: Duration,
+        headers: &[(String, String)],
+        max_response_size: usize,
+    ) -> Result<SettleResponseBody> {
+        match self {
+            Self::Http(client) => {
+                client
+                    .settle(body, timeout, headers, max_response_size)
+                    .await
+            }
+            #[cfg(feature = "grpc")]
+            Self::Grpc(client) => {
+                client
+                    .settle(body, timeout, headers, max_response_size)
+                    .await
+            }
+        }
+    }
+}
+
+/// Read a facilitator response body, refusing more than `max_size` bytes so a
+/// broken or hostile facilitator cannot exhaust worker memory.
+async fn read_body_limited(mut resp: reqwest::Response, max_size: usize) -> Result<Vec<u8>> {
+    if let Some(len) = resp.content_length().filter(|&len| len > max_size as u64) {
+        return Err(response_too_large(
+            &format!("Content-Length {len}"),
+            max_size,
+        ));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(|e| {
+        X402Error::FacilitatorError(format!("Failed to read facilitator response: {e}"))
+    })? {
+        if body.len() + chunk.len() > max_size {
+            return Err(response_too_large(
+                &format!("more than {max_size} bytes received"),
+                max_size,
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+fn response_too_large(size: &str, max_size: usize) -> X402Error {
+    log_warn(
+        None,
+        &format!("Facilitator response too large ({size}, limit {max_size} bytes)"),
+    );
+    crate::ngx_module::metrics::X402Metrics::get().record_facilitator_response_oversized();
+    X402Error::FacilitatorError("Facilitator response too large".into())
+}
+
+/// Record the protocol `url` is configured with. Returns `false` when another
+/// location already uses the same URL with the other protocol.
+pub fn register_facilitator_protocol(url: &str, protocol: FacilitatorProtocol) -> bool {
+    let protocols = FACILITATOR_PROTOCOLS.get_or_init(|| Mutex::new(HashMap::new()));
+    let Ok(mut protocols) = protocols.lock() else {
+        return false;
+    };
+    *protocols.entry(url.to_string()).or_insert(protocol) == protocol
+}
+
+/// Record the keep-alive settings `url` is configured with. Returns `false`
+/// when another location already uses the same URL with other settings.
+pub fn register_facilitator_keepalive(url: &str, keepalive: FacilitatorKeepalive) -> bool {
+    let settings = FACILITATOR_KEEPALIVE.get_or_init(|| Mutex::new(HashMap::new()));
+    let Ok(mut settings) = settings.lock() else {
+        return false;
+    };
+    *settings.entry(url.to_string()).or_insert(keepalive) == keepalive
+}
+
+fn facilitator_keepalive(url: &str) -> FacilitatorKeepalive {
+    FACILITATOR_KEEPALIVE
+        .get()
+        .and_then(|settings| settings.lock().ok()?.get(url).copied())
+        .unwrap_or_default()
+}
+
+fn facilitator_protocol(url: &str) -> FacilitatorProtocol {
+    FACILITATOR_PROTOCOLS
+        .get()
+        .and_then(|protocols| protocols.lock().ok()?.get(url).copied())
+        .unwrap_or(FacilitatorProtocol::Rest)
+}
+
+fn new_facilitator_client(url: &str) -> Result<FacilitatorClient> {
+    match facilitator_protocol(url) {
+        FacilitatorProtocol::Rest => Ok(FacilitatorClient::Http(HttpFacilitatorClient::new(url)?)),
+        #[cfg(feature = "grpc")]
+        FacilitatorProtocol::Grpc => Ok(FacilitatorClient::Grpc(
+            crate::ngx_module::grpc::GrpcFacilitatorClient::new(url)?,
+        )),
+        #[cfg(not(feature = "grpc"))]
+        FacilitatorProtocol::Grpc => Err(X402Error::Config(
+            "x402_facilitator_protocol grpc requires the grpc feature".into(),
+        )),
+    }
+}
+
+pub fn get_facilitator_client(url: &str) -> Result<Arc<FacilitatorClient>> {
     let clients = FACILITATOR_CLIENTS.get_or_init(|| Mutex::new(HashMap::new()));
 
     {
         let guard = clients
             .lock()
-            .map_err(|_| ConfigError::new("Lock poisoned"))?;
+            .map_err(|_| X402Error::Runtime("Lock poisoned".into()))?;
         if let Some(client) = guard.get(url) {
             return Ok(Arc::clone(client));
         }
     }
 
-    let client = HttpFacilitatorClient::new(url)?;
+    let client = new_facilitator_client(url)?;
     let client_arc = Arc::new(client);
 
     {
         let mut guard = clients
             .lock()
-            .map_err(|_| ConfigError::new("Lock poisoned"))?;
+            .map_err(|_| X402Error::Runtime("Lock poisoned".into()))?;
         guard.insert(url.to_string(), Arc::clone(&client_arc));
     }
 
     Ok(client_arc)
 }
 
+/// Queue a facilitator for [`run_facilitator_preflights`].
+pub fn register_preflight_target(url: &str, path: Option<&str>) {
+    let targets = PREFLIGHT_TARGETS.get_or_init(|| Mutex::new(Vec::new()));
+    if let Ok(mut targets) = targets.lock() {
+        let target = (url.to_string(), path.map(String::from));
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+}
+
+/// Ping every registered facilitator once, warning about the unreachable ones.
+///
+/// Runs in the master process before workers fork, so it uses a throwaway
+/// current-thread runtime instead of [`get_runtime`]. Returns whether all
+/// checks passed.
+pub fn run_facilitator_preflights() -> bool {
+    let targets = match PREFLIGHT_TARGETS.get().map(|t| t.lock()) {
+        Some(Ok(mut targets)) => std::mem::take(&mut *targets),
+        _ => return true,
+    };
+    if targets.is_empty() {
+        return true;
+    }
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(e) => {
+            log_warn(None, &format!("Facilitator preflight skipped: {e}"));
+            return false;
+        }
+    };
+    let mut all_ok = true;
+    for (url, path) in &targets {
+        let result = HttpFacilitatorClient::new(url).and_then(|client| {
+            runtime.block_on(client.preflight(path.as_deref(), PREFLIGHT_TIMEOUT))
+        });
+        match result {
+            Ok(()) => log_info(None, &format!("Facilitator preflight ok: {url}")),
+            Err(e) => {
+                all_ok = false;
+                log_warn(
+                    None,
+                    &format!("Facilitator {url} is not reachable yet: {e}"),
+                );
+            }
+        }
+    }
+    crate::ngx_module::metrics::X402Metrics::get().record_facilitator_preflight(all_ok);
+    all_ok
+}
+
+/// Path of a `file:///path/to/response.json` facilitator. For development
+/// only (`x402_dry_run on`): verify and settle both answer with that file,
+/// so it holds both shapes, e.g.
+/// `{"isValid": true, "payer": "0x...", "success": true, "txHash": "0x..."}`.
+pub fn file_facilitator_path(url: &str) -> Option<&str> {
+    url.trim().strip_prefix("file://")
+}
+
+/// Read a file facilitator's answer after `delay` of simulated latency.
+/// The file is read on every call, so it can be edited while nginx runs.
+pub(crate) async fn read_file_response<T: DeserializeOwned>(
+    path: &str,
+    delay: Duration,
+) -> Result<T> {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    crate::ngx_module::metrics::X402Metrics::get().record_file_facilitator_hit();
+    let body = std::fs::read_to_string(path).map_err(|e| {
+        X402Error::FacilitatorError(format!("Failed to read facilitator file {path}: {e}"))
+    })?;
+    serde_json::from_str(&body).map_err(|e| {
+        X402Error::FacilitatorError(format!("Failed to parse facilitator file {path}: {e}"))
+    })
+}
+
 pub async fn verify_payment(
     payment_b64: &str,
     requirements_json: &serde_json::Value,
     facilitator_url: &str,
     timeout_duration: Option<Duration>,
+    headers: &[(String, String)],
+    file_delay: Duration,
+    max_response_size: usize,
 ) -> Result<VerifyResponseBody> {
     use crate::ngx_module::error::user_errors;
 
     if payment_b64.is_empty() {
-        return Err(ConfigError::new(user_errors::INVALID_PAYMENT));
+        return Err(X402Error::InvalidPayment(
+            user_errors::INVALID_PAYMENT.into(),
+        ));
     }
     if facilitator_url.is_empty() {
-        return Err(ConfigError::new(user_errors::CONFIGURATION_ERROR));
+        return Err(X402Error::Config(user_errors::CONFIGURATION_ERROR.into()));
     }
 
     let payment_payload: serde_json::Value = serde_json::from_slice(
         &base64::Engine::decode(&base64::engine::general_purpose::STANDARD, payment_b64).map_err(
             |e| {
                 log_error(None, &format!("Failed to decode payment payload: {e}"));
-                ConfigError::new(user_errors::INVALID_PAYMENT)
+                X402Error::InvalidPayment(user_errors::INVALID_PAYMENT.into())
             },
         )?,
     )
     .map_err(|e| {
         log_error(None, &format!("Failed to parse payment JSON: {e}"));
-        ConfigError::new(user_errors::INVALID_PAYMENT)
+        X402Error::InvalidPayment(user_errors::INVALID_PAYMENT.into())
     })?;
 
     let body = FacilitatorRequestBody {
@@ -191,10 +613,20 @@ pub async fn verify_payment(
         payment_requirements: requirements_json.clone(),
     };
 
-    let client = get_facilitator_client(facilitator_url)?;
     let timeout = timeout_duration.unwrap_or(DEFAULT_FACILITATOR_TIMEOUT);
+    let result = match file_facilitator_path(facilitator_url) {
+        Some(path) => tokio::time::timeout(timeout, read_file_response(path, file_delay)).await,
+        None => {
+            let client = get_facilitator_client(facilitator_url)?;
+            tokio::time::timeout(
+                timeout,
+                client.verify(&body, timeout, headers, max_response_size),
+            )
+            .await
+        }
+    };
 
-    match tokio::time::timeout(timeout, client.verify(&body, timeout)).await {
+    match result {
         Ok(Ok(response)) => {
             log_debug(
                 None,
@@ -208,14 +640,16 @@ pub async fn verify_payment(
         }
         Ok(Err(e)) => {
             log_error(None, &format!("Payment verification failed: {e}"));
-            Err(ConfigError::new(user_errors::PAYMENT_VERIFICATION_FAILED))
+            Err(X402Error::FacilitatorError(
+                user_errors::PAYMENT_VERIFICATION_FAILED.into(),
+            ))
         }
         Err(_) => {
             log_warn(
                 None,
                 &format!("Payment verification timeout after {timeout:?}"),
             );
-            Err(ConfigError::new(user_errors::TIMEOUT))
+            Err(X402Error::Timeout(user_errors::TIMEOUT.into()))
         }
     }
 }
@@ -227,27 +661,32 @@ pub async fn settle_payment(
     requirements_json: &serde_json::Value,
     facilitator_url: &str,
     timeout_duration: Option<Duration>,
+    headers: &[(String, String)],
+    file_delay: Duration,
+    max_response_size: usize,
 ) -> Result<SettleResponseBody> {
     use crate::ngx_module::error::user_errors;
 
     if payment_b64.is_empty() {
-        return Err(ConfigError::new(user_errors::INVALID_PAYMENT));
+        return Err(X402Error::InvalidPayment(
+            user_errors::INVALID_PAYMENT.into(),
+        ));
     }
     if facilitator_url.is_empty() {
-        return Err(ConfigError::new(user_errors::CONFIGURATION_ERROR));
+        return Err(X402Error::Config(user_errors::CONFIGURATION_ERROR.into()));
     }
 
     let payment_payload: serde_json::Value = serde_json::from_slice(
         &base64::Engine::decode(&base64::engine::general_purpose::STANDARD, payment_b64).map_err(
             |e| {
                 log_error(None, &format!("Failed to decode payment payload: {e}"));
-                ConfigError::new(user_errors::INVALID_PAYMENT)
+                X402Error::InvalidPayment(user_errors::INVALID_PAYMENT.into())
             },
         )?,
     )
     .map_err(|e| {
         log_error(None, &format!("Failed to parse payment JSON: {e}"));
-        ConfigError::new(user_errors::INVALID_PAYMENT)
+        X402Error::InvalidPayment(user_errors::INVALID_PAYMENT.into())
     })?;
 
     let body = FacilitatorRequestBody {
@@ -256,12 +695,22 @@ pub async fn settle_payment(
         payment_requirements: requirements_json.clone(),
     };
 
-    let client = get_facilitator_client(facilitator_url)?;
-    let timeout = timeout_duration.unwrap_or(DEFAULT_FACILITATOR_TIMEOUT);
-
-    let settle_timeout = timeout.max(Duration::from_secs(30));
+    let settle_timeout = timeout_duration.unwrap_or(DEFAULT_SETTLE_TIMEOUT);
+    let result = match file_facilitator_path(facilitator_url) {
+        Some(path) => {
+            tokio::time::timeout(settle_timeout, read_file_response(path, file_delay)).await
+        }
+        None => {
+            let client = get_facilitator_client(facilitator_url)?;
+            tokio::time::timeout(
+                settle_timeout,
+                client.settle(&body, settle_timeout, headers, max_response_size),
+            )
+            .await
+        }
+    };
 
-    match tokio::time::timeout(settle_timeout, client.settle(&body, settle_timeout)).await {
+    match result {
         Ok(Ok(response)) => {
             log_info(
                 None,
@@ -277,14 +726,545 @@ pub async fn settle_payment(
         }
         Ok(Err(e)) => {
             log_error(None, &format!("Payment settlement failed: {e}"));
-            Err(ConfigError::new(user_errors::PAYMENT_VERIFICATION_FAILED))
+            Err(X402Error::FacilitatorError(
+                user_errors::PAYMENT_VERIFICATION_FAILED.into(),
+            ))
         }
         Err(_) => {
             log_warn(
                 None,
                 &format!("Payment settlement timeout after {settle_timeout:?}"),
             );
-            Err(ConfigError::new(user_errors::TIMEOUT))
+            Err(X402Error::Timeout(user_errors::TIMEOUT.into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngx_module::test_support::MockServer;
+
+    fn request_body() -> FacilitatorRequestBody {
+        FacilitatorRequestBody {
+            x402_version: X402Version2,
+            payment_payload: serde_json::json!({}),
+            payment_requirements: serde_json::json!({}),
+        }
+    }
+
+    #[test]
+    fn test_register_facilitator_protocol() {
+        let url = "https://protocol-test.example";
+        assert_eq!(facilitator_protocol(url), FacilitatorProtocol::Rest);
+        assert!(register_facilitator_protocol(
+            url,
+            FacilitatorProtocol::Rest
+        ));
+        assert!(register_facilitator_protocol(
+            url,
+            FacilitatorProtocol::Rest
+        ));
+        assert!(!register_facilitator_protocol(
+            url,
+            FacilitatorProtocol::Grpc
+        ));
+        assert_eq!(facilitator_protocol(url), FacilitatorProtocol::Rest);
+    }
+
+    #[test]
+    fn test_register_facilitator_keepalive() {
+        let url = "https://keepalive-test.example";
+        assert_eq!(facilitator_keepalive(url), FacilitatorKeepalive::default());
+        let keepalive = FacilitatorKeepalive {
+            max_idle: 8,
+            idle_timeout: Duration::from_secs(5),
+        };
+        assert!(register_facilitator_keepalive(url, keepalive));
+        assert!(register_facilitator_keepalive(url, keepalive));
+        assert!(!register_facilitator_keepalive(
+            url,
+            FacilitatorKeepalive::default()
+        ));
+        assert_eq!(facilitator_keepalive(url), keepalive);
+    }
+
+    #[test]
+    fn test_connection_tracker() {
+        let tracker = ConnectionTracker::new(Duration::from_secs(60));
+        let remote: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let (a, b): (SocketAddr, SocketAddr) = (
+            "127.0.0.1:40001".parse().unwrap(),
+            "127.0.0.1:40002".parse().unwrap(),
+        );
+        let now = Instant::now();
+
+        assert!(!tracker.observe(a, remote, now));
+        assert!(tracker.observe(a, remote, now));
+        assert!(!tracker.observe(b, remote, now));
+        assert_eq!(tracker.len(), 2);
+        // Past the idle timeout both were closed; `a` is a new connection.
+        assert!(!tracker.observe(a, remote, now + Duration::from_secs(61)));
+        assert_eq!(tracker.len(), 1);
+    }
+
+    /// Send `n` verify calls with `client`, one after the other.
+    fn verify_times(client: &HttpFacilitatorClient, n: usize) {
+        let runtime = get_runtime().unwrap();
+        for _ in 0..n {
+            let verify = runtime.block_on(client.verify(
+                &request_body(),
+                Duration::from_secs(5),
+                &[],
+                DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            ));
+            assert!(verify.unwrap().is_valid);
         }
     }
+
+    #[test]
+    fn test_keepalive_reuses_connection() {
+        let server = MockServer::start_keepalive(|_| (200, r#"{"isValid":true}"#.to_string()));
+        let client =
+            HttpFacilitatorClient::with_keepalive(&server.url, FacilitatorKeepalive::default())
+                .unwrap();
+        let metrics = crate::ngx_module::metrics::X402Metrics::get();
+        let reused = metrics.facilitator_connection_reuse.get();
+
+        verify_times(&client, 3);
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(server.connections(), 1);
+        // Other tests share the counter, so only a lower bound holds.
+        assert!(metrics.facilitator_connection_reuse.get() >= reused + 2);
+    }
+
+    #[test]
+    fn test_keepalive_disabled_opens_new_connections() {
+        let server = MockServer::start_keepalive(|_| (200, r#"{"isValid":true}"#.to_string()));
+        let keepalive = FacilitatorKeepalive {
+            max_idle: 0,
+            ..FacilitatorKeepalive::default()
+        };
+        let client = HttpFacilitatorClient::with_keepalive(&server.url, keepalive).unwrap();
+
+        verify_times(&client, 3);
+        assert_eq!(server.connections(), 3);
+    }
+
+    #[test]
+    fn test_spawn_via_handle() {
+        let handle = get_handle().unwrap();
+        assert!(std::ptr::eq(handle, get_handle().unwrap()));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        handle.spawn(async move { tx.send(7).unwrap() });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(7));
+
+        let task = spawn_on_runtime(async { 6 * 7 }).unwrap();
+        assert_eq!(get_runtime().unwrap().block_on(task).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_custom_headers_sent_on_verify_and_settle() {
+        let server = MockServer::start(|req: &str| {
+            if req.starts_with("POST /verify") {
+                (200, r#"{"isValid":true}"#.to_string())
+            } else {
+                (200, r#"{"success":true}"#.to_string())
+            }
+        });
+        let client = HttpFacilitatorClient::new(&server.url).unwrap();
+        let headers = vec![
+            ("X-Api-Key".to_string(), "secret-key".to_string()),
+            ("X-Tenant".to_string(), "acme".to_string()),
+        ];
+        let runtime = get_runtime().unwrap();
+
+        let verify = runtime
+            .block_on(client.verify(
+                &request_body(),
+                Duration::from_secs(5),
+                &headers,
+                DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            ))
+            .unwrap();
+        assert_eq!(
+            verify,
+            VerifyResponseBody {
+                is_valid: true,
+                invalid_reason: None,
+                payer: None,
+            }
+        );
+        let settle = runtime
+            .block_on(client.settle(
+                &request_body(),
+                Duration::from_secs(5),
+                &headers,
+                DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            ))
+            .unwrap();
+        assert_eq!(
+            settle,
+            SettleResponseBody {
+                success: true,
+                tx_hash: None,
+                error_reason: None,
+                error_message: None,
+                payer: None,
+            }
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for req in requests {
+            let req = req.to_ascii_lowercase();
+            assert!(req.contains("x-api-key: secret-key"));
+            assert!(req.contains("x-tenant: acme"));
+        }
+    }
+
+    #[test]
+    fn test_user_agent_sent_on_verify_and_settle() {
+        let server = MockServer::start(|req: &str| {
+            if req.starts_with("POST /verify") {
+                (200, r#"{"isValid":true}"#.to_string())
+            } else {
+                (200, r#"{"success":true}"#.to_string())
+            }
+        });
+        let client = HttpFacilitatorClient::new(&server.url).unwrap();
+        let runtime = get_runtime().unwrap();
+        let timeout = Duration::from_secs(5);
+        let custom = [("User-Agent".to_string(), "acme-gateway/2".to_string())];
+
+        for headers in [&[][..], &custom[..]] {
+            runtime
+                .block_on(client.verify(
+                    &request_body(),
+                    timeout,
+                    headers,
+                    DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+                ))
+                .unwrap();
+            runtime
+                .block_on(client.settle(
+                    &request_body(),
+                    timeout,
+                    headers,
+                    DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+                ))
+                .unwrap();
+        }
+
+        let user_agents: Vec<String> = server
+            .requests()
+            .iter()
+            .map(|req| {
+                req.lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .filter(|(name, _)| name.eq_ignore_ascii_case("user-agent"))
+                    .map(|(_, value)| value.trim().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect();
+        let default = default_user_agent();
+        assert!(default.starts_with(concat!("ngx-x402/", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(
+            user_agents,
+            [
+                default.as_str(),
+                &default,
+                "acme-gateway/2",
+                "acme-gateway/2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_oversized_response_rejected() {
+        let server = MockServer::start(|req: &str| {
+            let padding = "x".repeat(4096);
+            if req.starts_with("POST /verify") {
+                (
+                    200,
+                    format!(r#"{{"isValid":true,"invalidReason":"{padding}"}}"#),
+                )
+            } else {
+                (
+                    200,
+                    format!(r#"{{"success":true,"errorMessage":"{padding}"}}"#),
+                )
+            }
+        });
+        let client = HttpFacilitatorClient::new(&server.url).unwrap();
+        let runtime = get_runtime().unwrap();
+        let timeout = Duration::from_secs(5);
+        let oversized = || {
+            crate::ngx_module::metrics::X402Metrics::get()
+                .facilitator_response_oversized
+                .get()
+        };
+        let before = oversized();
+
+        let verify = runtime.block_on(client.verify(&request_body(), timeout, &[], 1024));
+        assert_eq!(
+            verify,
+            Err(X402Error::FacilitatorError(
+                "Facilitator response too large".into()
+            ))
+        );
+        let settle = runtime.block_on(client.settle(&request_body(), timeout, &[], 1024));
+        assert!(matches!(settle, Err(X402Error::FacilitatorError(_))));
+        assert!(oversized() >= before + 2);
+
+        // The same bodies are fine under the default limit.
+        assert!(
+            runtime
+                .block_on(client.verify(
+                    &request_body(),
+                    timeout,
+                    &[],
+                    DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE
+                ))
+                .unwrap()
+                .is_valid
+        );
+    }
+
+    #[test]
+    fn test_response_bodies_deserialize_and_clone() {
+        let verify: VerifyResponseBody = serde_json::from_str(
+            r#"{"isValid":false,"invalidReason":"insufficient_funds","payer":"0xabc"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            verify,
+            VerifyResponseBody {
+                is_valid: false,
+                invalid_reason: Some("insufficient_funds".into()),
+                payer: Some("0xabc".into()),
+            }
+        );
+        assert_eq!(verify.clone(), verify);
+
+        let settle: SettleResponseBody =
+            serde_json::from_str(r#"{"success":true,"txHash":"0xdef"}"#).unwrap();
+        assert_eq!(settle.payer, None);
+        let mut copy = settle.clone();
+        assert_eq!(copy, settle);
+        copy.tx_hash = None;
+        assert_ne!(copy, settle);
+    }
+
+    #[test]
+    fn test_settle_response_payer() {
+        let settle: SettleResponseBody = serde_json::from_str(
+            r#"{"success":true,"txHash":"0xdef","payer":"0x9999999999999999999999999999999999999999"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settle,
+            SettleResponseBody {
+                success: true,
+                tx_hash: Some("0xdef".into()),
+                error_reason: None,
+                error_message: None,
+                payer: Some("0x9999999999999999999999999999999999999999".into()),
+            }
+        );
+
+        let settle: SettleResponseBody =
+            serde_json::from_str(r#"{"success":false,"payer":null,"errorReason":"x"}"#).unwrap();
+        assert_eq!(settle.payer, None);
+        assert_eq!(settle.error_reason.as_deref(), Some("x"));
+    }
+
+    /// A `file://` facilitator fixture in a fresh temp file.
+    fn facilitator_file(name: &str, body: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "x402-facilitator-{}-{name}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, body).unwrap();
+        path
+    }
+
+    fn payment_b64() -> String {
+        base64::Engine::encode(&base64::engine::general_purpose::STANDARD, "{}")
+    }
+
+    #[test]
+    fn test_file_facilitator_verify_and_settle() {
+        let path = facilitator_file(
+            "ok",
+            r#"{"isValid":true,"payer":"0x9999999999999999999999999999999999999999","success":true,"txHash":"0xfeed"}"#,
+        );
+        let url = format!("file://{}", path.display());
+        assert_eq!(file_facilitator_path(&url), Some(path.to_str().unwrap()));
+        assert_eq!(file_facilitator_path("https://x402.org/facilitator"), None);
+
+        let runtime = get_runtime().unwrap();
+        let hits = crate::ngx_module::metrics::X402Metrics::get()
+            .file_facilitator_hits
+            .get();
+        let requirements = serde_json::json!({});
+        let verify = runtime
+            .block_on(verify_payment(
+                &payment_b64(),
+                &requirements,
+                &url,
+                None,
+                &[],
+                Duration::ZERO,
+                DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            ))
+            .unwrap();
+        assert!(verify.is_valid);
+        assert_eq!(
+            verify.payer.as_deref(),
+            Some("0x9999999999999999999999999999999999999999")
+        );
+        let settle = runtime
+            .block_on(settle_payment(
+                &payment_b64(),
+                &requirements,
+                &url,
+                None,
+                &[],
+                Duration::ZERO,
+                DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            ))
+            .unwrap();
+        assert!(settle.success);
+        assert_eq!(settle.tx_hash.as_deref(), Some("0xfeed"));
+        assert!(
+            crate::ngx_module::metrics::X402Metrics::get()
+                .file_facilitator_hits
+                .get()
+                >= hits + 2
+        );
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_file_facilitator_delay_and_errors() {
+        let path = facilitator_file("slow", r#"{"isValid":true}"#);
+        let url = format!("file://{}", path.display());
+        let runtime = get_runtime().unwrap();
+        let requirements = serde_json::json!({});
+
+        // The simulated latency counts against the verify timeout.
+        let result = runtime.block_on(verify_payment(
+            &payment_b64(),
+            &requirements,
+            &url,
+            Some(Duration::from_millis(50)),
+            &[],
+            Duration::from_secs(5),
+            DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+        ));
+        assert!(matches!(result, Err(X402Error::Timeout(_))));
+
+        std::fs::write(&path, "not json").unwrap();
+        let result = runtime.block_on(verify_payment(
+            &payment_b64(),
+            &requirements,
+            &url,
+            None,
+            &[],
+            Duration::ZERO,
+            DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+        ));
+        assert!(matches!(result, Err(X402Error::FacilitatorError(_))));
+
+        std::fs::remove_file(&path).ok();
+        let result = runtime.block_on(settle_payment(
+            &payment_b64(),
+            &requirements,
+            &url,
+            None,
+            &[],
+            Duration::ZERO,
+            DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+        ));
+        assert!(matches!(result, Err(X402Error::FacilitatorError(_))));
+    }
+
+    #[test]
+    fn test_verify_over_unix_socket() {
+        let server = MockServer::start_unix(|_| (200, r#"{"isValid":true}"#.to_string()));
+        let client = HttpFacilitatorClient::new(&server.url).unwrap();
+        let verify = get_runtime()
+            .unwrap()
+            .block_on(client.verify(
+                &request_body(),
+                Duration::from_secs(5),
+                &[],
+                DEFAULT_FACILITATOR_RESPONSE_MAX_SIZE,
+            ))
+            .unwrap();
+        assert_eq!(
+            verify,
+            VerifyResponseBody {
+                is_valid: true,
+                invalid_reason: None,
+                payer: None,
+            }
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with("POST /verify HTTP/1.1"));
+        assert!(requests[0].to_ascii_lowercase().contains("host: localhost"));
+    }
+
+    #[test]
+    fn test_preflight() {
+        let server = MockServer::start(|req: &str| {
+            if req.starts_with("GET /health") {
+                (200, "ok".to_string())
+            } else if req.starts_with("GET /down") {
+                (503, "down".to_string())
+            } else {
+                (404, String::new())
+            }
+        });
+        let client = HttpFacilitatorClient::new(&server.url).unwrap();
+        let runtime = get_runtime().unwrap();
+        let timeout = Duration::from_secs(5);
+
+        assert!(runtime
+            .block_on(client.preflight(Some("/health"), timeout))
+            .is_ok());
+        assert!(runtime
+            .block_on(client.preflight(Some("/down"), timeout))
+            .is_err());
+        // Any non-5xx answer to OPTIONS means the facilitator is up.
+        assert!(runtime.block_on(client.preflight(None, timeout)).is_ok());
+
+        let requests = server.requests();
+        assert!(requests[2].starts_with("OPTIONS / HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_run_facilitator_preflights() {
+        let server = MockServer::start(|_| (200, "ok".to_string()));
+        register_preflight_target(&server.url, Some("/health"));
+        register_preflight_target(&server.url, Some("/health"));
+        assert!(run_facilitator_preflights());
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(
+            crate::ngx_module::metrics::X402Metrics::get()
+                .facilitator_preflight_ok
+                .get(),
+            1
+        );
+
+        register_preflight_target("http://127.0.0.1:1", None);
+        assert!(!run_facilitator_preflights());
+        // Targets are consumed by each run.
+        assert!(run_facilitator_preflights());
+    }
 }
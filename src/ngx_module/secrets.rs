@@ -0,0 +1,423 @@
+//! `vault:<path>#<field>` references in secret-bearing directives, read from
+//! a HashiCorp Vault KV secrets engine.
+//!
+//! `<path>` is the API path under `/v1/`, e.g. `secret/data/x402` for a KV v2
+//! mount named `secret`; the field is read from `data.data` (v2) or `data`
+//! (v1). References are resolved while the configuration is merged, before
+//! nginx forks, so a secret that cannot be read fails the load and workers
+//! start with every value cached; failed reads are cached too. With
+//! `x402_vault_secret_ttl_secs` set, each worker re-reads its cached secrets in
+//! the background every TTL, so a rotated secret is picked up without a reload.
+
+use crate::ngx_module::error::{Result, X402Error};
+use crate::ngx_module::logging::log_warn;
+use crate::ngx_module::runtime::{default_user_agent, spawn_on_runtime};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub const VAULT_PREFIX: &str = "vault:";
+
+/// Timeout of a single Vault read.
+const VAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where secrets are read from: `x402_vault_addr` and
+/// `x402_vault_token_file`, falling back to `$VAULT_ADDR` and `$VAULT_TOKEN`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultSettings {
+    pub addr: String,
+    pub token_file: Option<PathBuf>,
+}
+
+impl VaultSettings {
+    /// `addr` defaults to `$VAULT_ADDR`.
+    pub fn new(addr: Option<String>, token_file: Option<PathBuf>) -> Result<Self> {
+        let addr = match addr {
+            Some(addr) => addr,
+            None => std::env::var("VAULT_ADDR").map_err(|_| {
+                X402Error::Config("no Vault address: set x402_vault_addr or $VAULT_ADDR".into())
+            })?,
+        };
+        Ok(Self {
+            addr: addr.trim_end_matches('/').to_string(),
+            token_file,
+        })
+    }
+
+    /// Read on every fetch, so a renewed token file is used by the next refresh.
+    fn token(&self) -> Result<String> {
+        let token = match &self.token_file {
+            Some(path) => std::fs::read_to_string(path).map_err(|e| {
+                X402Error::Config(format!(
+                    "cannot read Vault token file {}: {e}",
+                    path.display()
+                ))
+            })?,
+            None => std::env::var("VAULT_TOKEN").map_err(|_| {
+                X402Error::Config(
+                    "no Vault token: set x402_vault_token_file or $VAULT_TOKEN".into(),
+                )
+            })?,
+        };
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(X402Error::Config("Vault token is empty".into()));
+        }
+        Ok(token.to_string())
+    }
+}
+
+/// Split the part after `vault:` into `(path, field)`.
+pub fn parse_vault_ref(reference: &str) -> std::result::Result<(&str, &str), String> {
+    let (path, field) = reference
+        .split_once('#')
+        .ok_or_else(|| format!("expected vault:<path>#<field>, got vault:{reference}"))?;
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return Err("missing secret path after 'vault:'".to_string());
+    }
+    if field.is_empty() {
+        return Err(format!("missing field name after 'vault:{path}#'"));
+    }
+    Ok((path, field))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SecretKey {
+    addr: String,
+    token_file: Option<PathBuf>,
+    path: String,
+    field: String,
+}
+
+struct CachedSecret {
+    value: Result<String>,
+    settings: VaultSettings,
+    fetched_at: Instant,
+}
+
+static SECRETS: OnceLock<Mutex<HashMap<SecretKey, CachedSecret>>> = OnceLock::new();
+
+/// Shortest `x402_vault_secret_ttl_secs` seen while merging configs.
+static REFRESH_TTL: Mutex<Option<Duration>> = Mutex::new(None);
+
+fn secrets() -> &'static Mutex<HashMap<SecretKey, CachedSecret>> {
+    SECRETS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Read `field` of the secret at `vault_path`, using `$VAULT_ADDR` and
+/// `$VAULT_TOKEN`. Cached like [`resolve_vault_secret_with`].
+pub fn resolve_vault_secret(vault_path: &str, field: &str) -> Result<String> {
+    resolve_vault_secret_with(&VaultSettings::new(None, None)?, vault_path, field)
+}
+
+/// Read `field` of the secret at `vault_path`. Only the first call per
+/// secret goes to Vault; later calls return the cached value or error, which
+/// the refresh task keeps current.
+pub fn resolve_vault_secret_with(
+    settings: &VaultSettings,
+    vault_path: &str,
+    field: &str,
+) -> Result<String> {
+    let key = SecretKey {
+        addr: settings.addr.clone(),
+        token_file: settings.token_file.clone(),
+        path: vault_path.to_string(),
+        field: field.to_string(),
+    };
+    if let Some(cached) = secrets()
+        .lock()
+        .ok()
+        .and_then(|s| s.get(&key).map(|c| c.value.clone()))
+    {
+        return cached;
+    }
+
+    // References are resolved while merging configs, before the shared
+    // runtime exists (and before nginx forks), so the read uses a throwaway one.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| X402Error::Runtime(format!("Failed to create tokio runtime: {e}")))?;
+    let value = runtime.block_on(fetch_secret(settings, vault_path, field));
+    if let Ok(mut secrets) = secrets().lock() {
+        secrets.insert(
+            key,
+            CachedSecret {
+                value: value.clone(),
+                settings: settings.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+    }
+    value
+}
+
+/// Forget every secret and the refresh TTL, so the next configuration load
+/// reads them again.
+pub fn clear_vault_cache() {
+    if let Ok(mut secrets) = secrets().lock() {
+        secrets.clear();
+    }
+    if let Ok(mut ttl) = REFRESH_TTL.lock() {
+        *ttl = None;
+    }
+}
+
+async fn fetch_secret(settings: &VaultSettings, vault_path: &str, field: &str) -> Result<String> {
+    let token = settings.token()?;
+    let url = format!("{}/v1/{vault_path}", settings.addr);
+    let client = reqwest::Client::builder()
+        .timeout(VAULT_TIMEOUT)
+        .user_agent(default_user_agent())
+        .build()
+        .map_err(|e| X402Error::Runtime(format!("Failed to create Vault client: {e}")))?;
+    let resp = client
+        .get(&url)
+        .header("X-Vault-Token", token)
+        .send()
+        .await
+        .map_err(|e| X402Error::Config(format!("Vault request to {url} failed: {e}")))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(X402Error::Config(format!(
+            "Vault returned {status} for {vault_path}"
+        )));
+    }
+    let body: serde_json::Value = resp
+        .json()
+        .await
+        .map_err(|e| X402Error::Config(format!("invalid Vault response for {vault_path}: {e}")))?;
+    extract_field(&body, field).ok_or_else(|| {
+        X402Error::Config(format!(
+            "Vault secret {vault_path} has no string field '{field}'"
+        ))
+    })
+}
+
+/// `field` of a KV v2 (`data.data`) or KV v1 (`data`) read response.
+fn extract_field(body: &serde_json::Value, field: &str) -> Option<String> {
+    let data = &body["data"];
+    let secret = if data["data"].is_object() {
+        &data["data"]
+    } else {
+        data
+    };
+    secret[field].as_str().map(String::from)
+}
+
+/// Record `x402_vault_secret_ttl_secs`; the shortest TTL configured wins.
+pub fn register_vault_refresh(ttl: Duration) {
+    if let Ok(mut current) = REFRESH_TTL.lock() {
+        *current = Some(current.map_or(ttl, |c| c.min(ttl)));
+    }
+}
+
+/// Start re-reading cached secrets every TTL. Called once per worker, after
+/// the fork; does nothing unless `x402_vault_secret_ttl_secs` is set.
+pub fn start_vault_refresh() {
+    let Some(ttl) = REFRESH_TTL.lock().ok().and_then(|ttl| *ttl) else {
+        return;
+    };
+    let task = async move {
+        loop {
+            tokio::time::sleep(ttl).await;
+            refresh_secrets(ttl).await;
+        }
+    };
+    if let Err(e) = spawn_on_runtime(task) {
+        log_warn(None, &format!("x402_vault_secret_ttl_secs: {e}"));
+    }
+}
+
+/// Re-read every secret fetched more than `ttl` ago.
+async fn refresh_secrets(ttl: Duration) {
+    for (key, settings) in due_secrets(ttl) {
+        refresh_secret(&key, &settings).await;
+    }
+}
+
+fn due_secrets(ttl: Duration) -> Vec<(SecretKey, VaultSettings)> {
+    match secrets().lock() {
+        Ok(secrets) => secrets
+            .iter()
+            .filter(|(_, cached)| cached.fetched_at.elapsed() >= ttl)
+            .map(|(key, cached)| (key.clone(), cached.settings.clone()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// A failed read keeps the previous value.
+async fn refresh_secret(key: &SecretKey, settings: &VaultSettings) {
+    match fetch_secret(settings, &key.path, &key.field).await {
+        Ok(value) => {
+            if let Ok(mut secrets) = secrets().lock() {
+                if let Some(cached) = secrets.get_mut(key) {
+                    cached.value = Ok(value);
+                    cached.fetched_at = Instant::now();
+                }
+            }
+        }
+        Err(e) => log_warn(
+            None,
+            &format!(
+                "Vault refresh of {}#{} failed, keeping the cached value: {e}",
+                key.path, key.field
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ngx_module::runtime::get_runtime;
+    use crate::ngx_module::test_support::MockServer;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn token_file(token: &str) -> PathBuf {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "x402-vault-token-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, format!("{token}\n")).unwrap();
+        path
+    }
+
+    /// Vault answering KV v2 reads of `secret/data/x402` with `token`;
+    /// `version` counts reads so each answer differs.
+    fn start_vault(token: &'static str) -> (MockServer, Arc<AtomicUsize>) {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counter = reads.clone();
+        let server = MockServer::start(move |req: &str| {
+            if !req
+                .to_ascii_lowercase()
+                .contains(&format!("x-vault-token: {token}"))
+            {
+                return (403, r#"{"errors":["permission denied"]}"#.to_string());
+            }
+            if !req.starts_with("GET /v1/secret/data/x402 ") {
+                return (404, r#"{"errors":[]}"#.to_string());
+            }
+            let version = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            (
+                200,
+                format!(
+                    r#"{{"data":{{"data":{{"pay_to":"0x{version:040x}","url":"https://f.example"}},"metadata":{{"version":{version}}}}}}}"#
+                ),
+            )
+        });
+        (server, reads)
+    }
+
+    #[test]
+    fn test_parse_vault_ref() {
+        assert_eq!(
+            parse_vault_ref("secret/data/x402#pay_to"),
+            Ok(("secret/data/x402", "pay_to"))
+        );
+        assert_eq!(
+            parse_vault_ref("/secret/data/x402/#url"),
+            Ok(("secret/data/x402", "url"))
+        );
+        assert!(parse_vault_ref("secret/data/x402").is_err());
+        assert!(parse_vault_ref("#pay_to").is_err());
+        assert!(parse_vault_ref("secret/data/x402#").is_err());
+    }
+
+    #[test]
+    fn test_extract_field_kv_v1_and_v2() {
+        let v2 = serde_json::json!({"data": {"data": {"k": "v2"}, "metadata": {}}});
+        let v1 = serde_json::json!({"data": {"k": "v1", "n": 3}});
+        assert_eq!(extract_field(&v2, "k").as_deref(), Some("v2"));
+        assert_eq!(extract_field(&v1, "k").as_deref(), Some("v1"));
+        assert_eq!(extract_field(&v1, "n"), None);
+        assert_eq!(extract_field(&v1, "missing"), None);
+    }
+
+    #[test]
+    fn test_resolve_reads_once_and_caches() {
+        let (server, reads) = start_vault("s.test-token");
+        let settings =
+            VaultSettings::new(Some(server.url.clone()), Some(token_file("s.test-token"))).unwrap();
+
+        let url = resolve_vault_secret_with(&settings, "secret/data/x402", "url").unwrap();
+        assert_eq!(url, "https://f.example");
+        let again = resolve_vault_secret_with(&settings, "secret/data/x402", "url").unwrap();
+        assert_eq!(again, url);
+        assert_eq!(reads.load(Ordering::Relaxed), 1, "second lookup is cached");
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let (server, _) = start_vault("s.right");
+        let wrong =
+            VaultSettings::new(Some(server.url.clone()), Some(token_file("s.wrong"))).unwrap();
+        let err = resolve_vault_secret_with(&wrong, "secret/data/x402", "url").unwrap_err();
+        assert!(err.to_string().contains("403"), "{err}");
+        let seen = server.requests().len();
+        assert_eq!(
+            resolve_vault_secret_with(&wrong, "secret/data/x402", "url").unwrap_err(),
+            err
+        );
+        assert_eq!(server.requests().len(), seen, "the failure is cached");
+
+        let right =
+            VaultSettings::new(Some(server.url.clone()), Some(token_file("s.right"))).unwrap();
+        let err = resolve_vault_secret_with(&right, "secret/data/x402", "nope").unwrap_err();
+        assert!(err.to_string().contains("no string field 'nope'"), "{err}");
+        let err = resolve_vault_secret_with(&right, "secret/data/other", "url").unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+
+        let missing = VaultSettings::new(
+            Some(server.url.clone()),
+            Some(PathBuf::from("/nonexistent/x402-vault-token")),
+        )
+        .unwrap();
+        assert!(resolve_vault_secret_with(&missing, "secret/data/x402", "url").is_err());
+    }
+
+    #[test]
+    fn test_refresh_picks_up_rotated_secret() {
+        let (server, reads) = start_vault("s.rotate");
+        let settings =
+            VaultSettings::new(Some(server.url.clone()), Some(token_file("s.rotate"))).unwrap();
+
+        let first = resolve_vault_secret_with(&settings, "secret/data/x402", "pay_to").unwrap();
+        assert_eq!(first, format!("0x{:040x}", 1));
+
+        // The cache is shared with other tests, so only look at this entry.
+        let key = SecretKey {
+            addr: settings.addr.clone(),
+            token_file: settings.token_file.clone(),
+            path: "secret/data/x402".to_string(),
+            field: "pay_to".to_string(),
+        };
+        let is_due = |ttl| due_secrets(ttl).iter().any(|(k, _)| *k == key);
+        assert!(is_due(Duration::ZERO));
+        assert!(!is_due(Duration::from_secs(3600)), "fetched within the TTL");
+
+        get_runtime()
+            .unwrap()
+            .block_on(refresh_secret(&key, &settings));
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+        let rotated = resolve_vault_secret_with(&settings, "secret/data/x402", "pay_to").unwrap();
+        assert_eq!(rotated, format!("0x{:040x}", 2));
+
+        // A failed refresh keeps the cached value.
+        let broken = VaultSettings {
+            token_file: Some(PathBuf::from("/nonexistent/x402-vault-token")),
+            ..settings.clone()
+        };
+        get_runtime()
+            .unwrap()
+            .block_on(refresh_secret(&key, &broken));
+        let kept = resolve_vault_secret_with(&settings, "secret/data/x402", "pay_to").unwrap();
+        assert_eq!(kept, rotated);
+    }
+}
@@ -0,0 +1,145 @@
+//! Helpers shared by unit tests that exercise outbound HTTP calls.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Minimal blocking HTTP/1.1 server answering every request through `respond`.
+///
+/// Each connection serves one request and is then closed, unless started
+/// with [`MockServer::start_keepalive`]. Raw requests (head and body) are
+/// recorded for later assertions.
+pub(crate) struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    pub fn start<F>(respond: F) -> Self
+    where
+        F: Fn(&str) -> (u16, String) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        Self::serve(
+            url,
+            move || listener.accept().map(|(stream, _)| stream),
+            respond,
+            false,
+        )
+    }
+
+    /// Like [`MockServer::start`], but keeps each connection open until the
+    /// client closes it. Connections are served one at a time.
+    pub fn start_keepalive<F>(respond: F) -> Self
+    where
+        F: Fn(&str) -> (u16, String) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        Self::serve(
+            url,
+            move || listener.accept().map(|(stream, _)| stream),
+            respond,
+            true,
+        )
+    }
+
+    /// Like [`MockServer::start`], listening on a fresh Unix domain socket.
+    /// `url` is `unix:<socket path>`.
+    pub fn start_unix<F>(respond: F) -> Self
+    where
+        F: Fn(&str) -> (u16, String) + Send + 'static,
+    {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "x402-mock-{}-{}.sock",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("bind mock unix socket");
+        let url = format!("unix:{}", path.display());
+        Self::serve(
+            url,
+            move || listener.accept().map(|(stream, _)| stream),
+            respond,
+            false,
+        )
+    }
+
+    fn serve<S, A, F>(url: String, mut accept: A, respond: F, keepalive: bool) -> Self
+    where
+        S: Read + Write,
+        A: FnMut() -> std::io::Result<S> + Send + 'static,
+        F: Fn(&str) -> (u16, String) + Send + 'static,
+    {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = Arc::clone(&connections);
+        let connection = if keepalive { "keep-alive" } else { "close" };
+        std::thread::spawn(move || {
+            while let Ok(mut stream) = accept() {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                loop {
+                    let request = read_request(&mut stream);
+                    if request.is_empty() {
+                        break;
+                    }
+                    let (status, body) = respond(&request);
+                    recorded.lock().unwrap().push(request);
+                    let response = format!(
+                        "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: {connection}\r\n\r\n{body}",
+                        body.len()
+                    );
+                    if stream.write_all(response.as_bytes()).is_err() || !keepalive {
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            url,
+            requests,
+            connections,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+fn read_request(stream: &mut impl Read) -> String {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).unwrap_or(0);
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&buf);
+        if let Some(head_end) = text.find("\r\n\r\n") {
+            let content_length = text[..head_end]
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if buf.len() >= head_end + 4 + content_length {
+                break;
+            }
+        }
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
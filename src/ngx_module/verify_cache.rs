@@ -0,0 +1,242 @@
+//! In-process cache of successful facilitator verifications, used by
+//! `x402_facilitator_fallback local_cache` when the facilitator is unreachable.
+//!
+//! Keyed by the raw `Payment-Signature` header. Each worker has its own cache;
+//! entries expire after the requirements' `max_timeout_seconds`, and the least
+//! recently used entry is evicted once `x402_local_cache_max_entries` is reached.
+//!
+//! A signature serves from the cache at most once, and not at all once it has
+//! settled: either way its entry is kept as "used" until it would have expired,
+//! so a replay of it is not let through while the facilitator is down.
+
+use crate::ngx_module::runtime::VerifyResponseBody;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Entries kept when `x402_local_cache_max_entries` is unset.
+pub const DEFAULT_LOCAL_CACHE_MAX_ENTRIES: usize = 1000;
+
+static LOCAL_VERIFY_CACHE: OnceLock<Mutex<VerifyCache>> = OnceLock::new();
+
+struct Entry {
+    /// `None` once the signature is used.
+    responses: Option<Vec<VerifyResponseBody>>,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+/// Verify responses by payment signature, with LRU eviction.
+#[derive(Default)]
+pub struct VerifyCache {
+    entries: HashMap<String, Entry>,
+    /// Bumped on every access; orders entries by recency.
+    clock: u64,
+}
+
+impl VerifyCache {
+    /// Cached responses for `signature`, unless missing, used or expired at `now`.
+    pub fn get(&mut self, signature: &str, now: Instant) -> Option<Vec<VerifyResponseBody>> {
+        self.entry(signature, now)?.responses.clone()
+    }
+
+    /// Like [`get`](Self::get), but marks the signature used, so it is
+    /// returned only once.
+    pub fn take(&mut self, signature: &str, now: Instant) -> Option<Vec<VerifyResponseBody>> {
+        self.entry(signature, now)?.responses.take()
+    }
+
+    /// Mark `signature` used until `now + ttl`, e.g. once it has settled.
+    /// Returns how many entries were evicted to stay within `max_entries`.
+    pub fn mark_used(
+        &mut self,
+        signature: &str,
+        ttl: Duration,
+        now: Instant,
+        max_entries: usize,
+    ) -> usize {
+        self.put(signature, None, now + ttl, max_entries)
+    }
+
+    fn entry(&mut self, signature: &str, now: Instant) -> Option<&mut Entry> {
+        let expired = self.entries.get(signature)?.expires_at <= now;
+        if expired {
+            self.entries.remove(signature);
+            return None;
+        }
+        self.clock += 1;
+        let entry = self.entries.get_mut(signature)?;
+        entry.last_used = self.clock;
+        Some(entry)
+    }
+
+    /// Store `responses` until `now + ttl`, unless the signature is already
+    /// used. Returns how many entries were evicted to stay within `max_entries`.
+    pub fn insert(
+        &mut self,
+        signature: &str,
+        responses: Vec<VerifyResponseBody>,
+        ttl: Duration,
+        now: Instant,
+        max_entries: usize,
+    ) -> usize {
+        if self
+            .entries
+            .get(signature)
+            .is_some_and(|entry| entry.responses.is_none() && entry.expires_at > now)
+        {
+            return 0;
+        }
+        self.put(signature, Some(responses), now + ttl, max_entries)
+    }
+
+    fn put(
+        &mut self,
+        signature: &str,
+        responses: Option<Vec<VerifyResponseBody>>,
+        expires_at: Instant,
+        max_entries: usize,
+    ) -> usize {
+        self.clock += 1;
+        self.entries.insert(
+            signature.to_string(),
+            Entry {
+                responses,
+                expires_at,
+                last_used: self.clock,
+            },
+        );
+        let mut evicted = 0;
+        while self.entries.len() > max_entries {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.entries.remove(&oldest);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn cache() -> &'static Mutex<VerifyCache> {
+    LOCAL_VERIFY_CACHE.get_or_init(|| Mutex::new(VerifyCache::default()))
+}
+
+/// Take a verification made earlier in this worker; the signature is marked
+/// used, so it is served from the cache only once.
+pub fn take_cached_verification(signature: &str) -> Option<Vec<VerifyResponseBody>> {
+    cache().lock().ok()?.take(signature, Instant::now())
+}
+
+/// Mark a settled signature used, so the cache never serves it again.
+pub fn mark_verification_used(signature: &str, ttl: Duration, max_entries: usize) -> usize {
+    match cache().lock() {
+        Ok(mut cache) => cache.mark_used(signature, ttl, Instant::now(), max_entries),
+        Err(_) => 0,
+    }
+}
+
+/// Remember a successful verification; returns the number of evicted entries.
+pub fn cache_verification(
+    signature: &str,
+    responses: Vec<VerifyResponseBody>,
+    ttl: Duration,
+    max_entries: usize,
+) -> usize {
+    match cache().lock() {
+        Ok(mut cache) => cache.insert(signature, responses, ttl, Instant::now(), max_entries),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid(payer: &str) -> Vec<VerifyResponseBody> {
+        vec![VerifyResponseBody {
+            is_valid: true,
+            invalid_reason: None,
+            payer: Some(payer.to_string()),
+        }]
+    }
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_hit_and_miss() {
+        let mut cache = VerifyCache::default();
+        let now = Instant::now();
+        assert_eq!(cache.insert("sig-a", valid("0xa"), TTL, now, 10), 0);
+
+        assert_eq!(cache.get("sig-a", now), Some(valid("0xa")));
+        assert_eq!(cache.get("sig-b", now), None);
+    }
+
+    #[test]
+    fn test_take_serves_once_and_used_blocks_insert() {
+        let mut cache = VerifyCache::default();
+        let now = Instant::now();
+        cache.insert("sig-a", valid("0xa"), TTL, now, 10);
+        assert_eq!(cache.take("sig-a", now), Some(valid("0xa")));
+        assert_eq!(cache.take("sig-a", now), None);
+
+        // A settled signature is never cached again until its entry expires.
+        cache.mark_used("sig-b", TTL, now, 10);
+        cache.insert("sig-b", valid("0xb"), TTL, now, 10);
+        assert_eq!(cache.get("sig-b", now), None);
+        cache.insert("sig-b", valid("0xb"), TTL, now + TTL, 10);
+        assert_eq!(cache.get("sig-b", now + TTL), Some(valid("0xb")));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut cache = VerifyCache::default();
+        let now = Instant::now();
+        cache.insert("sig-a", valid("0xa"), TTL, now, 10);
+
+        assert!(cache
+            .get("sig-a", now + TTL - Duration::from_secs(1))
+            .is_some());
+        assert_eq!(cache.get("sig-a", now + TTL), None);
+        assert!(cache.is_empty(), "expired entries are dropped on lookup");
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = VerifyCache::default();
+        let now = Instant::now();
+        cache.insert("sig-a", valid("0xa"), TTL, now, 2);
+        cache.insert("sig-b", valid("0xb"), TTL, now, 2);
+        // Touch `a` so `b` becomes the least recently used.
+        assert!(cache.get("sig-a", now).is_some());
+
+        assert_eq!(cache.insert("sig-c", valid("0xc"), TTL, now, 2), 1);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("sig-a", now).is_some());
+        assert_eq!(cache.get("sig-b", now), None);
+        assert!(cache.get("sig-c", now).is_some());
+    }
+
+    #[test]
+    fn test_reinsert_does_not_evict() {
+        let mut cache = VerifyCache::default();
+        let now = Instant::now();
+        cache.insert("sig-a", valid("0xa"), TTL, now, 1);
+        assert_eq!(cache.insert("sig-a", valid("0xa2"), TTL, now, 1), 0);
+        assert_eq!(cache.get("sig-a", now), Some(valid("0xa2")));
+    }
+}
@@ -16,7 +16,9 @@ void *ngx_pcalloc(void *pool, size_t size) { return calloc(1, size); }
 void *ngx_alloc(size_t size, void *log) { return malloc(size); }
 void *ngx_calloc(size_t size, void *log) { return calloc(1, size); }
 ngx_uint_t ngx_hash_strlow(u_char *dst, u_char *src, size_t n) { return 0; }
+ngx_uint_t ngx_hash_key(u_char *data, size_t len) { return 0; }
 void *ngx_pool_cleanup_add(void *p, size_t size) { return NULL; }
+void ngx_conf_log_error(ngx_uint_t level, void *cf, int err, const char *fmt, ...) {}
 
 /* List / Array stubs */
 void *ngx_list_push(void *l) { return NULL; }
@@ -28,6 +30,7 @@ ngx_int_t ngx_http_output_filter(void *r, void *in) { return -1; }
 void *ngx_create_temp_buf(void *pool, size_t size) { return NULL; }
 void *ngx_alloc_chain_link(void *pool) { return NULL; }
 ngx_int_t ngx_http_complex_value(void *r, void *val, void *str) { return -1; }
+void *ngx_http_get_variable(void *r, void *name, ngx_uint_t key) { return NULL; }
 ngx_int_t ngx_http_discard_request_body(void *r) { return 0; }
 ngx_int_t ngx_http_subrequest(void *r, void *uri, void *args, void **psr, void *ps, int flags) { return -1; }
 void ngx_http_named_location(void *r, void *name) {}
@@ -35,3 +38,4 @@ void ngx_http_internal_redirect(void *r, void *uri, void *args) {}
 
 /* Global modules referenced by ngx crate */
 char ngx_http_core_module[4096];
+char ngx_core_module[4096];
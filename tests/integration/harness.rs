@@ -0,0 +1,149 @@
+//! Starts a real nginx with the compiled module loaded.
+//!
+//! The module has to be the `export-modules` build (`cargo build --release
+//! --features export-modules`): the `integration-test` feature links the nginx
+//! test stubs, which would shadow nginx's own symbols. nginx and the module
+//! must come from the same nginx source tree.
+//!
+//! - `X402_TEST_MODULE`: path to `libngx_x402.so` (default: `target/release/libngx_x402.so`)
+//! - `NGINX_BINARY_PATH`: nginx binary (default: `nginx` on `PATH`)
+//! - `X402_REQUIRE_NGINX=1`: fail instead of skipping when either is missing
+
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const TEMPLATE: &str = include_str!("nginx.conf.template");
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Nginx {
+    child: Child,
+    prefix: PathBuf,
+    pub base_url: String,
+}
+
+impl Nginx {
+    /// Start nginx proxying to a stand-in backend, with `/paid` gated by x402
+    /// against `facilitator_url`. Returns `None` when nginx or the module is
+    /// not available and `X402_REQUIRE_NGINX` is unset.
+    pub fn start(facilitator_url: &str) -> Option<Self> {
+        let module = module_path();
+        let nginx = std::env::var("NGINX_BINARY_PATH").unwrap_or_else(|_| "nginx".to_string());
+        if let Err(reason) = check_prerequisites(&nginx, &module) {
+            if std::env::var_os("X402_REQUIRE_NGINX").is_some_and(|v| v != "0") {
+                panic!("{reason}");
+            }
+            eprintln!("skipping nginx integration test: {reason}");
+            return None;
+        }
+
+        let prefix = fresh_prefix();
+        let port = free_port();
+        let conf = render(
+            TEMPLATE,
+            &[
+                ("PREFIX", prefix.display().to_string()),
+                ("MODULE_PATH", module.display().to_string()),
+                ("PORT", port.to_string()),
+                ("BACKEND_PORT", free_port().to_string()),
+                ("FACILITATOR_URL", facilitator_url.to_string()),
+            ],
+        );
+        let conf_path = prefix.join("nginx.conf");
+        std::fs::write(&conf_path, conf).expect("write nginx.conf");
+
+        let child = Command::new(&nginx)
+            .arg("-p")
+            .arg(&prefix)
+            .arg("-c")
+            .arg(&conf_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn nginx");
+        let mut instance = Self {
+            child,
+            prefix,
+            base_url: format!("http://127.0.0.1:{port}"),
+        };
+        instance.wait_until_listening(port);
+        Some(instance)
+    }
+
+    fn wait_until_listening(&mut self, port: u16) {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("nginx exited with {status}:\n{}", self.error_log());
+            }
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("nginx did not start listening:\n{}", self.error_log());
+    }
+
+    pub fn error_log(&self) -> String {
+        std::fs::read_to_string(self.prefix.join("error.log")).unwrap_or_default()
+    }
+}
+
+impl Drop for Nginx {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+        if std::thread::panicking() {
+            eprintln!("nginx error log:\n{}", self.error_log());
+        }
+        std::fs::remove_dir_all(&self.prefix).ok();
+    }
+}
+
+fn module_path() -> PathBuf {
+    std::env::var_os("X402_TEST_MODULE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("target/release/libngx_x402.so")
+        })
+}
+
+fn check_prerequisites(nginx: &str, module: &Path) -> Result<(), String> {
+    if !module.is_file() {
+        return Err(format!("module not found at {}", module.display()));
+    }
+    match Command::new(nginx).arg("-v").output() {
+        Ok(output) if output.status.success() => Ok(()),
+        _ => Err(format!("cannot run nginx binary '{nginx}'")),
+    }
+}
+
+/// Replace every `{{NAME}}` in `template`.
+fn render(template: &str, vars: &[(&str, String)]) -> String {
+    vars.iter()
+        .fold(template.to_string(), |conf, (name, value)| {
+            conf.replace(&format!("{{{{{name}}}}}"), value)
+        })
+}
+
+fn fresh_prefix() -> PathBuf {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let prefix = std::env::temp_dir().join(format!(
+        "x402-nginx-{}-{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&prefix);
+    std::fs::create_dir_all(prefix.join("logs")).expect("create nginx prefix");
+    prefix
+}
+
+/// A port that was free a moment ago; nginx binds it shortly after.
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("find free port")
+}
@@ -0,0 +1,125 @@
+//! End-to-end tests: a real nginx with the module loaded, a stand-in backend
+//! and a mock facilitator. See `harness.rs` for the required environment.
+
+mod harness;
+mod mock_facilitator;
+
+use base64::Engine;
+use harness::Nginx;
+use mock_facilitator::{MockFacilitator, PAYER};
+use serde_json::{json, Value};
+
+const PAY_TO: &str = "0x1234567890abcdef1234567890abcdef12345678";
+const USDC_BASE_SEPOLIA: &str = "0x036CbD53842c5426634e7929541eC2318f3dCF7e";
+
+/// `x402_amount 0.001` in USDC's smallest unit.
+const REQUIRED_AMOUNT: &str = "1000";
+
+/// A v2 `Payment-Signature` value signing for `amount`. The mock facilitator
+/// does not check signatures, only the amount.
+fn payment_header(amount: &str) -> String {
+    let payload = json!({
+        "x402Version": 2,
+        "accepted": {
+            "scheme": "exact",
+            "network": "eip155:84532",
+            "amount": amount,
+            "payTo": PAY_TO,
+            "maxTimeoutSeconds": 60,
+            "asset": USDC_BASE_SEPOLIA,
+        },
+        "payload": {
+            "signature": format!("0x{}", "ab".repeat(65)),
+            "authorization": {
+                "from": PAYER,
+                "to": PAY_TO,
+                "value": amount,
+                "validAfter": "0",
+                "validBefore": "9999999999",
+                "nonce": format!("0x{}", "00".repeat(32)),
+            },
+        },
+    });
+    base64::engine::general_purpose::STANDARD.encode(payload.to_string())
+}
+
+async fn get(nginx: &Nginx, path: &str, payment: Option<String>) -> (u16, String) {
+    let mut request = reqwest::Client::new()
+        .get(format!("{}{path}", nginx.base_url))
+        .header("Accept", "application/json");
+    if let Some(payment) = payment {
+        request = request.header("Payment-Signature", payment);
+    }
+    let resp = request.send().await.expect("request to nginx");
+    let status = resp.status().as_u16();
+    (status, resp.text().await.unwrap_or_default())
+}
+
+#[tokio::test]
+async fn test_no_payment_returns_402() {
+    let facilitator = MockFacilitator::start();
+    let Some(nginx) = Nginx::start(&facilitator.url) else {
+        return;
+    };
+
+    let (status, body) = get(&nginx, "/paid", None).await;
+    assert_eq!(status, 402);
+    let body: Value = serde_json::from_str(&body).expect("402 body is JSON");
+    assert_eq!(body["accepts"][0]["amount"], REQUIRED_AMOUNT);
+    assert_eq!(body["accepts"][0]["payTo"], PAY_TO);
+    assert!(facilitator.request_lines().is_empty());
+}
+
+#[tokio::test]
+async fn test_valid_payment_returns_200() {
+    let facilitator = MockFacilitator::start();
+    let Some(nginx) = Nginx::start(&facilitator.url) else {
+        return;
+    };
+
+    let (status, body) = get(&nginx, "/paid", Some(payment_header(REQUIRED_AMOUNT))).await;
+    assert_eq!(status, 200, "body: {body}");
+    assert_eq!(body, "backend ok");
+
+    let lines = facilitator.request_lines();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("POST /verify "));
+    assert!(lines[1].starts_with("POST /settle "));
+}
+
+#[tokio::test]
+async fn test_wrong_amount_returns_402() {
+    let facilitator = MockFacilitator::start();
+    let Some(nginx) = Nginx::start(&facilitator.url) else {
+        return;
+    };
+
+    let (status, _) = get(&nginx, "/paid", Some(payment_header("1"))).await;
+    assert_eq!(status, 402);
+
+    let lines = facilitator.request_lines();
+    assert_eq!(lines.len(), 1, "nothing is settled: {lines:?}");
+    assert!(lines[0].starts_with("POST /verify "));
+}
+
+#[tokio::test]
+async fn test_forward_payer_overwrites_client_header() {
+    let facilitator = MockFacilitator::start();
+    let Some(nginx) = Nginx::start(&facilitator.url) else {
+        return;
+    };
+
+    let resp = reqwest::Client::new()
+        .get(format!("{}/paid-forward", nginx.base_url))
+        .header("Accept", "application/json")
+        .header("Payment-Signature", payment_header(REQUIRED_AMOUNT))
+        .header(
+            "X-Payment-Payer",
+            "0x0000000000000000000000000000000000000001",
+        )
+        .send()
+        .await
+        .expect("request to nginx");
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.text().await.unwrap(), PAYER);
+}
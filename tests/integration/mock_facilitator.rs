@@ -0,0 +1,102 @@
+//! Facilitator stand-in for the nginx integration tests.
+//!
+//! `/verify` accepts a payment when the amount the client signed for
+//! (`paymentPayload.accepted.amount`) equals the required amount, which is the
+//! check a real facilitator makes that matters for these tests. `/settle`
+//! always succeeds.
+
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+pub const PAYER: &str = "0x9999999999999999999999999999999999999999";
+pub const TX_HASH: &str = "0xfeedfacefeedfacefeedfacefeedfacefeedfacefeedfacefeedfacefeedface";
+
+pub struct MockFacilitator {
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockFacilitator {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock facilitator");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            while let Ok((mut stream, _)) = listener.accept() {
+                let request = read_request(&mut stream);
+                let (status, body) = respond(&request);
+                recorded.lock().unwrap().push(request);
+                let response = format!(
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).ok();
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// Request lines (`POST /verify HTTP/1.1`, ...) received so far.
+    pub fn request_lines(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|req| req.lines().next().map(String::from))
+            .collect()
+    }
+}
+
+fn respond(request: &str) -> (u16, String) {
+    let body = request
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body)
+        .unwrap_or("");
+    let json: Value = serde_json::from_str(body).unwrap_or_default();
+    if request.starts_with("POST /verify") {
+        let signed = json.pointer("/paymentPayload/accepted/amount");
+        let required = json.pointer("/paymentRequirements/amount");
+        let valid = signed.is_some() && signed == required;
+        let body = json!({
+            "isValid": valid,
+            "invalidReason": (!valid).then_some("invalid_exact_evm_payload_authorization_value"),
+            "payer": PAYER,
+        });
+        (200, body.to_string())
+    } else if request.starts_with("POST /settle") {
+        (
+            200,
+            json!({ "success": true, "txHash": TX_HASH }).to_string(),
+        )
+    } else {
+        (404, "{}".to_string())
+    }
+}
+
+fn read_request(stream: &mut impl Read) -> String {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).unwrap_or(0);
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&buf);
+        if let Some(head_end) = text.find("\r\n\r\n") {
+            let content_length = text[..head_end]
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if buf.len() >= head_end + 4 + content_length {
+                break;
+            }
+        }
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}